@@ -1,138 +1,505 @@
-use crate::blockchain::Hash;
-use serde::{de::Visitor, Deserialize, Serialize};
-use sha3::{digest::Digest, Sha3_256};
-
-pub type PrivateKey = Hash; // Private keys have the same size of hashes.
-pub type PublicKey = PrivateKey;
-
-const CURRENT_VERSION: u8 = 0;
-
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Address {
-    base58: String,
-}
-
-impl Address {
-    pub fn from_private_key(private_key: &PrivateKey) -> Self {
-        // Get public key from the private one.
-        let private_key = k256::SecretKey::from_be_bytes(private_key).unwrap();
-
-        // Create hash of pubic key.
-        let public_key = private_key.public_key();
-        let public_key_bytes = bincode::serialize(&public_key).unwrap();
-
-        let mut hasher = Sha3_256::default();
-        hasher.update(&public_key_bytes);
-
-        let public_key_hash: Hash = hasher.finalize().as_slice().try_into().unwrap();
-
-        // Create checksum by double hashing the version and the public key.
-        let checksum = Self::calculate_checksum(CURRENT_VERSION, public_key_hash);
-        let checksum = checksum.as_slice();
-
-        // Create the address by concatenating the version, public key hash and checksum.
-        let mut value = vec![CURRENT_VERSION];
-        value.extend_from_slice(&public_key_hash);
-        value.extend_from_slice(checksum);
-
-        // Encode result in base58 format.
-        let base58 = bs58::encode(&value).into_string();
-
-        Self { base58 }
-    }
-
-    pub fn from_string(base58: &str) -> Self {
-        Self {
-            base58: base58.to_string(),
-        }
-    }
-
-    pub fn as_str(&self) -> &str {
-        self.base58.as_str()
-    }
-
-    pub fn validate(&self) -> bool {
-        // Decode address.
-        let address = {
-            if let Ok(address) = bs58::decode(&self.base58).into_vec() {
-                address
-            } else {
-                return false;
-            }
-        };
-
-        // Check if address has the correct size.
-        if address.len() != 1 + 32 + 4 {
-            return false;
-        }
-
-        let version = &address[0];
-        let public_key_hash = &address[1..33];
-        let checksum = &address[33..];
-
-        // Check if address has the correct version.
-        if *version != CURRENT_VERSION {
-            return false;
-        }
-
-        // Check if the checksum matches.
-        let check =
-            &Self::calculate_checksum(*version, public_key_hash.try_into().unwrap_or_default())
-                [..4];
-
-        if check != checksum {
-            return false;
-        }
-
-        true
-    }
-
-    fn calculate_checksum(version: u8, public_key_hash: Hash) -> Vec<u8> {
-        let mut hasher = Sha3_256::default();
-        hasher.update(&[version]);
-        hasher.update(&public_key_hash);
-        let hash: Hash = hasher.finalize().as_slice().try_into().unwrap();
-
-        let mut hasher = Sha3_256::default();
-        hasher.update(hash);
-
-        let hash: Hash = hasher.finalize().as_slice().try_into().unwrap();
-
-        hash[..4].to_vec()
-    }
-}
-
-impl Serialize for Address {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(self.as_str())
-    }
-}
-
-struct AddressVisitor;
-
-impl<'de> Visitor<'de> for AddressVisitor {
-    type Value = Address;
-
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("a string containing a valid address.")
-    }
-
-    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
-    where
-        E: serde::de::Error,
-    {
-        Ok(Address::from_string(v))
-    }
-}
-
-impl<'de> Deserialize<'de> for Address {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        deserializer.deserialize_str(AddressVisitor)
-    }
-}
+use crate::{
+    bech32,
+    blockchain::{Hash, PubKeyHash},
+    chain_params::ChainParams,
+};
+use anyhow::{bail, Context, Result};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use rand_core::OsRng;
+use rayon::prelude::*;
+use serde::{de::Visitor, Deserialize, Serialize};
+use sha3::{digest::Digest, Sha3_256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::Zeroize;
+
+// Private keys have the same size of hashes. They are compared in constant time, never printed and wiped on drop.
+#[derive(Clone)]
+pub struct PrivateKey([u8; 32]);
+
+impl PrivateKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl TryFrom<&[u8]> for PrivateKey {
+    type Error = anyhow::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes
+            .try_into()
+            .ok()
+            .context("Private keys must be exactly 32 bytes.")?;
+
+        Ok(Self(bytes))
+    }
+}
+
+impl ConstantTimeEq for PrivateKey {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl PartialEq for PrivateKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for PrivateKey {}
+
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PrivateKey(..)")
+    }
+}
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+const PUBLIC_KEY_SIZE: usize = 33; // Compressed SEC1 encoding.
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct PublicKey([u8; PUBLIC_KEY_SIZE]);
+
+impl PublicKey {
+    pub fn from_private_key(private_key: &PrivateKey) -> Self {
+        let private_key = k256::SecretKey::from_slice(private_key.as_bytes()).unwrap();
+
+        Self::from_k256(&private_key.public_key())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        // Make sure the bytes are really a point in the curve.
+        let public_key = k256::PublicKey::from_sec1_bytes(bytes)
+            .ok()
+            .context("Invalid public key encoding.")?;
+
+        Ok(Self::from_k256(&public_key))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn calculate_hash(&self) -> PubKeyHash {
+        let mut hasher = Sha3_256::default();
+        hasher.update(self.0);
+
+        hasher.finalize().as_slice().try_into().unwrap()
+    }
+
+    pub(crate) fn to_k256(self) -> k256::PublicKey {
+        // The bytes were validated when the key was created.
+        k256::PublicKey::from_sec1_bytes(&self.0).unwrap()
+    }
+
+    fn from_k256(public_key: &k256::PublicKey) -> Self {
+        let encoded_point = public_key.to_encoded_point(true);
+
+        Self(encoded_point.as_bytes().try_into().unwrap())
+    }
+}
+
+impl TryFrom<&[u8]> for PublicKey {
+    type Error = anyhow::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Self::from_bytes(bytes)
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+struct PublicKeyVisitor;
+
+impl<'de> Visitor<'de> for PublicKeyVisitor {
+    type Value = PublicKey;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a compressed SEC1 public key.")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        PublicKey::from_bytes(v).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(PublicKeyVisitor)
+    }
+}
+
+const BECH32_VERSION: u8 = 0;
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AddressEncoding {
+    Base58,
+    Bech32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Address {
+    encoded: String,
+    decoded: Option<DecodedAddress>, // Invalid addresses can't be decoded.
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct DecodedAddress {
+    address_version: u8,
+    public_key_hash: PubKeyHash,
+    encoding: AddressEncoding,
+}
+
+impl Address {
+    pub fn from_private_key(private_key: &PrivateKey) -> Self {
+        Self::from_private_key_for(&ChainParams::mainnet(), private_key)
+    }
+
+    pub fn from_private_key_for(chain_params: &ChainParams, private_key: &PrivateKey) -> Self {
+        let public_key = PublicKey::from_private_key(private_key);
+
+        Self::from_public_key_hash_for(chain_params, public_key.calculate_hash())
+    }
+
+    pub fn from_public_key(public_key: &PublicKey) -> Self {
+        Self::from_public_key_hash(public_key.calculate_hash())
+    }
+
+    pub fn from_public_key_hash(public_key_hash: PubKeyHash) -> Self {
+        Self::from_public_key_hash_for(&ChainParams::mainnet(), public_key_hash)
+    }
+
+    pub fn from_public_key_hash_for(
+        chain_params: &ChainParams,
+        public_key_hash: PubKeyHash,
+    ) -> Self {
+        Self::from_public_key_hash_with_encoding(
+            chain_params,
+            public_key_hash,
+            AddressEncoding::Base58,
+        )
+    }
+
+    pub fn from_public_key_hash_with_encoding(
+        chain_params: &ChainParams,
+        public_key_hash: PubKeyHash,
+        encoding: AddressEncoding,
+    ) -> Self {
+        let encoded = match encoding {
+            AddressEncoding::Base58 => {
+                let version = chain_params.address_version;
+
+                // Create checksum by double hashing the version and the public key.
+                let checksum = Self::calculate_checksum(version, public_key_hash);
+                let checksum = checksum.as_slice();
+
+                // Create the address by concatenating the version, public key hash and checksum.
+                let mut value = vec![version];
+                value.extend_from_slice(public_key_hash.as_bytes());
+                value.extend_from_slice(checksum);
+
+                // Encode result in base58 format.
+                bs58::encode(&value).into_string()
+            }
+
+            AddressEncoding::Bech32 => {
+                // The network is given by the human readable prefix, so only the hash is encoded.
+                bech32::encode(
+                    chain_params.bech32_hrp,
+                    BECH32_VERSION,
+                    public_key_hash.as_bytes(),
+                )
+            }
+        };
+
+        Self {
+            encoded,
+            decoded: Some(DecodedAddress {
+                address_version: chain_params.address_version,
+                public_key_hash,
+                encoding,
+            }),
+        }
+    }
+
+    pub fn from_string(encoded: &str) -> Self {
+        let decoded = Self::decode_base58(encoded).or_else(|| Self::decode_bech32(encoded));
+
+        Self {
+            encoded: encoded.to_string(),
+            decoded,
+        }
+    }
+
+    pub fn parse(encoded: &str) -> Result<Self> {
+        let address = Self::from_string(encoded);
+        if !address.validate() {
+            bail!("Invalid address: {encoded}.")
+        }
+
+        Ok(address)
+    }
+
+    pub fn search_prefix(
+        prefix: &str,
+        threads: usize,
+        cancel_flag: &AtomicBool,
+    ) -> Result<Option<(PrivateKey, Self)>> {
+        // Base58 has no 0, O, I or l, so such prefixes would never be found.
+        if prefix.is_empty()
+            || !prefix
+                .chars()
+                .all(|character| BASE58_ALPHABET.contains(character))
+        {
+            bail!("Prefix must be a non empty base58 string.")
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("Failed to start the vanity search threads.")?;
+
+        // Every thread grinds random keys until one of them finds a match or the search is cancelled.
+        let found = pool.install(|| {
+            (0..pool.current_num_threads())
+                .into_par_iter()
+                .find_map_any(|_| {
+                    while !cancel_flag.load(Ordering::Relaxed) {
+                        let private_key = PrivateKey::from_bytes(
+                            k256::SecretKey::random(&mut OsRng).to_bytes().into(),
+                        );
+                        let address = Self::from_private_key(&private_key);
+
+                        if address.as_str().starts_with(prefix) {
+                            // Let the other threads know they can stop.
+                            cancel_flag.store(true, Ordering::Relaxed);
+                            return Some((private_key, address));
+                        }
+                    }
+
+                    None
+                })
+        });
+
+        Ok(found)
+    }
+
+    pub fn to_encoding(&self, encoding: AddressEncoding) -> Result<Self> {
+        let chain_params = self.get_chain_params().context("Invalid address.")?;
+
+        Ok(Self::from_public_key_hash_with_encoding(
+            &chain_params,
+            self.public_key_hash()?,
+            encoding,
+        ))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.encoded.as_str()
+    }
+
+    pub fn get_encoding(&self) -> Option<AddressEncoding> {
+        self.decoded.map(|decoded| decoded.encoding)
+    }
+
+    pub fn public_key_hash(&self) -> Result<PubKeyHash> {
+        if let Some(decoded) = self.decoded {
+            Ok(decoded.public_key_hash)
+        } else {
+            bail!("Invalid address.")
+        }
+    }
+
+    pub fn get_chain_params(&self) -> Option<ChainParams> {
+        ChainParams::from_address_version(self.decoded?.address_version)
+    }
+
+    pub fn validate_for(&self, chain_params: &ChainParams) -> bool {
+        self.get_chain_params().is_some_and(|address_chain_params| {
+            address_chain_params.network == chain_params.network
+        })
+    }
+
+    pub fn validate(&self) -> bool {
+        self.decoded.is_some()
+    }
+
+    fn decode_base58(encoded: &str) -> Option<DecodedAddress> {
+        let address = bs58::decode(encoded).into_vec().ok()?;
+
+        Self::decode_bytes(&address)
+    }
+
+    fn decode_bytes(address: &[u8]) -> Option<DecodedAddress> {
+        // Check if address has the correct size.
+        if address.len() != 1 + 32 + 4 {
+            return None;
+        }
+
+        let version = address[0];
+        let public_key_hash: PubKeyHash = address[1..33].try_into().unwrap();
+        let checksum = &address[33..];
+
+        // Check if address has the version of a known network.
+        ChainParams::from_address_version(version)?;
+
+        // Check if the checksum matches.
+        let check = &Self::calculate_checksum(version, public_key_hash)[..4];
+        if !bool::from(check.ct_eq(checksum)) {
+            return None;
+        }
+
+        Some(DecodedAddress {
+            address_version: version,
+            public_key_hash,
+            encoding: AddressEncoding::Base58,
+        })
+    }
+
+    fn decode_bech32(encoded: &str) -> Option<DecodedAddress> {
+        let (hrp, version, payload) = bech32::decode(encoded)?;
+        let chain_params = ChainParams::from_bech32_hrp(&hrp)?;
+
+        if version != BECH32_VERSION {
+            return None;
+        }
+
+        Some(DecodedAddress {
+            address_version: chain_params.address_version,
+            public_key_hash: payload.as_slice().try_into().ok()?,
+            encoding: AddressEncoding::Bech32,
+        })
+    }
+
+    fn calculate_checksum(version: u8, public_key_hash: PubKeyHash) -> Vec<u8> {
+        let mut hasher = Sha3_256::default();
+        hasher.update([version]);
+        hasher.update(public_key_hash);
+        let hash: Hash = hasher.finalize().as_slice().try_into().unwrap();
+
+        let mut hasher = Sha3_256::default();
+        hasher.update(hash);
+
+        let hash: Hash = hasher.finalize().as_slice().try_into().unwrap();
+
+        hash[..4].to_vec()
+    }
+}
+
+impl PartialEq for Address {
+    fn eq(&self, other: &Self) -> bool {
+        // The same key is the same address no matter how it's encoded.
+        match (&self.decoded, &other.decoded) {
+            (Some(decoded), Some(other_decoded)) => {
+                decoded.address_version == other_decoded.address_version
+                    && decoded.public_key_hash == other_decoded.public_key_hash
+            }
+
+            _ => self.encoded == other.encoded,
+        }
+    }
+}
+
+impl Eq for Address {}
+
+impl std::hash::Hash for Address {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // Must agree with equality, so the encoding is left out.
+        match &self.decoded {
+            Some(decoded) => {
+                decoded.address_version.hash(state);
+                decoded.public_key_hash.hash(state);
+            }
+
+            None => self.encoded.hash(state),
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(encoded: &str) -> Result<Self> {
+        Self::parse(encoded)
+    }
+}
+
+impl TryFrom<&[u8]> for Address {
+    type Error = anyhow::Error;
+
+    // Raw base58 payload: version, public key hash and checksum.
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let decoded = Self::decode_bytes(bytes).context("Invalid address bytes.")?;
+
+        Ok(Self {
+            encoded: bs58::encode(bytes).into_string(),
+            decoded: Some(decoded),
+        })
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+struct AddressVisitor;
+
+impl<'de> Visitor<'de> for AddressVisitor {
+    type Value = Address;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a string containing a valid address.")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Address::parse(v).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(AddressVisitor)
+    }
+}
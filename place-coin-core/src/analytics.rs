@@ -0,0 +1,184 @@
+use crate::{
+    address::Address,
+    block::Block,
+    blockchain::PubKeyHash,
+    canvas::Canvas,
+    chain_params::ChainParams,
+    transaction::{Credits, TransactionOutput},
+};
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::RangeInclusive,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeatMap {
+    width: i32,
+    height: i32,
+    paints: Vec<u32>, // Number of paints per pixel, row by row.
+}
+
+impl HeatMap {
+    pub fn from_canvas(canvas: &Canvas, heights: RangeInclusive<u64>) -> Result<Self> {
+        let (width, height) = (canvas.get_width().max(0), canvas.get_height().max(0));
+        let mut paints = vec![0; width as usize * height as usize];
+
+        for ((x, y), records) in canvas.get_history()? {
+            if !(0..width).contains(x) || !(0..height).contains(y) {
+                continue;
+            }
+
+            let count = records
+                .iter()
+                .filter(|record| heights.contains(&record.height))
+                .count();
+
+            paints[(y * width + x) as usize] += count as u32;
+        }
+
+        Ok(Self {
+            width,
+            height,
+            paints,
+        })
+    }
+
+    pub fn get_paints(&self, x: i32, y: i32) -> u32 {
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            self.paints[(y * self.width + x) as usize]
+        } else {
+            0
+        }
+    }
+
+    pub fn get_max_paints(&self) -> u32 {
+        self.paints.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn get_width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn get_height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn render(&self) -> Vec<u8> {
+        // Untouched pixels are black, and the most contested ones are bright red.
+        let max_paints = self.get_max_paints().max(1);
+
+        self.paints
+            .iter()
+            .flat_map(|paints| {
+                let intensity = (*paints as u64 * 0xFF / max_paints as u64) as u8;
+
+                [intensity, 0, 0, 0xFF]
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "image")]
+    pub fn write_png<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        use anyhow::Context;
+        use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+
+        PngEncoder::new(writer)
+            .write_image(
+                &self.render(),
+                self.width as u32,
+                self.height as u32,
+                ColorType::Rgba8,
+            )
+            .context("Failed to encode the heat map as PNG.")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PainterStats {
+    pub address: Address,
+    pub pixels_painted: u64,
+    pub credits_spent: Credits,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PaintCounter {
+    blocks: BTreeMap<u64, HashMap<PubKeyHash, (u64, Credits)>>, // Paints and credits per painter, per height.
+}
+
+impl PaintCounter {
+    pub fn connect_block(&mut self, block: &Block) -> Result<()> {
+        let height = block.get_block_height()?;
+
+        let mut painters: HashMap<PubKeyHash, (u64, Credits)> = HashMap::new();
+        for transaction in block.get_transactions() {
+            let public_key = match transaction.get_signer() {
+                Some(public_key) => public_key,
+                None => continue,
+            };
+
+            for output in transaction.get_outputs() {
+                let (pixels, credits) = match output {
+                    TransactionOutput::ToPixel { value, .. } => (1, *value),
+                    TransactionOutput::LockPixel { value, .. } => (0, *value),
+                    TransactionOutput::ToInput { .. } | TransactionOutput::Moderate { .. } => {
+                        continue
+                    }
+                };
+
+                let counter = painters.entry(public_key.calculate_hash()).or_default();
+                counter.0 += pixels;
+                counter.1 += credits;
+            }
+        }
+
+        if !painters.is_empty() {
+            self.blocks.insert(height, painters);
+        }
+
+        Ok(())
+    }
+
+    pub fn disconnect_block(&mut self, height: u64) {
+        self.blocks.remove(&height);
+    }
+
+    pub fn top_painters(
+        &self,
+        chain_params: &ChainParams,
+        heights: RangeInclusive<u64>,
+        count: usize,
+    ) -> Vec<PainterStats> {
+        let mut totals: HashMap<PubKeyHash, (u64, Credits)> = HashMap::new();
+        for painters in self.blocks.range(heights).map(|(_, painters)| painters) {
+            for (public_key_hash, (pixels, credits)) in painters {
+                let total = totals.entry(*public_key_hash).or_default();
+                total.0 += pixels;
+                total.1 += credits;
+            }
+        }
+
+        // Most pixels first, then most credits, then by key so ties are stable.
+        let mut totals = totals.into_iter().collect::<Vec<_>>();
+        totals.sort_by(
+            |(hash, (pixels, credits)), (other_hash, (other_pixels, other_credits))| {
+                other_pixels
+                    .cmp(pixels)
+                    .then(other_credits.cmp(credits))
+                    .then(hash.cmp(other_hash))
+            },
+        );
+
+        totals
+            .into_iter()
+            .take(count)
+            .map(
+                |(public_key_hash, (pixels_painted, credits_spent))| PainterStats {
+                    address: Address::from_public_key_hash_for(chain_params, public_key_hash),
+                    pixels_painted,
+                    credits_spent,
+                },
+            )
+            .collect()
+    }
+}
@@ -0,0 +1,145 @@
+// Bech32m encoding (BIP-350), used for the human readable address format.
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32M_CONSTANT: u32 = 0x2bc8_30a3;
+const GENERATOR: [u32; 5] = [
+    0x3b6a_57b2,
+    0x2650_8e6d,
+    0x1ea1_19fa,
+    0x3d42_33dd,
+    0x2a14_62b3,
+];
+const CHECKSUM_SIZE: usize = 6;
+const MAX_SIZE: usize = 90;
+
+pub(crate) fn encode(hrp: &str, version: u8, payload: &[u8]) -> String {
+    debug_assert!(version < 32);
+
+    let mut data = vec![version];
+    data.extend(convert_bits(payload, 8, 5, true).unwrap());
+
+    let checksum = create_checksum(hrp, &data);
+
+    let mut encoded = format!("{hrp}1");
+    encoded.extend(
+        data.iter()
+            .chain(checksum.iter())
+            .map(|value| CHARSET[*value as usize] as char),
+    );
+
+    encoded
+}
+
+pub(crate) fn decode(encoded: &str) -> Option<(String, u8, Vec<u8>)> {
+    // Mixed case strings are never valid.
+    let lowercase = encoded.to_lowercase();
+    if lowercase != encoded && encoded.to_uppercase() != encoded {
+        return None;
+    }
+
+    if lowercase.len() > MAX_SIZE {
+        return None;
+    }
+
+    let separator = lowercase.rfind('1')?;
+    if separator == 0 || separator + 1 + CHECKSUM_SIZE > lowercase.len() {
+        return None;
+    }
+
+    let hrp = &lowercase[..separator];
+    if hrp.bytes().any(|byte| !(33..=126).contains(&byte)) {
+        return None;
+    }
+
+    let data = lowercase[separator + 1..]
+        .bytes()
+        .map(|byte| {
+            CHARSET
+                .iter()
+                .position(|character| *character == byte)
+                .map(|value| value as u8)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    if polymod(expand_hrp(hrp).into_iter().chain(data.iter().copied())) != BECH32M_CONSTANT {
+        return None;
+    }
+
+    let (version, values) = data[..data.len() - CHECKSUM_SIZE].split_first()?;
+    let payload = convert_bits(values, 5, 8, false)?;
+
+    Some((hrp.to_string(), *version, payload))
+}
+
+fn create_checksum(hrp: &str, data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let values = expand_hrp(hrp)
+        .into_iter()
+        .chain(data.iter().copied())
+        .chain([0; CHECKSUM_SIZE]);
+
+    let checksum = polymod(values) ^ BECH32M_CONSTANT;
+
+    let mut result = [0; CHECKSUM_SIZE];
+    for (index, value) in result.iter_mut().enumerate() {
+        *value = ((checksum >> (5 * (CHECKSUM_SIZE - 1 - index))) & 31) as u8;
+    }
+
+    result
+}
+
+fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ff_ffff) << 5) ^ value as u32;
+
+        for (index, generator) in GENERATOR.iter().enumerate() {
+            if (top >> index) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+
+    checksum
+}
+
+fn expand_hrp(hrp: &str) -> Vec<u8> {
+    hrp.bytes()
+        .map(|byte| byte >> 5)
+        .chain([0])
+        .chain(hrp.bytes().map(|byte| byte & 31))
+        .collect()
+}
+
+fn convert_bits(data: &[u8], from_bits: u32, to_bits: u32, pad: bool) -> Option<Vec<u8>> {
+    let max_value = (1u32 << to_bits) - 1;
+    let max_accumulator = (1u32 << (from_bits + to_bits - 1)) - 1;
+
+    let mut accumulator = 0u32;
+    let mut bits = 0u32;
+    let mut result = vec![];
+    for value in data {
+        let value = *value as u32;
+        if value >> from_bits != 0 {
+            return None;
+        }
+
+        accumulator = ((accumulator << from_bits) | value) & max_accumulator;
+        bits += from_bits;
+
+        while bits >= to_bits {
+            bits -= to_bits;
+            result.push(((accumulator >> bits) & max_value) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            result.push(((accumulator << (to_bits - bits)) & max_value) as u8);
+        }
+    } else if bits >= from_bits || ((accumulator << (to_bits - bits)) & max_value) != 0 {
+        return None;
+    }
+
+    Some(result)
+}
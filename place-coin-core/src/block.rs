@@ -1,12 +1,34 @@
 use crate::{
-    blockchain::{Blockchain, Hash, Proof},
-    transaction::{Transaction, TransactionInput},
+    blockchain::{
+        check_pixel_positions, check_pixels_per_address, encode_hex, BlockHash, Blockchain,
+        CanvasHash, Hash, Proof,
+    },
+    bloom::BloomFilter,
+    canvas::Canvas,
+    mempool::{get_tax_rate_percentiles, TAX_RATE_PERCENTILES},
+    merkle,
+    signature::{get_signature_checks, verify_signatures_batch},
+    transaction::{
+        sum_output_values, Credits, RawTransaction, Transaction, TransactionInput,
+        TransactionOutput, MAX_COINBASE_DATA_SIZE,
+    },
 };
 use anyhow::{bail, Context, Result};
-use chrono::{serde::ts_nanoseconds, DateTime, Utc};
-use serde::Serialize;
+use chrono::{serde::ts_nanoseconds, DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
 
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60; // In seconds.
+
+pub fn check_block_time(blockchain: &Blockchain, timestamp: &DateTime<Utc>) -> Result<()> {
+    // Blocks from too far in the future are turned away, by the clock the network agrees on.
+    if *timestamp > blockchain.get_adjusted_time() + Duration::seconds(MAX_FUTURE_BLOCK_TIME) {
+        bail!("Block is too far in the future.")
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Serialize)]
 pub struct Block {
     #[serde(with = "ts_nanoseconds")]
@@ -14,16 +36,243 @@ pub struct Block {
 
     transactions: Vec<Transaction>,
     proof: Proof,
-    previous_hash: Option<Hash>,
+    previous_hash: Option<BlockHash>,
+    canvas_root: CanvasHash, // Root of the canvas after this block, so it can be checked without replaying.
+}
+
+// Everything a block commits to, without the transactions themselves. The hash of a block is the
+// hash of its header, so headers can be checked before the rest of the block is downloaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    #[serde(with = "ts_nanoseconds")]
+    pub timestamp: DateTime<Utc>,
+
+    pub previous_hash: Option<BlockHash>,
+    pub proof: Proof,
+    pub canvas_root: CanvasHash,
+    pub transactions_root: Hash, // Merkle root of the transaction hashes.
+}
+
+impl BlockHeader {
+    pub fn calculate_hash(&self) -> BlockHash {
+        let encoded = bincode::serialize(self).unwrap();
+
+        let mut hasher = Sha3_256::default();
+        hasher.update(&encoded);
+
+        let digest = hasher.finalize();
+        let hash: Hash = digest.as_slice().try_into().unwrap();
+
+        hash.into()
+    }
+
+    pub fn to_hex(&self) -> String {
+        encode_hex(&bincode::serialize(self).unwrap())
+    }
+}
+
+// A block for light clients: its header and only the transactions they asked for, each one with
+// the Merkle path that ties it to the header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    pub transactions: Vec<(RawTransaction, Vec<(bool, Hash)>)>,
+}
+
+impl MerkleBlock {
+    pub fn verify(&self) -> bool {
+        self.transactions.iter().all(|(transaction, path)| {
+            let leaf = transaction.calculate_hash().into();
+
+            merkle::root_from_proof(&leaf, path) == self.header.transactions_root
+        })
+    }
+}
+
+// What peers send each other. Balances and hashes are worked out again on arrival.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawBlock {
+    #[serde(with = "ts_nanoseconds")]
+    pub timestamp: DateTime<Utc>,
+
+    pub transactions: Vec<RawTransaction>,
+    pub proof: Proof,
+    pub previous_hash: Option<BlockHash>,
+    pub canvas_root: CanvasHash,
+}
+
+impl RawBlock {
+    pub fn get_header(&self) -> BlockHeader {
+        let transaction_hashes = self
+            .transactions
+            .iter()
+            .map(|transaction| transaction.calculate_hash().into())
+            .collect::<Vec<_>>();
+
+        BlockHeader {
+            timestamp: self.timestamp,
+            previous_hash: self.previous_hash,
+            proof: self.proof,
+            canvas_root: self.canvas_root,
+            transactions_root: merkle::root(&transaction_hashes),
+        }
+    }
+
+    pub fn calculate_hash(&self) -> BlockHash {
+        self.get_header().calculate_hash()
+    }
+
+    // As peers send it, so the hex can be taken apart with the same code.
+    pub fn to_hex(&self) -> String {
+        encode_hex(&bincode::serialize(self).unwrap())
+    }
+}
+
+// What a block holds, summed up. The reward transaction pays no tax, so it's left out of the taxes
+// and tax rates, but not out of the counts and the size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockStats {
+    pub height: u64,
+    pub transaction_count: usize,
+    pub size: usize,
+    pub total_tax: Credits,
+    pub tax_rate_percentiles: [f64; TAX_RATE_PERCENTILES.len()], // Tax per byte.
+    pub pixel_count: usize, // Pixels painted, by any transaction.
+    pub subsidy: Credits,   // What the miner took on top of the taxes.
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    height: u64,
+    previous_hash: BlockHash,
+    last_proof: Proof, // What the proof of work has to be found against.
+    transactions: Vec<RawTransaction>, // The reward transaction comes last.
+    reward: Credits,
+    canvas_root: CanvasHash,
+}
+
+impl BlockTemplate {
+    pub(crate) fn new(
+        height: u64,
+        previous_hash: BlockHash,
+        last_proof: Proof,
+        transactions: Vec<RawTransaction>,
+        reward: Credits,
+        canvas_root: CanvasHash,
+    ) -> Self {
+        Self {
+            height,
+            previous_hash,
+            last_proof,
+            transactions,
+            reward,
+            canvas_root,
+        }
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn get_previous_hash(&self) -> &BlockHash {
+        &self.previous_hash
+    }
+
+    pub fn get_last_proof(&self) -> &Proof {
+        &self.last_proof
+    }
+
+    pub fn get_transactions(&self) -> &[RawTransaction] {
+        &self.transactions
+    }
+
+    pub fn get_reward(&self) -> Credits {
+        self.reward
+    }
+
+    pub fn get_canvas_root(&self) -> &CanvasHash {
+        &self.canvas_root
+    }
 }
 
 impl Block {
-    pub fn new(transactions: Vec<Transaction>, proof: Proof, previous_hash: Option<Hash>) -> Self {
+    pub fn new(
+        transactions: Vec<Transaction>,
+        proof: Proof,
+        previous_hash: Option<BlockHash>,
+        canvas_root: CanvasHash,
+    ) -> Self {
         Self {
             timestamp: Utc::now(),
             transactions,
             proof,
             previous_hash,
+            canvas_root,
+        }
+    }
+
+    pub(crate) fn genesis(canvas_root: CanvasHash) -> Self {
+        // Every node must come up with the same genesis block, so it can't depend on the clock.
+        Self {
+            timestamp: std::time::UNIX_EPOCH.into(),
+            transactions: vec![],
+            proof: 100,
+            previous_hash: None,
+            canvas_root,
+        }
+    }
+
+    pub fn from_raw(blockchain: &Blockchain, raw_block: RawBlock) -> Result<Self> {
+        // Transactions can spend from those before them in the same block.
+        let mut transactions: Vec<Transaction> = vec![];
+        for raw_transaction in raw_block.transactions {
+            let transaction =
+                Transaction::from_raw_after(blockchain, raw_transaction, &transactions)?;
+            transactions.push(transaction);
+        }
+
+        Ok(Self {
+            timestamp: raw_block.timestamp,
+            transactions,
+            proof: raw_block.proof,
+            previous_hash: raw_block.previous_hash,
+            canvas_root: raw_block.canvas_root,
+        })
+    }
+
+    pub fn to_raw(&self) -> RawBlock {
+        RawBlock {
+            timestamp: self.timestamp,
+            transactions: self.transactions.iter().map(Transaction::to_raw).collect(),
+            proof: self.proof,
+            previous_hash: self.previous_hash,
+            canvas_root: self.canvas_root,
+        }
+    }
+
+    pub fn get_merkle_block(&self, filter: &mut BloomFilter) -> MerkleBlock {
+        let transaction_hashes = self
+            .transactions
+            .iter()
+            .map(|transaction| (*transaction.get_hash()).into())
+            .collect::<Vec<_>>();
+
+        let transactions = self
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, transaction)| filter.matches_transaction(transaction))
+            .map(|(index, transaction)| {
+                (
+                    transaction.to_raw(),
+                    merkle::proof(&transaction_hashes, index),
+                )
+            })
+            .collect();
+
+        MerkleBlock {
+            header: self.get_header(),
+            transactions,
         }
     }
 
@@ -50,6 +299,44 @@ impl Block {
         }
     }
 
+    pub fn get_stats(&self) -> Result<BlockStats> {
+        let taxed_transactions = match self.transactions.split_last() {
+            Some((_, transactions)) => transactions,
+            None => &[],
+        };
+        let total_tax = taxed_transactions
+            .iter()
+            .map(Transaction::get_balance)
+            .sum();
+        let reward = match self
+            .transactions
+            .last()
+            .and_then(|transaction| transaction.get_inputs().first())
+        {
+            Some(TransactionInput::FromReward { value, .. }) => *value,
+            _ => 0,
+        };
+
+        Ok(BlockStats {
+            height: self.get_block_height()?,
+            transaction_count: self.transactions.len(),
+            size: self.transactions.iter().map(Transaction::get_size).sum(),
+            total_tax,
+            tax_rate_percentiles: get_tax_rate_percentiles(taxed_transactions),
+            pixel_count: self
+                .transactions
+                .iter()
+                .flat_map(Transaction::get_outputs)
+                .filter(|output| matches!(output, TransactionOutput::ToPixel { .. }))
+                .count(),
+            subsidy: reward.saturating_sub(total_tax),
+        })
+    }
+
+    pub fn get_timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+
     pub fn get_transactions(&self) -> &Vec<Transaction> {
         &self.transactions
     }
@@ -58,16 +345,32 @@ impl Block {
         &self.proof
     }
 
-    pub fn calculate_hash(&self) -> Hash {
-        let encoded = bincode::serialize(self).unwrap();
+    pub fn get_previous_hash(&self) -> Option<&BlockHash> {
+        self.previous_hash.as_ref()
+    }
 
-        let mut hasher = Sha3_256::default();
-        hasher.update(&encoded);
+    pub fn get_canvas_root(&self) -> &CanvasHash {
+        &self.canvas_root
+    }
 
-        let digest = hasher.finalize();
-        let hash: Hash = digest.as_slice().try_into().unwrap();
+    pub fn get_header(&self) -> BlockHeader {
+        let transaction_hashes = self
+            .transactions
+            .iter()
+            .map(|transaction| (*transaction.get_hash()).into())
+            .collect::<Vec<_>>();
 
-        hash
+        BlockHeader {
+            timestamp: self.timestamp,
+            previous_hash: self.previous_hash,
+            proof: self.proof,
+            canvas_root: self.canvas_root,
+            transactions_root: merkle::root(&transaction_hashes),
+        }
+    }
+
+    pub fn calculate_hash(&self) -> BlockHash {
+        self.get_header().calculate_hash()
     }
 
     pub fn is_valid(&self, blockchain: &Blockchain) -> bool {
@@ -89,8 +392,93 @@ impl Block {
             return false;
         }
 
-        // TODO: Check if transactions are valid.
+        // Outputs can't be negative or add up past what credits hold, whoever built the block.
+        if self
+            .transactions
+            .iter()
+            .any(|transaction| sum_output_values(transaction.get_outputs()).is_err())
+        {
+            return false;
+        }
+
+        if check_block_time(blockchain, &self.timestamp).is_err() {
+            return false;
+        }
+
+        // Pixels are checked against the canvas size at this block height.
+        let height = match self.get_block_height() {
+            Ok(height) => height,
+            Err(_) => return false,
+        };
+
+        if self.transactions.iter().any(|transaction| {
+            check_pixel_positions(blockchain.get_chain_params(), transaction, height).is_err()
+        }) {
+            return false;
+        }
+
+        let size: usize = self
+            .transactions
+            .iter()
+            .map(|transaction| transaction.get_size())
+            .sum();
+        if size > blockchain.get_chain_params().max_block_size {
+            return false;
+        }
+
+        // Miners can tag their reward, but only with a little data.
+        if self
+            .transactions
+            .iter()
+            .flat_map(|transaction| transaction.get_inputs())
+            .any(|input| {
+                matches!(input, TransactionInput::FromReward { data, .. } if data.len() > MAX_COINBASE_DATA_SIZE)
+            })
+        {
+            return false;
+        }
+
+        if check_pixels_per_address(blockchain.get_chain_params(), self.transactions.iter())
+            .is_err()
+        {
+            return false;
+        }
+
+        if self.check_canvas(blockchain).is_err() {
+            return false;
+        }
+
+        if self.verify_signatures().is_err() {
+            return false;
+        }
 
         true
     }
+
+    pub fn check_canvas(&self, blockchain: &Blockchain) -> Result<()> {
+        // Prices depend on the canvas as it was before this block.
+        let mut canvas = match &self.previous_hash {
+            Some(previous_hash) => Canvas::from_chain_at(blockchain, previous_hash)?,
+            None => Canvas::new(blockchain.get_chain_params().clone()),
+        };
+
+        canvas.connect_block(self)?;
+
+        if canvas.calculate_root() != self.canvas_root {
+            bail!("Canvas root doesn't match the canvas after the block.")
+        }
+
+        Ok(())
+    }
+
+    pub fn verify_signatures(&self) -> Result<()> {
+        let checks = self
+            .transactions
+            .iter()
+            .map(|transaction| get_signature_checks(transaction.get_raw()))
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+
+        verify_signatures_batch(&checks)
+    }
 }
@@ -1,285 +1,1846 @@
-use crate::{
-    address::{Address, PrivateKey, PublicKey},
-    block::Block,
-    signature::sign_transaction,
-    transaction::{Credits, Transaction, TransactionInput, TransactionOutput},
-};
-use anyhow::{bail, Result};
-use rayon::prelude::*;
-use sha3::{Digest, Sha3_256};
-use std::collections::HashMap;
-
-pub type Proof = u128;
-pub type Hash = [u8; 32];
-
-const BLOCK_LOCK_TIME: u32 = 0; // Minimum block height that must exist before the reward can be cashed out.
-
-#[derive(Debug)]
-pub struct Blockchain {
-    miner_public_key_address: Address,
-    blocks: HashMap<Hash, Block>,
-    transactions: Vec<Transaction>,
-    last_block_hash: Hash,
-}
-
-impl Blockchain {
-    pub fn new(miner_public_key_address: Address) -> Self {
-        let genesis_block = Block::new(Default::default(), 100, Default::default());
-        let genesis_block_hash = genesis_block.calculate_hash();
-
-        let mut blocks = HashMap::new();
-        blocks.insert(genesis_block_hash, genesis_block);
-
-        Self {
-            miner_public_key_address,
-            blocks,
-            transactions: Default::default(),
-            last_block_hash: genesis_block_hash,
-        }
-    }
-
-    pub fn new_transaction(&mut self, transaction: Transaction) -> Result<()> {
-        // Add the transaction to be later added to the next block.
-        self.transactions.push(transaction);
-
-        Ok(())
-    }
-
-    pub fn mine(&mut self) -> Result<()> {
-        let mut transactions = Default::default();
-        std::mem::swap(&mut self.transactions, &mut transactions);
-
-        // Calculate block reward.
-        let total_unspent_outputs: Credits = transactions
-            .iter()
-            .map(|transaction| transaction.get_balance())
-            .sum();
-
-        let block_reward = 1000 + total_unspent_outputs;
-
-        // Add reward transaction.
-        let last_block = self.get_last_block();
-        let inputs = vec![TransactionInput::FromReward {
-            height: last_block.get_block_height()? + 1,
-            value: block_reward,
-        }];
-
-        let outputs = vec![TransactionOutput::ToInput {
-            value: block_reward,
-            public_key_address: self.miner_public_key_address.clone(),
-        }];
-
-        let reward_transaction = Transaction::try_new(self, inputs, outputs, BLOCK_LOCK_TIME)?;
-        transactions.push(reward_transaction);
-
-        // Create proof of work.
-        let proof = self.proof_of_work();
-
-        // Create the new block.
-        let new_block = Block::new(transactions, proof, Some(self.last_block_hash));
-        let new_block_hash = new_block.calculate_hash();
-
-        self.blocks.insert(new_block_hash, new_block);
-        self.last_block_hash = new_block_hash;
-
-        Ok(())
-    }
-
-    pub fn get_peer_credits(&self, peer_address: &Address) -> Credits {
-        self.blocks
-            .par_iter()
-            .flat_map(|(_, block)| block.get_transactions())
-            .map(|transaction| {
-                std::iter::repeat(transaction).zip(transaction.get_outputs().iter().enumerate())
-            })
-            .flatten_iter()
-            .filter_map(|(transaction, (output_index, output))| match output {
-                TransactionOutput::ToInput {
-                    value,
-                    public_key_address: public_key_hash,
-                } => {
-                    let transaction_hash = transaction.get_hash();
-
-                    if public_key_hash != peer_address
-                        || self.is_output_spent(transaction_hash, output_index as u32)
-                    {
-                        None
-                    } else {
-                        Some(value)
-                    }
-                }
-
-                TransactionOutput::ToPixel { .. } => None,
-            })
-            .sum()
-    }
-
-    pub fn get_block(&self, hash: &Hash) -> Option<&Block> {
-        self.blocks.get(hash)
-    }
-
-    pub fn get_last_block(&self) -> &Block {
-        self.blocks.get(&self.last_block_hash).unwrap()
-    }
-
-    pub fn get_all_unspent_outputs(
-        &self,
-    ) -> impl ParallelIterator<Item = (&Transaction, &TransactionOutput, usize)> + '_ {
-        self.blocks
-            .par_iter()
-            .flat_map(|(_, block)| block.get_transactions())
-            .map(|transaction| {
-                std::iter::repeat(transaction).zip(transaction.get_outputs().iter().enumerate())
-            })
-            .flatten_iter()
-            .filter_map(|(transaction, (output_index, output))| match output {
-                TransactionOutput::ToInput { .. } => {
-                    if self.is_output_spent(transaction.get_hash(), output_index as u32) {
-                        None
-                    } else {
-                        Some((transaction, output, output_index))
-                    }
-                }
-
-                TransactionOutput::ToPixel { .. } => None,
-            })
-    }
-
-    pub fn find_transaction(&self, transaction_hash: &Hash) -> Option<&Transaction> {
-        self.blocks
-            .par_iter()
-            .flat_map(|(_, block)| block.get_transactions())
-            .find_any(|transaction| {
-                let this_transaction_hash = transaction.get_hash();
-
-                this_transaction_hash == transaction_hash
-            })
-    }
-
-    pub fn create_simple_transaction(
-        &mut self,
-        sender_public_key: &PublicKey,
-        sender_public_key_address: &Address,
-        recipient_public_key_address: &Address,
-        recipient_private_key: &PrivateKey,
-        value: Credits,
-        tax: Credits,
-    ) -> Result<()> {
-        debug_assert!(value > 0);
-        debug_assert!(tax >= 0);
-
-        // Collect unspent transactions to create the amount of credits needed.
-        let unspent_outputs = self
-            .get_all_unspent_outputs()
-            .filter_map(|(transaction, output, output_index)| match output {
-                TransactionOutput::ToInput {
-                    value,
-                    public_key_address,
-                } => {
-                    if public_key_address == sender_public_key_address {
-                        Some((transaction, output_index, *value))
-                    } else {
-                        None
-                    }
-                }
-                TransactionOutput::ToPixel { .. } => None,
-            })
-            .collect::<Vec<_>>();
-
-        let (transactions, total) = {
-            let mut unspent_outputs = unspent_outputs.iter();
-
-            let total_target_value = value + tax;
-            let mut total = 0;
-            let mut outputs = vec![];
-            while total < total_target_value {
-                if let Some((transaction, output_index, value)) = unspent_outputs.next() {
-                    total += value;
-                    outputs.push((transaction.get_hash(), output_index));
-                } else {
-                    break;
-                }
-            }
-
-            (outputs, total)
-        };
-
-        if total < value + tax {
-            bail!("Not enough credits to make the transaction.")
-        }
-
-        // Create transaction that uses all the unpent transaction outputs necessary.
-        let inputs = transactions
-            .into_iter()
-            .map(
-                |(transaction_hash, output_index)| TransactionInput::FromOutput {
-                    transaction_hash: *transaction_hash,
-                    output_index: *output_index as u32,
-                    public_key: *sender_public_key,
-                    signature: sign_transaction(
-                        transaction_hash,
-                        *output_index as u32,
-                        sender_public_key,
-                        recipient_private_key,
-                    ),
-                },
-            )
-            .collect();
-
-        let value_output = TransactionOutput::ToInput {
-            value,
-            public_key_address: recipient_public_key_address.clone(),
-        };
-
-        let change_value = total - value - tax;
-        let change_output = TransactionOutput::ToInput {
-            value: change_value,
-            public_key_address: sender_public_key_address.clone(),
-        };
-
-        let outputs = vec![value_output, change_output];
-
-        let transaction = Transaction::try_new(self, inputs, outputs, 0)?;
-        self.new_transaction(transaction)?;
-
-        Ok(())
-    }
-
-    // TODO: Add difficulty parameter.
-    pub fn validate_proof(last_proof: &Proof, proof: &Proof) -> bool {
-        let mut hasher = Sha3_256::default();
-        hasher.update(&last_proof.to_le_bytes());
-        hasher.update(&proof.to_le_bytes());
-
-        let digest = hasher.finalize();
-        let hash: Hash = digest.as_slice().try_into().unwrap();
-
-        hash.iter().take(1).all(|e| *e == 0)
-    }
-
-    fn proof_of_work(&self) -> Proof {
-        let last_block = self.get_last_block();
-        let last_proof = last_block.get_proof();
-
-        (0..Proof::MAX)
-            .into_par_iter()
-            .find_any(|possible_proof| Self::validate_proof(last_proof, possible_proof))
-            .unwrap()
-    }
-
-    fn is_output_spent(&self, transaction_hash: &Hash, output_index: u32) -> bool {
-        self.blocks
-            .par_iter()
-            .flat_map(|(_, block)| block.get_transactions())
-            .flat_map(|transaction| transaction.get_inputs())
-            .any(|input| match input {
-                TransactionInput::FromOutput {
-                    transaction_hash: hash,
-                    output_index: index,
-                    ..
-                } => *index == output_index && hash == transaction_hash,
-
-                TransactionInput::FromReward { .. } => false, // Because miners automatically cache in rewards.
-            })
-    }
-}
+use crate::{
+    address::{Address, PrivateKey, PublicKey},
+    analytics::{PaintCounter, PainterStats},
+    block::{Block, BlockHeader, BlockTemplate, RawBlock},
+    canvas::{Canvas, CanvasEvent},
+    chain_params::ChainParams,
+    color::Color,
+    fees::{FeeEstimate, FeeEstimator},
+    mempool::{Mempool, OrphanPool, Rejection},
+    signature::{
+        get_signature_scheme, sign_transaction_inputs, verify_transaction_signatures, Signature,
+    },
+    transaction::{
+        sum_output_values, Credits, FactionTag, Layer, ModerationAction, Point, RawTransaction,
+        Transaction, TransactionInput, TransactionOutput, CURRENT_TRANSACTION_VERSION,
+        MAX_COINBASE_DATA_SIZE,
+    },
+};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+pub type Proof = u128;
+pub type Hash = [u8; 32]; // Raw digest. The newtypes below say what was hashed.
+
+macro_rules! hash_newtype {
+    ($name:ident) => {
+        #[derive(
+            Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+        )]
+        pub struct $name(Hash);
+
+        impl $name {
+            pub fn from_bytes(bytes: Hash) -> Self {
+                Self(bytes)
+            }
+
+            pub fn as_bytes(&self) -> &Hash {
+                &self.0
+            }
+        }
+
+        impl From<Hash> for $name {
+            fn from(bytes: Hash) -> Self {
+                Self(bytes)
+            }
+        }
+
+        impl From<$name> for Hash {
+            fn from(hash: $name) -> Self {
+                hash.0
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl TryFrom<&[u8]> for $name {
+            type Error = anyhow::Error;
+
+            fn try_from(bytes: &[u8]) -> Result<Self> {
+                Ok(Self(HashHex::try_from(bytes)?.0))
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), HashHex(self.0))
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                HashHex(self.0).fmt(f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = anyhow::Error;
+
+            fn from_str(hex: &str) -> Result<Self> {
+                Ok(Self(hex.parse::<HashHex>()?.0))
+            }
+        }
+    };
+}
+
+hash_newtype!(BlockHash);
+hash_newtype!(TxHash);
+hash_newtype!(PubKeyHash);
+hash_newtype!(CanvasHash);
+hash_newtype!(StateHash);
+
+const BLOCK_SUBSIDY: Credits = 1000; // New credits every block, on top of the taxes it collects.
+const BLOCK_LOCK_TIME: u32 = 0; // Minimum block height that must exist before the reward can be cashed out.
+
+#[derive(Debug, Clone, Default)]
+pub struct PaintOptions {
+    pub layer: Layer,
+    pub faction: Option<FactionTag>,
+    pub rental: Option<u64>, // Blocks the paint lasts, if it's only rented.
+    pub memo: Option<String>,
+}
+
+// What changed on the chain, for whoever subscribed. Pixels have their own events, see `CanvasEvent`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    BlockConnected { block_hash: BlockHash, height: u64 },
+    BlockDisconnected { block_hash: BlockHash, height: u64 }, // Left the main chain.
+    TransactionAdded(TxHash),                                 // To the mempool.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TipStatus {
+    Active,
+    ValidFork, // Checked, but another branch has more work.
+    Invalid,   // Has a block that was marked invalid, or that failed to connect.
+}
+
+// The last block of a branch, which no known block builds on, or the last of the main chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainTip {
+    pub block_hash: BlockHash,
+    pub height: u64,
+    pub branch_length: usize, // Blocks since the branch left the main chain, 0 for the main chain.
+    pub status: TipStatus,
+}
+
+#[derive(Debug)]
+pub struct Blockchain {
+    chain_params: ChainParams,
+    miner_public_key_address: Address,
+    blocks: HashMap<BlockHash, Block>, // Only the main chain, so everything else can trust them.
+    stale_blocks: HashMap<BlockHash, Block>, // Left the main chain, for whichever branch they're on.
+    invalid_hashes: HashSet<BlockHash>,
+    mempool: Mempool,
+    orphans: OrphanPool,
+    fee_estimator: FeeEstimator,
+    coinbase_data: Vec<u8>, // Put in the reward transaction of every block built here.
+    time_offset: i64,       // Seconds the network clock is ahead of the local one.
+    last_block_hash: BlockHash,
+    paint_counter: PaintCounter,
+    canvas_senders: Vec<Sender<CanvasEvent>>,
+    chain_senders: Vec<Sender<ChainEvent>>,
+}
+
+impl Blockchain {
+    pub fn new(miner_public_key_address: Address) -> Self {
+        Self::with_chain_params(ChainParams::mainnet(), miner_public_key_address)
+    }
+
+    pub fn with_chain_params(chain_params: ChainParams, miner_public_key_address: Address) -> Self {
+        let genesis_block = Block::genesis(Canvas::new(chain_params.clone()).calculate_root());
+        let genesis_block_hash = genesis_block.calculate_hash();
+
+        let mut blocks = HashMap::new();
+        blocks.insert(genesis_block_hash, genesis_block);
+
+        Self {
+            chain_params,
+            miner_public_key_address,
+            blocks,
+            stale_blocks: Default::default(),
+            invalid_hashes: Default::default(),
+            mempool: Default::default(),
+            orphans: Default::default(),
+            fee_estimator: Default::default(),
+            coinbase_data: Default::default(),
+            time_offset: 0,
+            last_block_hash: genesis_block_hash,
+            paint_counter: Default::default(),
+            canvas_senders: Default::default(),
+            chain_senders: Default::default(),
+        }
+    }
+
+    pub fn get_chain_params(&self) -> &ChainParams {
+        &self.chain_params
+    }
+
+    pub fn new_transaction(&mut self, transaction: Transaction) -> Result<()> {
+        self.accept_transaction(transaction)?;
+        self.accept_orphans();
+
+        Ok(())
+    }
+
+    pub fn submit_raw_transaction(&mut self, transaction: RawTransaction) -> Result<()> {
+        // Parents can arrive after their children, so those wait until they do.
+        if !self.has_parents(&transaction) {
+            self.orphans.add(transaction);
+            return Ok(());
+        }
+
+        let transaction = Transaction::from_raw(self, transaction)?;
+        self.new_transaction(transaction)
+    }
+
+    // Everything `submit_raw_transaction` would check, and that the inputs are signed by who they
+    // pay to, without letting the transaction in. It's handed back ready to be.
+    pub fn test_accept_raw_transaction(
+        &self,
+        transaction: RawTransaction,
+    ) -> Result<Transaction, Rejection> {
+        let mut spent_outputs = vec![];
+        for input in &transaction.inputs {
+            let (transaction_hash, output_index) = match input {
+                TransactionInput::FromOutput {
+                    transaction_hash,
+                    output_index,
+                    ..
+                } => (transaction_hash, *output_index),
+                TransactionInput::FromReward { .. } => {
+                    return Err(Rejection::Invalid(
+                        "Only blocks can have reward inputs.".to_string(),
+                    ))
+                }
+            };
+            if spent_outputs.contains(&(transaction_hash, output_index)) {
+                return Err(Rejection::Invalid(
+                    "Transaction spends the same output more than once.".to_string(),
+                ));
+            }
+            spent_outputs.push((transaction_hash, output_index));
+
+            let output = self
+                .find_transaction(transaction_hash)
+                .or_else(|| self.find_pending_transaction(transaction_hash))
+                .and_then(|parent| parent.get_outputs().get(output_index as usize))
+                .ok_or(Rejection::MissingInputs)?;
+            if self.is_output_spent(transaction_hash, output_index) {
+                return Err(Rejection::SpentInputs);
+            }
+
+            if !is_spent_by_owner(input, output) {
+                return Err(Rejection::BadSignature);
+            }
+        }
+        if verify_transaction_signatures(&transaction).is_err() {
+            return Err(Rejection::BadSignature);
+        }
+
+        let transaction = Transaction::from_raw(self, transaction)
+            .map_err(|error| Rejection::Invalid(error.to_string()))?;
+        self.mempool.test_accept(&transaction)?;
+
+        let evicted_hashes = if self.mempool.conflicts(&transaction).is_empty() {
+            vec![]
+        } else {
+            self.mempool
+                .check_replacement(&transaction)
+                .map_err(|error| Rejection::Invalid(error.to_string()))?
+        };
+        self.check_pending_transaction(&transaction, &evicted_hashes)
+            .map_err(|error| Rejection::Invalid(error.to_string()))?;
+
+        Ok(transaction)
+    }
+
+    fn accept_transaction(&mut self, transaction: Transaction) -> Result<()> {
+        self.check_unknown(&transaction)?;
+
+        // Spending what a pending transaction already spends is only allowed as a replacement.
+        if !self.mempool.conflicts(&transaction).is_empty() {
+            return self.accept_replacement(transaction);
+        }
+
+        self.check_pending_transaction(&transaction, &[])?;
+
+        // Add the transaction to be later added to the next block.
+        self.add_to_mempool(transaction)
+    }
+
+    pub fn replace_transaction(&mut self, transaction: Transaction) -> Result<()> {
+        self.check_unknown(&transaction)?;
+        if self.mempool.conflicts(&transaction).is_empty() {
+            bail!("Replacement doesn't conflict with any pending transaction.")
+        }
+
+        self.accept_replacement(transaction)?;
+        self.accept_orphans();
+
+        Ok(())
+    }
+
+    fn accept_replacement(&mut self, transaction: Transaction) -> Result<()> {
+        let evicted_hashes = self.mempool.check_replacement(&transaction)?;
+        self.check_pending_transaction(&transaction, &evicted_hashes)?;
+
+        self.mempool.remove(&evicted_hashes);
+        self.add_to_mempool(transaction)
+    }
+
+    fn check_unknown(&self, transaction: &Transaction) -> Result<()> {
+        // The same transaction twice would pay its outputs twice.
+        let transaction_hash = transaction.get_hash();
+        if self.mempool.contains(transaction_hash)
+            || self.find_transaction(transaction_hash).is_some()
+        {
+            bail!(Rejection::AlreadyKnown)
+        }
+
+        Ok(())
+    }
+
+    fn check_pending_transaction(
+        &self,
+        transaction: &Transaction,
+        evicted_hashes: &[TxHash], // Pending transactions it would replace.
+    ) -> Result<()> {
+        self.check_input_spends(transaction)?;
+        self.check_input_owners(transaction)?;
+        self.check_output_addresses(transaction)?;
+        check_pixel_positions(
+            &self.chain_params,
+            transaction,
+            self.get_next_block_height()?,
+        )?;
+        check_signature_encodings(transaction)?;
+        check_pixels_per_address(
+            &self.chain_params,
+            self.mempool
+                .iter()
+                .filter(|pending| !evicted_hashes.contains(pending.get_hash()))
+                .chain([transaction]),
+        )?;
+
+        // Pixels must pay the price they'll have once the pending transactions are mined.
+        self.get_pending_canvas_without(evicted_hashes)?
+            .connect_transaction(transaction, self.get_next_block_height()?)?;
+
+        Ok(())
+    }
+
+    fn add_to_mempool(&mut self, transaction: Transaction) -> Result<()> {
+        // Keep track of when it came in, to learn how long transactions paying as much wait.
+        let height = self.get_next_block_height()?;
+        let transaction_hash = *transaction.get_hash();
+        let tax_rate = transaction.get_tax_rate();
+        self.mempool.add(transaction)?;
+        self.fee_estimator.track(transaction_hash, tax_rate, height);
+        self.send_chain_event(ChainEvent::TransactionAdded(transaction_hash));
+
+        Ok(())
+    }
+
+    pub fn mine(&mut self) -> Result<()> {
+        let template = self.build_block_template(&self.miner_public_key_address.clone())?;
+        let proof = self.proof_of_work();
+
+        self.submit_block(template, proof)
+    }
+
+    pub fn set_coinbase_data(&mut self, coinbase_data: Vec<u8>) -> Result<()> {
+        if coinbase_data.len() > MAX_COINBASE_DATA_SIZE {
+            bail!("Coinbase data can't be longer than {MAX_COINBASE_DATA_SIZE} bytes.")
+        }
+
+        self.coinbase_data = coinbase_data;
+
+        Ok(())
+    }
+
+    pub fn set_time_offset(&mut self, time_offset: i64) {
+        self.time_offset = time_offset;
+    }
+
+    pub fn get_adjusted_time(&self) -> DateTime<Utc> {
+        Utc::now() + Duration::seconds(self.time_offset)
+    }
+
+    pub fn build_block_template(&self, coinbase_address: &Address) -> Result<BlockTemplate> {
+        self.build_block_template_with_payouts(&[(coinbase_address.clone(), 1)])
+    }
+
+    pub fn build_block_template_with_payouts(
+        &self,
+        payouts: &[(Address, u64)],
+    ) -> Result<BlockTemplate> {
+        // The reward is split by weight, like the shares of a mining pool.
+        if payouts.is_empty() || payouts.iter().any(|(_, weight)| *weight == 0) {
+            bail!("Every payout needs a positive weight.")
+        }
+
+        if payouts
+            .iter()
+            .any(|(address, _)| !address.validate_for(&self.chain_params))
+        {
+            bail!("Miner address doesn't belong to this network.")
+        }
+
+        let height = self.get_next_block_height()?;
+        let inputs = vec![TransactionInput::FromReward {
+            height,
+            value: 0,
+            data: self.coinbase_data.clone(),
+        }];
+
+        // The reward goes in last, so keep room for it. Its size doesn't depend on the values, and
+        // it's at most one output per payout.
+        let outputs = payouts
+            .iter()
+            .map(|(public_key_address, _)| TransactionOutput::ToInput {
+                value: 0,
+                public_key_address: public_key_address.clone(),
+            })
+            .collect();
+        let reward_size = Transaction::try_new(self, inputs, outputs, BLOCK_LOCK_TIME)?.get_size();
+        let max_size = self
+            .chain_params
+            .max_block_size
+            .checked_sub(reward_size)
+            .context("Reward doesn't fit in a block.")?;
+        let (transaction_hashes, mut canvas) = self.select_transactions(height, max_size)?;
+        let transactions = transaction_hashes
+            .iter()
+            .filter_map(|transaction_hash| self.mempool.get(transaction_hash))
+            .collect::<Vec<_>>();
+
+        // Calculate block reward.
+        let total_unspent_outputs: Credits = transactions
+            .iter()
+            .map(|transaction| transaction.get_balance())
+            .sum();
+
+        let block_reward = BLOCK_SUBSIDY + total_unspent_outputs;
+
+        // Add reward transaction.
+        let inputs = vec![TransactionInput::FromReward {
+            height,
+            value: block_reward,
+            data: self.coinbase_data.clone(),
+        }];
+
+        let outputs = split_reward(block_reward, payouts)
+            .into_iter()
+            .map(|(public_key_address, value)| TransactionOutput::ToInput {
+                value,
+                public_key_address,
+            })
+            .collect();
+
+        let reward_transaction = Transaction::try_new(self, inputs, outputs, BLOCK_LOCK_TIME)?;
+
+        // Commit to the canvas as it will be once the block is connected.
+        canvas.connect_transaction(&reward_transaction, height)?;
+
+        Ok(BlockTemplate::new(
+            height,
+            self.last_block_hash,
+            *self.get_last_block().get_proof(),
+            transactions
+                .into_iter()
+                .chain([&reward_transaction])
+                .map(Transaction::to_raw)
+                .collect(),
+            block_reward,
+            canvas.calculate_root(),
+        ))
+    }
+
+    pub fn submit_block(&mut self, template: BlockTemplate, proof: Proof) -> Result<()> {
+        if *template.get_previous_hash() != self.last_block_hash {
+            bail!("Block template is stale, the chain has moved on.")
+        }
+
+        if !Self::validate_proof(template.get_last_proof(), &proof) {
+            bail!("Proof of work isn't valid.")
+        }
+
+        // Everything but the reward must still be pending.
+        let (reward_transaction, transactions) = template
+            .get_transactions()
+            .split_last()
+            .context("Block template has no reward transaction.")?;
+        let transaction_hashes = transactions
+            .iter()
+            .map(RawTransaction::calculate_hash)
+            .collect::<Vec<_>>();
+        if !transaction_hashes
+            .iter()
+            .all(|transaction_hash| self.mempool.contains(transaction_hash))
+        {
+            bail!("Block template is stale, some of its transactions are gone.")
+        }
+
+        let reward_transaction = Transaction::from_raw(self, reward_transaction.clone())?;
+
+        let mut transactions = self.mempool.take(&transaction_hashes);
+        transactions.push(reward_transaction);
+
+        // Create the new block.
+        let new_block = Block::new(
+            transactions,
+            proof,
+            Some(self.last_block_hash),
+            *template.get_canvas_root(),
+        );
+
+        // Templates come from the mempool, so a block that doesn't pass means something got in that
+        // shouldn't have. Transactions breaking the spending rules would break every block after,
+        // so they are dropped, and only the rest go back.
+        let validity = self.check_block_spends(&new_block).and_then(|()| {
+            if !new_block.is_valid(self) {
+                bail!("Block isn't valid.")
+            }
+
+            Ok(())
+        });
+        if let Err(error) = validity {
+            let mut kept_transactions = vec![];
+            let mut spent_outputs = vec![];
+            for transaction in new_block.get_transactions().split_last().unwrap().1 {
+                if self
+                    .check_transaction_spends(transaction, &kept_transactions, &mut spent_outputs)
+                    .is_ok()
+                {
+                    kept_transactions.push(transaction);
+                }
+            }
+
+            let kept_transactions = kept_transactions
+                .into_iter()
+                .map(Transaction::to_raw)
+                .collect::<Vec<_>>();
+            for transaction in kept_transactions {
+                if let Ok(transaction) = Transaction::from_raw(self, transaction) {
+                    let _ = self.accept_transaction(transaction);
+                }
+            }
+
+            return Err(error);
+        }
+
+        self.connect_block(new_block, template.get_height())
+    }
+
+    pub fn submit_raw_block(&mut self, raw_block: RawBlock) -> Result<BlockHash> {
+        // Branches are only switched to by hand, so blocks from peers must build on the last one.
+        if raw_block.previous_hash != Some(self.last_block_hash) {
+            bail!("Block doesn't build on the last block.")
+        }
+        if self.invalid_hashes.contains(&raw_block.calculate_hash()) {
+            bail!("Block was marked invalid.")
+        }
+
+        let block = Block::from_raw(self, raw_block)?;
+        let height = self.get_next_block_height()?;
+        if block.get_block_height()? != height {
+            bail!("Block has the wrong height.")
+        }
+
+        self.check_block_spends(&block)?;
+        if !block.is_valid(self) {
+            bail!("Block isn't valid.")
+        }
+
+        let block_hash = block.calculate_hash();
+        self.connect_block(block, height)?;
+
+        Ok(block_hash)
+    }
+
+    fn connect_block(&mut self, new_block: Block, height: u64) -> Result<()> {
+        // Only replay the canvas when someone is listening for what changed.
+        let previous_canvas = match self.canvas_senders.is_empty() {
+            true => None,
+            false => Some(Canvas::from_chain(self)?),
+        };
+
+        let new_block_hash = new_block.calculate_hash();
+
+        self.paint_counter.connect_block(&new_block)?;
+        self.mempool.remove_confirmed(&new_block);
+        self.fee_estimator.connect_block(&new_block)?;
+
+        let mempool = &self.mempool;
+        self.fee_estimator
+            .retain_pending(|transaction_hash| mempool.contains(transaction_hash));
+
+        let events = match previous_canvas {
+            Some(previous_canvas) => {
+                let since_version = previous_canvas.get_version();
+                let mut canvas = previous_canvas.clone();
+                canvas.connect_block(&new_block)?;
+
+                canvas.get_events(&previous_canvas, since_version, height)
+            }
+            None => vec![],
+        };
+
+        self.stale_blocks.remove(&new_block_hash);
+        self.blocks.insert(new_block_hash, new_block);
+        self.last_block_hash = new_block_hash;
+        self.send_chain_event(ChainEvent::BlockConnected {
+            block_hash: new_block_hash,
+            height,
+        });
+
+        // The block may confirm parents some orphans were waiting for.
+        self.accept_orphans();
+
+        for event in events {
+            self.canvas_senders
+                .retain(|sender| sender.send(event.clone()).is_ok());
+        }
+
+        Ok(())
+    }
+
+    // Marks the block and everything built on it invalid. If it's on the main chain, the chain goes
+    // back to before it, and on to the branch with the most blocks left.
+    pub fn invalidate_block(&mut self, block_hash: &BlockHash) -> Result<()> {
+        let block = self
+            .get_block(block_hash)
+            .or_else(|| self.get_stale_block(block_hash))
+            .context("Block isn't known.")?;
+        if block.get_previous_hash().is_none() {
+            bail!("Genesis block can't be invalidated.")
+        }
+
+        self.invalid_hashes.insert(*block_hash);
+
+        let mut disconnected = vec![];
+        while self.blocks.contains_key(block_hash) {
+            disconnected.push(self.disconnect_last_block()?);
+        }
+        disconnected.extend(self.activate_best_chain()?);
+
+        self.return_to_mempool(disconnected);
+
+        Ok(())
+    }
+
+    // Undoes `invalidate_block` for the block, the ones it builds on and the ones built on it, and
+    // switches to their branch if it has more blocks.
+    pub fn reconsider_block(&mut self, block_hash: &BlockHash) -> Result<()> {
+        if self.get_block(block_hash).is_none() && self.get_stale_block(block_hash).is_none() {
+            bail!("Block isn't known.")
+        }
+
+        let ancestor_hashes = self
+            .get_chain_ending_at(block_hash)
+            .into_iter()
+            .map(Block::calculate_hash)
+            .collect::<HashSet<_>>();
+        let reconsidered_hashes = self
+            .invalid_hashes
+            .iter()
+            .filter(|invalid_hash| {
+                ancestor_hashes.contains(invalid_hash)
+                    || self
+                        .get_chain_ending_at(invalid_hash)
+                        .into_iter()
+                        .any(|block| block.calculate_hash() == *block_hash)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        for reconsidered_hash in reconsidered_hashes {
+            self.invalid_hashes.remove(&reconsidered_hash);
+        }
+
+        let disconnected = self.activate_best_chain()?;
+        self.return_to_mempool(disconnected);
+
+        Ok(())
+    }
+
+    fn activate_best_chain(&mut self) -> Result<Vec<BlockHash>> {
+        let mut disconnected = vec![];
+
+        // Blocks that fail to connect are marked invalid, so this ends once no branch beats the
+        // main chain.
+        loop {
+            let height = self.get_last_block().get_block_height()?;
+            let best_tip = self
+                .get_chain_tips()?
+                .into_iter()
+                .filter(|tip| tip.status == TipStatus::ValidFork && tip.height > height)
+                .max_by_key(|tip| tip.height);
+            let Some(best_tip) = best_tip else {
+                return Ok(disconnected);
+            };
+
+            let branch_hashes = self
+                .get_chain_ending_at(&best_tip.block_hash)
+                .into_iter()
+                .skip_while(|block| self.blocks.contains_key(&block.calculate_hash()))
+                .map(Block::calculate_hash)
+                .collect::<Vec<_>>();
+            let fork_hash = *self
+                .get_stale_block(&branch_hashes[0])
+                .and_then(Block::get_previous_hash)
+                .context("Branch doesn't build on the main chain.")?;
+            while self.last_block_hash != fork_hash {
+                disconnected.push(self.disconnect_last_block()?);
+            }
+
+            for block_hash in branch_hashes {
+                let block = self.stale_blocks.remove(&block_hash).unwrap();
+                let height = self.get_next_block_height()?;
+                let is_valid = block.get_block_height()? == height
+                    && self.check_block_spends(&block).is_ok()
+                    && block.is_valid(self);
+                if !is_valid {
+                    self.stale_blocks.insert(block_hash, block);
+                    self.invalid_hashes.insert(block_hash);
+                    break;
+                }
+
+                self.connect_block(block, height)?;
+            }
+        }
+    }
+
+    fn disconnect_last_block(&mut self) -> Result<BlockHash> {
+        let block_hash = self.last_block_hash;
+        let block = self.blocks.remove(&block_hash).unwrap();
+        let height = block.get_block_height()?;
+
+        self.last_block_hash = *block
+            .get_previous_hash()
+            .context("Genesis block can't be disconnected.")?;
+        self.paint_counter.disconnect_block(height);
+        self.stale_blocks.insert(block_hash, block);
+        self.send_chain_event(ChainEvent::BlockDisconnected { block_hash, height });
+
+        Ok(block_hash)
+    }
+
+    fn return_to_mempool(&mut self, disconnected_hashes: Vec<BlockHash>) {
+        // Oldest first, so parents go back before their children. Rewards only existed in their
+        // block, and whatever the main chain has now confirmed or spent doesn't get back in.
+        let transactions = disconnected_hashes
+            .iter()
+            .rev()
+            .filter_map(|block_hash| self.stale_blocks.get(block_hash))
+            .flat_map(Block::get_transactions)
+            .filter(|transaction| {
+                !matches!(
+                    transaction.get_inputs(),
+                    [TransactionInput::FromReward { .. }]
+                )
+            })
+            .map(Transaction::to_raw)
+            .collect::<Vec<_>>();
+
+        for transaction in transactions {
+            if let Ok(transaction) = Transaction::from_raw(self, transaction) {
+                let _ = self.accept_transaction(transaction);
+            }
+        }
+    }
+
+    pub fn subscribe_canvas(&mut self) -> Receiver<CanvasEvent> {
+        let (sender, receiver) = channel();
+        self.canvas_senders.push(sender);
+
+        receiver
+    }
+
+    pub fn subscribe_chain(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel();
+        self.chain_senders.push(sender);
+
+        receiver
+    }
+
+    pub fn get_peer_credits(&self, peer_address: &Address) -> Credits {
+        self.blocks
+            .par_iter()
+            .flat_map(|(_, block)| block.get_transactions())
+            .map(|transaction| {
+                std::iter::repeat(transaction).zip(transaction.get_outputs().iter().enumerate())
+            })
+            .flatten_iter()
+            .filter_map(|(transaction, (output_index, output))| match output {
+                TransactionOutput::ToInput {
+                    value,
+                    public_key_address: public_key_hash,
+                } => {
+                    let transaction_hash = transaction.get_hash();
+
+                    if public_key_hash != peer_address
+                        || self.is_output_spent(transaction_hash, output_index as u32)
+                    {
+                        None
+                    } else {
+                        Some(value)
+                    }
+                }
+
+                TransactionOutput::ToPixel { .. }
+                | TransactionOutput::LockPixel { .. }
+                | TransactionOutput::Moderate { .. } => None,
+            })
+            .sum()
+    }
+
+    pub fn get_block(&self, hash: &BlockHash) -> Option<&Block> {
+        self.blocks.get(hash)
+    }
+
+    pub fn get_stale_block(&self, hash: &BlockHash) -> Option<&Block> {
+        self.stale_blocks.get(hash)
+    }
+
+    pub fn get_last_block(&self) -> &Block {
+        self.blocks.get(&self.last_block_hash).unwrap()
+    }
+
+    pub fn get_last_block_hash(&self) -> &BlockHash {
+        &self.last_block_hash
+    }
+
+    pub fn get_next_block_height(&self) -> Result<u64> {
+        Ok(self.get_last_block().get_block_height()? + 1)
+    }
+
+    pub fn get_main_chain(&self) -> Vec<&Block> {
+        self.get_chain_ending_at(&self.last_block_hash)
+    }
+
+    pub fn get_chain_ending_at(&self, block_hash: &BlockHash) -> Vec<&Block> {
+        // Walk back from the given block, then flip so the genesis block comes first.
+        let mut blocks = vec![];
+        let mut block_hash = Some(block_hash);
+        while let Some(block) = block_hash.and_then(|block_hash| {
+            self.get_block(block_hash)
+                .or_else(|| self.get_stale_block(block_hash))
+        }) {
+            blocks.push(block);
+            block_hash = block.get_previous_hash();
+        }
+
+        blocks.reverse();
+
+        blocks
+    }
+
+    pub fn get_chain_tips(&self) -> Result<Vec<ChainTip>> {
+        let known_blocks = self.blocks.iter().chain(&self.stale_blocks);
+        let parent_hashes = known_blocks
+            .clone()
+            .filter_map(|(_, block)| block.get_previous_hash())
+            .collect::<HashSet<_>>();
+        let main_hashes = self
+            .get_main_chain()
+            .into_iter()
+            .map(Block::calculate_hash)
+            .collect::<HashSet<_>>();
+
+        let mut tips = vec![];
+        for (block_hash, block) in known_blocks {
+            // Stale blocks can build on the last one, which stays the tip of the main chain.
+            if parent_hashes.contains(block_hash) && *block_hash != self.last_block_hash {
+                continue;
+            }
+
+            let branch_hashes = self
+                .get_chain_ending_at(block_hash)
+                .into_iter()
+                .rev()
+                .map(Block::calculate_hash)
+                .take_while(|hash| !main_hashes.contains(hash))
+                .collect::<Vec<_>>();
+            let status = if *block_hash == self.last_block_hash {
+                TipStatus::Active
+            } else if branch_hashes
+                .iter()
+                .any(|hash| self.invalid_hashes.contains(hash))
+            {
+                TipStatus::Invalid
+            } else {
+                TipStatus::ValidFork
+            };
+
+            tips.push(ChainTip {
+                block_hash: *block_hash,
+                height: block.get_block_height()?,
+                branch_length: branch_hashes.len(),
+                status,
+            });
+        }
+
+        // The active tip first, then the highest.
+        tips.sort_by_key(|tip| {
+            (
+                tip.status != TipStatus::Active,
+                std::cmp::Reverse(tip.height),
+                tip.block_hash,
+            )
+        });
+
+        Ok(tips)
+    }
+
+    pub fn block_locator(&self) -> Vec<BlockHash> {
+        let hashes = self
+            .get_main_chain()
+            .into_iter()
+            .map(|block| block.calculate_hash())
+            .collect::<Vec<_>>();
+
+        build_locator(&hashes)
+    }
+
+    pub fn find_fork_point(&self, locator: &[BlockHash]) -> usize {
+        // The first hash the main chain has is where the other chain branched off. Peers on the
+        // same network share at least the genesis block.
+        let hashes = self
+            .get_main_chain()
+            .into_iter()
+            .map(|block| block.calculate_hash())
+            .collect::<Vec<_>>();
+
+        locator
+            .iter()
+            .find_map(|block_hash| hashes.iter().position(|hash| hash == block_hash))
+            .unwrap_or(0)
+    }
+
+    pub fn get_headers_after(&self, locator: &[BlockHash], max_count: usize) -> Vec<BlockHeader> {
+        let start = self.find_fork_point(locator) + 1;
+
+        self.get_main_chain()
+            .into_iter()
+            .skip(start)
+            .take(max_count)
+            .map(|block| block.get_header())
+            .collect()
+    }
+
+    pub fn canvas(&self) -> Result<Canvas> {
+        Canvas::from_chain(self)
+    }
+
+    pub fn top_painters(&self, heights: RangeInclusive<u64>, count: usize) -> Vec<PainterStats> {
+        self.paint_counter
+            .top_painters(&self.chain_params, heights, count)
+    }
+
+    pub fn get_pending_canvas(&self) -> Result<Canvas> {
+        self.get_pending_canvas_without(&[])
+    }
+
+    pub fn get_all_unspent_outputs(
+        &self,
+    ) -> impl ParallelIterator<Item = (&Transaction, &TransactionOutput, usize)> + '_ {
+        self.blocks
+            .par_iter()
+            .flat_map(|(_, block)| block.get_transactions())
+            .map(|transaction| {
+                std::iter::repeat(transaction).zip(transaction.get_outputs().iter().enumerate())
+            })
+            .flatten_iter()
+            .filter_map(|(transaction, (output_index, output))| match output {
+                TransactionOutput::ToInput { .. } => {
+                    if self.is_output_spent(transaction.get_hash(), output_index as u32) {
+                        None
+                    } else {
+                        Some((transaction, output, output_index))
+                    }
+                }
+
+                TransactionOutput::ToPixel { .. }
+                | TransactionOutput::LockPixel { .. }
+                | TransactionOutput::Moderate { .. } => None,
+            })
+    }
+
+    pub fn get_mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    pub fn estimate_fee(&self, target_blocks: u64) -> Option<FeeEstimate> {
+        self.fee_estimator.estimate_fee(target_blocks)
+    }
+
+    pub fn get_orphans(&self) -> &OrphanPool {
+        &self.orphans
+    }
+
+    pub fn set_max_mempool_size(&mut self, max_size: usize) {
+        self.mempool.set_max_size(max_size);
+    }
+
+    pub fn set_min_tax_rate(&mut self, min_tax_rate: Credits) {
+        self.mempool.set_min_tax_rate(min_tax_rate);
+    }
+
+    pub fn get_pending_transactions(&self) -> &[Transaction] {
+        self.mempool.get_transactions()
+    }
+
+    pub fn find_pending_transaction(&self, transaction_hash: &TxHash) -> Option<&Transaction> {
+        self.mempool.get(transaction_hash)
+    }
+
+    pub fn get_spendable_outputs(
+        &self,
+        public_key_address: &Address,
+    ) -> Vec<(&TxHash, u32, Credits)> {
+        let confirmed_outputs = self.get_all_unspent_outputs().collect::<Vec<_>>();
+
+        // Outputs of pending transactions come last, so confirmed credits are spent first.
+        let pending_outputs = self.mempool.iter().flat_map(|transaction| {
+            transaction
+                .get_outputs()
+                .iter()
+                .enumerate()
+                .map(move |(output_index, output)| (transaction, output, output_index))
+        });
+
+        confirmed_outputs
+            .into_iter()
+            .chain(pending_outputs)
+            .filter_map(|(transaction, output, output_index)| match output {
+                TransactionOutput::ToInput {
+                    value,
+                    public_key_address: output_address,
+                } => {
+                    let transaction_hash = transaction.get_hash();
+                    let output_index = output_index as u32;
+
+                    // Outputs already claimed by a pending transaction can't be spent twice.
+                    if output_address != public_key_address
+                        || self.mempool.is_output_spent(transaction_hash, output_index)
+                    {
+                        None
+                    } else {
+                        Some((transaction_hash, output_index, *value))
+                    }
+                }
+
+                TransactionOutput::ToPixel { .. }
+                | TransactionOutput::LockPixel { .. }
+                | TransactionOutput::Moderate { .. } => None,
+            })
+            .collect()
+    }
+
+    pub fn find_transaction(&self, transaction_hash: &TxHash) -> Option<&Transaction> {
+        self.blocks
+            .par_iter()
+            .flat_map(|(_, block)| block.get_transactions())
+            .find_any(|transaction| {
+                let this_transaction_hash = transaction.get_hash();
+
+                this_transaction_hash == transaction_hash
+            })
+    }
+
+    pub fn create_simple_transaction(
+        &mut self,
+        sender_public_key_address: &Address,
+        recipient_public_key_address: &Address,
+        sender_private_key: &PrivateKey,
+        value: Credits,
+        tax: Credits,
+    ) -> Result<TxHash> {
+        self.create_batch_transaction(
+            sender_public_key_address,
+            &[(recipient_public_key_address.clone(), value)],
+            sender_private_key,
+            tax,
+        )
+    }
+
+    pub fn create_batch_transaction(
+        &mut self,
+        sender_public_key_address: &Address,
+        recipients: &[(Address, Credits)],
+        sender_private_key: &PrivateKey,
+        tax: Credits,
+    ) -> Result<TxHash> {
+        debug_assert!(!recipients.is_empty());
+        debug_assert!(recipients.iter().all(|(_, value)| *value > 0));
+
+        let outputs = recipients
+            .iter()
+            .map(|(public_key_address, value)| TransactionOutput::ToInput {
+                value: *value,
+                public_key_address: public_key_address.clone(),
+            })
+            .collect();
+
+        self.create_funded_transaction(sender_public_key_address, outputs, sender_private_key, tax)
+    }
+
+    pub fn create_pixel_transaction(
+        &mut self,
+        sender_private_key: &PrivateKey,
+        pixels: &[(Point, Color)],
+        tax: Credits,
+    ) -> Result<TxHash> {
+        self.create_pixel_transaction_with(sender_private_key, pixels, &Default::default(), tax)
+    }
+
+    pub fn create_pixel_transaction_with(
+        &mut self,
+        sender_private_key: &PrivateKey,
+        pixels: &[(Point, Color)],
+        options: &PaintOptions,
+        tax: Credits,
+    ) -> Result<TxHash> {
+        if pixels.is_empty() {
+            bail!("At least one pixel is needed.")
+        }
+
+        // Painting the same pixel twice would only pay for the second color.
+        if pixels.iter().enumerate().any(|(index, (position, _))| {
+            pixels[..index].iter().any(|(other, _)| other == position)
+        }) {
+            bail!("Every pixel can only be painted once per transaction.")
+        }
+
+        let sender_public_key_address =
+            Address::from_private_key_for(&self.chain_params, sender_private_key);
+
+        let canvas = self.get_pending_canvas()?;
+        let height = self.get_next_block_height()?;
+
+        let mut outputs = vec![];
+        let mut royalties: Vec<(Address, Credits)> = vec![];
+        for (position, color) in pixels {
+            let mut value = match options.rental {
+                Some(duration) => self.chain_params.get_pixel_rental_price(duration),
+                None => canvas.get_layer_pixel_price(options.layer, position, height),
+            };
+
+            // The previous painters get their share as regular outputs, unless the pixel is only rented.
+            let royalty = match options.rental {
+                Some(_) => None,
+                None => canvas.get_layer_pixel_royalty(
+                    options.layer,
+                    position,
+                    Some(&sender_public_key_address),
+                    height,
+                ),
+            };
+
+            if let Some((address, royalty)) = royalty {
+                value -= royalty;
+
+                match royalties.iter_mut().find(|(other, _)| *other == address) {
+                    Some((_, total)) => *total += royalty,
+                    None => royalties.push((address, royalty)),
+                }
+            }
+
+            outputs.push(TransactionOutput::ToPixel {
+                value,
+                position: *position,
+                color: *color,
+                layer: options.layer,
+                faction: options.faction.clone(),
+                rental: options.rental,
+                memo: options.memo.clone(),
+            });
+        }
+
+        outputs.extend(royalties.into_iter().map(|(public_key_address, value)| {
+            TransactionOutput::ToInput {
+                value,
+                public_key_address,
+            }
+        }));
+
+        // Every pixel carries the memo, and pays for it.
+        let memo_size = options.memo.as_ref().map_or(0, String::len) * pixels.len();
+        let tax = tax + self.chain_params.get_memo_fee(memo_size);
+
+        self.create_funded_transaction(&sender_public_key_address, outputs, sender_private_key, tax)
+    }
+
+    pub fn create_pixel_lock_transaction(
+        &mut self,
+        sender_private_key: &PrivateKey,
+        position: &Point,
+        duration: u64,
+        tax: Credits,
+    ) -> Result<TxHash> {
+        let sender_public_key_address =
+            Address::from_private_key_for(&self.chain_params, sender_private_key);
+
+        let outputs = vec![TransactionOutput::LockPixel {
+            value: self.chain_params.get_pixel_lock_price(duration),
+            position: *position,
+            duration,
+        }];
+
+        self.create_funded_transaction(&sender_public_key_address, outputs, sender_private_key, tax)
+    }
+
+    pub fn create_moderation_transaction(
+        &mut self,
+        moderator_private_key: &PrivateKey,
+        position: &Point,
+        (width, height): (i32, i32),
+        action: ModerationAction,
+        tax: Credits,
+    ) -> Result<TxHash> {
+        let moderator_public_key_address =
+            Address::from_private_key_for(&self.chain_params, moderator_private_key);
+
+        let outputs = vec![TransactionOutput::Moderate {
+            value: 0,
+            position: *position,
+            width,
+            height,
+            action,
+        }];
+
+        self.create_funded_transaction(
+            &moderator_public_key_address,
+            outputs,
+            moderator_private_key,
+            tax,
+        )
+    }
+
+    pub fn get_pixel_price(&self, position: &Point) -> Result<Credits> {
+        Ok(self
+            .get_pending_canvas()?
+            .get_pixel_price(position, self.get_next_block_height()?))
+    }
+
+    fn create_funded_transaction(
+        &mut self,
+        sender_public_key_address: &Address,
+        mut outputs: Vec<TransactionOutput>,
+        sender_private_key: &PrivateKey,
+        tax: Credits,
+    ) -> Result<TxHash> {
+        debug_assert!(tax >= 0);
+
+        // The sender outputs can only be spent by the key the address was derived from.
+        let sender_public_key = &PublicKey::from_private_key(sender_private_key);
+        if sender_public_key_address.public_key_hash()? != sender_public_key.calculate_hash() {
+            bail!("Private key doesn't belong to the sender address.")
+        }
+
+        let value: Credits = outputs.iter().map(TransactionOutput::get_value).sum();
+
+        // Collect unspent transactions to create the amount of credits needed.
+        let unspent_outputs = self.get_spendable_outputs(sender_public_key_address);
+
+        let (transactions, total) = {
+            let mut unspent_outputs = unspent_outputs.iter();
+
+            let total_target_value = value + tax;
+            let mut total = 0;
+            let mut outputs = vec![];
+            // Always spend something, since the inputs are what prove who sent the transaction.
+            while total < total_target_value || outputs.is_empty() {
+                if let Some((transaction_hash, output_index, value)) = unspent_outputs.next() {
+                    total += value;
+                    outputs.push((*transaction_hash, output_index));
+                } else {
+                    break;
+                }
+            }
+
+            (outputs, total)
+        };
+
+        if total < value + tax || transactions.is_empty() {
+            bail!("Not enough credits to make the transaction.")
+        }
+
+        // Create transaction that uses all the unpent transaction outputs necessary.
+        let inputs = transactions
+            .into_iter()
+            .map(
+                |(transaction_hash, output_index)| TransactionInput::FromOutput {
+                    transaction_hash: *transaction_hash,
+                    output_index: *output_index,
+                    public_key: *sender_public_key,
+                    signature: Signature::unsigned(),
+                },
+            )
+            .collect();
+
+        // The change goes back to the sender.
+        let change_value = total - value - tax;
+        outputs.push(TransactionOutput::ToInput {
+            value: change_value,
+            public_key_address: sender_public_key_address.clone(),
+        });
+
+        // Signed last, as the signatures commit to the whole transaction.
+        let mut raw_transaction = RawTransaction {
+            version: CURRENT_TRANSACTION_VERSION,
+            inputs,
+            outputs,
+            lock_time: 0,
+        };
+        sign_transaction_inputs(&mut raw_transaction, sender_private_key);
+
+        let transaction = Transaction::from_raw(self, raw_transaction)?;
+        let transaction_hash = *transaction.get_hash();
+        self.new_transaction(transaction)?;
+
+        Ok(transaction_hash)
+    }
+
+    // TODO: Add difficulty parameter.
+    pub fn validate_proof(last_proof: &Proof, proof: &Proof) -> bool {
+        let hash = Self::calculate_proof_hash(last_proof, proof);
+
+        hash.iter().take(1).all(|e| *e == 0)
+    }
+
+    pub fn calculate_proof_hash(last_proof: &Proof, proof: &Proof) -> Hash {
+        let mut hasher = Sha3_256::default();
+        hasher.update(last_proof.to_le_bytes());
+        hasher.update(proof.to_le_bytes());
+
+        let digest = hasher.finalize();
+
+        digest.as_slice().try_into().unwrap()
+    }
+
+    fn proof_of_work(&self) -> Proof {
+        let last_block = self.get_last_block();
+        let last_proof = last_block.get_proof();
+
+        (0..Proof::MAX)
+            .into_par_iter()
+            .find_any(|possible_proof| Self::validate_proof(last_proof, possible_proof))
+            .unwrap()
+    }
+
+    fn select_transactions(&self, height: u64, max_size: usize) -> Result<(Vec<TxHash>, Canvas)> {
+        // Packages of a transaction and its pending ancestors go in by their tax rate as a whole,
+        // so a child can pay for its parents, until the block is full.
+        let mut canvas = Canvas::from_chain(self)?;
+        let mut selected_hashes = vec![];
+        let mut skipped_hashes = vec![];
+        let mut size = 0;
+        loop {
+            let mut best_package: Option<(Vec<&Transaction>, Credits, usize)> = None;
+            for transaction in self.mempool.iter_by_fee() {
+                if selected_hashes.contains(transaction.get_hash())
+                    || skipped_hashes.contains(transaction.get_hash())
+                {
+                    continue;
+                }
+
+                let package = match self.get_package(transaction, &selected_hashes, &skipped_hashes)
+                {
+                    Some(package) => package,
+                    None => continue,
+                };
+
+                let package_tax: Credits = package.iter().map(|tx| tx.get_balance()).sum();
+                let package_size: usize = package.iter().map(|tx| tx.get_size()).sum();
+                if size + package_size > max_size {
+                    continue;
+                }
+
+                // Tax per byte, compared without dividing so rounding doesn't get in the way.
+                let is_better = best_package
+                    .as_ref()
+                    .is_none_or(|(_, best_tax, best_size)| {
+                        package_tax as i128 * *best_size as i128
+                            > *best_tax as i128 * package_size as i128
+                    });
+                if is_better {
+                    best_package = Some((package, package_tax, package_size));
+                }
+            }
+
+            let (package, _, package_size) = match best_package {
+                Some(best_package) => best_package,
+                None => break,
+            };
+
+            // Paints may no longer be valid in this order, so those wait for a later block.
+            let mut package_canvas = canvas.clone();
+            let is_valid = check_pixels_per_address(
+                &self.chain_params,
+                selected_hashes
+                    .iter()
+                    .filter_map(|selected_hash| self.mempool.get(selected_hash))
+                    .chain(package.iter().copied()),
+            )
+            .is_ok()
+                && package.iter().all(|transaction| {
+                    package_canvas
+                        .connect_transaction(transaction, height)
+                        .is_ok()
+                });
+
+            if is_valid {
+                canvas = package_canvas;
+                selected_hashes.extend(package.iter().map(|transaction| *transaction.get_hash()));
+                size += package_size;
+            } else {
+                // Its ancestors still get a chance on their own.
+                skipped_hashes.push(*package.last().unwrap().get_hash());
+            }
+        }
+
+        Ok((selected_hashes, canvas))
+    }
+
+    fn get_package<'a>(
+        &'a self,
+        transaction: &'a Transaction,
+        selected_hashes: &[TxHash],
+        skipped_hashes: &[TxHash],
+    ) -> Option<Vec<&'a Transaction>> {
+        // Parents come before their children. Those that aren't pending are already confirmed.
+        let mut package: Vec<&Transaction> = vec![];
+        for parent_hash in transaction.get_parent_hashes() {
+            if selected_hashes.contains(&parent_hash) {
+                continue;
+            }
+
+            if let Some(parent) = self.mempool.get(&parent_hash) {
+                for ancestor in self.get_package(parent, selected_hashes, skipped_hashes)? {
+                    if !package
+                        .iter()
+                        .any(|included| included.get_hash() == ancestor.get_hash())
+                    {
+                        package.push(ancestor);
+                    }
+                }
+            }
+        }
+
+        if skipped_hashes.contains(transaction.get_hash()) {
+            return None;
+        }
+        package.push(transaction);
+
+        Some(package)
+    }
+
+    fn has_parents(&self, transaction: &RawTransaction) -> bool {
+        transaction.get_parent_hashes().iter().all(|parent_hash| {
+            self.mempool.contains(parent_hash) || self.find_transaction(parent_hash).is_some()
+        })
+    }
+
+    fn send_chain_event(&mut self, event: ChainEvent) {
+        // Subscribers that went away are forgotten.
+        self.chain_senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    fn accept_orphans(&mut self) {
+        // Accepting an orphan can make its own children ready, so keep going until nothing changes.
+        loop {
+            let ready_hashes = self
+                .orphans
+                .iter()
+                .filter(|(_, orphan)| self.has_parents(orphan))
+                .map(|(orphan_hash, _)| *orphan_hash)
+                .collect::<Vec<_>>();
+
+            if ready_hashes.is_empty() {
+                break;
+            }
+
+            // Orphans that turn out to be invalid are simply dropped.
+            for orphan in self.orphans.take(&ready_hashes) {
+                if let Ok(transaction) = Transaction::from_raw(self, orphan) {
+                    let _ = self.accept_transaction(transaction);
+                }
+            }
+        }
+    }
+
+    fn get_pending_canvas_without(&self, excluded_hashes: &[TxHash]) -> Result<Canvas> {
+        let mut canvas = self.canvas()?;
+        let height = self.get_next_block_height()?;
+
+        // Pending transactions are mined in order, so each one sees the paints before it.
+        for transaction in self.mempool.iter() {
+            if !excluded_hashes.contains(transaction.get_hash()) {
+                canvas.connect_transaction(transaction, height)?;
+            }
+        }
+
+        Ok(canvas)
+    }
+
+    fn check_block_spends(&self, block: &Block) -> Result<()> {
+        // Everything but the reward must spend confirmed outputs, or those earlier in the block, and
+        // each of them only once.
+        let (reward_transaction, transactions) = block
+            .get_transactions()
+            .split_last()
+            .context("Block has no reward transaction.")?;
+
+        let mut earlier_transactions = vec![];
+        let mut spent_outputs = vec![];
+        for transaction in transactions {
+            self.check_transaction_spends(transaction, &earlier_transactions, &mut spent_outputs)?;
+            earlier_transactions.push(transaction);
+        }
+
+        // Every output pays something, and nothing adds up past what credits hold.
+        for transaction in block.get_transactions() {
+            sum_output_values(transaction.get_outputs())?;
+        }
+
+        // The miner can only take the subsidy and the taxes.
+        let reward_limit = transactions
+            .iter()
+            .map(Transaction::get_balance)
+            .try_fold(BLOCK_SUBSIDY, Credits::checked_add)
+            .context("Block taxes overflow.")?;
+        let is_reward = matches!(
+            reward_transaction.get_inputs(),
+            [TransactionInput::FromReward { value, .. }] if *value <= reward_limit
+        );
+        if !is_reward {
+            bail!("Block reward isn't valid.")
+        }
+
+        Ok(())
+    }
+
+    fn check_transaction_spends(
+        &self,
+        transaction: &Transaction,
+        earlier_transactions: &[&Transaction], // Those before it in the same block.
+        spent_outputs: &mut Vec<(TxHash, u32)>, // By the earlier transactions, and then this one.
+    ) -> Result<()> {
+        let mut new_spent_outputs = vec![];
+        for input in transaction.get_inputs() {
+            let (transaction_hash, output_index) = match input {
+                TransactionInput::FromOutput {
+                    transaction_hash,
+                    output_index,
+                    ..
+                } => (transaction_hash, *output_index),
+                TransactionInput::FromReward { .. } => {
+                    bail!("Only the last transaction of a block can be a reward.")
+                }
+            };
+
+            let parent = earlier_transactions
+                .iter()
+                .find(|earlier| earlier.get_hash() == transaction_hash)
+                .copied()
+                .or_else(|| self.find_transaction(transaction_hash));
+            let Some(parent) = parent else {
+                bail!("Block spends from a transaction that isn't confirmed.")
+            };
+
+            // Signatures are checked against the key in the input, so it must be the owner's.
+            let is_owner = parent
+                .get_outputs()
+                .get(output_index as usize)
+                .is_some_and(|output| is_spent_by_owner(input, output));
+            if !is_owner {
+                bail!("Block spends an output with a key it isn't paid to.")
+            }
+
+            let spent_output = (*transaction_hash, output_index);
+            if spent_outputs.contains(&spent_output)
+                || new_spent_outputs.contains(&spent_output)
+                || self.is_output_spent(transaction_hash, output_index)
+            {
+                bail!("Block spends an output that is already spent.")
+            }
+            new_spent_outputs.push(spent_output);
+        }
+        spent_outputs.extend(new_spent_outputs);
+
+        Ok(())
+    }
+
+    fn is_output_spent(&self, transaction_hash: &TxHash, output_index: u32) -> bool {
+        self.blocks
+            .par_iter()
+            .flat_map(|(_, block)| block.get_transactions())
+            .flat_map(|transaction| transaction.get_inputs())
+            .any(|input| match input {
+                TransactionInput::FromOutput {
+                    transaction_hash: hash,
+                    output_index: index,
+                    ..
+                } => *index == output_index && hash == transaction_hash,
+
+                TransactionInput::FromReward { .. } => false, // Because miners automatically cache in rewards.
+            })
+    }
+
+    fn check_input_spends(&self, transaction: &Transaction) -> Result<()> {
+        // Confirmed outputs are spent for good, and no output can pay out twice in one transaction.
+        let mut spent_outputs = vec![];
+        for input in transaction.get_inputs() {
+            if let TransactionInput::FromOutput {
+                transaction_hash,
+                output_index,
+                ..
+            } = input
+            {
+                if spent_outputs.contains(&(transaction_hash, output_index)) {
+                    bail!("Transaction spends the same output more than once.")
+                }
+                if self.is_output_spent(transaction_hash, *output_index) {
+                    bail!(Rejection::SpentInputs)
+                }
+                spent_outputs.push((transaction_hash, output_index));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_input_owners(&self, transaction: &Transaction) -> Result<()> {
+        // Each input must be signed, by the key the output it spends pays to.
+        for input in transaction.get_inputs() {
+            let TransactionInput::FromOutput {
+                transaction_hash,
+                output_index,
+                ..
+            } = input
+            else {
+                bail!("Only blocks can have reward inputs.")
+            };
+
+            let output = self
+                .find_transaction(transaction_hash)
+                .or_else(|| self.find_pending_transaction(transaction_hash))
+                .and_then(|parent| parent.get_outputs().get(*output_index as usize))
+                .context("Fail to find output in input transaction.")?;
+            if !is_spent_by_owner(input, output) {
+                bail!("Transaction spends an output with a key it isn't paid to.")
+            }
+        }
+        verify_transaction_signatures(transaction.get_raw())
+            .context("Transaction input isn't signed by its key.")?;
+
+        Ok(())
+    }
+
+    fn check_output_addresses(&self, transaction: &Transaction) -> Result<()> {
+        // Credits sent to an address of another network would be lost.
+        let is_valid = transaction.get_outputs().iter().all(|output| match output {
+            TransactionOutput::ToInput {
+                public_key_address, ..
+            } => public_key_address.validate_for(&self.chain_params),
+
+            TransactionOutput::ToPixel { .. }
+            | TransactionOutput::LockPixel { .. }
+            | TransactionOutput::Moderate { .. } => true,
+        });
+
+        if !is_valid {
+            bail!("Transaction pays to an address that isn't valid on this network.")
+        }
+
+        Ok(())
+    }
+}
+
+fn split_reward(reward: Credits, payouts: &[(Address, u64)]) -> Vec<(Address, Credits)> {
+    let total_weight: u128 = payouts.iter().map(|(_, weight)| *weight as u128).sum();
+    let mut values = payouts
+        .iter()
+        .map(|(address, weight)| {
+            let value = reward as u128 * *weight as u128 / total_weight;
+            (address.clone(), value as Credits)
+        })
+        .collect::<Vec<_>>();
+
+    // What rounding leaves over goes to the first payout, and empty payouts are left out.
+    let paid: Credits = values.iter().map(|(_, value)| value).sum();
+    values[0].1 += reward - paid;
+    values.retain(|(_, value)| *value > 0);
+
+    values
+}
+
+pub(crate) fn build_locator(chain: &[BlockHash]) -> Vec<BlockHash> {
+    // The last few blocks one by one, then twice as far back each time, and the genesis block.
+    let mut locator = vec![];
+    let mut index = chain.len() - 1;
+    let mut step = 1;
+    while index > 0 {
+        locator.push(chain[index]);
+        if locator.len() >= 10 {
+            step *= 2;
+        }
+        index = index.saturating_sub(step);
+    }
+    locator.push(chain[0]);
+
+    locator
+}
+
+pub(crate) fn check_pixel_positions(
+    chain_params: &ChainParams,
+    transaction: &Transaction,
+    height: u64,
+) -> Result<()> {
+    // Pixels outside the canvas would never be seen.
+    let is_valid = transaction.get_outputs().iter().all(|output| match output {
+        TransactionOutput::ToInput { .. } | TransactionOutput::Moderate { .. } => true,
+        TransactionOutput::ToPixel { position, .. }
+        | TransactionOutput::LockPixel { position, .. } => {
+            chain_params.is_on_canvas(position, height)
+        }
+    });
+
+    if !is_valid {
+        bail!("Transaction paints a pixel outside of the canvas.")
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_pixels_per_address<'a>(
+    chain_params: &ChainParams,
+    transactions: impl Iterator<Item = &'a Transaction>,
+) -> Result<()> {
+    // Pixels are counted by whoever signed the transaction, which is who paints them.
+    let mut pixel_counts = HashMap::new();
+    for transaction in transactions {
+        let signer = match transaction.get_signer() {
+            Some(public_key) => public_key.calculate_hash(),
+            None => continue,
+        };
+
+        let pixel_count = transaction
+            .get_outputs()
+            .iter()
+            .filter(|output| matches!(output, TransactionOutput::ToPixel { .. }))
+            .count();
+
+        let total = pixel_counts.entry(signer).or_insert(0);
+        *total += pixel_count;
+        if *total > chain_params.max_pixels_per_block_per_address {
+            bail!(
+                "Address can't paint more than {} pixels in a block.",
+                chain_params.max_pixels_per_block_per_address
+            )
+        }
+    }
+
+    Ok(())
+}
+
+// Credits can only be spent with the key their address was made from.
+fn is_spent_by_owner(input: &TransactionInput, output: &TransactionOutput) -> bool {
+    match (input, output) {
+        (
+            TransactionInput::FromOutput { public_key, .. },
+            TransactionOutput::ToInput {
+                public_key_address, ..
+            },
+        ) => public_key_address
+            .public_key_hash()
+            .is_ok_and(|public_key_hash| public_key_hash == public_key.calculate_hash()),
+        _ => true, // Spending anything else is refused when the transaction is made.
+    }
+}
+
+fn check_signature_encodings(transaction: &Transaction) -> Result<()> {
+    // Signatures are part of the transaction hash, so a second encoding would be a second transaction.
+    let scheme = get_signature_scheme(transaction.get_version())?;
+
+    transaction
+        .get_inputs()
+        .iter()
+        .try_for_each(|input| match input {
+            TransactionInput::FromOutput { signature, .. } => scheme.check_canonical(signature),
+            TransactionInput::FromReward { .. } => Ok(()),
+        })
+}
+
+// Prints and parses hashes as hex so they can be shown to humans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HashHex(pub Hash);
+
+impl From<Hash> for HashHex {
+    fn from(hash: Hash) -> Self {
+        Self(hash)
+    }
+}
+
+impl TryFrom<&[u8]> for HashHex {
+    type Error = anyhow::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let hash = bytes
+            .try_into()
+            .ok()
+            .context("Hashes must be exactly 32 bytes.")?;
+
+        Ok(Self(hash))
+    }
+}
+
+impl fmt::Display for HashHex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", encode_hex(&self.0))
+    }
+}
+
+impl FromStr for HashHex {
+    type Err = anyhow::Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        if hex.len() != 64 {
+            bail!("Hashes must be exactly 64 hex characters.")
+        }
+
+        Self::try_from(decode_hex(hex)?.as_slice())
+    }
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        bail!("Hex must be an even number of hex characters.")
+    }
+
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+        .collect())
+}
@@ -0,0 +1,131 @@
+// Bloom filters, so light clients can ask only for the transactions they care about. Elements are
+// the public key hashes of addresses, and outpoints: a transaction hash followed by the output
+// index. An element is in the filter if all of its bits are set, which sometimes happens by chance
+// too, so a node never learns exactly what a client is watching.
+
+use crate::{
+    address::{Address, PublicKey},
+    blockchain::TxHash,
+    transaction::{Transaction, TransactionInput, TransactionOutput},
+};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::f64::consts::LN_2;
+
+pub const MAX_FILTER_SIZE: usize = 36_000; // In bytes.
+pub const MAX_HASH_FUNCTIONS: u32 = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hash_count: u32,
+    tweak: u32, // So filters of different clients set different bits for the same elements.
+}
+
+impl BloomFilter {
+    pub fn new(element_count: usize, false_positive_rate: f64, tweak: u32) -> Self {
+        // The sizes that give the asked for false positive rate with the fewest bits.
+        let element_count = element_count.max(1) as f64;
+        let size = (-element_count * false_positive_rate.ln() / LN_2.powi(2) / 8.0) as usize;
+        let size = size.clamp(1, MAX_FILTER_SIZE);
+        let hash_count = (size as f64 * 8.0 / element_count * LN_2) as u32;
+
+        Self {
+            bits: vec![0; size],
+            hash_count: hash_count.clamp(1, MAX_HASH_FUNCTIONS),
+            tweak,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        // Filters come from peers, who could ask for far too much work otherwise.
+        !self.bits.is_empty()
+            && self.bits.len() <= MAX_FILTER_SIZE
+            && (1..=MAX_HASH_FUNCTIONS).contains(&self.hash_count)
+    }
+
+    pub fn insert(&mut self, element: &[u8]) {
+        for index in self.get_bit_indices(element).collect::<Vec<_>>() {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub fn contains(&self, element: &[u8]) -> bool {
+        self.get_bit_indices(element)
+            .all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    pub fn insert_address(&mut self, address: &Address) {
+        self.insert(&get_address_element(address));
+    }
+
+    pub fn insert_outpoint(&mut self, transaction_hash: &TxHash, output_index: u32) {
+        self.insert(&get_outpoint_element(transaction_hash, output_index));
+    }
+
+    pub fn matches_transaction(&mut self, transaction: &Transaction) -> bool {
+        let transaction_hash = transaction.get_hash();
+        let mut is_match = self.contains(transaction_hash.as_ref());
+
+        // Outputs to a watched address are watched as well, so whatever spends them matches too.
+        for (output_index, output) in transaction.get_outputs().iter().enumerate() {
+            if let TransactionOutput::ToInput {
+                public_key_address, ..
+            } = output
+            {
+                if self.contains(&get_address_element(public_key_address)) {
+                    self.insert_outpoint(transaction_hash, output_index as u32);
+                    is_match = true;
+                }
+            }
+        }
+
+        for input in transaction.get_inputs() {
+            if let TransactionInput::FromOutput {
+                transaction_hash,
+                output_index,
+                public_key,
+                ..
+            } = input
+            {
+                if self.contains(&get_outpoint_element(transaction_hash, *output_index))
+                    || self.contains(&get_public_key_element(public_key))
+                {
+                    is_match = true;
+                }
+            }
+        }
+
+        is_match
+    }
+
+    fn get_bit_indices<'a>(&'a self, element: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let bit_count = self.bits.len() * 8;
+
+        (0..self.hash_count).map(move |function| {
+            let mut hasher = Sha3_256::default();
+            hasher.update(self.tweak.to_le_bytes());
+            hasher.update(function.to_le_bytes());
+            hasher.update(element);
+            let digest = hasher.finalize();
+
+            u32::from_le_bytes(digest[..4].try_into().unwrap()) as usize % bit_count
+        })
+    }
+}
+
+fn get_address_element(address: &Address) -> Vec<u8> {
+    // The same key in another encoding is still the same address.
+    address.public_key_hash().map_or_else(
+        |_| address.as_str().as_bytes().to_vec(),
+        |hash| hash.as_ref().to_vec(),
+    )
+}
+
+fn get_public_key_element(public_key: &PublicKey) -> Vec<u8> {
+    public_key.calculate_hash().as_ref().to_vec()
+}
+
+fn get_outpoint_element(transaction_hash: &TxHash, output_index: u32) -> Vec<u8> {
+    [transaction_hash.as_ref(), &output_index.to_le_bytes()].concat()
+}
@@ -0,0 +1,1325 @@
+use crate::{
+    address::Address,
+    block::Block,
+    blockchain::{BlockHash, Blockchain, CanvasHash, Hash, StateHash, TxHash},
+    chain_params::ChainParams,
+    color::Color,
+    merkle,
+    transaction::{
+        Credits, FactionTag, Layer, ModerationAction, Point, Transaction, TransactionInput,
+        TransactionOutput, BASE_LAYER, MAX_FACTION_TAG_SIZE, MAX_MEMO_SIZE,
+    },
+};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pixel {
+    pub color: Color,
+    pub owner: Option<Address>, // Reward transactions aren't owned by anyone.
+    pub height: u64,
+    pub transaction_hash: TxHash,
+    pub price: Credits, // What painting it cost, which sets the price of the next paint.
+    pub locked_until: u64, // First height at which the pixel can be painted again.
+    pub faction: Option<FactionTag>,
+    pub expires_at: Option<u64>, // Height at which a rented pixel goes back to the previous one.
+    pub previous: Option<Box<Pixel>>,
+}
+
+impl Pixel {
+    pub fn calculate_hash(&self) -> Hash {
+        let owner = self
+            .owner
+            .as_ref()
+            .and_then(|owner| owner.public_key_hash().ok());
+        let encoded = bincode::serialize(&(
+            self.color,
+            owner,
+            self.height,
+            self.transaction_hash,
+            self.price,
+            self.locked_until,
+            &self.faction,
+            self.expires_at,
+            self.previous
+                .as_ref()
+                .map(|previous| previous.calculate_hash()),
+        ))
+        .unwrap();
+
+        Sha3_256::digest(encoded).into()
+    }
+
+    pub fn get_permanent(&self) -> Option<&Pixel> {
+        // What stays once the rental is over, which is what prices and owners go by.
+        match self.expires_at {
+            Some(_) => self.previous.as_deref(),
+            None => Some(self),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaintRecord {
+    pub height: u64,
+    pub layer: Layer,
+    pub color: Color,
+    pub painter: Option<Address>,
+    pub transaction_hash: TxHash,
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PixelProof {
+    pub position: Point,
+    pub pixel: Option<Pixel>,      // Nothing if the pixel was never painted.
+    pixel_path: Vec<(bool, Hash)>, // From the pixel up to the root of its tile.
+    tile_path: Vec<(bool, Hash)>,  // From the tile up to the root of the canvas.
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OwnershipStats {
+    pub pixels_owned: u64,
+    pub pixels_painted: u64,
+    pub pixels_overwritten: u64, // Times someone else painted over one of their pixels.
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CanvasEvent {
+    PixelPainted {
+        point: Point,
+        color: Color,
+        painter: Option<Address>,
+        height: u64,
+    },
+
+    PixelReverted {
+        point: Point,
+        color: Option<Color>, // What shows again, nothing if the pixel is blank.
+        height: u64,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Canvas {
+    chain_params: ChainParams,
+    tiles: HashMap<Point, Tile>, // Tiles are only stored once something is painted on them.
+    version: u64, // Bumped on every change, so tiles can tell when they were last touched.
+    history: Option<HashMap<Point, Vec<PaintRecord>>>, // Every paint of every pixel, if enabled.
+    height: u64,  // Height of the last connected block, which sets the size of the canvas.
+    ownership: HashMap<Address, OwnershipStats>, // Only counts the base layer.
+    expirations: BTreeMap<u64, Vec<(Layer, Point)>>, // Rented pixels by the height they expire at.
+    frozen: Vec<((Point, i32, i32), u64)>, // Rectangles frozen by the moderators, and the height they thaw at.
+}
+
+pub const TILE_SIZE: i32 = 64;
+
+#[derive(Debug, Clone)]
+struct Tile {
+    pixels: Vec<Option<Pixel>>, // The base layer.
+    overlays: BTreeMap<Layer, Vec<Option<Pixel>>>,
+    version: u64,
+}
+
+impl Tile {
+    fn get_layer(&self, layer: Layer) -> Option<&Vec<Option<Pixel>>> {
+        match layer {
+            BASE_LAYER => Some(&self.pixels),
+            _ => self.overlays.get(&layer),
+        }
+    }
+
+    fn get_layer_mut(&mut self, layer: Layer) -> &mut Vec<Option<Pixel>> {
+        match layer {
+            BASE_LAYER => &mut self.pixels,
+            _ => self
+                .overlays
+                .entry(layer)
+                .or_insert_with(|| vec![None; (TILE_SIZE * TILE_SIZE) as usize]),
+        }
+    }
+
+    fn get_visible_pixel(&self, index: usize) -> Option<&Pixel> {
+        // The highest layer with something painted covers the others.
+        self.overlays
+            .values()
+            .rev()
+            .find_map(|pixels| pixels[index].as_ref())
+            .or(self.pixels[index].as_ref())
+    }
+
+    fn get_leaves(pixels: &[Option<Pixel>]) -> Vec<Hash> {
+        pixels
+            .iter()
+            .map(|pixel| {
+                pixel
+                    .as_ref()
+                    .map_or(merkle::EMPTY_ROOT, Pixel::calculate_hash)
+            })
+            .collect()
+    }
+
+    fn get_layer_leaves(&self) -> Vec<Hash> {
+        // The base layer comes first, so a tile without overlays has the root of its base pixels.
+        std::iter::once(merkle::root(&Self::get_leaves(&self.pixels)))
+            .chain(self.overlays.iter().map(|(layer, pixels)| {
+                let mut hasher = Sha3_256::default();
+                hasher.update([*layer]);
+                hasher.update(merkle::root(&Self::get_leaves(pixels)));
+
+                hasher.finalize().into()
+            }))
+            .collect()
+    }
+}
+
+impl Default for Tile {
+    fn default() -> Self {
+        Self {
+            pixels: vec![None; (TILE_SIZE * TILE_SIZE) as usize],
+            overlays: Default::default(),
+            version: 0,
+        }
+    }
+}
+
+impl Canvas {
+    pub fn new(chain_params: ChainParams) -> Self {
+        Self {
+            chain_params,
+            tiles: Default::default(),
+            version: 0,
+            history: None,
+            height: 0,
+            ownership: Default::default(),
+            expirations: Default::default(),
+            frozen: Default::default(),
+        }
+    }
+
+    pub fn with_history(chain_params: ChainParams) -> Self {
+        Self {
+            history: Some(Default::default()),
+            ..Self::new(chain_params)
+        }
+    }
+
+    pub fn from_chain_with_history(blockchain: &Blockchain) -> Result<Self> {
+        let mut canvas = Self::with_history(blockchain.get_chain_params().clone());
+        for block in blockchain.get_main_chain() {
+            canvas.connect_block(block)?;
+        }
+
+        Ok(canvas)
+    }
+
+    pub fn from_chain(blockchain: &Blockchain) -> Result<Self> {
+        Self::from_chain_at(blockchain, blockchain.get_last_block_hash())
+    }
+
+    pub fn from_chain_at(blockchain: &Blockchain, block_hash: &BlockHash) -> Result<Self> {
+        let mut canvas = Self::new(blockchain.get_chain_params().clone());
+
+        // Replay from the genesis block so later paints win over older ones.
+        for block in blockchain.get_chain_ending_at(block_hash) {
+            canvas.connect_block(block)?;
+        }
+
+        Ok(canvas)
+    }
+
+    pub fn at_height(blockchain: &Blockchain, height: u64) -> Result<Self> {
+        let mut canvas = Self::new(blockchain.get_chain_params().clone());
+
+        for block in blockchain.get_main_chain() {
+            if block.get_block_height()? > height {
+                break;
+            }
+
+            canvas.connect_block(block)?;
+        }
+
+        Ok(canvas)
+    }
+
+    pub fn timelapse(
+        blockchain: &Blockchain,
+        step: u64,
+        mut on_frame: impl FnMut(u64, &Self) -> Result<()>,
+    ) -> Result<()> {
+        if step == 0 {
+            bail!("Timelapse step must be at least one block.")
+        }
+
+        let mut canvas = Self::new(blockchain.get_chain_params().clone());
+        let blocks = blockchain.get_main_chain();
+
+        // One frame every step blocks, and always one for the last block.
+        for (index, block) in blocks.iter().enumerate() {
+            canvas.connect_block(block)?;
+
+            let height = block.get_block_height()?;
+            if height % step == 0 || index + 1 == blocks.len() {
+                on_frame(height, &canvas)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "image")]
+    pub fn write_timelapse_gif<W: std::io::Write>(
+        blockchain: &Blockchain,
+        step: u64,
+        writer: W,
+    ) -> Result<()> {
+        use image::{codecs::gif::GifEncoder, Frame, RgbaImage};
+
+        let mut encoder = GifEncoder::new(writer);
+        Self::timelapse(blockchain, step, |_, canvas| {
+            let image = RgbaImage::from_raw(
+                canvas.get_width().max(0) as u32,
+                canvas.get_height().max(0) as u32,
+                canvas.render(),
+            )
+            .unwrap();
+
+            Ok(encoder.encode_frame(Frame::new(image))?)
+        })
+    }
+
+    pub fn connect_block(&mut self, block: &Block) -> Result<()> {
+        let height = block.get_block_height()?;
+        self.height = self.height.max(height);
+        self.expire_rentals(height);
+
+        for transaction in block.get_transactions() {
+            self.connect_transaction(transaction, height)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn connect_transaction(&mut self, transaction: &Transaction, height: u64) -> Result<()> {
+        self.expire_rentals(height);
+
+        // Everything is checked up front, so a transaction is either painted in full or not at all.
+        self.check_transaction(transaction, height)?;
+
+        self.height = self.height.max(height);
+        let owner = self.get_owner(transaction);
+
+        for output in transaction.get_outputs() {
+            match output {
+                TransactionOutput::ToPixel {
+                    position,
+                    color,
+                    layer,
+                    faction,
+                    rental,
+                    memo,
+                    ..
+                } => {
+                    let permanent = self
+                        .get_layer_pixel(*layer, position)
+                        .and_then(Pixel::get_permanent)
+                        .cloned();
+
+                    // Rentals keep the pixel they cover, to put it back when they expire.
+                    let (price, expires_at, previous) = match rental {
+                        Some(duration) => {
+                            self.expirations
+                                .entry(height + duration)
+                                .or_default()
+                                .push((*layer, *position));
+
+                            (
+                                self.chain_params.get_pixel_rental_price(*duration),
+                                Some(height + duration),
+                                permanent.map(Box::new),
+                            )
+                        }
+
+                        None => {
+                            if *layer == BASE_LAYER {
+                                let previous_owner =
+                                    permanent.and_then(|permanent| permanent.owner);
+                                self.update_ownership(previous_owner, owner.clone());
+                            }
+
+                            (
+                                self.get_layer_pixel_price(*layer, position, height),
+                                None,
+                                None,
+                            )
+                        }
+                    };
+
+                    if let Some(history) = &mut self.history {
+                        history.entry(*position).or_default().push(PaintRecord {
+                            height,
+                            layer: *layer,
+                            color: *color,
+                            painter: owner.clone(),
+                            transaction_hash: *transaction.get_hash(),
+                            memo: memo.clone(),
+                        });
+                    }
+
+                    self.set_pixel(
+                        *layer,
+                        *position,
+                        Some(Pixel {
+                            color: *color,
+                            owner: owner.clone(),
+                            height,
+                            transaction_hash: *transaction.get_hash(),
+                            price,
+                            locked_until: 0,
+                            faction: faction.clone(),
+                            expires_at,
+                            previous,
+                        }),
+                    );
+                }
+
+                TransactionOutput::LockPixel {
+                    position, duration, ..
+                } => {
+                    // Locks don't add up with each other.
+                    let pixel = self.get_pixel_mut(position).unwrap();
+                    pixel.locked_until = pixel.locked_until.max(height + duration);
+                }
+
+                TransactionOutput::Moderate {
+                    position,
+                    width,
+                    height: rectangle_height,
+                    action,
+                    ..
+                } => match action {
+                    ModerationAction::Blank => self.blank(position, *width, *rectangle_height),
+                    ModerationAction::Freeze { duration } => self
+                        .frozen
+                        .push(((*position, *width, *rectangle_height), height + duration)),
+                },
+
+                TransactionOutput::ToInput { .. } => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn check_transaction(&self, transaction: &Transaction, height: u64) -> Result<()> {
+        let owner = self.get_owner(transaction);
+
+        let painted = transaction
+            .get_outputs()
+            .iter()
+            .filter_map(|output| match output {
+                TransactionOutput::ToPixel {
+                    position, layer, ..
+                } => Some((*layer, *position)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if painted.len() > self.chain_params.max_pixels_per_transaction {
+            bail!(
+                "Transactions can paint at most {} pixels.",
+                self.chain_params.max_pixels_per_transaction
+            )
+        }
+
+        // Prices are taken from the canvas before the transaction, so each pixel is painted once.
+        if painted
+            .iter()
+            .enumerate()
+            .any(|(index, pixel)| painted[..index].contains(pixel))
+        {
+            bail!("Every pixel can only be painted once per transaction.")
+        }
+
+        let mut paid = 0;
+        let mut price = 0;
+        let mut memo_size = 0;
+        let mut royalties: Vec<(Address, Credits)> = vec![];
+        for output in transaction.get_outputs() {
+            match output {
+                TransactionOutput::ToPixel {
+                    value,
+                    position,
+                    layer,
+                    faction,
+                    rental,
+                    memo,
+                    ..
+                } => {
+                    if faction.as_ref().is_some_and(|faction| {
+                        faction.is_empty() || faction.len() > MAX_FACTION_TAG_SIZE
+                    }) {
+                        bail!("Faction tags must have between 1 and {MAX_FACTION_TAG_SIZE} bytes.")
+                    }
+
+                    if let Some(memo) = memo {
+                        if memo.is_empty() || memo.len() > MAX_MEMO_SIZE {
+                            bail!("Memos must have between 1 and {MAX_MEMO_SIZE} bytes.")
+                        }
+
+                        memo_size += memo.len();
+                    }
+
+                    // Locks only protect the base layer, overlays are painted on top of it.
+                    if *layer == BASE_LAYER && self.is_locked(position, height) {
+                        bail!("Pixel at {position:?} is locked.")
+                    }
+
+                    if self.is_frozen(position, height) {
+                        bail!("Pixel at {position:?} was frozen by the moderators.")
+                    }
+
+                    paid += value;
+
+                    // Rentals pay by the block, and nothing to the painter they cover for a while.
+                    if let Some(duration) = rental {
+                        if *duration == 0 || *duration > self.chain_params.max_pixel_rental_duration
+                        {
+                            bail!(
+                                "Pixel rentals must last between 1 and {} blocks.",
+                                self.chain_params.max_pixel_rental_duration
+                            )
+                        }
+
+                        price += self.chain_params.get_pixel_rental_price(*duration);
+                        continue;
+                    }
+
+                    // Part of the price goes to the previous painter instead.
+                    let royalty =
+                        self.get_layer_pixel_royalty(*layer, position, owner.as_ref(), height);
+                    let royalty_value = royalty.as_ref().map_or(0, |(_, value)| *value);
+
+                    price += self.get_layer_pixel_price(*layer, position, height) - royalty_value;
+
+                    if let Some((address, value)) = royalty {
+                        match royalties.iter_mut().find(|(other, _)| *other == address) {
+                            Some((_, total)) => *total += value,
+                            None => royalties.push((address, value)),
+                        }
+                    }
+                }
+
+                TransactionOutput::LockPixel {
+                    value,
+                    position,
+                    duration,
+                } => {
+                    if *duration == 0 || *duration > self.chain_params.max_pixel_lock_duration {
+                        bail!(
+                            "Pixel locks must last between 1 and {} blocks.",
+                            self.chain_params.max_pixel_lock_duration
+                        )
+                    }
+
+                    let price = self.chain_params.get_pixel_lock_price(*duration);
+                    if *value < price {
+                        bail!("Locking pixel at {position:?} costs {price} credits.")
+                    }
+
+                    if self.is_frozen(position, height) {
+                        bail!("Pixel at {position:?} was frozen by the moderators.")
+                    }
+
+                    // Only the painter can lock a pixel, which includes painting it in the same transaction.
+                    // Rented pixels go away on their own, so they can't be locked.
+                    let paints_it = transaction.get_outputs().iter().any(|output| {
+                        matches!(
+                            output,
+                            TransactionOutput::ToPixel {
+                                position: other,
+                                layer: BASE_LAYER,
+                                rental: None,
+                                ..
+                            } if other == position
+                        )
+                    });
+                    let is_owner = owner.is_some()
+                        && (paints_it
+                            || self.get_pixel(position).is_some_and(|pixel| {
+                                pixel.owner == owner && pixel.expires_at.is_none()
+                            }));
+
+                    if !is_owner {
+                        bail!("Only the owner of the pixel at {position:?} can lock it.")
+                    }
+                }
+
+                TransactionOutput::Moderate {
+                    position,
+                    width,
+                    height: rectangle_height,
+                    ..
+                } => {
+                    if *width <= 0 || *rectangle_height <= 0 {
+                        bail!("Moderated rectangle at {position:?} is empty.")
+                    }
+
+                    // Every signer counts once, no matter how many inputs they sign.
+                    let mut moderators = transaction
+                        .get_inputs()
+                        .iter()
+                        .filter_map(|input| match input {
+                            TransactionInput::FromOutput { public_key, .. } => {
+                                Some(public_key.calculate_hash())
+                            }
+                            TransactionInput::FromReward { .. } => None,
+                        })
+                        .filter(|public_key_hash| {
+                            self.chain_params.moderators.contains(public_key_hash)
+                        })
+                        .collect::<Vec<_>>();
+                    moderators.sort();
+                    moderators.dedup();
+
+                    if moderators.is_empty()
+                        || moderators.len() < self.chain_params.moderation_threshold
+                    {
+                        bail!(
+                            "Moderation must be signed by {} moderators.",
+                            self.chain_params.moderation_threshold.max(1)
+                        )
+                    }
+                }
+
+                TransactionOutput::ToInput { .. } => {}
+            }
+        }
+
+        // Pixels are paid for as a whole, so value can be spread between them freely.
+        if paid < price {
+            bail!("Pixels cost {price} credits, but only {paid} were paid.")
+        }
+
+        // Memos are paid for as tax, so they go to whoever mines them.
+        let memo_fee = self.chain_params.get_memo_fee(memo_size);
+        if transaction.get_balance() < memo_fee {
+            bail!("Memos need {memo_fee} credits of tax.")
+        }
+
+        for (address, royalty) in royalties {
+            let paid: Credits = transaction
+                .get_outputs()
+                .iter()
+                .filter_map(|output| match output {
+                    TransactionOutput::ToInput {
+                        value,
+                        public_key_address,
+                    } if *public_key_address == address => Some(value),
+                    _ => None,
+                })
+                .sum();
+
+            if paid < royalty {
+                bail!("Transaction must pay {royalty} credits of royalties to {address}.")
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_pixel_royalty(
+        &self,
+        position: &Point,
+        painter: Option<&Address>,
+        height: u64,
+    ) -> Option<(Address, Credits)> {
+        self.get_layer_pixel_royalty(BASE_LAYER, position, painter, height)
+    }
+
+    pub fn get_layer_pixel_royalty(
+        &self,
+        layer: Layer,
+        position: &Point,
+        painter: Option<&Address>,
+        height: u64,
+    ) -> Option<(Address, Credits)> {
+        let previous_owner = self
+            .get_layer_pixel(layer, position)?
+            .get_permanent()?
+            .owner
+            .as_ref()?;
+
+        // Painting over your own pixel pays nobody.
+        if Some(previous_owner) == painter {
+            return None;
+        }
+
+        let royalty = self.get_layer_pixel_price(layer, position, height)
+            * self.chain_params.pixel_royalty_percent
+            / 100;
+
+        (royalty > 0).then(|| (previous_owner.clone(), royalty))
+    }
+
+    pub fn get_pixel_price(&self, position: &Point, height: u64) -> Credits {
+        self.get_layer_pixel_price(BASE_LAYER, position, height)
+    }
+
+    pub fn get_layer_pixel_price(&self, layer: Layer, position: &Point, height: u64) -> Credits {
+        let base_price = self.chain_params.pixel_price;
+        let window = self.chain_params.pixel_contest_window.max(1);
+
+        match self
+            .get_layer_pixel(layer, position)
+            .and_then(Pixel::get_permanent)
+        {
+            None => base_price,
+
+            Some(pixel) => {
+                // Overwriting a recent paint doubles its price, which then halves every window until it's back to base.
+                let age = height.saturating_sub(pixel.height);
+                if age < window {
+                    pixel.price.saturating_mul(2)
+                } else {
+                    let halvings = (age / window).min(62) as u32;
+
+                    (pixel.price >> halvings).max(base_price)
+                }
+            }
+        }
+    }
+
+    pub fn is_frozen(&self, position: &Point, height: u64) -> bool {
+        let (x, y) = *position;
+
+        self.frozen
+            .iter()
+            .any(|(((left, top), width, rectangle_height), until)| {
+                height < *until
+                    && (*left..left + width).contains(&x)
+                    && (*top..top + rectangle_height).contains(&y)
+            })
+    }
+
+    pub fn is_locked(&self, position: &Point, height: u64) -> bool {
+        self.get_pixel(position)
+            .is_some_and(|pixel| height < pixel.locked_until)
+    }
+
+    pub fn render(&self) -> Vec<u8> {
+        self.render_with(|position| self.get_visible_pixel(position))
+    }
+
+    pub fn render_layer(&self, layer: Layer) -> Vec<u8> {
+        self.render_with(|position| self.get_layer_pixel(layer, position))
+    }
+
+    fn render_with<'a>(&self, get_pixel: impl Fn(&Point) -> Option<&'a Pixel>) -> Vec<u8> {
+        let width = self.get_width().max(0);
+        let height = self.get_height().max(0);
+
+        // One RGBA quadruple per pixel, row by row. Unpainted pixels are left white.
+        let mut framebuffer = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let color = get_pixel(&(x, y)).map_or(Color::White, |pixel| pixel.color);
+                let (red, green, blue) = color.to_rgb();
+
+                framebuffer.extend_from_slice(&[red, green, blue, 0xFF]);
+            }
+        }
+
+        framebuffer
+    }
+
+    #[cfg(feature = "image")]
+    pub fn write_png<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        use anyhow::Context;
+        use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+
+        PngEncoder::new(writer)
+            .write_image(
+                &self.render(),
+                self.get_width().max(0) as u32,
+                self.get_height().max(0) as u32,
+                ColorType::Rgba8,
+            )
+            .context("Failed to encode the canvas as PNG.")
+    }
+
+    pub fn write_svg<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
+        let (width, height) = (self.get_width(), self.get_height());
+
+        writeln!(
+            writer,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" shape-rendering="crispEdges">"#
+        )?;
+        writeln!(
+            writer,
+            r#"<rect width="{width}" height="{height}" fill="white"/>"#
+        )?;
+
+        // Sort the pixels so the same canvas always gives the same document.
+        let mut pixels = self.get_pixels().collect::<Vec<_>>();
+        pixels.sort_by_key(|((x, y), _)| (*y, *x));
+
+        for ((x, y), pixel) in pixels {
+            let (red, green, blue) = pixel.color.to_rgb();
+            let owner = pixel
+                .owner
+                .as_ref()
+                .map_or(String::new(), |owner| owner.to_string());
+
+            writeln!(
+                writer,
+                r##"<rect x="{x}" y="{y}" width="1" height="1" fill="#{red:02x}{green:02x}{blue:02x}" data-owner="{owner}" data-height="{}" data-transaction="{}"/>"##,
+                pixel.height, pixel.transaction_hash
+            )?;
+        }
+
+        writeln!(writer, "</svg>")?;
+
+        Ok(())
+    }
+
+    pub fn write_ansi<W: std::io::Write>(
+        &self,
+        mut writer: W,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) -> Result<()> {
+        let rgb = |position: Point| {
+            self.get_visible_pixel(&position)
+                .map_or(Color::White, |pixel| pixel.color)
+                .to_rgb()
+        };
+
+        // Each character is an upper half block, so one row of text shows two rows of pixels.
+        for row in (y..y + height).step_by(2) {
+            for column in x..x + width {
+                let (red, green, blue) = rgb((column, row));
+                write!(writer, "\x1b[38;2;{red};{green};{blue}m")?;
+
+                if row + 1 < y + height {
+                    let (red, green, blue) = rgb((column, row + 1));
+                    write!(writer, "\x1b[48;2;{red};{green};{blue}m")?;
+                } else {
+                    write!(writer, "\x1b[49m")?;
+                }
+
+                write!(writer, "\u{2580}")?;
+            }
+
+            writeln!(writer, "\x1b[0m")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_width(&self) -> i32 {
+        self.chain_params.get_canvas_size(self.height).0
+    }
+
+    pub fn get_height(&self) -> i32 {
+        self.chain_params.get_canvas_size(self.height).1
+    }
+
+    pub fn region(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<Option<&Pixel>> {
+        let (width, height) = (width.max(0), height.max(0));
+        let mut region = vec![None; width as usize * height as usize];
+
+        // Only visit the stored tiles that overlap the region.
+        let (first_tile_x, first_tile_y) = get_tile_position(&(x, y));
+        let (last_tile_x, last_tile_y) = get_tile_position(&(x + width - 1, y + height - 1));
+        for tile_y in first_tile_y..=last_tile_y {
+            for tile_x in first_tile_x..=last_tile_x {
+                let tile = match self.tiles.get(&(tile_x, tile_y)) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+
+                let start_x = (tile_x * TILE_SIZE).max(x);
+                let end_x = ((tile_x + 1) * TILE_SIZE).min(x + width);
+                let start_y = (tile_y * TILE_SIZE).max(y);
+                let end_y = ((tile_y + 1) * TILE_SIZE).min(y + height);
+
+                for pixel_y in start_y..end_y {
+                    for pixel_x in start_x..end_x {
+                        let index = ((pixel_y - y) * width + (pixel_x - x)) as usize;
+                        region[index] = tile.pixels[get_tile_index(&(pixel_x, pixel_y))].as_ref();
+                    }
+                }
+            }
+        }
+
+        region
+    }
+
+    pub fn get_pixel(&self, position: &Point) -> Option<&Pixel> {
+        self.get_layer_pixel(BASE_LAYER, position)
+    }
+
+    pub fn get_layer_pixel(&self, layer: Layer, position: &Point) -> Option<&Pixel> {
+        self.tiles
+            .get(&get_tile_position(position))?
+            .get_layer(layer)?[get_tile_index(position)]
+        .as_ref()
+    }
+
+    pub fn get_visible_pixel(&self, position: &Point) -> Option<&Pixel> {
+        self.tiles
+            .get(&get_tile_position(position))?
+            .get_visible_pixel(get_tile_index(position))
+    }
+
+    pub fn get_pixels(&self) -> impl Iterator<Item = (Point, &Pixel)> + '_ {
+        self.tiles.iter().flat_map(|((tile_x, tile_y), tile)| {
+            tile.pixels
+                .iter()
+                .enumerate()
+                .filter_map(move |(index, pixel)| {
+                    let position = (
+                        tile_x * TILE_SIZE + index as i32 % TILE_SIZE,
+                        tile_y * TILE_SIZE + index as i32 / TILE_SIZE,
+                    );
+
+                    pixel.as_ref().map(|pixel| (position, pixel))
+                })
+        })
+    }
+
+    pub fn pixel_history(&self, position: &Point) -> Result<&[PaintRecord]> {
+        Ok(self.get_history()?.get(position).map_or(&[], Vec::as_slice))
+    }
+
+    pub fn get_history(&self) -> Result<&HashMap<Point, Vec<PaintRecord>>> {
+        match &self.history {
+            Some(history) => Ok(history),
+            None => bail!("Canvas wasn't built with a paint history."),
+        }
+    }
+
+    pub fn owned_pixels(&self, address: &Address) -> Vec<Point> {
+        let mut positions = self
+            .get_pixels()
+            .filter(|(_, pixel)| {
+                pixel
+                    .get_permanent()
+                    .is_some_and(|pixel| pixel.owner.as_ref() == Some(address))
+            })
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>();
+
+        positions.sort_by_key(|(x, y)| (*y, *x));
+
+        positions
+    }
+
+    pub fn get_faction_scores(&self) -> Vec<(FactionTag, u64)> {
+        let mut scores: HashMap<&FactionTag, u64> = HashMap::new();
+        for pixel in self
+            .get_pixels()
+            .filter_map(|(_, pixel)| pixel.get_permanent())
+        {
+            if let Some(faction) = &pixel.faction {
+                *scores.entry(faction).or_default() += 1;
+            }
+        }
+
+        // Most pixels first, then by tag so ties are stable.
+        let mut scores = scores
+            .into_iter()
+            .map(|(faction, pixels)| (faction.clone(), pixels))
+            .collect::<Vec<_>>();
+        scores.sort_by(|(faction, pixels), (other_faction, other_pixels)| {
+            other_pixels.cmp(pixels).then(faction.cmp(other_faction))
+        });
+
+        scores
+    }
+
+    pub fn get_ownership_stats(&self, address: &Address) -> OwnershipStats {
+        self.ownership.get(address).copied().unwrap_or_default()
+    }
+
+    pub fn calculate_root(&self) -> CanvasHash {
+        let (_, tile_leaves) = self.get_tile_leaves();
+
+        merkle::root(&tile_leaves).into()
+    }
+
+    pub fn state_hash(&self) -> StateHash {
+        // Only what the canvas looks like, so tiles that were painted and then blanked don't count.
+        let mut tile_positions = self.tiles.keys().copied().collect::<Vec<_>>();
+        tile_positions.sort_by_key(|(x, y)| (*y, *x));
+
+        let mut hasher = Sha3_256::default();
+        hasher.update(bincode::serialize(&(self.get_width(), self.get_height())).unwrap());
+        for tile_position in tile_positions {
+            let tile = &self.tiles[&tile_position];
+            let colors = (0..(TILE_SIZE * TILE_SIZE) as usize)
+                .map(|index| tile.get_visible_pixel(index).map(|pixel| pixel.color))
+                .collect::<Vec<_>>();
+            if colors.iter().all(Option::is_none) {
+                continue;
+            }
+
+            hasher.update(bincode::serialize(&(tile_position, colors)).unwrap());
+        }
+
+        let hash: Hash = hasher.finalize().into();
+
+        hash.into()
+    }
+
+    pub fn prove_pixel(&self, position: &Point) -> Result<PixelProof> {
+        let tile_position = get_tile_position(position);
+        let tile = match self.tiles.get(&tile_position) {
+            Some(tile) => tile,
+            None => bail!("Pixel at {position:?} is in a tile that was never painted."),
+        };
+
+        let (tile_positions, tile_leaves) = self.get_tile_leaves();
+        let tile_index = tile_positions
+            .iter()
+            .position(|other| *other == tile_position)
+            .unwrap();
+
+        Ok(PixelProof {
+            position: *position,
+            pixel: tile.pixels[get_tile_index(position)].clone(),
+            pixel_path: [
+                merkle::proof(&Tile::get_leaves(&tile.pixels), get_tile_index(position)),
+                merkle::proof(&tile.get_layer_leaves(), 0),
+            ]
+            .concat(),
+            tile_path: merkle::proof(&tile_leaves, tile_index),
+        })
+    }
+
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get_tile_version(&self, tile_position: &Point) -> u64 {
+        self.tiles.get(tile_position).map_or(0, |tile| tile.version)
+    }
+
+    pub fn get_dirty_tiles(&self, since_version: u64) -> Vec<Point> {
+        let mut tile_positions = self
+            .tiles
+            .iter()
+            .filter(|(_, tile)| tile.version > since_version)
+            .map(|(tile_position, _)| *tile_position)
+            .collect::<Vec<_>>();
+
+        tile_positions.sort_by_key(|(x, y)| (*y, *x));
+
+        tile_positions
+    }
+
+    pub fn get_events(
+        &self,
+        previous: &Canvas,
+        since_version: u64,
+        height: u64,
+    ) -> Vec<CanvasEvent> {
+        // Only tiles touched since the previous canvas can have changed.
+        let mut events = vec![];
+        for (tile_x, tile_y) in self.get_dirty_tiles(since_version) {
+            for index in 0..TILE_SIZE * TILE_SIZE {
+                let point = (
+                    tile_x * TILE_SIZE + index % TILE_SIZE,
+                    tile_y * TILE_SIZE + index / TILE_SIZE,
+                );
+
+                // Locks and such don't change what viewers see.
+                let pixel = self.get_visible_pixel(&point);
+                let previous_pixel = previous.get_visible_pixel(&point);
+                let shown = |pixel: Option<&Pixel>| {
+                    pixel.map(|pixel| (pixel.color, pixel.transaction_hash))
+                };
+                if shown(pixel) == shown(previous_pixel) {
+                    continue;
+                }
+
+                // Anything that shows again without being painted now was reverted.
+                events.push(match pixel {
+                    Some(pixel) if pixel.height == height => CanvasEvent::PixelPainted {
+                        point,
+                        color: pixel.color,
+                        painter: pixel.owner.clone(),
+                        height,
+                    },
+                    pixel => CanvasEvent::PixelReverted {
+                        point,
+                        color: pixel.map(|pixel| pixel.color),
+                        height,
+                    },
+                });
+            }
+        }
+
+        events
+    }
+
+    pub fn render_tile(&self, tile_position: &Point) -> Vec<u8> {
+        let tile = self.tiles.get(tile_position);
+
+        (0..(TILE_SIZE * TILE_SIZE) as usize)
+            .flat_map(|index| {
+                let (red, green, blue) = tile
+                    .and_then(|tile| tile.get_visible_pixel(index))
+                    .map_or(Color::White, |pixel| pixel.color)
+                    .to_rgb();
+
+                [red, green, blue, 0xFF]
+            })
+            .collect()
+    }
+
+    // A tile of `scale` by `scale` tiles, shrunk by keeping the top-left pixel of each square.
+    pub fn render_scaled_tile(&self, (tile_x, tile_y): &Point, scale: i32) -> Vec<u8> {
+        if scale == 1 {
+            return self.render_tile(&(*tile_x, *tile_y));
+        }
+
+        (0..TILE_SIZE * TILE_SIZE)
+            .flat_map(|index| {
+                let position = (
+                    (tile_x * TILE_SIZE + index % TILE_SIZE) * scale,
+                    (tile_y * TILE_SIZE + index / TILE_SIZE) * scale,
+                );
+                let (red, green, blue) = self
+                    .get_visible_pixel(&position)
+                    .map_or(Color::White, |pixel| pixel.color)
+                    .to_rgb();
+
+                [red, green, blue, 0xFF]
+            })
+            .collect()
+    }
+
+    pub fn get_scaled_tile_version(&self, (tile_x, tile_y): &Point, scale: i32) -> u64 {
+        (0..scale * scale)
+            .map(|index| {
+                self.get_tile_version(&(
+                    tile_x * scale + index % scale,
+                    tile_y * scale + index / scale,
+                ))
+            })
+            .max()
+            .unwrap_or_default()
+    }
+
+    #[cfg(feature = "image")]
+    pub fn write_scaled_tile_png<W: std::io::Write>(
+        &self,
+        tile_position: &Point,
+        scale: i32,
+        writer: W,
+    ) -> Result<()> {
+        use anyhow::Context;
+        use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+
+        PngEncoder::new(writer)
+            .write_image(
+                &self.render_scaled_tile(tile_position, scale),
+                TILE_SIZE as u32,
+                TILE_SIZE as u32,
+                ColorType::Rgba8,
+            )
+            .context("Failed to encode the tile as PNG.")
+    }
+
+    fn get_tile_leaves(&self) -> (Vec<Point>, Vec<Hash>) {
+        // One leaf per stored tile, in row order, each committing to the root of its own pixels.
+        let mut tile_positions = self.tiles.keys().copied().collect::<Vec<_>>();
+        tile_positions.sort_by_key(|(x, y)| (*y, *x));
+
+        let tile_leaves = tile_positions
+            .iter()
+            .map(|tile_position| {
+                hash_tile(
+                    tile_position,
+                    &merkle::root(&self.tiles[tile_position].get_layer_leaves()),
+                )
+            })
+            .collect();
+
+        (tile_positions, tile_leaves)
+    }
+
+    fn get_pixel_mut(&mut self, position: &Point) -> Option<&mut Pixel> {
+        let tile = self.tiles.get_mut(&get_tile_position(position))?;
+        let pixel = tile.pixels[get_tile_index(position)].as_mut()?;
+
+        // Handing out a mutable pixel counts as changing its tile.
+        self.version += 1;
+        tile.version = self.version;
+
+        Some(pixel)
+    }
+
+    fn set_pixel(&mut self, layer: Layer, position: Point, pixel: Option<Pixel>) {
+        let tile = self.tiles.entry(get_tile_position(&position)).or_default();
+        tile.get_layer_mut(layer)[get_tile_index(&position)] = pixel;
+
+        self.version += 1;
+        tile.version = self.version;
+    }
+
+    fn blank(&mut self, position: &Point, width: i32, height: i32) {
+        let (x, y) = *position;
+
+        // Only visit the stored tiles that overlap the rectangle.
+        let (first_tile_x, first_tile_y) = get_tile_position(&(x, y));
+        let (last_tile_x, last_tile_y) = get_tile_position(&(x + width - 1, y + height - 1));
+        for tile_y in first_tile_y..=last_tile_y {
+            for tile_x in first_tile_x..=last_tile_x {
+                let tile = match self.tiles.get_mut(&(tile_x, tile_y)) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+
+                let mut owners = vec![];
+                for pixel_y in
+                    (tile_y * TILE_SIZE).max(y)..((tile_y + 1) * TILE_SIZE).min(y + height)
+                {
+                    for pixel_x in
+                        (tile_x * TILE_SIZE).max(x)..((tile_x + 1) * TILE_SIZE).min(x + width)
+                    {
+                        let index = get_tile_index(&(pixel_x, pixel_y));
+
+                        let pixel = tile.pixels[index].take();
+                        owners.extend(
+                            pixel
+                                .as_ref()
+                                .and_then(Pixel::get_permanent)
+                                .and_then(|pixel| pixel.owner.clone()),
+                        );
+
+                        for pixels in tile.overlays.values_mut() {
+                            pixels[index] = None;
+                        }
+                    }
+                }
+
+                self.version += 1;
+                tile.version = self.version;
+
+                // Blanked pixels are lost to their owners, but nobody painted over them.
+                for owner in owners {
+                    if let Some(stats) = self.ownership.get_mut(&owner) {
+                        stats.pixels_owned -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn expire_rentals(&mut self, height: u64) {
+        let heights = self
+            .expirations
+            .range(..=height)
+            .map(|(expires_at, _)| *expires_at)
+            .collect::<Vec<_>>();
+
+        for expires_at in heights {
+            for (layer, position) in self.expirations.remove(&expires_at).unwrap() {
+                // The rental may have been painted over already.
+                let previous = match self.get_layer_pixel(layer, &position) {
+                    Some(pixel) if pixel.expires_at == Some(expires_at) => pixel.previous.clone(),
+                    _ => continue,
+                };
+
+                self.set_pixel(layer, position, previous.map(|previous| *previous));
+            }
+        }
+    }
+
+    fn update_ownership(&mut self, previous_owner: Option<Address>, owner: Option<Address>) {
+        if let Some(owner) = &owner {
+            self.ownership
+                .entry(owner.clone())
+                .or_default()
+                .pixels_painted += 1;
+        }
+
+        // Painting over our own pixel doesn't change what we own.
+        if previous_owner == owner {
+            return;
+        }
+
+        if let Some(previous_owner) = previous_owner {
+            let stats = self.ownership.entry(previous_owner).or_default();
+            stats.pixels_owned -= 1;
+            stats.pixels_overwritten += 1;
+        }
+
+        if let Some(owner) = owner {
+            self.ownership.entry(owner).or_default().pixels_owned += 1;
+        }
+    }
+
+    fn get_owner(&self, transaction: &Transaction) -> Option<Address> {
+        transaction.get_signer().map(|public_key| {
+            Address::from_public_key_hash_for(&self.chain_params, public_key.calculate_hash())
+        })
+    }
+}
+
+pub fn verify_pixel_proof(canvas_root: &CanvasHash, proof: &PixelProof) -> bool {
+    // Layers are full trees, so the path must follow the bits of the pixel index. Above that, the
+    // base layer is always the leftmost one.
+    let index = get_tile_index(&proof.position);
+    let levels = (TILE_SIZE * TILE_SIZE).ilog2() as usize;
+    if proof.pixel_path.len() < levels
+        || proof
+            .pixel_path
+            .iter()
+            .enumerate()
+            .any(|(level, (is_left, _))| match level < levels {
+                true => *is_left != ((index >> level) & 1 == 1),
+                false => *is_left,
+            })
+    {
+        return false;
+    }
+
+    let leaf = proof
+        .pixel
+        .as_ref()
+        .map_or(merkle::EMPTY_ROOT, Pixel::calculate_hash);
+    let tile_root = merkle::root_from_proof(&leaf, &proof.pixel_path);
+
+    // The tile leaf commits to its position, which ties the proof to this pixel.
+    let tile_leaf = hash_tile(&get_tile_position(&proof.position), &tile_root);
+
+    merkle::root_from_proof(&tile_leaf, &proof.tile_path) == *canvas_root.as_bytes()
+}
+
+fn hash_tile(tile_position: &Point, tile_root: &Hash) -> Hash {
+    let mut hasher = Sha3_256::default();
+    hasher.update(bincode::serialize(tile_position).unwrap());
+    hasher.update(tile_root);
+
+    hasher.finalize().into()
+}
+
+fn get_tile_position(position: &Point) -> Point {
+    let (x, y) = *position;
+
+    (x.div_euclid(TILE_SIZE), y.div_euclid(TILE_SIZE))
+}
+
+fn get_tile_index(position: &Point) -> usize {
+    let (x, y) = *position;
+
+    (y.rem_euclid(TILE_SIZE) * TILE_SIZE + x.rem_euclid(TILE_SIZE)) as usize
+}
@@ -0,0 +1,174 @@
+use crate::{
+    blockchain::PubKeyHash,
+    transaction::{Credits, Point},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainParams {
+    pub network: Network,
+    pub address_version: u8, // First byte of every address, so networks can't be mixed up.
+    pub bech32_hrp: &'static str,
+    pub magic: [u8; 4], // Starts every message between peers, so networks can't talk to each other.
+    pub pixel_price: Credits, // Credits burned into every painted pixel.
+    pub pixel_contest_window: u64, // Blocks during which overwriting a pixel doubles its price.
+    pub pixel_royalty_percent: Credits, // Share of the price paid to the previous painter, zero to disable.
+    pub pixel_lock_price: Credits, // Credits per block for keeping a pixel from being painted over.
+    pub max_pixel_lock_duration: u64,
+    pub pixel_rental_price: Credits, // Credits per block for showing a color that then goes away.
+    pub max_pixel_rental_duration: u64,
+    pub max_pixels_per_transaction: usize,
+    pub max_pixels_per_block_per_address: usize, // Keeps a single painter from taking up whole blocks.
+    pub max_block_size: usize, // Serialized bytes of all the transactions in a block.
+    pub memo_fee_per_byte: Credits, // Tax a transaction must pay for the memos on its pixels.
+    pub canvas_width: i32,
+    pub canvas_height: i32,
+    pub canvas_expansions: &'static [(u64, i32, i32)], // Height at which the canvas grows, and its new size.
+    pub moderators: Vec<PubKeyHash>, // Keys that can blank or freeze parts of the canvas.
+    pub moderation_threshold: usize, // Moderators that must sign a moderation together.
+}
+
+impl ChainParams {
+    pub fn mainnet() -> Self {
+        Self {
+            network: Network::Mainnet,
+            address_version: 0x00,
+            bech32_hrp: "pc",
+            magic: *b"PLCN",
+            pixel_price: 10,
+            pixel_contest_window: 100,
+            pixel_royalty_percent: 10,
+            pixel_lock_price: 5,
+            max_pixel_lock_duration: 1000,
+            pixel_rental_price: 1,
+            max_pixel_rental_duration: 1000,
+            max_pixels_per_transaction: 1024,
+            max_pixels_per_block_per_address: 4096,
+            max_block_size: 1_000_000,
+            memo_fee_per_byte: 1,
+            canvas_width: 1000,
+            canvas_height: 1000,
+            canvas_expansions: &[(50_000, 2000, 1000), (100_000, 2000, 2000)],
+            moderators: vec![],
+            moderation_threshold: 1,
+        }
+    }
+
+    pub fn testnet() -> Self {
+        Self {
+            network: Network::Testnet,
+            address_version: 0x6F,
+            bech32_hrp: "tpc",
+            magic: *b"PLCT",
+            pixel_price: 10,
+            pixel_contest_window: 100,
+            pixel_royalty_percent: 10,
+            pixel_lock_price: 5,
+            max_pixel_lock_duration: 1000,
+            pixel_rental_price: 1,
+            max_pixel_rental_duration: 1000,
+            max_pixels_per_transaction: 1024,
+            max_pixels_per_block_per_address: 4096,
+            max_block_size: 1_000_000,
+            memo_fee_per_byte: 1,
+            canvas_width: 1000,
+            canvas_height: 1000,
+            canvas_expansions: &[(50_000, 2000, 1000), (100_000, 2000, 2000)],
+            moderators: vec![],
+            moderation_threshold: 1,
+        }
+    }
+
+    pub fn regtest() -> Self {
+        Self {
+            network: Network::Regtest,
+            address_version: 0xC4,
+            bech32_hrp: "pcrt",
+            magic: *b"PLCR",
+            pixel_price: 1,
+            pixel_contest_window: 10,
+            pixel_royalty_percent: 10,
+            pixel_lock_price: 1,
+            max_pixel_lock_duration: 100,
+            pixel_rental_price: 1,
+            max_pixel_rental_duration: 100,
+            max_pixels_per_transaction: 1024,
+            max_pixels_per_block_per_address: 4096,
+            max_block_size: 1_000_000,
+            memo_fee_per_byte: 1,
+            canvas_width: 100,
+            canvas_height: 100,
+            canvas_expansions: &[(10, 200, 100), (20, 200, 200)],
+            moderators: vec![],
+            moderation_threshold: 1,
+        }
+    }
+
+    pub fn for_network(network: Network) -> Self {
+        match network {
+            Network::Mainnet => Self::mainnet(),
+            Network::Testnet => Self::testnet(),
+            Network::Regtest => Self::regtest(),
+        }
+    }
+
+    pub fn get_canvas_size(&self, height: u64) -> (i32, i32) {
+        // Expansions are sorted by height, so the last one that already happened wins.
+        self.canvas_expansions
+            .iter()
+            .take_while(|(expansion_height, ..)| *expansion_height <= height)
+            .last()
+            .map_or(
+                (self.canvas_width, self.canvas_height),
+                |(_, width, height)| (*width, *height),
+            )
+    }
+
+    pub fn is_on_canvas(&self, position: &Point, height: u64) -> bool {
+        let (x, y) = *position;
+        let (width, canvas_height) = self.get_canvas_size(height);
+
+        (0..width).contains(&x) && (0..canvas_height).contains(&y)
+    }
+
+    pub fn get_pixel_lock_price(&self, duration: u64) -> Credits {
+        self.pixel_lock_price
+            .saturating_mul(duration.try_into().unwrap_or(Credits::MAX))
+    }
+
+    pub fn get_pixel_rental_price(&self, duration: u64) -> Credits {
+        self.pixel_rental_price
+            .saturating_mul(duration.try_into().unwrap_or(Credits::MAX))
+    }
+
+    pub fn get_memo_fee(&self, size: usize) -> Credits {
+        self.memo_fee_per_byte
+            .saturating_mul(size.try_into().unwrap_or(Credits::MAX))
+    }
+
+    pub fn from_address_version(address_version: u8) -> Option<Self> {
+        [Network::Mainnet, Network::Testnet, Network::Regtest]
+            .into_iter()
+            .map(Self::for_network)
+            .find(|chain_params| chain_params.address_version == address_version)
+    }
+
+    pub fn from_bech32_hrp(bech32_hrp: &str) -> Option<Self> {
+        [Network::Mainnet, Network::Testnet, Network::Regtest]
+            .into_iter()
+            .map(Self::for_network)
+            .find(|chain_params| chain_params.bech32_hrp == bech32_hrp)
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
@@ -1,7 +1,8 @@
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
 #[repr(u8)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Color {
     White,
     Black,
@@ -39,3 +40,108 @@ pub enum Color {
     LightYellow,
     LightIndigo,
 }
+
+impl Color {
+    // Every color of the palette, in the order of their discriminants.
+    pub const PALETTE: [Color; 32] = [
+        Self::White,
+        Self::Black,
+        Self::Gray,
+        Self::Brown,
+        Self::Blue,
+        Self::Green,
+        Self::Teal,
+        Self::Pink,
+        Self::Purple,
+        Self::Red,
+        Self::Yellow,
+        Self::Indigo,
+        Self::DarkGray,
+        Self::DarkBrown,
+        Self::DarkBlue,
+        Self::DarkGreen,
+        Self::DarkTeal,
+        Self::DarkPink,
+        Self::DarkPurple,
+        Self::DarkRed,
+        Self::DarkYellow,
+        Self::DarkIndigo,
+        Self::LightGray,
+        Self::LightBrown,
+        Self::LightBlue,
+        Self::LightGreen,
+        Self::LightTeal,
+        Self::LightPink,
+        Self::LightPurple,
+        Self::LightRed,
+        Self::LightYellow,
+        Self::LightIndigo,
+    ];
+
+    pub fn from_rgb_nearest(rgb: (u8, u8, u8)) -> Self {
+        *Self::PALETTE
+            .iter()
+            .min_by_key(|color| perceptual_distance(color.to_rgb(), rgb))
+            .unwrap()
+    }
+
+    pub fn to_rgb(&self) -> (u8, u8, u8) {
+        match self {
+            Self::White => (0xFF, 0xFF, 0xFF),
+            Self::Black => (0x00, 0x00, 0x00),
+            Self::Gray => (0x89, 0x8D, 0x90),
+            Self::Brown => (0x9C, 0x69, 0x26),
+            Self::Blue => (0x36, 0x90, 0xEA),
+            Self::Green => (0x00, 0xA3, 0x68),
+            Self::Teal => (0x00, 0x9E, 0xAA),
+            Self::Pink => (0xFF, 0x99, 0xAA),
+            Self::Purple => (0xB4, 0x4A, 0xC0),
+            Self::Red => (0xFF, 0x45, 0x00),
+            Self::Yellow => (0xFF, 0xD6, 0x35),
+            Self::Indigo => (0x6A, 0x5C, 0xFF),
+            Self::DarkGray => (0x51, 0x52, 0x52),
+            Self::DarkBrown => (0x6D, 0x48, 0x2F),
+            Self::DarkBlue => (0x24, 0x50, 0xA4),
+            Self::DarkGreen => (0x00, 0x75, 0x6F),
+            Self::DarkTeal => (0x00, 0x6D, 0x74),
+            Self::DarkPink => (0xDE, 0x10, 0x7F),
+            Self::DarkPurple => (0x81, 0x1E, 0x9F),
+            Self::DarkRed => (0xBE, 0x00, 0x39),
+            Self::DarkYellow => (0xFF, 0xA8, 0x00),
+            Self::DarkIndigo => (0x49, 0x3A, 0xC1),
+            Self::LightGray => (0xD4, 0xD7, 0xD9),
+            Self::LightBrown => (0xFF, 0xB4, 0x70),
+            Self::LightBlue => (0x51, 0xE9, 0xF4),
+            Self::LightGreen => (0x7E, 0xED, 0x56),
+            Self::LightTeal => (0x00, 0xCC, 0xC0),
+            Self::LightPink => (0xFC, 0xC8, 0xD8),
+            Self::LightPurple => (0xE4, 0xAB, 0xFF),
+            Self::LightRed => (0xFF, 0x8A, 0x8A),
+            Self::LightYellow => (0xFF, 0xF8, 0xB8),
+            Self::LightIndigo => (0x94, 0xB3, 0xFF),
+        }
+    }
+}
+
+impl TryFrom<u8> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(index: u8) -> Result<Self> {
+        Self::PALETTE
+            .get(index as usize)
+            .copied()
+            .context("Color isn't part of the palette.")
+    }
+}
+
+// Squared "redmean" distance, a cheap approximation of how different two colors look.
+fn perceptual_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i64 {
+    let red_mean = (a.0 as i64 + b.0 as i64) / 2;
+    let red = a.0 as i64 - b.0 as i64;
+    let green = a.1 as i64 - b.1 as i64;
+    let blue = a.2 as i64 - b.2 as i64;
+
+    (((512 + red_mean) * red * red) >> 8)
+        + 4 * green * green
+        + (((767 - red_mean) * blue * blue) >> 8)
+}
@@ -0,0 +1,346 @@
+// Indexes the node doesn't need itself, so a block and canvas explorer can be built on it without a
+// database of its own:
+//
+//     address history   transactions paying or spending from an address, with how much
+//     pixel history     every paint of a pixel, on any layer, with who painted it
+//     block stats       `BlockStats` of every block
+//     balances          confirmed credits of every address, for the richest addresses and how
+//                       credits are spread between them
+//
+// An explorer follows the main chain with `sync`, which takes back blocks that left it and indexes
+// the ones that joined since the last call. Everything is paged newest first, and pages are at
+// most `MAX_PAGE_SIZE` long.
+
+use crate::{
+    address::Address,
+    block::{Block, BlockStats},
+    blockchain::{BlockHash, Blockchain, TxHash},
+    chain_params::ChainParams,
+    color::Color,
+    transaction::{Credits, Layer, Point, TransactionInput, TransactionOutput},
+};
+use anyhow::Result;
+use std::collections::HashMap;
+
+pub const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressEntry {
+    pub transaction_hash: TxHash,
+    pub height: u64,
+    pub received: Credits, // Paid to the address.
+    pub sent: Credits,     // Spent from outputs paying the address.
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PixelEntry {
+    pub transaction_hash: TxHash,
+    pub height: u64,
+    pub color: Color,
+    pub layer: Layer,
+    pub painter: Option<Address>, // Reward transactions aren't signed by anyone.
+    pub value: Credits,
+}
+
+// Addresses with at least `min_balance` credits, and less than ten times that.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceBucket {
+    pub min_balance: Credits,
+    pub address_count: usize,
+    pub total_balance: Credits,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize, // Items there are on all the pages.
+}
+
+#[derive(Debug)]
+struct IndexedBlock {
+    block_hash: BlockHash,
+    stats: BlockStats,
+    addresses: Vec<Address>, // Whose history the block added to, so it can be taken back.
+    points: Vec<Point>,
+    outputs: Vec<(TxHash, u32)>,
+}
+
+#[derive(Debug)]
+pub struct Explorer {
+    chain_params: ChainParams,
+    blocks: Vec<IndexedBlock>, // The main chain as of the last sync, by height.
+    outputs: HashMap<(TxHash, u32), (Address, Credits)>, // Paid to addresses, to find who spends them.
+    address_history: HashMap<Address, Vec<AddressEntry>>, // Oldest first, here and below.
+    pixel_history: HashMap<Point, Vec<PixelEntry>>,
+    balances: HashMap<Address, Credits>, // Only addresses with credits left.
+}
+
+impl Explorer {
+    pub fn new(chain_params: ChainParams) -> Self {
+        Self {
+            chain_params,
+            blocks: vec![],
+            outputs: HashMap::new(),
+            address_history: HashMap::new(),
+            pixel_history: HashMap::new(),
+            balances: HashMap::new(),
+        }
+    }
+
+    pub fn sync(&mut self, blockchain: &Blockchain) -> Result<()> {
+        let main_chain = blockchain.get_main_chain();
+
+        // Heights are indices on the main chain, so the first block still on it ends the rewind.
+        while let Some(indexed_block) = self.blocks.last() {
+            let height = self.blocks.len() - 1;
+            if main_chain
+                .get(height)
+                .is_some_and(|block| block.calculate_hash() == indexed_block.block_hash)
+            {
+                break;
+            }
+
+            self.disconnect_last_block();
+        }
+
+        for block in &main_chain[self.blocks.len()..] {
+            self.connect_block(block)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn get_address_history(
+        &self,
+        address: &Address,
+        offset: usize,
+        limit: usize,
+    ) -> Page<AddressEntry> {
+        let history = self.address_history.get(address).map(Vec::as_slice);
+
+        get_page(history.unwrap_or_default(), offset, limit)
+    }
+
+    pub fn get_pixel_history(
+        &self,
+        point: &Point,
+        offset: usize,
+        limit: usize,
+    ) -> Page<PixelEntry> {
+        let history = self.pixel_history.get(point).map(Vec::as_slice);
+
+        get_page(history.unwrap_or_default(), offset, limit)
+    }
+
+    pub fn get_block_stats(&self, offset: usize, limit: usize) -> Page<(BlockHash, BlockStats)> {
+        let stats = self
+            .blocks
+            .iter()
+            .map(|indexed_block| (indexed_block.block_hash, indexed_block.stats.clone()))
+            .collect::<Vec<_>>();
+
+        get_page(&stats, offset, limit)
+    }
+
+    // The richest first, and by address when they have as much.
+    pub fn get_rich_list(&self, count: usize) -> Vec<(Address, Credits)> {
+        let mut balances = self
+            .balances
+            .iter()
+            .map(|(address, balance)| (address.clone(), *balance))
+            .collect::<Vec<_>>();
+        balances.sort_by(|(address, balance), (other_address, other_balance)| {
+            other_balance
+                .cmp(balance)
+                .then_with(|| address.as_str().cmp(other_address.as_str()))
+        });
+        balances.truncate(count);
+
+        balances
+    }
+
+    // Buckets from 1 credit up by powers of ten, until the richest address.
+    pub fn get_balance_distribution(&self) -> Vec<BalanceBucket> {
+        let mut buckets: Vec<BalanceBucket> = vec![];
+        for balance in self.balances.values() {
+            let index = balance.max(&1).ilog10() as usize;
+            while buckets.len() <= index {
+                buckets.push(BalanceBucket {
+                    min_balance: 10i64.pow(buckets.len() as u32),
+                    address_count: 0,
+                    total_balance: 0,
+                });
+            }
+
+            buckets[index].address_count += 1;
+            buckets[index].total_balance += balance;
+        }
+
+        buckets
+    }
+
+    fn connect_block(&mut self, block: &Block) -> Result<()> {
+        let height = block.get_block_height()?;
+        let mut indexed_block = IndexedBlock {
+            block_hash: block.calculate_hash(),
+            stats: block.get_stats()?,
+            addresses: vec![],
+            points: vec![],
+            outputs: vec![],
+        };
+
+        for transaction in block.get_transactions() {
+            let transaction_hash = *transaction.get_hash();
+
+            // Each address gets one entry per transaction, however many inputs and outputs it has.
+            let mut totals: Vec<(Address, Credits, Credits)> = vec![];
+            let mut add_to_totals = |address: &Address, received, sent| match totals
+                .iter_mut()
+                .find(|(known, ..)| known == address)
+            {
+                Some(total) => {
+                    total.1 += received;
+                    total.2 += sent;
+                }
+                None => totals.push((address.clone(), received, sent)),
+            };
+
+            for input in transaction.get_inputs() {
+                if let TransactionInput::FromOutput {
+                    transaction_hash,
+                    output_index,
+                    ..
+                } = input
+                {
+                    if let Some((address, value)) =
+                        self.outputs.get(&(*transaction_hash, *output_index))
+                    {
+                        add_to_totals(address, 0, *value);
+                    }
+                }
+            }
+
+            let painter = transaction.get_signer().map(|public_key| {
+                Address::from_public_key_hash_for(&self.chain_params, public_key.calculate_hash())
+            });
+            for (output_index, output) in transaction.get_outputs().iter().enumerate() {
+                match output {
+                    TransactionOutput::ToInput {
+                        value,
+                        public_key_address,
+                    } => {
+                        add_to_totals(public_key_address, *value, 0);
+
+                        let output = (transaction_hash, output_index as u32);
+                        self.outputs
+                            .insert(output, (public_key_address.clone(), *value));
+                        indexed_block.outputs.push(output);
+                    }
+                    TransactionOutput::ToPixel {
+                        value,
+                        position,
+                        color,
+                        layer,
+                        ..
+                    } => {
+                        self.pixel_history
+                            .entry(*position)
+                            .or_default()
+                            .push(PixelEntry {
+                                transaction_hash,
+                                height,
+                                color: *color,
+                                layer: *layer,
+                                painter: painter.clone(),
+                                value: *value,
+                            });
+                        indexed_block.points.push(*position);
+                    }
+                    TransactionOutput::LockPixel { .. } | TransactionOutput::Moderate { .. } => {}
+                }
+            }
+
+            for (address, received, sent) in totals {
+                self.add_to_balance(&address, received - sent);
+                self.address_history
+                    .entry(address.clone())
+                    .or_default()
+                    .push(AddressEntry {
+                        transaction_hash,
+                        height,
+                        received,
+                        sent,
+                    });
+                if !indexed_block.addresses.contains(&address) {
+                    indexed_block.addresses.push(address);
+                }
+            }
+        }
+
+        indexed_block.points.sort();
+        indexed_block.points.dedup();
+        self.blocks.push(indexed_block);
+
+        Ok(())
+    }
+
+    fn disconnect_last_block(&mut self) {
+        let Some(indexed_block) = self.blocks.pop() else {
+            return;
+        };
+        let height = self.blocks.len() as u64;
+
+        // Entries are added in the order of their blocks, so the last block's are at the end.
+        for address in indexed_block.addresses {
+            if let Some(history) = self.address_history.get_mut(&address) {
+                let mut change = 0;
+                while history.last().is_some_and(|entry| entry.height >= height) {
+                    let entry = history.pop().unwrap();
+                    change += entry.sent - entry.received;
+                }
+                if history.is_empty() {
+                    self.address_history.remove(&address);
+                }
+                self.add_to_balance(&address, change);
+            }
+        }
+        for point in indexed_block.points {
+            if let Some(history) = self.pixel_history.get_mut(&point) {
+                while history.last().is_some_and(|entry| entry.height >= height) {
+                    history.pop();
+                }
+                if history.is_empty() {
+                    self.pixel_history.remove(&point);
+                }
+            }
+        }
+        for output in indexed_block.outputs {
+            self.outputs.remove(&output);
+        }
+    }
+
+    fn add_to_balance(&mut self, address: &Address, change: Credits) {
+        let balance = self.balances.entry(address.clone()).or_default();
+        *balance += change;
+        if *balance == 0 {
+            self.balances.remove(address);
+        }
+    }
+}
+
+fn get_page<T: Clone>(entries: &[T], offset: usize, limit: usize) -> Page<T> {
+    Page {
+        items: entries
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit.min(MAX_PAGE_SIZE))
+            .cloned()
+            .collect(),
+        total: entries.len(),
+    }
+}
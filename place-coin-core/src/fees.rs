@@ -0,0 +1,88 @@
+use crate::{block::Block, blockchain::TxHash};
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+
+pub const MAX_FEE_SAMPLES: usize = 1000;
+const SUCCESS_THRESHOLD: f64 = 0.85; // Share of transactions that must confirm in time for a rate to be good enough.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeEstimate {
+    pub tax_rate: f64,   // Tax per byte.
+    pub confidence: f64, // Share of transactions paying at least this much that confirmed in time.
+    pub samples: usize,  // Transactions paying at least this much that the estimate is based on.
+}
+
+#[derive(Debug, Default)]
+pub struct FeeEstimator {
+    pending: HashMap<TxHash, (f64, u64)>, // Tax rate, and the height of the first block it could be in.
+    samples: VecDeque<(f64, u64)>, // Tax rate, and how many blocks it waited to be confirmed.
+}
+
+impl FeeEstimator {
+    pub fn track(&mut self, transaction_hash: TxHash, tax_rate: f64, height: u64) {
+        self.pending.insert(transaction_hash, (tax_rate, height));
+    }
+
+    pub fn connect_block(&mut self, block: &Block) -> Result<()> {
+        let height = block.get_block_height()?;
+        for transaction in block.get_transactions() {
+            if let Some((tax_rate, first_height)) = self.pending.remove(transaction.get_hash()) {
+                // Getting into the first block it could be in counts as waiting one block.
+                self.samples
+                    .push_back((tax_rate, height.saturating_sub(first_height) + 1));
+            }
+        }
+
+        while self.samples.len() > MAX_FEE_SAMPLES {
+            self.samples.pop_front();
+        }
+
+        Ok(())
+    }
+
+    pub fn retain_pending(&mut self, mut is_pending: impl FnMut(&TxHash) -> bool) {
+        // Transactions that left without being confirmed say nothing about how long it takes.
+        self.pending
+            .retain(|transaction_hash, _| is_pending(transaction_hash));
+    }
+
+    pub fn estimate_fee(&self, target_blocks: u64) -> Option<FeeEstimate> {
+        let mut samples = self.samples.iter().copied().collect::<Vec<_>>();
+        samples.sort_by(|(rate, _), (other_rate, _)| other_rate.total_cmp(rate));
+
+        // Go down from the highest rate, and stop at the first one that doesn't confirm in time often
+        // enough.
+        let mut estimate = None;
+        let mut confirmed = 0;
+        for (index, (tax_rate, blocks_waited)) in samples.iter().enumerate() {
+            if *blocks_waited <= target_blocks {
+                confirmed += 1;
+            }
+
+            // Only look at a rate once every sample paying it is counted.
+            if samples
+                .get(index + 1)
+                .is_some_and(|(next_rate, _)| next_rate == tax_rate)
+            {
+                continue;
+            }
+
+            let confidence = confirmed as f64 / (index + 1) as f64;
+            if confidence < SUCCESS_THRESHOLD {
+                break;
+            }
+
+            estimate = Some(FeeEstimate {
+                tax_rate: *tax_rate,
+                confidence,
+                samples: index + 1,
+            });
+        }
+
+        estimate
+    }
+
+    pub fn get_sample_count(&self) -> usize {
+        self.samples.len()
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    address::PrivateKey,
+    blockchain::{Blockchain, TxHash},
+    color::Color,
+    transaction::{Credits, Point},
+};
+use anyhow::{bail, Result};
+
+pub const MAX_PIXELS_PER_TRANSACTION: usize = 256;
+
+pub fn quantize_rgba(rgba: &[u8], width: u32, offset: Point) -> Vec<(Point, Color)> {
+    // Mostly transparent pixels are left alone, so artwork doesn't need to be rectangular.
+    let (offset_x, offset_y) = offset;
+
+    rgba.chunks_exact(4)
+        .enumerate()
+        .filter(|(_, pixel)| pixel[3] >= 0x80)
+        .map(|(index, pixel)| {
+            let x = offset_x + (index as u32 % width) as i32;
+            let y = offset_y + (index as u32 / width) as i32;
+
+            (
+                (x, y),
+                Color::from_rgb_nearest((pixel[0], pixel[1], pixel[2])),
+            )
+        })
+        .collect()
+}
+
+pub fn import_pixels(
+    blockchain: &mut Blockchain,
+    sender_private_key: &PrivateKey,
+    pixels: &[(Point, Color)],
+    budget: Credits,
+    tax: Credits,
+) -> Result<Vec<TxHash>> {
+    let canvas = blockchain.get_pending_canvas()?;
+    let chain_params = blockchain.get_chain_params().clone();
+    let height = blockchain.get_next_block_height()?;
+
+    // Only paint what isn't already there.
+    let mut pixels = pixels
+        .iter()
+        .filter(|(position, color)| {
+            chain_params.is_on_canvas(position, height)
+                && canvas.get_pixel(position).map(|pixel| pixel.color) != Some(*color)
+        })
+        .copied()
+        .collect::<Vec<_>>();
+
+    pixels.sort_by_key(|((x, y), _)| (*y, *x));
+    pixels.dedup_by_key(|(position, _)| *position);
+
+    let mut transaction_hashes = vec![];
+    let mut spent = 0;
+    let batch_size = MAX_PIXELS_PER_TRANSACTION.min(chain_params.max_pixels_per_transaction);
+    for batch in pixels.chunks(batch_size) {
+        // Cut the batch short once the budget runs out.
+        let height = blockchain.get_next_block_height()?;
+        let mut cost = tax;
+        let mut affordable = 0;
+        for (position, _) in batch {
+            let price = canvas.get_pixel_price(position, height);
+            if spent + cost + price > budget {
+                break;
+            }
+
+            cost += price;
+            affordable += 1;
+        }
+
+        if affordable == 0 {
+            break;
+        }
+
+        blockchain.create_pixel_transaction(sender_private_key, &batch[..affordable], tax)?;
+        transaction_hashes.push(
+            *blockchain
+                .get_pending_transactions()
+                .last()
+                .unwrap()
+                .get_hash(),
+        );
+
+        spent += cost;
+        if affordable < batch.len() {
+            break;
+        }
+    }
+
+    if transaction_hashes.is_empty() && !pixels.is_empty() {
+        bail!("Budget can't pay for a single pixel.")
+    }
+
+    Ok(transaction_hashes)
+}
+
+#[cfg(feature = "image")]
+pub fn import_image(
+    blockchain: &mut Blockchain,
+    sender_private_key: &PrivateKey,
+    path: impl AsRef<std::path::Path>,
+    offset: Point,
+    budget: Credits,
+    tax: Credits,
+) -> Result<Vec<TxHash>> {
+    use anyhow::Context;
+
+    let image = image::open(path)
+        .context("Failed to open the image.")?
+        .to_rgba8();
+    let pixels = quantize_rgba(image.as_raw(), image.width(), offset);
+
+    import_pixels(blockchain, sender_private_key, &pixels, budget, tax)
+}
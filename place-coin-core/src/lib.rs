@@ -1,146 +1,3187 @@
-pub mod address;
-pub mod block;
-pub mod blockchain;
-pub mod color;
-pub mod signature;
-pub mod transaction;
-
-pub const CURRENT_VERSION: u32 = 1;
-
-#[cfg(test)]
-mod tests {
-    use crate::{
-        address::Address,
-        blockchain::{Blockchain, Hash},
-        signature::Signature,
-        transaction::{Credits, TransactionOutput},
-    };
-    use anyhow::Result;
-    use rand_core::OsRng;
-    use rayon::iter::ParallelIterator;
-
-    const MY_NODE_ID: Hash = [1; 32];
-    const OTHER_NODE_ID: Hash = [8; 32];
-
-    fn setup_blockchain() -> Result<Blockchain> {
-        let miner_address = Address::from_private_key(&MY_NODE_ID);
-        let mut blockchain = Blockchain::new(miner_address);
-
-        // Mine a block.
-        blockchain.mine()?;
-
-        // Validate block mined.
-        assert!(blockchain.get_last_block().is_valid(&blockchain));
-
-        Ok(blockchain)
-    }
-
-    #[test]
-    fn test_simple_transaction() -> Result<()> {
-        let mut blockchain = setup_blockchain()?;
-
-        // Create a new transaction to transfer some credits.
-        let sender_address = Address::from_private_key(&MY_NODE_ID);
-        let recipient_address = Address::from_private_key(&OTHER_NODE_ID);
-
-        blockchain.create_simple_transaction(
-            &MY_NODE_ID,
-            &sender_address,
-            &recipient_address,
-            &OTHER_NODE_ID,
-            99,
-            5,
-        )?;
-
-        // Mine to commit the new block.
-        blockchain.mine()?;
-
-        // Validate block mined.
-        assert!(blockchain.get_last_block().is_valid(&blockchain));
-
-        // Check if credits were transferred.
-        let credits = blockchain.get_peer_credits(&recipient_address);
-        assert_eq!(credits, 99, "Peer did not receive the credits.");
-
-        let total_unspent_credits = blockchain
-            .get_all_unspent_outputs()
-            .map(|(_, output, _)| match output {
-                TransactionOutput::ToInput { value, .. } => *value,
-                TransactionOutput::ToPixel { .. } => 0,
-            })
-            .sum::<Credits>();
-
-        assert_eq!(total_unspent_credits, 2000);
-
-        Ok(())
-    }
-
-    // #[test]
-    // fn test_paint_pixel() -> Result<()> {
-    //     let mut blockchain = setup_blockchain()?;
-
-    //     // Paint a pixel.
-    //     blockchain.add_transaction(Transaction::Pixel {
-    //         sender: MY_NODE_ID,
-    //         position: (0, 0),
-    //         color: (0xFF, 0, 0),
-    //     })?;
-
-    //     // Mine again.
-    //     let proof = blockchain.proof_of_work();
-
-    //     // Reward ourselves.
-    //     blockchain.add_transaction(Transaction::Peer {
-    //         sender: 0,
-    //         recipient: MY_NODE_ID,
-    //         amount: 1000,
-    //     })?;
-
-    //     // Forge a new block.
-    //     let last_block = blockchain.get_last_block();
-    //     let previous_hash = last_block.calculate_hash();
-    //     let _block = blockchain.new_block(proof, previous_hash);
-
-    //     let credits = blockchain.get_peer_credits(&MY_NODE_ID, None);
-    //     assert_eq!(credits, 1999);
-
-    //     Ok(())
-    // }
-
-    #[test]
-    fn test_signature_by_changing_one_byte() -> Result<()> {
-        let mut hash: Hash = [0xFF; 32];
-
-        let private_key = k256::SecretKey::random(&mut OsRng);
-        let private_key = private_key.to_be_bytes();
-        let private_key = private_key.as_slice();
-        let signature_1 = Signature::new(private_key.try_into()?, &hash);
-
-        hash[16] = 0;
-
-        let signature_2 = Signature::new(private_key.try_into()?, &hash);
-
-        assert_ne!(signature_1, signature_2);
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_signature_with_different_private_keys() -> Result<()> {
-        let hash: Hash = [0xFF; 32];
-
-        let private_key_1 = k256::SecretKey::random(&mut OsRng);
-        let private_key_1 = private_key_1.to_be_bytes();
-        let private_key_1 = private_key_1.as_slice();
-        let signature_1 = Signature::new(private_key_1.try_into()?, &hash);
-
-        let private_key_2 = k256::SecretKey::random(&mut OsRng);
-        let private_key_2 = private_key_2.to_be_bytes();
-        let private_key_2 = private_key_2.as_slice();
-        let signature_2 = Signature::new(private_key_2.try_into()?, &hash);
-
-        assert_ne!(signature_1, signature_2);
-
-        Ok(())
-    }
-}
+pub mod address;
+pub mod analytics;
+mod bech32;
+pub mod block;
+pub mod blockchain;
+pub mod bloom;
+pub mod canvas;
+pub mod chain_params;
+pub mod color;
+pub mod explorer;
+pub mod fees;
+pub mod import;
+pub mod light_client;
+pub mod mempool;
+mod merkle;
+pub mod signature;
+pub mod transaction;
+pub mod wallet;
+
+pub const CURRENT_VERSION: u32 = 1;
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        address::{Address, AddressEncoding, PrivateKey, PublicKey},
+        analytics::HeatMap,
+        bech32,
+        blockchain::{
+            BlockHash, Blockchain, ChainEvent, Hash, HashHex, PaintOptions, Proof, PubKeyHash,
+            TipStatus, TxHash,
+        },
+        bloom::BloomFilter,
+        canvas::{verify_pixel_proof, Canvas, CanvasEvent, OwnershipStats, TILE_SIZE},
+        chain_params::ChainParams,
+        color::Color,
+        explorer::{BalanceBucket, Explorer},
+        import::{import_pixels, quantize_rgba, MAX_PIXELS_PER_TRANSACTION},
+        light_client::LightClient,
+        mempool::{Rejection, DEFAULT_MAX_MEMPOOL_SIZE},
+        signature::{
+            get_signature_checks, sign_message, sign_transaction, sign_transaction_inputs,
+            verify_message, verify_signatures_batch, verify_transaction_input, Ecdsa,
+            MessageSignature, Signature, SignatureScheme,
+        },
+        transaction::{
+            Credits, ModerationAction, RawTransaction, Transaction, TransactionInput,
+            TransactionOutput, BASE_LAYER, CURRENT_TRANSACTION_VERSION, MAX_COINBASE_DATA_SIZE,
+            MAX_MEMO_SIZE, SCHNORR_TRANSACTION_VERSION,
+        },
+        wallet::{Wallet, WalletEvent},
+    };
+    use anyhow::Result;
+    use chrono::{Duration, Utc};
+    use rand_core::OsRng;
+    use rayon::iter::ParallelIterator;
+    use std::sync::atomic::AtomicBool;
+
+    const MY_NODE_SEED: &str = "my node";
+    const OTHER_NODE_SEED: &str = "other node";
+
+    fn node_key(seed: &str) -> PrivateKey {
+        Wallet::deterministic(seed).get_private_keys()[0].clone()
+    }
+
+    fn setup_blockchain() -> Result<Blockchain> {
+        let miner_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let mut blockchain = Blockchain::new(miner_address);
+
+        // Mine a block.
+        blockchain.mine()?;
+
+        // Validate block mined.
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        Ok(blockchain)
+    }
+
+    #[test]
+    fn test_simple_transaction() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        // Create a new transaction to transfer some credits.
+        let sender_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+
+        // Only the sender key can spend the sender outputs.
+        assert!(blockchain
+            .create_simple_transaction(
+                &sender_address,
+                &recipient_address,
+                &node_key(OTHER_NODE_SEED),
+                99,
+                5,
+            )
+            .is_err());
+
+        blockchain.create_simple_transaction(
+            &sender_address,
+            &recipient_address,
+            &node_key(MY_NODE_SEED),
+            99,
+            5,
+        )?;
+
+        // Mine to commit the new block.
+        blockchain.mine()?;
+
+        // Validate block mined.
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        // Check if credits were transferred.
+        let credits = blockchain.get_peer_credits(&recipient_address);
+        assert_eq!(credits, 99, "Peer did not receive the credits.");
+
+        let total_unspent_credits = blockchain
+            .get_all_unspent_outputs()
+            .map(|(_, output, _)| match output {
+                TransactionOutput::ToInput { value, .. } => *value,
+                TransactionOutput::ToPixel { .. }
+                | TransactionOutput::LockPixel { .. }
+                | TransactionOutput::Moderate { .. } => 0,
+            })
+            .sum::<Credits>();
+
+        assert_eq!(total_unspent_credits, 2000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sweep() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        // Move all the mined credits to another address.
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        wallet.sweep(&mut blockchain, &recipient_address, 10)?;
+
+        // Sweeping again must fail since the outputs are already pending.
+        assert!(wallet
+            .sweep(&mut blockchain, &recipient_address, 10)
+            .is_err());
+
+        blockchain.mine()?;
+
+        let credits = blockchain.get_peer_credits(&recipient_address);
+        assert_eq!(credits, 990, "Peer did not receive the swept credits.");
+
+        let miner_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let credits = blockchain.get_peer_credits(&miner_address);
+        assert_eq!(credits, 1010, "Miner did not receive the sweep tax.");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_many() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let first_address = Address::from_private_key(&node_key("first recipient"));
+        let second_address = Address::from_private_key(&node_key("second recipient"));
+
+        wallet.send_many(
+            &mut blockchain,
+            vec![(first_address.clone(), 100), (second_address.clone(), 200)],
+            5,
+        )?;
+
+        blockchain.mine()?;
+
+        // Both recipients are paid by a single transaction.
+        let block = blockchain.get_last_block();
+        assert_eq!(block.get_transactions().len(), 2);
+        assert_eq!(blockchain.get_peer_credits(&first_address), 100);
+        assert_eq!(blockchain.get_peer_credits(&second_address), 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_bumping() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        wallet.send_many(&mut blockchain, vec![(recipient_address.clone(), 100)], 1)?;
+
+        // Replace the pending transaction with one paying a higher tax.
+        let transaction_hash = *blockchain.get_pending_transactions()[0].get_hash();
+        assert!(wallet
+            .bump_fee(&mut blockchain, &transaction_hash, 1)
+            .is_err());
+        wallet.bump_fee(&mut blockchain, &transaction_hash, 20)?;
+
+        let pending_transactions = blockchain.get_pending_transactions();
+        assert_eq!(pending_transactions.len(), 1);
+        assert_eq!(pending_transactions[0].get_balance(), 20);
+
+        // Pull the replacement in with a high tax child spending its change.
+        let parent_hash = *pending_transactions[0].get_hash();
+        wallet.bump_fee_with_child(&mut blockchain, &parent_hash, 30)?;
+        assert_eq!(blockchain.get_pending_transactions().len(), 2);
+
+        blockchain.mine()?;
+
+        let miner_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        assert_eq!(blockchain.get_peer_credits(&recipient_address), 100);
+        assert_eq!(blockchain.get_peer_credits(&miner_address), 1900);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_wallet() {
+        let wallet_1 = Wallet::deterministic(MY_NODE_SEED);
+        let wallet_2 = Wallet::deterministic(MY_NODE_SEED);
+        assert_eq!(wallet_1.get_addresses(), wallet_2.get_addresses());
+
+        let other_wallet = Wallet::deterministic(OTHER_NODE_SEED);
+        assert_ne!(wallet_1.get_addresses(), other_wallet.get_addresses());
+    }
+
+    #[test]
+    fn test_wallet_events() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let mut sender_wallet = Wallet::deterministic(MY_NODE_SEED);
+        let sender_events = sender_wallet.subscribe();
+
+        let mut recipient_wallet = Wallet::deterministic(OTHER_NODE_SEED);
+        let recipient_events = recipient_wallet.subscribe();
+
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        sender_wallet.sweep(&mut blockchain, &recipient_address, 10)?;
+        blockchain.mine()?;
+
+        let block = blockchain.get_last_block();
+        sender_wallet.connect_block(&blockchain, block)?;
+        recipient_wallet.connect_block(&blockchain, block)?;
+
+        let sweep_hash = *block.get_transactions()[0].get_hash();
+        assert!(sender_events.try_iter().any(|event| event
+            == WalletEvent::TransactionConfirmed {
+                transaction_hash: sweep_hash,
+                height: 2,
+            }));
+
+        assert_eq!(
+            recipient_events.try_iter().collect::<Vec<_>>(),
+            vec![WalletEvent::PaymentReceived {
+                transaction_hash: sweep_hash,
+                public_key_address: recipient_address,
+                value: 990,
+            }]
+        );
+
+        Ok(())
+    }
+
+    // #[test]
+    // fn test_paint_pixel() -> Result<()> {
+    //     let mut blockchain = setup_blockchain()?;
+
+    //     // Paint a pixel.
+    //     blockchain.add_transaction(Transaction::Pixel {
+    //         sender: MY_NODE_ID,
+    //         position: (0, 0),
+    //         color: (0xFF, 0, 0),
+    //     })?;
+
+    //     // Mine again.
+    //     let proof = blockchain.proof_of_work();
+
+    //     // Reward ourselves.
+    //     blockchain.add_transaction(Transaction::Peer {
+    //         sender: 0,
+    //         recipient: MY_NODE_ID,
+    //         amount: 1000,
+    //     })?;
+
+    //     // Forge a new block.
+    //     let last_block = blockchain.get_last_block();
+    //     let previous_hash = last_block.calculate_hash();
+    //     let _block = blockchain.new_block(proof, previous_hash);
+
+    //     let credits = blockchain.get_peer_credits(&MY_NODE_ID, None);
+    //     assert_eq!(credits, 1999);
+
+    //     Ok(())
+    // }
+
+    #[test]
+    fn test_signature_by_changing_one_byte() -> Result<()> {
+        let mut hash: Hash = [0xFF; 32];
+
+        let private_key = k256::SecretKey::random(&mut OsRng);
+        let private_key = private_key.to_bytes();
+        let private_key = private_key.as_slice();
+        let signature_1 = Signature::new(&private_key.try_into()?, &hash);
+
+        hash[16] = 0;
+
+        let signature_2 = Signature::new(&private_key.try_into()?, &hash);
+
+        assert_ne!(signature_1, signature_2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_with_different_private_keys() -> Result<()> {
+        let hash: Hash = [0xFF; 32];
+
+        let private_key_1 = k256::SecretKey::random(&mut OsRng);
+        let private_key_1 = private_key_1.to_bytes();
+        let private_key_1 = private_key_1.as_slice();
+        let signature_1 = Signature::new(&private_key_1.try_into()?, &hash);
+
+        let private_key_2 = k256::SecretKey::random(&mut OsRng);
+        let private_key_2 = private_key_2.to_bytes();
+        let private_key_2 = private_key_2.as_slice();
+        let signature_2 = Signature::new(&private_key_2.try_into()?, &hash);
+
+        assert_ne!(signature_1, signature_2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        wallet.send_many(&mut blockchain, vec![(recipient_address.clone(), 100)], 1)?;
+        let parent_hash = *blockchain.get_pending_transactions()[0].get_hash();
+        wallet.bump_fee_with_child(&mut blockchain, &parent_hash, 30)?;
+        let child_hash = *blockchain.get_pending_transactions()[1].get_hash();
+
+        let mempool = blockchain.get_mempool();
+        assert_eq!(mempool.len(), 2);
+        assert_eq!(
+            mempool
+                .iter_by_fee()
+                .map(|transaction| *transaction.get_hash())
+                .collect::<Vec<_>>(),
+            vec![child_hash, parent_hash]
+        );
+
+        // Spending the parent's input again conflicts with it, and with the child through it.
+        let input = mempool.get(&parent_hash).unwrap().get_inputs()[0].clone();
+        let outputs = vec![TransactionOutput::ToInput {
+            value: 10,
+            public_key_address: recipient_address,
+        }];
+        let double_spend = Transaction::try_new(&blockchain, vec![input], outputs, 0)?;
+        assert_eq!(mempool.conflicts(&double_spend), vec![parent_hash]);
+        assert_eq!(
+            mempool.with_descendants(&[parent_hash]),
+            vec![parent_hash, child_hash]
+        );
+
+        blockchain.mine()?;
+        assert!(blockchain.get_mempool().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_eviction() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.mine()?;
+
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let pay = |value| {
+            vec![TransactionOutput::ToInput {
+                value,
+                public_key_address: recipient_address.clone(),
+            }]
+        };
+
+        let cheap = spend_first_output(&blockchain, MY_NODE_SEED, pay(999))?;
+        let (cheap_hash, cheap_inputs) = (*cheap.get_hash(), cheap.get_inputs().to_vec());
+        blockchain.new_transaction(cheap)?;
+
+        // Only one of the two fits, so the one paying less per byte goes.
+        let expensive = spend_first_output(&blockchain, MY_NODE_SEED, pay(950))?;
+        let expensive_hash = *expensive.get_hash();
+        blockchain
+            .set_max_mempool_size(blockchain.get_mempool().get_size() + expensive.get_size() - 1);
+        blockchain.new_transaction(expensive)?;
+
+        let mempool = blockchain.get_mempool();
+        assert!(mempool.contains(&expensive_hash));
+        assert!(!mempool.contains(&cheap_hash));
+        assert!(mempool.get_size() <= mempool.get_max_size());
+        assert!(mempool.was_recently_evicted(&cheap_hash));
+
+        // Evicted transactions aren't taken back right away, even once there is room again.
+        blockchain.set_max_mempool_size(DEFAULT_MAX_MEMPOOL_SIZE);
+        let cheap = Transaction::try_new(&blockchain, cheap_inputs, pay(999), 0)?;
+        assert_eq!(*cheap.get_hash(), cheap_hash);
+        assert!(blockchain.new_transaction(cheap).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_tax_rate() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.set_min_tax_rate(1);
+
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let pay = |value| {
+            vec![TransactionOutput::ToInput {
+                value,
+                public_key_address: recipient_address.clone(),
+            }]
+        };
+
+        let free = spend_first_output(&blockchain, MY_NODE_SEED, pay(1000))?;
+        assert!(blockchain.new_transaction(free).is_err());
+
+        let paying = spend_first_output(&blockchain, MY_NODE_SEED, pay(500))?;
+        assert!(paying.get_balance() >= paying.get_size() as Credits);
+        blockchain.new_transaction(paying)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_orphan_transactions() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let other_key = node_key(OTHER_NODE_SEED);
+        let other_public_key = PublicKey::from_private_key(&other_key);
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&other_key);
+
+        let parent = spend_first_output(
+            &blockchain,
+            MY_NODE_SEED,
+            vec![TransactionOutput::ToInput {
+                value: 1000,
+                public_key_address: other_address,
+            }],
+        )?;
+        let parent_hash = *parent.get_hash();
+
+        // The child shows up first, so it has to wait for its parent.
+        let mut child = RawTransaction {
+            version: CURRENT_TRANSACTION_VERSION,
+            inputs: vec![TransactionInput::FromOutput {
+                transaction_hash: parent_hash,
+                output_index: 0,
+                public_key: other_public_key,
+                signature: Signature::unsigned(),
+            }],
+            outputs: vec![TransactionOutput::ToInput {
+                value: 1000,
+                public_key_address: my_address,
+            }],
+            lock_time: 0,
+        };
+        sign_transaction_inputs(&mut child, &other_key);
+        let child_hash = child.calculate_hash();
+        blockchain.submit_raw_transaction(child)?;
+        assert!(blockchain.get_orphans().contains(&child_hash));
+        assert!(blockchain.find_pending_transaction(&child_hash).is_none());
+
+        blockchain.new_transaction(parent)?;
+        assert!(blockchain.get_orphans().is_empty());
+        assert!(blockchain.find_pending_transaction(&child_hash).is_some());
+
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+        assert!(blockchain.find_transaction(&child_hash).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_assembly_order() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.mine()?;
+
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        wallet.send_many(&mut blockchain, vec![(recipient_address.clone(), 100)], 1)?;
+        let parent_hash = *blockchain.get_pending_transactions()[0].get_hash();
+        wallet.bump_fee_with_child(&mut blockchain, &parent_hash, 30)?;
+        let child_hash = *blockchain.get_pending_transactions()[1].get_hash();
+
+        let unrelated = spend_first_output(
+            &blockchain,
+            MY_NODE_SEED,
+            vec![TransactionOutput::ToInput {
+                value: 990,
+                public_key_address: recipient_address,
+            }],
+        )?;
+        let unrelated_hash = *unrelated.get_hash();
+        blockchain.new_transaction(unrelated)?;
+
+        // The child pays the most, but can't go in before its parent.
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+        let hashes = blockchain
+            .get_last_block()
+            .get_transactions()
+            .iter()
+            .map(|transaction| *transaction.get_hash())
+            .collect::<Vec<_>>();
+        assert_eq!(hashes[..3], [parent_hash, child_hash, unrelated_hash]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_size_limit() -> Result<()> {
+        let my_key = node_key(MY_NODE_SEED);
+        let my_address = Address::from_private_key(&my_key);
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let to_address = |address: &Address, value| TransactionOutput::ToInput {
+            value,
+            public_key_address: address.clone(),
+        };
+
+        // Sizes only depend on the shape of a transaction, so measure them on a chain without a limit.
+        let unlimited = setup_blockchain()?;
+        let reward_size = unlimited.get_last_block().get_transactions()[0].get_size();
+        let transaction_size =
+            spend_first_output(&unlimited, MY_NODE_SEED, vec![to_address(&my_address, 1)])?
+                .get_size();
+
+        // Room for the reward and two transactions, but not three. Signatures vary a little in size.
+        let chain_params = ChainParams {
+            max_block_size: reward_size + 2 * transaction_size + transaction_size / 2,
+            ..ChainParams::mainnet()
+        };
+        let mut blockchain = Blockchain::with_chain_params(chain_params, my_address.clone());
+        blockchain.mine()?;
+        blockchain.mine()?;
+
+        let parent = spend_first_output(
+            &blockchain,
+            MY_NODE_SEED,
+            vec![to_address(&my_address, 999)],
+        )?;
+        let parent_hash = *parent.get_hash();
+        blockchain.new_transaction(parent)?;
+
+        let unrelated = spend_first_output(
+            &blockchain,
+            MY_NODE_SEED,
+            vec![to_address(&other_address, 980)],
+        )?;
+        let unrelated_hash = *unrelated.get_hash();
+        blockchain.new_transaction(unrelated)?;
+
+        let mut child = RawTransaction {
+            version: CURRENT_TRANSACTION_VERSION,
+            inputs: vec![TransactionInput::FromOutput {
+                transaction_hash: parent_hash,
+                output_index: 0,
+                public_key: PublicKey::from_private_key(&my_key),
+                signature: Signature::unsigned(),
+            }],
+            outputs: vec![to_address(&other_address, 949)],
+            lock_time: 0,
+        };
+        sign_transaction_inputs(&mut child, &my_key);
+        let child = Transaction::from_raw(&blockchain, child)?;
+        let child_hash = *child.get_hash();
+        blockchain.new_transaction(child)?;
+
+        // Together the parent and its child pay more per byte than the unrelated transaction.
+        blockchain.mine()?;
+        let block = blockchain.get_last_block();
+        assert!(block.is_valid(&blockchain));
+        let hashes = block
+            .get_transactions()
+            .iter()
+            .map(|transaction| *transaction.get_hash())
+            .collect::<Vec<_>>();
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes[..2], [parent_hash, child_hash]);
+        assert_eq!(blockchain.get_pending_transactions().len(), 1);
+        assert_eq!(
+            blockchain.get_pending_transactions()[0].get_hash(),
+            &unrelated_hash
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_submit_raw_block() -> Result<()> {
+        let blockchain = setup_blockchain()?;
+        let mut other_blockchain =
+            Blockchain::new(Address::from_private_key(&node_key(OTHER_NODE_SEED)));
+
+        // Only blocks that pay the miner more than it's owed are turned away.
+        let mut raw_block = blockchain.get_last_block().to_raw();
+        if let TransactionInput::FromReward { value, .. } = &mut raw_block.transactions[0].inputs[0]
+        {
+            *value += 1;
+        }
+        assert!(other_blockchain.submit_raw_block(raw_block).is_err());
+
+        let block_hash = other_blockchain.submit_raw_block(blockchain.get_last_block().to_raw())?;
+        assert_eq!(block_hash, *blockchain.get_last_block_hash());
+        assert_eq!(
+            other_blockchain.get_last_block_hash(),
+            blockchain.get_last_block_hash()
+        );
+
+        // It can't be connected twice.
+        assert!(other_blockchain
+            .submit_raw_block(blockchain.get_last_block().to_raw())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_filter() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let sender_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &sender_address,
+            &recipient_address,
+            &node_key(MY_NODE_SEED),
+            99,
+            5,
+        )?;
+        blockchain.mine()?;
+        let block = blockchain.get_last_block();
+        let payment_hash = *block.get_transactions()[0].get_hash();
+
+        // A light client watching the recipient only gets the payment, not the reward.
+        let mut filter = BloomFilter::new(10, 0.000_001, 7);
+        filter.insert_address(&recipient_address);
+        let mut merkle_block = block.get_merkle_block(&mut filter);
+        assert!(merkle_block.verify());
+        assert_eq!(
+            merkle_block.header.calculate_hash(),
+            *blockchain.get_last_block_hash()
+        );
+        assert_eq!(merkle_block.transactions.len(), 1);
+        assert_eq!(
+            merkle_block.transactions[0].0.calculate_hash(),
+            payment_hash
+        );
+
+        // The outputs it was paid with are watched from then on.
+        let output_index = blockchain.get_spendable_outputs(&recipient_address)[0].1;
+        let mut expected_filter = BloomFilter::new(10, 0.000_001, 7);
+        expected_filter.insert_address(&recipient_address);
+        expected_filter.insert_outpoint(&payment_hash, output_index);
+        assert_eq!(filter, expected_filter);
+
+        // Transactions that aren't in the block don't verify.
+        merkle_block.transactions[0].0.lock_time += 1;
+        assert!(!merkle_block.verify());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_light_client() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((3, 4), Color::Red)], 0)?;
+        blockchain.mine()?;
+        blockchain.mine()?;
+
+        // Only headers are synced, starting from the same genesis block.
+        let mut light_client = LightClient::new(ChainParams::mainnet());
+        let headers = blockchain.get_headers_after(&light_client.get_locator(), 100);
+        assert_eq!(headers.len(), 3);
+
+        let mut bad_header = headers[0].clone();
+        bad_header.proof = (0..Proof::MAX)
+            .find(|proof| !Blockchain::validate_proof(&bad_header.proof, proof))
+            .unwrap();
+        assert!(light_client.add_header(bad_header).is_err());
+
+        for header in headers {
+            assert!(light_client.add_header(header)?);
+        }
+        assert_eq!(
+            light_client.get_best_hash(),
+            blockchain.get_last_block_hash()
+        );
+
+        // Pixels are checked against the canvas root of a block on the best chain.
+        let main_chain = blockchain.get_main_chain();
+        let paint_block_hash = main_chain[2].calculate_hash();
+        assert_eq!(light_client.get_confirmations(&paint_block_hash), Some(2));
+        let proof = Canvas::from_chain(&blockchain)?.prove_pixel(&(3, 4))?;
+        assert!(light_client.verify_pixel(&paint_block_hash, &proof));
+        assert!(!light_client.verify_pixel(&main_chain[1].calculate_hash(), &proof));
+
+        // And transactions against the transaction root.
+        let mut filter = BloomFilter::new(1, 0.000_001, 0);
+        filter.insert_address(&Address::from_private_key(&node_key(MY_NODE_SEED)));
+        let merkle_block = main_chain[2].get_merkle_block(&mut filter);
+        assert!(!merkle_block.transactions.is_empty());
+        assert!(light_client.verify_merkle_block(&merkle_block));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_locator() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let mut other_blockchain =
+            Blockchain::new(Address::from_private_key(&node_key(OTHER_NODE_SEED)));
+        other_blockchain.submit_raw_block(blockchain.get_last_block().to_raw())?;
+        for _ in 0..2 {
+            blockchain.mine()?;
+            other_blockchain.submit_raw_block(blockchain.get_last_block().to_raw())?;
+        }
+        for _ in 0..12 {
+            blockchain.mine()?;
+        }
+        other_blockchain.mine()?;
+
+        // Ten blocks back from the tip one by one, then further apart, down to the genesis block.
+        let main_chain = blockchain.get_main_chain();
+        let locator = blockchain.block_locator();
+        let heights = locator
+            .iter()
+            .map(|block_hash| blockchain.get_block(block_hash).unwrap().get_block_height())
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(heights, [15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 4, 0]);
+        assert_eq!(locator[11], main_chain[0].calculate_hash());
+
+        // The other chain branched off after the third block.
+        let other_locator = other_blockchain.block_locator();
+        assert_eq!(blockchain.find_fork_point(&other_locator), 3);
+        let headers = blockchain.get_headers_after(&other_locator, 5);
+        assert_eq!(headers.len(), 5);
+        assert_eq!(headers[0], main_chain[4].get_header());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_adjusted_time() -> Result<()> {
+        let blockchain = setup_blockchain()?;
+        let mut other_blockchain =
+            Blockchain::new(Address::from_private_key(&node_key(OTHER_NODE_SEED)));
+
+        // A block from three hours ahead is too far in the future for this node's clock...
+        let mut raw_block = blockchain.get_last_block().to_raw();
+        raw_block.timestamp = Utc::now() + Duration::hours(3);
+        assert!(other_blockchain
+            .submit_raw_block(raw_block.clone())
+            .is_err());
+
+        // ...but not once the network says its clock is an hour and a half behind.
+        other_blockchain.set_time_offset(90 * 60);
+        other_blockchain.submit_raw_block(raw_block)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_template() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 5)?;
+
+        let template = blockchain.build_block_template(&other_address)?;
+        assert_eq!(template.get_height(), 2);
+        assert_eq!(template.get_transactions().len(), 2);
+        assert_eq!(template.get_reward(), 1005);
+
+        // An external miner only needs the last proof to find the next one.
+        let proof = (0..Proof::MAX)
+            .find(|proof| Blockchain::validate_proof(template.get_last_proof(), proof))
+            .unwrap();
+        blockchain.submit_block(template.clone(), proof)?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+        assert!(blockchain.get_pending_transactions().is_empty());
+        assert_eq!(blockchain.get_peer_credits(&other_address), 1005);
+        assert_eq!(
+            blockchain.get_last_block().get_canvas_root(),
+            template.get_canvas_root()
+        );
+
+        // Once the chain moves on, the template can't be used anymore.
+        assert!(blockchain.submit_block(template, proof).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_by_fee() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        wallet.send_many(&mut blockchain, vec![(recipient_address.clone(), 100)], 10)?;
+        let original = &blockchain.get_pending_transactions()[0];
+        let original_hash = *original.get_hash();
+        let input = original.get_inputs()[0].clone();
+        let pay = |value| -> Result<RawTransaction> {
+            let mut raw_transaction = RawTransaction {
+                version: CURRENT_TRANSACTION_VERSION,
+                inputs: vec![input.clone()],
+                outputs: vec![TransactionOutput::ToInput {
+                    value,
+                    public_key_address: recipient_address.clone(),
+                }],
+                lock_time: 0,
+            };
+            wallet.sign_raw_transaction(&mut raw_transaction)?;
+
+            Ok(raw_transaction)
+        };
+
+        // Spending the same output again needs to pay more than what it replaces.
+        let cheaper = Transaction::from_raw(&blockchain, pay(995)?)?;
+        assert!(blockchain.new_transaction(cheaper).is_err());
+        assert!(blockchain
+            .find_pending_transaction(&original_hash)
+            .is_some());
+
+        let replacement = Transaction::from_raw(&blockchain, pay(950)?)?;
+        let replacement_hash = *replacement.get_hash();
+        blockchain.new_transaction(replacement)?;
+        assert!(blockchain
+            .find_pending_transaction(&original_hash)
+            .is_none());
+        assert!(blockchain
+            .find_pending_transaction(&replacement_hash)
+            .is_some());
+
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+        assert_eq!(blockchain.get_peer_credits(&recipient_address), 950);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_info() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        assert_eq!(blockchain.get_mempool().info().transaction_count, 0);
+
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        wallet.send_many(&mut blockchain, vec![(recipient_address, 100)], 1)?;
+        let parent_hash = *blockchain.get_pending_transactions()[0].get_hash();
+        wallet.bump_fee_with_child(&mut blockchain, &parent_hash, 30)?;
+
+        let mempool = blockchain.get_mempool();
+        let info = mempool.info();
+        assert_eq!(info.transaction_count, 2);
+        assert_eq!(info.size, mempool.get_size());
+        assert_eq!(info.total_tax, 31);
+        assert_eq!(info.age_counts, [2, 0, 0, 0]);
+        assert_eq!(
+            info.oldest_added_at.as_ref(),
+            mempool.get_added_at(&parent_hash)
+        );
+
+        // Percentiles go from the cheapest transaction up to the most expensive one.
+        let parent = mempool.get(&parent_hash).unwrap();
+        assert_eq!(
+            info.tax_rate_percentiles[0],
+            parent.get_balance() as f64 / parent.get_size() as f64
+        );
+        assert!(info.tax_rate_percentiles[4] > info.tax_rate_percentiles[0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_estimation() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        assert!(blockchain.estimate_fee(1).is_none());
+
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let mut tax_rates = vec![];
+        for tax in [50, 100] {
+            wallet.send_many(&mut blockchain, vec![(recipient_address.clone(), 10)], tax)?;
+            tax_rates.push(blockchain.get_pending_transactions()[0].get_tax_rate());
+            blockchain.mine()?;
+        }
+
+        // Both made it into the next block, so the cheaper one was already enough.
+        let estimate = blockchain.estimate_fee(1).unwrap();
+        assert_eq!(estimate.tax_rate, tax_rates[0]);
+        assert_eq!(estimate.confidence, 1.0);
+        assert_eq!(estimate.samples, 2);
+
+        let recipients = vec![(recipient_address.clone(), 10)];
+        let tax = wallet.estimate_tax(&blockchain, &recipients, 1)?;
+        assert!((40..=60).contains(&tax));
+
+        wallet.send_many_within(&mut blockchain, recipients, 1)?;
+        assert_eq!(blockchain.get_pending_transactions()[0].get_balance(), tax);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coinbase_data() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        assert!(blockchain
+            .set_coinbase_data(vec![0; MAX_COINBASE_DATA_SIZE + 1])
+            .is_err());
+
+        blockchain.set_coinbase_data(b"my pool".to_vec())?;
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        let reward_transaction = blockchain
+            .get_last_block()
+            .get_transactions()
+            .last()
+            .unwrap();
+        assert!(matches!(
+            &reward_transaction.get_inputs()[0],
+            TransactionInput::FromReward { data, .. } if data == b"my pool"
+        ));
+
+        // The data is part of the hash, so it can't be changed without it being noticed.
+        let mut raw_transaction = reward_transaction.to_raw();
+        if let TransactionInput::FromReward { data, .. } = &mut raw_transaction.inputs[0] {
+            data.push(b'!');
+        }
+        assert_ne!(
+            raw_transaction.calculate_hash(),
+            *reward_transaction.get_hash()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_transaction_input() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        wallet.sweep(&mut blockchain, &recipient_address, 10)?;
+
+        let transaction = blockchain.get_pending_transactions()[0].to_raw();
+        verify_transaction_input(&transaction, 0)?;
+
+        // Claiming the input was signed by someone else must fail.
+        let mut forged_transaction = transaction.clone();
+        if let TransactionInput::FromOutput { public_key, .. } = &mut forged_transaction.inputs[0] {
+            *public_key = PublicKey::from_private_key(&node_key(OTHER_NODE_SEED));
+        }
+        assert!(verify_transaction_input(&forged_transaction, 0).is_err());
+
+        // The signatures commit to the outputs, so none of them can be changed afterwards.
+        let mut redirected_transaction = transaction.clone();
+        redirected_transaction.outputs[0] = TransactionOutput::ToInput {
+            value: redirected_transaction.outputs[0].get_value(),
+            public_key_address: Address::from_private_key(&node_key("thief")),
+        };
+        assert!(verify_transaction_input(&redirected_transaction, 0).is_err());
+        redirected_transaction.outputs = transaction.outputs.clone();
+        redirected_transaction.lock_time += 1;
+        assert!(verify_transaction_input(&redirected_transaction, 0).is_err());
+
+        // The whole block must fail batch verification because of the forged input.
+        let valid_checks = get_signature_checks(&transaction)?;
+        let checks = [
+            valid_checks.clone(),
+            get_signature_checks(&forged_transaction)?,
+        ]
+        .concat();
+        assert!(verify_signatures_batch(&valid_checks).is_ok());
+        assert!(verify_signatures_batch(&checks).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_address_public_key_hash() -> Result<()> {
+        let public_key = PublicKey::from_private_key(&node_key(MY_NODE_SEED));
+        let address = Address::from_public_key(&public_key);
+
+        let public_key_hash = address.public_key_hash()?;
+        assert_eq!(public_key_hash, public_key.calculate_hash());
+        assert_eq!(Address::from_public_key_hash(public_key_hash), address);
+
+        assert!(Address::from_string("not an address")
+            .public_key_hash()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_testnet_addresses() -> Result<()> {
+        let testnet = ChainParams::testnet();
+        let private_key = node_key(MY_NODE_SEED);
+
+        let mainnet_address = Address::from_private_key(&private_key);
+        let testnet_address = Address::from_private_key_for(&testnet, &private_key);
+        assert_ne!(mainnet_address, testnet_address);
+        assert!(testnet_address.validate_for(&testnet));
+        assert!(!mainnet_address.validate_for(&testnet));
+
+        // A testnet chain refuses to mine or pay to mainnet addresses.
+        let mut blockchain =
+            Blockchain::with_chain_params(testnet.clone(), mainnet_address.clone());
+        assert!(blockchain.mine().is_err());
+
+        let mut blockchain = Blockchain::with_chain_params(testnet.clone(), testnet_address);
+        blockchain.mine()?;
+
+        let wallet = Wallet::for_chain(testnet, vec![private_key]);
+        assert!(wallet
+            .send_many(&mut blockchain, vec![(mainnet_address, 100)], 1)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bech32_addresses() -> Result<()> {
+        // Test vector from BIP-350.
+        let (hrp, version, payload) = bech32::decode(
+            "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+        )
+        .unwrap();
+        assert_eq!((hrp.as_str(), version, payload.len()), ("bc", 1, 40));
+
+        let base58_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let bech32_address = base58_address.to_encoding(AddressEncoding::Bech32)?;
+        assert!(bech32_address.as_str().starts_with("pc1"));
+
+        // Both encodings refer to the same address.
+        let parsed_address = Address::from_string(bech32_address.as_str());
+        assert!(parsed_address.validate());
+        assert_eq!(parsed_address.get_encoding(), Some(AddressEncoding::Bech32));
+        assert_eq!(parsed_address, base58_address);
+
+        // A single typo must be detected.
+        let mut typo = bech32_address.as_str().to_string();
+        let last = typo.pop().unwrap();
+        typo.push(if last == 'q' { 'p' } else { 'q' });
+        assert!(!Address::from_string(&typo).validate());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_address_deserialization() -> Result<()> {
+        let address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let encoded = bincode::serialize(&address)?;
+        assert_eq!(bincode::deserialize::<Address>(&encoded)?, address);
+
+        // Garbage must be rejected instead of flowing into transactions.
+        let encoded = bincode::serialize("not an address")?;
+        assert!(bincode::deserialize::<Address>(&encoded).is_err());
+        assert!(Address::parse("not an address").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schnorr_transactions() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let mut wallet = Wallet::deterministic(MY_NODE_SEED);
+        assert!(wallet.set_transaction_version(99).is_err());
+        wallet.set_transaction_version(SCHNORR_TRANSACTION_VERSION)?;
+
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        wallet.sweep(&mut blockchain, &recipient_address, 10)?;
+
+        let transaction = &blockchain.get_pending_transactions()[0];
+        assert_eq!(transaction.get_version(), SCHNORR_TRANSACTION_VERSION);
+
+        // The input only verifies under the scheme its transaction version selects.
+        let mut raw_transaction = transaction.to_raw();
+        verify_transaction_input(&raw_transaction, 0)?;
+        raw_transaction.version = CURRENT_TRANSACTION_VERSION;
+        assert!(verify_transaction_input(&raw_transaction, 0).is_err());
+
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+        assert_eq!(blockchain.get_peer_credits(&recipient_address), 990);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_high_s_signatures_are_rejected() -> Result<()> {
+        let public_key = PublicKey::from_private_key(&node_key(MY_NODE_SEED));
+        let hash: Hash = [0xAB; 32];
+
+        let signature = Ecdsa.sign(&node_key(MY_NODE_SEED), &hash);
+        Ecdsa.check_canonical(&signature)?;
+        Ecdsa.verify(&public_key, &hash, &signature)?;
+
+        // Flip s to n - s, which is the same signature in a different encoding.
+        let (r, s) = k256::ecdsa::Signature::from_slice(signature.as_bytes())?.split_scalars();
+        let high_s = k256::ecdsa::Signature::from_scalars(r, -*s)?;
+        let high_s = Signature::from_bytes(&high_s.to_bytes())?;
+
+        assert!(Ecdsa.check_canonical(&high_s).is_err());
+        assert!(Ecdsa.verify(&public_key, &hash, &high_s).is_err());
+        assert!(Signature::from_bytes(&[0; 63]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_message() -> Result<()> {
+        let address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let message = b"I painted the pixel at (10, 20).";
+
+        let signature = sign_message(&node_key(MY_NODE_SEED), message);
+        verify_message(&address, message, &signature)?;
+
+        // The signature must survive a round trip through its text encoding.
+        let signature = MessageSignature::from_base58(&signature.to_base58())?;
+        verify_message(&address, message, &signature)?;
+
+        // Other addresses and tampered messages must be rejected.
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        assert!(verify_message(&other_address, message, &signature).is_err());
+        assert!(verify_message(&address, b"I painted nothing.", &signature).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_private_keys_are_not_printed() -> Result<()> {
+        let private_key = node_key(MY_NODE_SEED);
+        let printed = format!("{private_key:?}");
+
+        assert_eq!(printed, "PrivateKey(..)");
+        assert_eq!(private_key, node_key(MY_NODE_SEED));
+        assert_ne!(private_key, node_key(OTHER_NODE_SEED));
+
+        let bytes = *private_key.as_bytes();
+        assert_eq!(PrivateKey::try_from(&bytes[..])?, private_key);
+        assert!(PrivateKey::try_from(&bytes[..31]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_and_parse() -> Result<()> {
+        let address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        assert_eq!(address.to_string().parse::<Address>()?, address);
+        assert!("not an address".parse::<Address>().is_err());
+
+        let bytes = bs58::decode(address.as_str()).into_vec()?;
+        assert_eq!(Address::try_from(bytes.as_slice())?, address);
+        assert!(Address::try_from(&bytes[1..]).is_err());
+
+        let hash: Hash = [0xAB; 32];
+        let hex = HashHex(hash).to_string();
+        assert_eq!(hex, "ab".repeat(32));
+        assert_eq!(hex.parse::<HashHex>()?, HashHex(hash));
+        assert_eq!(HashHex::try_from(&hash[..])?, HashHex(hash));
+        assert!("+b".repeat(32).parse::<HashHex>().is_err());
+        assert!(HashHex::try_from(&hash[..31]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vanity_address_search() -> Result<()> {
+        // Mainnet addresses always start with 1, so one more character is quick to find.
+        let cancel_flag = AtomicBool::new(false);
+        let (private_key, address) =
+            Address::search_prefix("1a", 2, &cancel_flag)?.expect("search wasn't cancelled");
+
+        assert!(address.as_str().starts_with("1a"));
+        assert_eq!(Address::from_private_key(&private_key), address);
+
+        // A cancelled search gives up, and impossible prefixes are refused.
+        let cancel_flag = AtomicBool::new(true);
+        assert!(Address::search_prefix("1a", 2, &cancel_flag)?.is_none());
+        assert!(Address::search_prefix("10", 2, &cancel_flag).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_newtypes() -> Result<()> {
+        let blockchain = setup_blockchain()?;
+        let block = blockchain.get_last_block();
+        let block_hash = block.calculate_hash();
+        let transaction_hash = *block.get_transactions()[0].get_hash();
+
+        // Hashes print as hex and parse back into the same kind of hash.
+        assert_eq!(block_hash.to_string().parse::<BlockHash>()?, block_hash);
+        assert_eq!(
+            transaction_hash.to_string().parse::<TxHash>()?,
+            transaction_hash
+        );
+        assert_eq!(
+            TxHash::try_from(transaction_hash.as_bytes().as_slice())?,
+            transaction_hash
+        );
+        assert!(format!("{block_hash:?}").starts_with("BlockHash("));
+
+        let public_key = PublicKey::from_private_key(&node_key(MY_NODE_SEED));
+        let public_key_hash = public_key.calculate_hash();
+        assert_eq!(Hash::from(public_key_hash), *public_key_hash.as_bytes());
+        assert_eq!(PubKeyHash::from_bytes([0; 32]), PubKeyHash::default());
+        assert!("00".parse::<PubKeyHash>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        assert!(blockchain.canvas()?.get_pixels().next().is_none());
+
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &my_address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            100,
+            0,
+        )?;
+        blockchain.mine()?;
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((1, 2), Color::Red)], 0)?;
+        blockchain.create_pixel_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &[((3, 4), Color::Blue), ((5, 6), Color::Blue)],
+            1,
+        )?;
+        blockchain.mine()?;
+
+        let canvas = blockchain.canvas()?;
+        let pixel = canvas.get_pixel(&(1, 2)).expect("pixel was painted");
+        assert_eq!(pixel.color, Color::Red);
+        assert_eq!(pixel.owner.as_ref(), Some(&my_address));
+        assert_eq!(pixel.height, 3);
+        assert!(canvas.get_pixel(&(0, 0)).is_none());
+        assert_eq!(canvas.get_pixels().count(), 3);
+
+        // Paints are paid at the pixel price, on top of the tax.
+        let price = blockchain.get_chain_params().pixel_price;
+        assert_eq!(
+            blockchain.get_peer_credits(&other_address),
+            100 - 2 * price - 1
+        );
+        assert!(blockchain
+            .create_pixel_transaction(&node_key(OTHER_NODE_SEED), &[], 0)
+            .is_err());
+
+        // The paint in the latest block wins.
+        blockchain.create_pixel_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &[((1, 2), Color::Green)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let pixel = blockchain.canvas()?.get_pixel(&(1, 2)).cloned().unwrap();
+        assert_eq!(pixel.color, Color::Green);
+        assert_eq!(pixel.owner, Some(other_address));
+        assert_eq!(pixel.height, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_palette_colors() -> Result<()> {
+        for (index, color) in Color::PALETTE.iter().enumerate() {
+            assert_eq!(Color::try_from(index as u8)?, *color);
+            assert_eq!(*color as usize, index);
+        }
+
+        assert!(Color::try_from(32).is_err());
+
+        // Colors outside the palette can't sneak in through serialized data either.
+        let encoded = bincode::serialize(&32u32)?;
+        assert!(bincode::deserialize::<Color>(&encoded).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas_bounds() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let chain_params = blockchain.get_chain_params().clone();
+        let (width, height) = (chain_params.canvas_width, chain_params.canvas_height);
+
+        assert!(chain_params.is_on_canvas(&(0, 0), 2));
+        assert!(chain_params.is_on_canvas(&(width - 1, height - 1), 2));
+
+        for position in [(-1, 0), (0, -1), (width, 0), (0, height)] {
+            assert!(blockchain
+                .create_pixel_transaction(&node_key(MY_NODE_SEED), &[(position, Color::Red)], 0)
+                .is_err());
+        }
+
+        assert!(blockchain.get_pending_transactions().is_empty());
+
+        Ok(())
+    }
+
+    fn spend_first_output(
+        blockchain: &Blockchain,
+        seed: &str,
+        outputs: Vec<TransactionOutput>,
+    ) -> Result<Transaction> {
+        let private_key = node_key(seed);
+        let public_key = PublicKey::from_private_key(&private_key);
+        let address = Address::from_private_key(&private_key);
+
+        let (transaction_hash, output_index, _) = blockchain
+            .get_spendable_outputs(&address)
+            .into_iter()
+            .max_by_key(|(_, _, value)| *value)
+            .unwrap();
+        let mut raw_transaction = RawTransaction {
+            version: CURRENT_TRANSACTION_VERSION,
+            inputs: vec![TransactionInput::FromOutput {
+                transaction_hash: *transaction_hash,
+                output_index,
+                public_key,
+                signature: Signature::unsigned(),
+            }],
+            outputs,
+            lock_time: 0,
+        };
+        sign_transaction_inputs(&mut raw_transaction, &private_key);
+
+        Transaction::from_raw(blockchain, raw_transaction)
+    }
+
+    #[test]
+    fn test_contested_pixel_prices() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let chain_params = blockchain.get_chain_params().clone();
+        let base_price = chain_params.pixel_price;
+
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &my_address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            500,
+            0,
+        )?;
+        blockchain.mine()?;
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((1, 2), Color::Red)], 0)?;
+        assert_eq!(blockchain.get_pixel_price(&(1, 2))?, 2 * base_price);
+        blockchain.mine()?;
+
+        // Overwriting a recent paint costs double, and more again while that paint is pending.
+        assert_eq!(blockchain.get_pixel_price(&(1, 2))?, 2 * base_price);
+        blockchain.create_pixel_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &[((1, 2), Color::Blue)],
+            0,
+        )?;
+        assert_eq!(blockchain.get_pixel_price(&(1, 2))?, 4 * base_price);
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        let canvas = blockchain.canvas()?;
+        let pixel = canvas.get_pixel(&(1, 2)).unwrap();
+        assert_eq!(pixel.price, 2 * base_price);
+
+        // Underpaying is rejected.
+        let outputs = vec![TransactionOutput::ToPixel {
+            value: base_price,
+            position: (1, 2),
+            color: Color::Green,
+            layer: BASE_LAYER,
+            faction: None,
+            rental: None,
+            memo: None,
+        }];
+        let underpaid = spend_first_output(&blockchain, MY_NODE_SEED, outputs)?;
+        assert!(blockchain.new_transaction(underpaid).is_err());
+
+        // The price decays back to base once the pixel is left alone.
+        let window = chain_params.pixel_contest_window;
+        assert_eq!(
+            canvas.get_pixel_price(&(1, 2), pixel.height + window),
+            base_price
+        );
+        assert_eq!(
+            canvas.get_pixel_price(&(1, 2), pixel.height + 10 * window),
+            base_price
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pixel_royalties() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let chain_params = blockchain.get_chain_params().clone();
+
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &my_address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            500,
+            0,
+        )?;
+        blockchain.mine()?;
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((1, 2), Color::Red)], 0)?;
+        blockchain.mine()?;
+
+        // Painting over someone else pays them their share of the price.
+        let price = blockchain.get_pixel_price(&(1, 2))?;
+        let royalty = price * chain_params.pixel_royalty_percent / 100;
+        assert!(royalty > 0);
+
+        blockchain.create_pixel_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &[((1, 2), Color::Blue)],
+            0,
+        )?;
+        let outputs = blockchain.get_pending_transactions()[0]
+            .get_outputs()
+            .to_vec();
+        assert!(outputs.iter().any(|output| matches!(
+            output,
+            TransactionOutput::ToPixel { value, .. } if *value == price - royalty
+        )));
+        assert!(outputs.iter().any(|output| matches!(
+            output,
+            TransactionOutput::ToInput { value, public_key_address }
+                if *value == royalty && *public_key_address == my_address
+        )));
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        // Skipping the royalty is rejected, but painting over yourself doesn't owe anything.
+        let price = blockchain.get_pixel_price(&(1, 2))?;
+        let outputs = vec![TransactionOutput::ToPixel {
+            value: price,
+            position: (1, 2),
+            color: Color::Green,
+            layer: BASE_LAYER,
+            faction: None,
+            rental: None,
+            memo: None,
+        }];
+        let transaction = spend_first_output(&blockchain, MY_NODE_SEED, outputs.clone())?;
+        assert!(blockchain.new_transaction(transaction).is_err());
+
+        let transaction = spend_first_output(&blockchain, OTHER_NODE_SEED, outputs)?;
+        blockchain.new_transaction(transaction)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pixel_locks() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &my_address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            500,
+            0,
+        )?;
+        blockchain.mine()?;
+
+        // Only painted pixels can be locked, and only by their painter.
+        assert!(blockchain
+            .create_pixel_lock_transaction(&node_key(MY_NODE_SEED), &(1, 2), 2, 0)
+            .is_err());
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((1, 2), Color::Red)], 0)?;
+        blockchain.mine()?;
+
+        assert!(blockchain
+            .create_pixel_lock_transaction(&node_key(OTHER_NODE_SEED), &(1, 2), 2, 0)
+            .is_err());
+        assert!(blockchain
+            .create_pixel_lock_transaction(&node_key(MY_NODE_SEED), &(1, 2), 0, 0)
+            .is_err());
+
+        blockchain.create_pixel_lock_transaction(&node_key(MY_NODE_SEED), &(1, 2), 2, 0)?;
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        // The lock was mined at height 4, so the pixel is frozen for heights 4 and 5.
+        let canvas = blockchain.canvas()?;
+        assert_eq!(canvas.get_pixel(&(1, 2)).unwrap().locked_until, 6);
+        assert!(blockchain
+            .create_pixel_transaction(&node_key(OTHER_NODE_SEED), &[((1, 2), Color::Blue)], 0)
+            .is_err());
+
+        blockchain.mine()?;
+        blockchain.create_pixel_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &[((1, 2), Color::Blue)],
+            0,
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_canvas() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Red), ((2, 1), Color::Black)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let canvas = blockchain.canvas()?;
+        let framebuffer = canvas.render();
+        let width = canvas.get_width() as usize;
+        assert_eq!(framebuffer.len(), width * canvas.get_height() as usize * 4);
+
+        let (red, green, blue) = Color::Red.to_rgb();
+        assert_eq!(framebuffer[..4], [red, green, blue, 0xFF]);
+        assert_eq!(framebuffer[4..8], [0xFF; 4]);
+
+        let offset = (width + 2) * 4;
+        assert_eq!(framebuffer[offset..offset + 4], [0, 0, 0, 0xFF]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas_at_height() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 0)?;
+        blockchain.mine()?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Blue)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        assert!(Canvas::at_height(&blockchain, 1)?
+            .get_pixel(&(0, 0))
+            .is_none());
+        assert_eq!(
+            Canvas::at_height(&blockchain, 2)?
+                .get_pixel(&(0, 0))
+                .unwrap()
+                .color,
+            Color::Red
+        );
+        assert_eq!(
+            Canvas::at_height(&blockchain, 100)?
+                .get_pixel(&(0, 0))
+                .unwrap()
+                .color,
+            Color::Blue
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_write_png() -> Result<()> {
+        let blockchain = setup_blockchain()?;
+
+        let mut png = vec![];
+        blockchain.canvas()?.write_png(&mut png)?;
+
+        assert_eq!(
+            png[..8],
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_svg() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((3, 4), Color::Black)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let canvas = blockchain.canvas()?;
+        let mut svg = vec![];
+        canvas.write_svg(&mut svg)?;
+        let svg = String::from_utf8(svg)?;
+
+        let pixel = canvas.get_pixel(&(3, 4)).unwrap();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains(&format!(
+            r##"<rect x="3" y="4" width="1" height="1" fill="#000000" data-owner="{my_address}" data-height="2" data-transaction="{}"/>"##,
+            pixel.transaction_hash
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ansi() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Black), ((1, 1), Color::Red)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let mut ansi = vec![];
+        blockchain.canvas()?.write_ansi(&mut ansi, 0, 0, 2, 3)?;
+        let ansi = String::from_utf8(ansi)?;
+
+        // Two rows of text, the last one only showing its top half.
+        let (red, green, blue) = Color::Red.to_rgb();
+        assert_eq!(
+            ansi,
+            format!(
+                "\x1b[38;2;0;0;0m\x1b[48;2;255;255;255m\u{2580}\x1b[38;2;255;255;255m\x1b[48;2;{red};{green};{blue}m\u{2580}\x1b[0m\n\
+                 \x1b[38;2;255;255;255m\x1b[49m\u{2580}\x1b[38;2;255;255;255m\x1b[49m\u{2580}\x1b[0m\n"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timelapse() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        for color in [Color::Red, Color::Green, Color::Blue] {
+            blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), color)], 0)?;
+            blockchain.mine()?;
+        }
+
+        let mut frames = vec![];
+        Canvas::timelapse(&blockchain, 2, |height, canvas| {
+            frames.push((height, canvas.get_pixel(&(0, 0)).map(|pixel| pixel.color)));
+            Ok(())
+        })?;
+
+        assert_eq!(
+            frames,
+            [(0, None), (2, Some(Color::Red)), (4, Some(Color::Blue))]
+        );
+        assert!(Canvas::timelapse(&blockchain, 0, |_, _| Ok(())).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_write_timelapse_gif() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.mine()?;
+
+        let mut gif = vec![];
+        Canvas::write_timelapse_gif(&blockchain, 1, &mut gif)?;
+
+        assert_eq!(gif[..6], *b"GIF89a");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas_region() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((63, 10), Color::Red), ((64, 11), Color::Blue)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        // The region crosses a tile border.
+        let canvas = blockchain.canvas()?;
+        let region = canvas.region(62, 10, 4, 2);
+        assert_eq!(region.len(), 8);
+
+        let colors = region
+            .iter()
+            .map(|pixel| pixel.map(|pixel| pixel.color))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            colors,
+            [
+                None,
+                Some(Color::Red),
+                None,
+                None,
+                None,
+                None,
+                Some(Color::Blue),
+                None
+            ]
+        );
+
+        assert!(canvas.region(0, 0, 0, 5).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas_tiles() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((1, 1), Color::Red), ((TILE_SIZE + 1, 1), Color::Blue)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let mut canvas = blockchain.canvas()?;
+        let version = canvas.get_version();
+        assert_eq!(canvas.get_dirty_tiles(0), [(0, 0), (1, 0)]);
+        assert!(canvas.get_dirty_tiles(version).is_empty());
+        assert_eq!(canvas.get_tile_version(&(5, 5)), 0);
+
+        // Only the tile that changed is dirty.
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((2, 2), Color::Green)],
+            0,
+        )?;
+        blockchain.mine()?;
+        canvas.connect_block(blockchain.get_last_block())?;
+
+        assert_eq!(canvas.get_dirty_tiles(version), [(0, 0)]);
+        assert!(canvas.get_tile_version(&(0, 0)) > canvas.get_tile_version(&(1, 0)));
+
+        let tile = canvas.render_tile(&(0, 0));
+        let offset = ((2 * TILE_SIZE + 2) * 4) as usize;
+        let (red, green, blue) = Color::Green.to_rgb();
+        assert_eq!(tile.len(), (TILE_SIZE * TILE_SIZE * 4) as usize);
+        assert_eq!(tile[offset..offset + 4], [red, green, blue, 0xFF]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pixel_history() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        for color in [Color::Red, Color::Green] {
+            blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((7, 7), color)], 0)?;
+            blockchain.mine()?;
+        }
+
+        let canvas = Canvas::from_chain_with_history(&blockchain)?;
+        let history = canvas.pixel_history(&(7, 7))?;
+        let colors = history
+            .iter()
+            .map(|record| (record.height, record.color))
+            .collect::<Vec<_>>();
+
+        assert_eq!(colors, [(2, Color::Red), (3, Color::Green)]);
+        assert!(history
+            .iter()
+            .all(|record| record.painter.as_ref() == Some(&my_address)));
+        assert_eq!(
+            history[1].transaction_hash,
+            canvas.get_pixel(&(7, 7)).unwrap().transaction_hash
+        );
+
+        assert!(canvas.pixel_history(&(8, 8))?.is_empty());
+        assert!(blockchain.canvas()?.pixel_history(&(7, 7)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_pixels() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let price = blockchain.get_chain_params().pixel_price;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((1, 0), Color::Black)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        // A red, a black and a transparent pixel, placed at (0, 0).
+        let rgba = [
+            0xFE, 0x40, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+        ];
+        let pixels = quantize_rgba(&rgba, 3, (0, 0));
+        assert_eq!(pixels, [((0, 0), Color::Red), ((1, 0), Color::Black)]);
+
+        // The black pixel is already there, so only the red one is painted.
+        let transaction_hashes =
+            import_pixels(&mut blockchain, &node_key(MY_NODE_SEED), &pixels, 100, 1)?;
+        assert_eq!(transaction_hashes.len(), 1);
+        blockchain.mine()?;
+        assert_eq!(
+            blockchain.canvas()?.get_pixel(&(0, 0)).unwrap().color,
+            Color::Red
+        );
+
+        // Big imports are split into several transactions and stop when the budget runs out.
+        let pixels = (0..MAX_PIXELS_PER_TRANSACTION as i32 + 10)
+            .map(|index| ((index % 100, 10 + index / 100), Color::Blue))
+            .collect::<Vec<_>>();
+
+        let budget = (MAX_PIXELS_PER_TRANSACTION as i64 + 5) * price + 2;
+        let transaction_hashes =
+            import_pixels(&mut blockchain, &node_key(MY_NODE_SEED), &pixels, budget, 1)?;
+        assert_eq!(transaction_hashes.len(), 2);
+
+        let pending_pixels = blockchain
+            .get_pending_transactions()
+            .iter()
+            .flat_map(|transaction| transaction.get_outputs())
+            .filter(|output| matches!(output, TransactionOutput::ToPixel { .. }))
+            .count();
+        assert_eq!(pending_pixels, MAX_PIXELS_PER_TRANSACTION + 5);
+
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        assert!(import_pixels(
+            &mut blockchain,
+            &node_key(MY_NODE_SEED),
+            &[((50, 50), Color::Red)],
+            0,
+            0
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_color_quantization() {
+        // Every palette color maps back to itself.
+        for color in Color::PALETTE {
+            assert_eq!(Color::from_rgb_nearest(color.to_rgb()), color);
+        }
+
+        assert_eq!(Color::from_rgb_nearest((0xFF, 0xFF, 0xFE)), Color::White);
+        assert_eq!(Color::from_rgb_nearest((0x10, 0x08, 0x08)), Color::Black);
+        assert_eq!(Color::from_rgb_nearest((0xF0, 0x40, 0x10)), Color::Red);
+        assert_eq!(Color::from_rgb_nearest((0x30, 0x80, 0xF0)), Color::Blue);
+    }
+
+    #[test]
+    fn test_canvas_expansions() -> Result<()> {
+        let chain_params = ChainParams::regtest();
+        let (first_height, first_width, _) = chain_params.canvas_expansions[0];
+
+        assert_eq!(chain_params.get_canvas_size(0), (100, 100));
+        assert_eq!(chain_params.get_canvas_size(first_height - 1), (100, 100));
+        assert_eq!(
+            chain_params.get_canvas_size(first_height),
+            (first_width, 100)
+        );
+        assert_eq!(chain_params.get_canvas_size(u64::MAX), (200, 200));
+
+        let private_key = node_key(MY_NODE_SEED);
+        let miner_address = Address::from_private_key_for(&chain_params, &private_key);
+        let mut blockchain = Blockchain::with_chain_params(chain_params, miner_address);
+        blockchain.mine()?;
+
+        // Pixels past the original border only become paintable once the canvas grows.
+        assert!(blockchain
+            .create_pixel_transaction(&private_key, &[((150, 0), Color::Red)], 0)
+            .is_err());
+
+        while blockchain.get_next_block_height()? < first_height {
+            blockchain.mine()?;
+        }
+
+        blockchain.create_pixel_transaction(&private_key, &[((150, 0), Color::Red)], 0)?;
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        let canvas = blockchain.canvas()?;
+        assert_eq!(canvas.get_width(), first_width);
+        assert_eq!(canvas.render().len(), (first_width * 100 * 4) as usize);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_heat_map() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        for color in [Color::Red, Color::Green, Color::Blue] {
+            blockchain.create_pixel_transaction(
+                &node_key(MY_NODE_SEED),
+                &[((4, 4), color), ((5, 5), Color::Black)],
+                0,
+            )?;
+            blockchain.mine()?;
+        }
+
+        let canvas = Canvas::from_chain_with_history(&blockchain)?;
+        let heat_map = HeatMap::from_canvas(&canvas, 0..=u64::MAX)?;
+        assert_eq!(heat_map.get_paints(4, 4), 3);
+        assert_eq!(heat_map.get_paints(0, 0), 0);
+        assert_eq!(heat_map.get_max_paints(), 3);
+
+        // Only paints inside the range are counted.
+        let heat_map = HeatMap::from_canvas(&canvas, 3..=4)?;
+        assert_eq!(heat_map.get_paints(4, 4), 2);
+        assert_eq!(heat_map.get_paints(5, 5), 2);
+
+        let framebuffer = heat_map.render();
+        let offset = ((4 * heat_map.get_width() + 4) * 4) as usize;
+        assert_eq!(framebuffer[offset..offset + 4], [0xFF, 0, 0, 0xFF]);
+
+        assert!(HeatMap::from_canvas(&blockchain.canvas()?, 0..=u64::MAX).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_painters() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let price = blockchain.get_chain_params().pixel_price;
+
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &my_address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            500,
+            0,
+        )?;
+        blockchain.mine()?;
+
+        blockchain.create_pixel_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &[((0, 0), Color::Red)],
+            0,
+        )?;
+        blockchain.mine()?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((1, 0), Color::Red), ((2, 0), Color::Red)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let top_painters = blockchain.top_painters(0..=u64::MAX, 10);
+        assert_eq!(top_painters.len(), 2);
+        assert_eq!(top_painters[0].address, my_address);
+        assert_eq!(top_painters[0].pixels_painted, 2);
+        assert_eq!(top_painters[0].credits_spent, 2 * price);
+        assert_eq!(top_painters[1].address, other_address);
+
+        // Only the paints inside the range count.
+        let top_painters = blockchain.top_painters(0..=3, 1);
+        assert_eq!(top_painters.len(), 1);
+        assert_eq!(top_painters[0].address, other_address);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ownership_stats() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &my_address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            500,
+            0,
+        )?;
+        blockchain.mine()?;
+
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Red), ((1, 0), Color::Red)],
+            0,
+        )?;
+        blockchain.mine()?;
+        blockchain.create_pixel_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &[((1, 0), Color::Blue)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let canvas = Canvas::from_chain(&blockchain)?;
+        assert_eq!(canvas.owned_pixels(&my_address), vec![(0, 0)]);
+        assert_eq!(canvas.owned_pixels(&other_address), vec![(1, 0)]);
+        assert_eq!(
+            canvas.get_ownership_stats(&my_address),
+            OwnershipStats {
+                pixels_owned: 1,
+                pixels_painted: 2,
+                pixels_overwritten: 1,
+            }
+        );
+        assert_eq!(
+            canvas.get_ownership_stats(&other_address),
+            OwnershipStats {
+                pixels_owned: 1,
+                pixels_painted: 1,
+                pixels_overwritten: 0,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas_root() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let empty_root = *blockchain.get_last_block().get_canvas_root();
+        assert_eq!(
+            empty_root,
+            Canvas::from_chain(&blockchain)?.calculate_root()
+        );
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((3, 4), Color::Red)], 0)?;
+        blockchain.mine()?;
+
+        // Every block commits to the canvas as it is after the block.
+        let root = *blockchain.get_last_block().get_canvas_root();
+        assert_ne!(root, empty_root);
+        assert_eq!(root, Canvas::from_chain(&blockchain)?.calculate_root());
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((3, 4), Color::Blue)],
+            0,
+        )?;
+        blockchain.mine()?;
+        assert_ne!(*blockchain.get_last_block().get_canvas_root(), root);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pixel_proofs() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((3, 4), Color::Red), ((TILE_SIZE + 1, 0), Color::Blue)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let canvas = Canvas::from_chain(&blockchain)?;
+        let canvas_root = blockchain.get_last_block().get_canvas_root();
+
+        let proof = canvas.prove_pixel(&(3, 4))?;
+        assert_eq!(proof.pixel.as_ref().unwrap().color, Color::Red);
+        assert!(verify_pixel_proof(canvas_root, &proof));
+
+        // Unpainted pixels in painted tiles can be proven too.
+        let proof = canvas.prove_pixel(&(5, 5))?;
+        assert!(proof.pixel.is_none());
+        assert!(verify_pixel_proof(canvas_root, &proof));
+        assert!(canvas.prove_pixel(&(5, TILE_SIZE + 5)).is_err());
+
+        // Lying about the color, or the position, breaks the proof.
+        let mut proof = canvas.prove_pixel(&(TILE_SIZE + 1, 0))?;
+        assert!(verify_pixel_proof(canvas_root, &proof));
+        proof.pixel.as_mut().unwrap().color = Color::Green;
+        assert!(!verify_pixel_proof(canvas_root, &proof));
+
+        let mut proof = canvas.prove_pixel(&(3, 4))?;
+        proof.position = (4, 4);
+        assert!(!verify_pixel_proof(canvas_root, &proof));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas_layers() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let event_layer = BASE_LAYER + 1;
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 0)?;
+        blockchain.mine()?;
+        blockchain.create_pixel_lock_transaction(&node_key(MY_NODE_SEED), &(0, 0), 10, 0)?;
+        blockchain.mine()?;
+
+        // Overlays are painted on top, even over locked pixels, and leave the base layer alone.
+        blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Blue), ((1, 0), Color::Green)],
+            &PaintOptions {
+                layer: event_layer,
+                ..Default::default()
+            },
+            0,
+        )?;
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        let canvas = Canvas::from_chain(&blockchain)?;
+        assert_eq!(canvas.get_pixel(&(0, 0)).unwrap().color, Color::Red);
+        assert!(canvas.get_pixel(&(1, 0)).is_none());
+        assert_eq!(
+            canvas.get_layer_pixel(event_layer, &(0, 0)).unwrap().color,
+            Color::Blue
+        );
+        assert_eq!(
+            canvas.get_visible_pixel(&(0, 0)).unwrap().color,
+            Color::Blue
+        );
+
+        let (red, green, blue) = Color::Blue.to_rgb();
+        assert_eq!(canvas.render()[..4], [red, green, blue, 0xFF]);
+        let (red, green, blue) = Color::Red.to_rgb();
+        assert_eq!(
+            canvas.render_layer(BASE_LAYER)[..4],
+            [red, green, blue, 0xFF]
+        );
+
+        // Base pixels of tiles with overlays can still be proven.
+        let proof = canvas.prove_pixel(&(0, 0))?;
+        assert!(verify_pixel_proof(
+            blockchain.get_last_block().get_canvas_root(),
+            &proof
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_faction_tags() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let team = |name: &str| PaintOptions {
+            faction: Some(name.as_bytes().to_vec()),
+            ..Default::default()
+        };
+
+        blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Red), ((1, 0), Color::Red)],
+            &team("red"),
+            0,
+        )?;
+        blockchain.mine()?;
+        blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((1, 0), Color::Blue)],
+            &team("blue"),
+            0,
+        )?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[((2, 0), Color::Blue)],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        let canvas = Canvas::from_chain(&blockchain)?;
+        assert_eq!(
+            canvas.get_pixel(&(1, 0)).unwrap().faction,
+            Some(b"blue".to_vec())
+        );
+        assert_eq!(
+            canvas.get_faction_scores(),
+            vec![(b"blue".to_vec(), 1), (b"red".to_vec(), 1)]
+        );
+
+        // Tags are kept small.
+        let result = blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((3, 0), Color::Red)],
+            &team("a faction name that is far too long"),
+            0,
+        );
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_pixel_validation() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let chain_params = blockchain.get_chain_params().clone();
+        let price = chain_params.pixel_price;
+        let paint = |value, position| TransactionOutput::ToPixel {
+            value,
+            position,
+            color: Color::Red,
+            layer: BASE_LAYER,
+            faction: None,
+            rental: None,
+            memo: None,
+        };
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 0)?;
+        blockchain.mine()?;
+        blockchain.create_pixel_lock_transaction(&node_key(MY_NODE_SEED), &(0, 0), 10, 0)?;
+        blockchain.mine()?;
+
+        // Pixels are paid for together, and can be locked in the same transaction that paints them.
+        let outputs = vec![
+            paint(2 * price, (5, 5)),
+            paint(0, (6, 6)),
+            TransactionOutput::LockPixel {
+                value: chain_params.get_pixel_lock_price(5),
+                position: (6, 6),
+                duration: 5,
+            },
+        ];
+        let transaction = spend_first_output(&blockchain, MY_NODE_SEED, outputs)?;
+        blockchain.new_transaction(transaction)?;
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+        assert!(blockchain.canvas()?.is_locked(&(6, 6), 5));
+
+        // A single locked pixel rejects the whole transaction.
+        let outputs = vec![paint(price, (7, 7)), paint(2 * price, (0, 0))];
+        let transaction = spend_first_output(&blockchain, MY_NODE_SEED, outputs)?;
+        assert!(blockchain.new_transaction(transaction).is_err());
+        assert!(blockchain
+            .get_pending_canvas()?
+            .get_pixel(&(7, 7))
+            .is_none());
+
+        let outputs = vec![paint(price, (8, 8)), paint(price, (8, 8))];
+        let transaction = spend_first_output(&blockchain, MY_NODE_SEED, outputs)?;
+        assert!(blockchain.new_transaction(transaction).is_err());
+
+        let outputs = (0..=chain_params.max_pixels_per_transaction as i32)
+            .map(|index| paint(0, (index % 100, 10 + index / 100)))
+            .collect();
+        let transaction = spend_first_output(&blockchain, MY_NODE_SEED, outputs)?;
+        assert!(blockchain.new_transaction(transaction).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pixels_per_block_per_address() -> Result<()> {
+        let chain_params = ChainParams {
+            max_pixels_per_block_per_address: 2,
+            ..ChainParams::regtest()
+        };
+        let private_key = node_key(MY_NODE_SEED);
+        let miner_address = Address::from_private_key_for(&chain_params, &private_key);
+        let mut blockchain = Blockchain::with_chain_params(chain_params, miner_address);
+        blockchain.mine()?;
+
+        blockchain.create_pixel_transaction(&private_key, &[((0, 0), Color::Red)], 0)?;
+        blockchain.create_pixel_transaction(&private_key, &[((1, 0), Color::Red)], 0)?;
+
+        // The address already has as many pixels as it can get into the next block.
+        assert!(blockchain
+            .create_pixel_transaction(&private_key, &[((2, 0), Color::Red)], 0)
+            .is_err());
+
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        blockchain.create_pixel_transaction(&private_key, &[((2, 0), Color::Red)], 0)?;
+        blockchain.mine()?;
+        assert!(blockchain.canvas()?.get_pixel(&(2, 0)).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pixel_rentals() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let rental = PaintOptions {
+            rental: Some(2),
+            ..Default::default()
+        };
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 0)?;
+        blockchain.mine()?;
+        let price = blockchain.get_pixel_price(&(0, 0))?;
+
+        // Rentals cost by the block, and don't change what painting over the pixel costs.
+        blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Blue), ((1, 0), Color::Blue)],
+            &rental,
+            0,
+        )?;
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+        assert_eq!(blockchain.get_pixel_price(&(0, 0))?, price);
+
+        let canvas = Canvas::from_chain(&blockchain)?;
+        assert_eq!(canvas.get_pixel(&(0, 0)).unwrap().color, Color::Blue);
+        assert_eq!(canvas.get_pixel(&(0, 0)).unwrap().expires_at, Some(5));
+
+        // Once the rental is over, the pixel goes back to what it was.
+        blockchain.mine()?;
+        assert_eq!(
+            Canvas::at_height(&blockchain, 4)?
+                .get_pixel(&(0, 0))
+                .unwrap()
+                .color,
+            Color::Blue
+        );
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        let canvas = Canvas::from_chain(&blockchain)?;
+        assert_eq!(canvas.get_pixel(&(0, 0)).unwrap().color, Color::Red);
+        assert!(canvas.get_pixel(&(1, 0)).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas_events() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let events = blockchain.subscribe_canvas();
+        let painter = Address::from_private_key(&node_key(MY_NODE_SEED));
+
+        blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Red)],
+            &PaintOptions {
+                rental: Some(1),
+                ..Default::default()
+            },
+            0,
+        )?;
+        blockchain.mine()?;
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![CanvasEvent::PixelPainted {
+                point: (0, 0),
+                color: Color::Red,
+                painter: Some(painter),
+                height: 2,
+            }]
+        );
+
+        // The rental running out is sent too, so viewers blank the pixel again.
+        blockchain.mine()?;
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![CanvasEvent::PixelReverted {
+                point: (0, 0),
+                color: None,
+                height: 3,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_events() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let events = blockchain.subscribe_chain();
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 0)?;
+        let transaction_hash = *blockchain.get_pending_transactions()[0].get_hash();
+        blockchain.mine()?;
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![
+                ChainEvent::TransactionAdded(transaction_hash),
+                ChainEvent::BlockConnected {
+                    block_hash: *blockchain.get_last_block_hash(),
+                    height: 2,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_hash() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let empty = Canvas::from_chain(&blockchain)?;
+
+        blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Red)],
+            &PaintOptions {
+                rental: Some(1),
+                ..Default::default()
+            },
+            0,
+        )?;
+        blockchain.mine()?;
+        let painted = Canvas::from_chain(&blockchain)?;
+        assert_ne!(painted.state_hash(), empty.state_hash());
+        assert_eq!(painted.state_hash(), painted.clone().state_hash());
+
+        // Once the rental is over the canvas looks empty again, even though it isn't the same state.
+        blockchain.mine()?;
+        let expired = Canvas::from_chain(&blockchain)?;
+        assert_eq!(expired.state_hash(), empty.state_hash());
+        assert_ne!(expired.calculate_root(), empty.calculate_root());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_paint_memos() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let chain_params = blockchain.get_chain_params().clone();
+        let signed = |memo: &str| PaintOptions {
+            memo: Some(memo.to_string()),
+            ..Default::default()
+        };
+
+        blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((0, 0), Color::Red)],
+            &signed("painted by me"),
+            0,
+        )?;
+        assert_eq!(
+            blockchain.get_pending_transactions()[0].get_balance(),
+            chain_params.get_memo_fee("painted by me".len())
+        );
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        let canvas = Canvas::from_chain_with_history(&blockchain)?;
+        assert_eq!(
+            canvas.pixel_history(&(0, 0))?[0].memo.as_deref(),
+            Some("painted by me")
+        );
+
+        let result = blockchain.create_pixel_transaction_with(
+            &node_key(MY_NODE_SEED),
+            &[((1, 0), Color::Red)],
+            &signed(&"x".repeat(MAX_MEMO_SIZE + 1)),
+            0,
+        );
+        assert!(result.is_err());
+
+        // Memos that don't pay their fee are rejected.
+        let address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let (_, _, value) = blockchain
+            .get_spendable_outputs(&address)
+            .into_iter()
+            .max_by_key(|(_, _, value)| *value)
+            .unwrap();
+        let outputs = vec![
+            TransactionOutput::ToPixel {
+                value: chain_params.pixel_price,
+                position: (2, 0),
+                color: Color::Red,
+                layer: BASE_LAYER,
+                faction: None,
+                rental: None,
+                memo: Some("free".to_string()),
+            },
+            TransactionOutput::ToInput {
+                value: value - chain_params.pixel_price,
+                public_key_address: address,
+            },
+        ];
+        let transaction = spend_first_output(&blockchain, MY_NODE_SEED, outputs)?;
+        assert!(blockchain.new_transaction(transaction).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_moderation() -> Result<()> {
+        let moderator_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let chain_params = ChainParams {
+            moderators: vec![moderator_address.public_key_hash()?],
+            ..ChainParams::mainnet()
+        };
+        let mut blockchain = Blockchain::with_chain_params(chain_params, moderator_address.clone());
+        blockchain.mine()?;
+
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &moderator_address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            500,
+            0,
+        )?;
+        blockchain.create_pixel_transaction(
+            &node_key(MY_NODE_SEED),
+            &[
+                ((0, 0), Color::Red),
+                ((1, 1), Color::Red),
+                ((5, 5), Color::Red),
+            ],
+            0,
+        )?;
+        blockchain.mine()?;
+
+        // Moderators can blank a rectangle, and keep it from being painted for a while.
+        blockchain.create_moderation_transaction(
+            &node_key(MY_NODE_SEED),
+            &(0, 0),
+            (2, 2),
+            ModerationAction::Blank,
+            0,
+        )?;
+        blockchain.create_moderation_transaction(
+            &node_key(MY_NODE_SEED),
+            &(5, 5),
+            (1, 1),
+            ModerationAction::Freeze { duration: 10 },
+            0,
+        )?;
+        blockchain.mine()?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+
+        let canvas = Canvas::from_chain(&blockchain)?;
+        assert!(canvas.get_pixel(&(0, 0)).is_none());
+        assert!(canvas.get_pixel(&(1, 1)).is_none());
+        assert_eq!(canvas.get_pixel(&(5, 5)).unwrap().color, Color::Red);
+        assert_eq!(
+            canvas.get_ownership_stats(&moderator_address).pixels_owned,
+            1
+        );
+
+        let result = blockchain.create_pixel_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &[((5, 5), Color::Blue)],
+            0,
+        );
+        assert!(result.is_err());
+
+        // Everyone else can't moderate.
+        let result = blockchain.create_moderation_transaction(
+            &node_key(OTHER_NODE_SEED),
+            &(0, 0),
+            (10, 10),
+            ModerationAction::Blank,
+            0,
+        );
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_transaction_hex() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 0)?;
+        let transaction = &blockchain.get_pending_transactions()[0];
+
+        // Decoding gives back the same transaction, down to its hash.
+        let hex = transaction.to_raw().to_hex();
+        let raw_transaction = RawTransaction::from_hex(&hex)?;
+        assert_eq!(raw_transaction.calculate_hash(), *transaction.get_hash());
+        assert_eq!(raw_transaction.to_hex(), hex);
+
+        assert!(RawTransaction::from_hex(&format!("{hex}00")).is_err());
+        assert!(RawTransaction::from_hex(&hex[..hex.len() - 2]).is_err());
+        assert!(RawTransaction::from_hex(&hex[1..]).is_err());
+        assert!(RawTransaction::from_hex("zz").is_err());
+
+        // Placeholders for signatures never pass for real ones.
+        let private_key = node_key(MY_NODE_SEED);
+        let public_key = PublicKey::from_private_key(&private_key);
+        assert!(Signature::unsigned().is_unsigned());
+        assert!(Signature::unsigned()
+            .verify(&public_key, &Hash::default())
+            .is_err());
+        assert!(!sign_transaction(&raw_transaction, 0, &private_key).is_unsigned());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_acceptance() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let raw_transaction = spend_first_output(
+            &blockchain,
+            MY_NODE_SEED,
+            vec![TransactionOutput::ToInput {
+                value: 100,
+                public_key_address: other_address,
+            }],
+        )?
+        .to_raw();
+
+        // Testing lets nothing in.
+        let transaction = blockchain.test_accept_raw_transaction(raw_transaction.clone())?;
+        assert_eq!(*transaction.get_hash(), raw_transaction.calculate_hash());
+        assert!(blockchain.get_pending_transactions().is_empty());
+
+        let with_input = |change: &dyn Fn(&mut TransactionInput)| {
+            let mut raw_transaction = raw_transaction.clone();
+            change(&mut raw_transaction.inputs[0]);
+            blockchain.test_accept_raw_transaction(raw_transaction)
+        };
+        let other_key = node_key(OTHER_NODE_SEED);
+        let other_public_key = PublicKey::from_private_key(&other_key);
+        let mut other_transaction = raw_transaction.clone();
+        if let TransactionInput::FromOutput { public_key, .. } = &mut other_transaction.inputs[0] {
+            *public_key = other_public_key;
+        }
+        let other_signature = sign_transaction(&other_transaction, 0, &other_key);
+
+        assert_eq!(
+            with_input(&|input| {
+                if let TransactionInput::FromOutput { signature, .. } = input {
+                    *signature = Signature::unsigned();
+                }
+            })
+            .unwrap_err(),
+            Rejection::BadSignature
+        );
+        // Signed well, but by someone the output doesn't pay to.
+        assert_eq!(
+            with_input(&|input| {
+                if let TransactionInput::FromOutput {
+                    public_key,
+                    signature,
+                    ..
+                } = input
+                {
+                    (*public_key, *signature) = (other_public_key, other_signature.clone());
+                }
+            })
+            .unwrap_err(),
+            Rejection::BadSignature
+        );
+        assert_eq!(
+            with_input(&|input| {
+                if let TransactionInput::FromOutput {
+                    transaction_hash, ..
+                } = input
+                {
+                    *transaction_hash = TxHash::from(Hash::default());
+                }
+            })
+            .unwrap_err(),
+            Rejection::MissingInputs
+        );
+
+        blockchain.set_min_tax_rate(1_000_000);
+        assert_eq!(
+            blockchain
+                .test_accept_raw_transaction(raw_transaction.clone())
+                .unwrap_err(),
+            Rejection::BelowMinTaxRate {
+                min_tax_rate: 1_000_000
+            }
+        );
+        blockchain.set_min_tax_rate(0);
+
+        blockchain.new_transaction(transaction)?;
+        assert_eq!(
+            blockchain
+                .test_accept_raw_transaction(raw_transaction.clone())
+                .unwrap_err(),
+            Rejection::AlreadyKnown
+        );
+        blockchain.mine()?;
+        assert_eq!(
+            blockchain
+                .test_accept_raw_transaction(raw_transaction)
+                .unwrap_err(),
+            Rejection::SpentInputs
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalidate_block() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let first_hash = *blockchain.get_last_block_hash();
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 0)?;
+        let transaction_hash = *blockchain.get_pending_transactions()[0].get_hash();
+        blockchain.mine()?;
+        let painted_hash = *blockchain.get_last_block_hash();
+        blockchain.mine()?;
+        let old_tip_hash = *blockchain.get_last_block_hash();
+        let events = blockchain.subscribe_chain();
+
+        // Everything from the invalid block on leaves the main chain, and its painting waits again.
+        blockchain.invalidate_block(&painted_hash)?;
+        assert_eq!(*blockchain.get_last_block_hash(), first_hash);
+        assert!(blockchain
+            .find_pending_transaction(&transaction_hash)
+            .is_some());
+        assert!(blockchain.canvas()?.get_pixel(&(0, 0)).is_none());
+        assert!(events.try_iter().any(|event| event
+            == ChainEvent::BlockDisconnected {
+                block_hash: painted_hash,
+                height: 2,
+            }));
+        let tips = blockchain.get_chain_tips()?;
+        assert_eq!(tips.len(), 2);
+        assert_eq!(tips[0].status, TipStatus::Active);
+        assert_eq!(
+            (tips[1].block_hash, tips[1].branch_length, tips[1].status),
+            (old_tip_hash, 2, TipStatus::Invalid)
+        );
+        assert!(blockchain.get_stale_block(&painted_hash).is_some());
+
+        // Not switched back to, unless it has more blocks than the main chain.
+        blockchain.mine()?;
+        let new_painted_hash = *blockchain.get_last_block_hash();
+        blockchain.mine()?;
+        blockchain.reconsider_block(&painted_hash)?;
+        assert_ne!(*blockchain.get_last_block_hash(), old_tip_hash);
+        assert_eq!(blockchain.get_chain_tips()?[1].status, TipStatus::ValidFork);
+
+        blockchain.invalidate_block(&new_painted_hash)?;
+        assert_eq!(*blockchain.get_last_block_hash(), old_tip_hash);
+        assert!(blockchain.get_pending_transactions().is_empty());
+        assert!(blockchain.canvas()?.get_pixel(&(0, 0)).is_some());
+
+        let genesis_hash = blockchain.get_main_chain()[0].calculate_hash();
+        assert!(blockchain.invalidate_block(&genesis_hash).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explorer() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let mut explorer = Explorer::new(blockchain.get_chain_params().clone());
+        let address = Address::from_private_key(&node_key(MY_NODE_SEED));
+
+        blockchain.create_pixel_transaction(&node_key(MY_NODE_SEED), &[((0, 0), Color::Red)], 0)?;
+        let transaction_hash = *blockchain.get_pending_transactions()[0].get_hash();
+        blockchain.mine()?;
+        let painted_hash = *blockchain.get_last_block_hash();
+        explorer.sync(&blockchain)?;
+        assert_eq!(explorer.get_block_count(), 3);
+
+        // Both rewards, and the paint in between, newest first.
+        let history = explorer.get_address_history(&address, 0, 10);
+        assert_eq!(history.total, 3);
+        assert_eq!(
+            history
+                .items
+                .iter()
+                .map(|entry| entry.height)
+                .collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        let page = explorer.get_address_history(&address, 1, 1);
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].transaction_hash, transaction_hash);
+        assert_eq!(page.items[0].sent, 1000);
+
+        let history = explorer.get_pixel_history(&(0, 0), 0, 10);
+        assert_eq!(history.total, 1);
+        assert_eq!(history.items[0].color, Color::Red);
+        assert_eq!(history.items[0].painter, Some(address.clone()));
+        let stats = explorer.get_block_stats(0, 1);
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.items[0].0, painted_hash);
+        assert_eq!(stats.items[0].1.pixel_count, 1);
+
+        // What left the main chain leaves the indexes.
+        blockchain.invalidate_block(&painted_hash)?;
+        explorer.sync(&blockchain)?;
+        assert_eq!(explorer.get_block_count(), 2);
+        assert_eq!(explorer.get_address_history(&address, 0, 10).total, 1);
+        assert_eq!(explorer.get_pixel_history(&(0, 0), 0, 10).total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rich_list() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let mut explorer = Explorer::new(blockchain.get_chain_params().clone());
+        let address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            50,
+            5,
+        )?;
+        blockchain.mine()?;
+        explorer.sync(&blockchain)?;
+
+        // Balances add up as the chain has them.
+        let rich_list = explorer.get_rich_list(10);
+        assert_eq!(
+            rich_list,
+            vec![
+                (address.clone(), blockchain.get_peer_credits(&address)),
+                (other_address.clone(), 50),
+            ]
+        );
+        assert_eq!(explorer.get_rich_list(1).len(), 1);
+        assert_eq!(
+            explorer.get_balance_distribution(),
+            vec![
+                BalanceBucket {
+                    min_balance: 1,
+                    address_count: 0,
+                    total_balance: 0,
+                },
+                BalanceBucket {
+                    min_balance: 10,
+                    address_count: 1,
+                    total_balance: 50,
+                },
+                BalanceBucket {
+                    min_balance: 100,
+                    address_count: 0,
+                    total_balance: 0,
+                },
+                BalanceBucket {
+                    min_balance: 1000,
+                    address_count: 1,
+                    total_balance: rich_list[0].1,
+                },
+            ]
+        );
+
+        let sent_hash = *blockchain.get_last_block_hash();
+        blockchain.invalidate_block(&sent_hash)?;
+        explorer.sync(&blockchain)?;
+        assert_eq!(explorer.get_rich_list(10), vec![(address, 1000)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_foreign_key_spend() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let mut other_blockchain =
+            Blockchain::new(Address::from_private_key(&node_key(OTHER_NODE_SEED)));
+        other_blockchain.submit_raw_block(blockchain.get_last_block().to_raw())?;
+
+        let victim_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let thief_key = node_key("thief");
+        blockchain.create_simple_transaction(
+            &victim_address,
+            &Address::from_private_key(&thief_key),
+            &node_key(MY_NODE_SEED),
+            100,
+            10,
+        )?;
+        blockchain.mine()?;
+
+        // The same block, but with the spend signed by a key the output doesn't pay to.
+        let mut raw_block = blockchain.get_last_block().to_raw();
+        let spend = &mut raw_block.transactions[0];
+        if let TransactionInput::FromOutput { public_key, .. } = &mut spend.inputs[0] {
+            *public_key = PublicKey::from_private_key(&thief_key);
+        }
+        sign_transaction_inputs(spend, &thief_key);
+        assert!(other_blockchain.submit_raw_block(raw_block).is_err());
+
+        other_blockchain.submit_raw_block(blockchain.get_last_block().to_raw())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_transaction_signatures() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let victim_key = node_key(MY_NODE_SEED);
+        let thief_key = node_key("thief");
+        let thief_address = Address::from_private_key(&thief_key);
+        let (output_hash, output_index, value) =
+            blockchain.get_spendable_outputs(&Address::from_private_key(&victim_key))[0];
+        let output_hash = *output_hash;
+
+        let spend = |private_key: &PrivateKey, is_signed: bool| {
+            let mut raw_transaction = RawTransaction {
+                version: CURRENT_TRANSACTION_VERSION,
+                inputs: vec![TransactionInput::FromOutput {
+                    transaction_hash: output_hash,
+                    output_index,
+                    public_key: PublicKey::from_private_key(private_key),
+                    signature: Signature::unsigned(),
+                }],
+                outputs: vec![TransactionOutput::ToInput {
+                    value: value - 10,
+                    public_key_address: thief_address.clone(),
+                }],
+                lock_time: 0,
+            };
+            if is_signed {
+                sign_transaction_inputs(&mut raw_transaction, private_key);
+            }
+
+            raw_transaction
+        };
+
+        // Neither the thief's own key nor the victim's without a signature can spend it.
+        assert!(blockchain
+            .submit_raw_transaction(spend(&thief_key, true))
+            .is_err());
+        assert!(blockchain
+            .submit_raw_transaction(spend(&victim_key, false))
+            .is_err());
+        assert!(blockchain.get_pending_transactions().is_empty());
+
+        blockchain.submit_raw_transaction(spend(&victim_key, true))?;
+        blockchain.mine()?;
+        assert_eq!(blockchain.get_peer_credits(&thief_address), value - 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_transaction() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let transaction = spend_first_output(
+            &blockchain,
+            MY_NODE_SEED,
+            vec![TransactionOutput::ToInput {
+                value: 990,
+                public_key_address: other_address.clone(),
+            }],
+        )?;
+        let raw_transaction = transaction.to_raw();
+
+        // A second copy would pay the recipient twice.
+        blockchain.submit_raw_transaction(raw_transaction.clone())?;
+        let error = blockchain
+            .submit_raw_transaction(raw_transaction.clone())
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Rejection>(),
+            Some(&Rejection::AlreadyKnown)
+        );
+        assert_eq!(blockchain.get_pending_transactions().len(), 1);
+
+        blockchain.mine()?;
+        assert_eq!(blockchain.get_peer_credits(&other_address), 990);
+        assert!(blockchain.submit_raw_transaction(raw_transaction).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_outputs() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let private_key = node_key(MY_NODE_SEED);
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let (output_hash, output_index, value) =
+            blockchain.get_spendable_outputs(&Address::from_private_key(&private_key))[0];
+        let output_hash = *output_hash;
+
+        let spend = |values: &[Credits]| {
+            let mut raw_transaction = RawTransaction {
+                version: CURRENT_TRANSACTION_VERSION,
+                inputs: vec![TransactionInput::FromOutput {
+                    transaction_hash: output_hash,
+                    output_index,
+                    public_key: PublicKey::from_private_key(&private_key),
+                    signature: Signature::unsigned(),
+                }],
+                outputs: values
+                    .iter()
+                    .map(|value| TransactionOutput::ToInput {
+                        value: *value,
+                        public_key_address: other_address.clone(),
+                    })
+                    .collect(),
+                lock_time: 0,
+            };
+            sign_transaction_inputs(&mut raw_transaction, &private_key);
+
+            raw_transaction
+        };
+
+        // Balanced out by a negative output, a billion credits would come out of nowhere.
+        let minted = spend(&[1_000_000_000, -(1_000_000_000 - (value - 10))]);
+        assert!(matches!(
+            blockchain.test_accept_raw_transaction(minted.clone()),
+            Err(Rejection::Invalid(_))
+        ));
+        assert!(blockchain.submit_raw_transaction(minted).is_err());
+
+        // Neither can the outputs add up past what credits can hold.
+        let overflowing = spend(&[Credits::MAX, Credits::MAX, 2]);
+        assert!(matches!(
+            blockchain.test_accept_raw_transaction(overflowing.clone()),
+            Err(Rejection::Invalid(_))
+        ));
+        assert!(blockchain.submit_raw_transaction(overflowing).is_err());
+        assert!(blockchain.get_pending_transactions().is_empty());
+
+        blockchain.submit_raw_transaction(spend(&[value - 10]))?;
+        blockchain.mine()?;
+        assert_eq!(blockchain.get_peer_credits(&other_address), value - 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_outputs_in_blocks() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let mut other_blockchain =
+            Blockchain::new(Address::from_private_key(&node_key(OTHER_NODE_SEED)));
+        other_blockchain.submit_raw_block(blockchain.get_last_block().to_raw())?;
+
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        blockchain.create_simple_transaction(
+            &my_address,
+            &other_address,
+            &node_key(MY_NODE_SEED),
+            100,
+            10,
+        )?;
+        blockchain.mine()?;
+
+        // The same block, but with a billion credits paid out and made up for by the change.
+        let mut raw_block = blockchain.get_last_block().to_raw();
+        let spend = &mut raw_block.transactions[0];
+        for (output, extra_value) in spend
+            .outputs
+            .iter_mut()
+            .zip([1_000_000_000, -1_000_000_000])
+        {
+            if let TransactionOutput::ToInput { value, .. } = output {
+                *value += extra_value;
+            }
+        }
+        sign_transaction_inputs(spend, &node_key(MY_NODE_SEED));
+        assert!(other_blockchain.submit_raw_block(raw_block).is_err());
+        assert_eq!(other_blockchain.get_peer_credits(&other_address), 0);
+
+        other_blockchain.submit_raw_block(blockchain.get_last_block().to_raw())?;
+        assert_eq!(other_blockchain.get_peer_credits(&other_address), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mining_after_bad_transaction() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let private_key = node_key(MY_NODE_SEED);
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let (output_hash, output_index, value) =
+            blockchain.get_spendable_outputs(&Address::from_private_key(&private_key))[0];
+
+        // The same output spent twice by one transaction, paying out both times.
+        let input = TransactionInput::FromOutput {
+            transaction_hash: *output_hash,
+            output_index,
+            public_key: PublicKey::from_private_key(&private_key),
+            signature: Signature::unsigned(),
+        };
+        let mut raw_transaction = RawTransaction {
+            version: CURRENT_TRANSACTION_VERSION,
+            inputs: vec![input.clone(), input],
+            outputs: vec![TransactionOutput::ToInput {
+                value: 2 * value - 10,
+                public_key_address: other_address.clone(),
+            }],
+            lock_time: 0,
+        };
+        sign_transaction_inputs(&mut raw_transaction, &private_key);
+        let transaction_hash = raw_transaction.calculate_hash();
+        assert!(matches!(
+            blockchain.test_accept_raw_transaction(raw_transaction.clone()),
+            Err(Rejection::Invalid(_))
+        ));
+        assert!(blockchain.submit_raw_transaction(raw_transaction).is_err());
+
+        // Nothing got in that would keep the next block from being mined.
+        assert!(blockchain
+            .find_pending_transaction(&transaction_hash)
+            .is_none());
+        blockchain.mine()?;
+        assert!(blockchain.find_transaction(&transaction_hash).is_none());
+        assert_eq!(blockchain.get_peer_credits(&other_address), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spent_inputs() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let other_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+        let pay = |value| TransactionOutput::ToInput {
+            value,
+            public_key_address: other_address.clone(),
+        };
+
+        let transaction = spend_first_output(&blockchain, MY_NODE_SEED, vec![pay(100)])?;
+        let raw_transaction = transaction.to_raw();
+        blockchain.new_transaction(transaction)?;
+        blockchain.mine()?;
+
+        // The spent output may still be found, but spending it again is refused on every path.
+        let mut respend = raw_transaction.clone();
+        respend.outputs = vec![pay(200)];
+        sign_transaction_inputs(&mut respend, &node_key(MY_NODE_SEED));
+        assert_eq!(
+            blockchain
+                .test_accept_raw_transaction(respend.clone())
+                .unwrap_err(),
+            Rejection::SpentInputs
+        );
+        let error = blockchain
+            .submit_raw_transaction(respend.clone())
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Rejection>(),
+            Some(&Rejection::SpentInputs)
+        );
+        let error = blockchain
+            .new_transaction(Transaction::from_raw(&blockchain, respend)?)
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Rejection>(),
+            Some(&Rejection::SpentInputs)
+        );
+        assert!(blockchain.get_pending_transactions().is_empty());
+
+        blockchain.mine()?;
+        assert_eq!(blockchain.get_peer_credits(&other_address), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_order_by_tax_rate() -> Result<()> {
+        let mut blockchain = setup_blockchain()?;
+        let my_address = Address::from_private_key(&node_key(MY_NODE_SEED));
+        let recipient_address = Address::from_private_key(&node_key(OTHER_NODE_SEED));
+
+        let small_hash = blockchain.create_simple_transaction(
+            &my_address,
+            &recipient_address,
+            &node_key(MY_NODE_SEED),
+            10,
+            20,
+        )?;
+
+        // A big transaction paying more in all, but less for each of its bytes.
+        let wallet = Wallet::deterministic(MY_NODE_SEED);
+        let big_hash = wallet.send_many(&mut blockchain, vec![(recipient_address, 1); 20], 25)?;
+
+        let (small, big) = (
+            blockchain.find_pending_transaction(&small_hash).unwrap(),
+            blockchain.find_pending_transaction(&big_hash).unwrap(),
+        );
+        assert!(big.get_balance() > small.get_balance());
+        assert!(big.get_tax_rate() < small.get_tax_rate());
+        assert_eq!(
+            blockchain
+                .get_mempool()
+                .iter_by_fee()
+                .map(|transaction| *transaction.get_hash())
+                .collect::<Vec<_>>(),
+            vec![small_hash, big_hash]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+// Light clients keep only block headers. Their proof of work can be checked on their own, which is
+// enough to follow the best chain. Transactions and pixels are then checked against the roots in
+// those headers with Merkle proofs, without downloading or replaying a single block.
+
+use crate::{
+    block::{Block, BlockHeader, MerkleBlock, MAX_FUTURE_BLOCK_TIME},
+    blockchain::{build_locator, BlockHash, Blockchain},
+    canvas::{verify_pixel_proof, Canvas, PixelProof},
+    chain_params::ChainParams,
+};
+use anyhow::{bail, Context, Result};
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct LightClient {
+    headers: HashMap<BlockHash, (BlockHeader, u64)>, // With their heights.
+    best_chain: Vec<BlockHash>,                      // Indexed by height.
+}
+
+impl LightClient {
+    pub fn new(chain_params: ChainParams) -> Self {
+        let genesis_header =
+            Block::genesis(Canvas::new(chain_params).calculate_root()).get_header();
+        let genesis_hash = genesis_header.calculate_hash();
+
+        Self {
+            headers: HashMap::from([(genesis_hash, (genesis_header, 0))]),
+            best_chain: vec![genesis_hash],
+        }
+    }
+
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<bool> {
+        let header_hash = header.calculate_hash();
+        if self.headers.contains_key(&header_hash) {
+            return Ok(false);
+        }
+
+        let previous_hash = header
+            .previous_hash
+            .context("Only the genesis block has no previous block.")?;
+        let (previous_header, previous_height) = self
+            .headers
+            .get(&previous_hash)
+            .context("Header doesn't connect to a known header.")?;
+
+        if !Blockchain::validate_proof(&previous_header.proof, &header.proof) {
+            bail!("Header proof of work isn't valid.")
+        }
+
+        if header.timestamp > Utc::now() + Duration::seconds(MAX_FUTURE_BLOCK_TIME) {
+            bail!("Header is too far in the future.")
+        }
+
+        // Every block takes the same work, so the best chain is the longest one.
+        let height = previous_height + 1;
+        self.headers.insert(header_hash, (header, height));
+        if height >= self.best_chain.len() as u64 {
+            self.switch_to(header_hash, height);
+        }
+
+        Ok(true)
+    }
+
+    pub fn get_best_height(&self) -> u64 {
+        self.best_chain.len() as u64 - 1
+    }
+
+    pub fn get_best_hash(&self) -> &BlockHash {
+        self.best_chain.last().unwrap()
+    }
+
+    pub fn get_header(&self, block_hash: &BlockHash) -> Option<&BlockHeader> {
+        self.headers.get(block_hash).map(|(header, _)| header)
+    }
+
+    pub fn get_locator(&self) -> Vec<BlockHash> {
+        build_locator(&self.best_chain)
+    }
+
+    pub fn get_confirmations(&self, block_hash: &BlockHash) -> Option<u64> {
+        // Blocks off the best chain may never make it, so they have none.
+        let (_, height) = self.headers.get(block_hash)?;
+        if self.best_chain.get(*height as usize) != Some(block_hash) {
+            return None;
+        }
+
+        Some(self.get_best_height() - height + 1)
+    }
+
+    pub fn verify_merkle_block(&self, merkle_block: &MerkleBlock) -> bool {
+        let block_hash = merkle_block.header.calculate_hash();
+
+        self.get_confirmations(&block_hash).is_some() && merkle_block.verify()
+    }
+
+    pub fn verify_pixel(&self, block_hash: &BlockHash, proof: &PixelProof) -> bool {
+        // The canvas root in a header is the canvas right after that block.
+        self.get_confirmations(block_hash).is_some()
+            && verify_pixel_proof(&self.headers[block_hash].0.canvas_root, proof)
+    }
+
+    fn switch_to(&mut self, block_hash: BlockHash, height: u64) {
+        // Walk back until the new chain meets the old one.
+        let mut branch = vec![];
+        let mut block_hash = block_hash;
+        let mut height = height;
+        while self.best_chain.get(height as usize) != Some(&block_hash) {
+            branch.push(block_hash);
+            block_hash = self.headers[&block_hash].0.previous_hash.unwrap();
+            height -= 1;
+        }
+
+        self.best_chain.truncate(height as usize + 1);
+        self.best_chain.extend(branch.into_iter().rev());
+    }
+}
@@ -0,0 +1,517 @@
+use crate::{
+    block::Block,
+    blockchain::TxHash,
+    transaction::{Credits, RawTransaction, Transaction, TransactionInput},
+};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+pub const DEFAULT_MAX_MEMPOOL_SIZE: usize = 10_000_000;
+const MAX_RECENTLY_EVICTED: usize = 10_000;
+const MAX_ORPHANS: usize = 100;
+const MIN_REPLACEMENT_TAX_INCREMENT: Credits = 1; // Extra tax a replacement must pay over what it evicts.
+const MAX_REPLACEMENT_EVICTIONS: usize = 100;
+
+pub const TAX_RATE_PERCENTILES: [usize; 5] = [10, 25, 50, 75, 90];
+pub const AGE_BUCKETS: [i64; 3] = [60, 60 * 60, 24 * 60 * 60]; // In seconds, the last bucket is everything older.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolInfo {
+    pub transaction_count: usize,
+    pub size: usize,
+    pub total_tax: Credits,
+    pub tax_rate_percentiles: [f64; TAX_RATE_PERCENTILES.len()], // Tax per byte.
+    pub age_counts: [usize; AGE_BUCKETS.len() + 1], // Transactions waiting for up to each of the buckets.
+    pub oldest_added_at: Option<DateTime<Utc>>,
+}
+
+// Why a transaction is kept out of the mempool, so whoever sent it can tell what to fix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    AlreadyKnown,
+    MissingInputs, // Spends outputs of transactions that aren't known, or that they don't have.
+    SpentInputs,   // Spends outputs the chain has already spent.
+    BadSignature,  // An input isn't signed, or not by the key its output pays to.
+    BelowMinTaxRate { min_tax_rate: Credits },
+    RecentlyEvicted,
+    MempoolFull,
+    Invalid(String), // Breaks any of the other rules, as said.
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::AlreadyKnown => write!(f, "Transaction is already in the mempool."),
+            Rejection::MissingInputs => write!(f, "Transaction spends outputs that aren't known."),
+            Rejection::SpentInputs => {
+                write!(f, "Transaction spends outputs that are already spent.")
+            }
+            Rejection::BadSignature => {
+                write!(f, "Transaction has an input that isn't validly signed.")
+            }
+            Rejection::BelowMinTaxRate { min_tax_rate } => write!(
+                f,
+                "Transaction pays less than the minimum tax rate of {min_tax_rate} per byte."
+            ),
+            Rejection::RecentlyEvicted => {
+                write!(f, "Transaction was recently evicted from the mempool.")
+            }
+            Rejection::MempoolFull => {
+                write!(
+                    f,
+                    "Mempool is full of transactions paying a higher tax rate."
+                )
+            }
+            Rejection::Invalid(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+#[derive(Debug)]
+pub struct Mempool {
+    transactions: Vec<Transaction>, // In the order they came in, so parents come before their children.
+    size: usize,                    // Serialized size of all the transactions.
+    max_size: usize,
+    min_tax_rate: Credits, // Tax per serialized byte to get in. Only a policy, blocks can have anything.
+    recently_evicted: VecDeque<TxHash>, // Kept out for a while, so they can't just come right back.
+    added_at: HashMap<TxHash, DateTime<Utc>>,
+}
+
+impl Mempool {
+    pub fn with_max_size(max_size: usize) -> Self {
+        Self {
+            transactions: Default::default(),
+            size: 0,
+            max_size,
+            min_tax_rate: 0,
+            recently_evicted: Default::default(),
+            added_at: Default::default(),
+        }
+    }
+
+    pub fn add(&mut self, transaction: Transaction) -> Result<()> {
+        // Checking the transaction is up to the chain, which knows what it can spend.
+        if self.contains(transaction.get_hash()) {
+            bail!(Rejection::AlreadyKnown)
+        }
+        if self.was_recently_evicted(transaction.get_hash()) {
+            bail!(Rejection::RecentlyEvicted)
+        }
+
+        let size = transaction.get_size();
+        if !self.pays_min_tax_rate(&transaction) {
+            bail!(Rejection::BelowMinTaxRate {
+                min_tax_rate: self.min_tax_rate
+            })
+        }
+
+        let transaction_hash = *transaction.get_hash();
+        self.size += size;
+        self.added_at.insert(transaction_hash, Utc::now());
+        self.transactions.push(transaction);
+
+        self.evict_to_fit();
+        if !self.contains(&transaction_hash) {
+            bail!(Rejection::MempoolFull)
+        }
+
+        Ok(())
+    }
+
+    pub fn test_accept(&self, transaction: &Transaction) -> Result<(), Rejection> {
+        // Only the mempool's own policy, like `add`, but nothing is changed.
+        if self.contains(transaction.get_hash()) {
+            return Err(Rejection::AlreadyKnown);
+        }
+        if self.was_recently_evicted(transaction.get_hash()) {
+            return Err(Rejection::RecentlyEvicted);
+        }
+        if !self.pays_min_tax_rate(transaction) {
+            return Err(Rejection::BelowMinTaxRate {
+                min_tax_rate: self.min_tax_rate,
+            });
+        }
+
+        // Eviction goes from the lowest tax rate up, and ties go before what comes in, so this is
+        // what would go to make room before the transaction itself would.
+        let size = transaction.get_size();
+        let cheaper_hashes = self
+            .transactions
+            .iter()
+            .filter(|pending| compare_tax_rates(pending, transaction) != Ordering::Greater)
+            .map(|pending| *pending.get_hash())
+            .collect::<Vec<_>>();
+        let evictable_size: usize = self
+            .with_descendants(&cheaper_hashes)
+            .iter()
+            .filter_map(|evictable_hash| self.get(evictable_hash))
+            .map(Transaction::get_size)
+            .sum();
+        if self.size + size > self.max_size + evictable_size {
+            return Err(Rejection::MempoolFull);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict_to_fit();
+    }
+
+    pub fn set_min_tax_rate(&mut self, min_tax_rate: Credits) {
+        // Transactions already in stay, the rate is only checked when they come in.
+        self.min_tax_rate = min_tax_rate;
+    }
+
+    pub fn get_min_tax_rate(&self) -> Credits {
+        self.min_tax_rate
+    }
+
+    pub fn was_recently_evicted(&self, transaction_hash: &TxHash) -> bool {
+        self.recently_evicted.contains(transaction_hash)
+    }
+
+    pub fn remove(&mut self, transaction_hashes: &[TxHash]) {
+        self.take(transaction_hashes);
+    }
+
+    pub fn take(&mut self, transaction_hashes: &[TxHash]) -> Vec<Transaction> {
+        let (mut taken, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.transactions)
+            .into_iter()
+            .partition(|transaction| transaction_hashes.contains(transaction.get_hash()));
+        self.transactions = kept;
+        self.size -= taken.iter().map(Transaction::get_size).sum::<usize>();
+        for transaction in &taken {
+            self.added_at.remove(transaction.get_hash());
+        }
+
+        // Handed out in the order they were asked for.
+        taken.sort_by_key(|transaction| {
+            transaction_hashes
+                .iter()
+                .position(|transaction_hash| transaction_hash == transaction.get_hash())
+        });
+
+        taken
+    }
+
+    pub fn remove_confirmed(&mut self, block: &Block) {
+        // Whatever spends the same outputs as the block can never be mined anymore, and neither can
+        // anything built on top of it.
+        let mut removed_hashes = vec![];
+        for transaction in block.get_transactions() {
+            removed_hashes.push(*transaction.get_hash());
+            removed_hashes.extend(self.conflicts(transaction));
+        }
+
+        let removed_hashes = self.with_descendants(&removed_hashes);
+        self.remove(&removed_hashes);
+    }
+
+    pub fn conflicts(&self, transaction: &Transaction) -> Vec<TxHash> {
+        self.transactions
+            .iter()
+            .filter(|pending| pending.get_hash() != transaction.get_hash())
+            .filter(|pending| {
+                pending.get_inputs().iter().any(|input| {
+                    transaction
+                        .get_inputs()
+                        .iter()
+                        .any(|other| spends_same_output(input, other))
+                })
+            })
+            .map(|pending| *pending.get_hash())
+            .collect()
+    }
+
+    pub fn check_replacement(&self, transaction: &Transaction) -> Result<Vec<TxHash>> {
+        // It has to replace something.
+        let conflicting_hashes = self.conflicts(transaction);
+        if conflicting_hashes.is_empty() {
+            bail!("Replacement doesn't conflict with any pending transaction.")
+        }
+
+        // Descendants of the replaced transactions would become invalid, so they are evicted too,
+        // but only so many at once.
+        let evicted_hashes = self.with_descendants(&conflicting_hashes);
+        if evicted_hashes.len() > MAX_REPLACEMENT_EVICTIONS {
+            bail!("Replacement would evict more than {MAX_REPLACEMENT_EVICTIONS} transactions.")
+        }
+
+        // It can't spend the outputs of what it evicts.
+        if transaction
+            .get_parent_hashes()
+            .iter()
+            .any(|parent_hash| evicted_hashes.contains(parent_hash))
+        {
+            bail!("Replacement spends a transaction it evicts.")
+        }
+
+        // It pays for everything it evicts, and for being relayed itself.
+        let evicted_tax: Credits = self
+            .transactions
+            .iter()
+            .filter(|pending| evicted_hashes.contains(pending.get_hash()))
+            .map(|pending| pending.get_balance())
+            .sum();
+        let relay_tax = self.min_tax_rate * transaction.get_size() as Credits;
+
+        if transaction.get_balance() < evicted_tax + MIN_REPLACEMENT_TAX_INCREMENT.max(relay_tax) {
+            bail!("Replacement must pay more tax than the transactions it evicts.")
+        }
+
+        // Miners must be better off with it than with any of the transactions it replaces.
+        let pays_more = conflicting_hashes
+            .iter()
+            .filter_map(|conflicting_hash| self.get(conflicting_hash))
+            .all(|conflicting| compare_tax_rates(transaction, conflicting) == Ordering::Greater);
+
+        if !pays_more {
+            bail!("Replacement must pay a higher tax rate than the transactions it replaces.")
+        }
+
+        Ok(evicted_hashes)
+    }
+
+    pub fn with_descendants(&self, transaction_hashes: &[TxHash]) -> Vec<TxHash> {
+        let mut descendant_hashes = transaction_hashes.to_vec();
+        let mut index = 0;
+        while index < descendant_hashes.len() {
+            let parent_hash = descendant_hashes[index];
+            for pending in &self.transactions {
+                let is_child = pending.get_inputs().iter().any(|input| match input {
+                    TransactionInput::FromOutput {
+                        transaction_hash, ..
+                    } => *transaction_hash == parent_hash,
+
+                    TransactionInput::FromReward { .. } => false,
+                });
+
+                if is_child && !descendant_hashes.contains(pending.get_hash()) {
+                    descendant_hashes.push(*pending.get_hash());
+                }
+            }
+
+            index += 1;
+        }
+
+        descendant_hashes
+    }
+
+    pub fn get(&self, transaction_hash: &TxHash) -> Option<&Transaction> {
+        self.transactions
+            .iter()
+            .find(|transaction| transaction.get_hash() == transaction_hash)
+    }
+
+    pub fn contains(&self, transaction_hash: &TxHash) -> bool {
+        self.get(transaction_hash).is_some()
+    }
+
+    pub fn is_output_spent(&self, transaction_hash: &TxHash, output_index: u32) -> bool {
+        self.transactions
+            .iter()
+            .flat_map(|transaction| transaction.get_inputs())
+            .any(|input| match input {
+                TransactionInput::FromOutput {
+                    transaction_hash: hash,
+                    output_index: index,
+                    ..
+                } => *index == output_index && hash == transaction_hash,
+
+                TransactionInput::FromReward { .. } => false,
+            })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> + '_ {
+        self.transactions.iter()
+    }
+
+    pub fn iter_by_fee(&self) -> impl Iterator<Item = &Transaction> + '_ {
+        // By tax per byte, highest first. The sort is stable, so transactions paying the same keep
+        // the order they came in.
+        let mut transactions = self.transactions.iter().collect::<Vec<_>>();
+        transactions.sort_by(|transaction, other| compare_tax_rates(other, transaction));
+
+        transactions.into_iter()
+    }
+
+    pub fn get_transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    pub fn info(&self) -> MempoolInfo {
+        let now = Utc::now();
+        let mut age_counts = [0; AGE_BUCKETS.len() + 1];
+        for added_at in self.added_at.values() {
+            let age = (now - *added_at).num_seconds();
+            let bucket = AGE_BUCKETS
+                .iter()
+                .position(|limit| age < *limit)
+                .unwrap_or(AGE_BUCKETS.len());
+
+            age_counts[bucket] += 1;
+        }
+
+        MempoolInfo {
+            transaction_count: self.transactions.len(),
+            size: self.size,
+            total_tax: self.transactions.iter().map(Transaction::get_balance).sum(),
+            tax_rate_percentiles: get_tax_rate_percentiles(&self.transactions),
+            age_counts,
+            oldest_added_at: self.added_at.values().min().copied(),
+        }
+    }
+
+    pub fn get_added_at(&self, transaction_hash: &TxHash) -> Option<&DateTime<Utc>> {
+        self.added_at.get(transaction_hash)
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    fn pays_min_tax_rate(&self, transaction: &Transaction) -> bool {
+        transaction.get_balance() as i128
+            >= self.min_tax_rate as i128 * transaction.get_size() as i128
+    }
+
+    fn evict_to_fit(&mut self) {
+        while self.size > self.max_size {
+            // Children can't stay without their parent, so they go along with it.
+            let lowest_hash = match self
+                .transactions
+                .iter()
+                .min_by(|transaction, other| compare_tax_rates(transaction, other))
+            {
+                Some(transaction) => *transaction.get_hash(),
+                None => break,
+            };
+
+            let evicted_hashes = self.with_descendants(&[lowest_hash]);
+            self.remove(&evicted_hashes);
+
+            self.recently_evicted.extend(evicted_hashes);
+            while self.recently_evicted.len() > MAX_RECENTLY_EVICTED {
+                self.recently_evicted.pop_front();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct OrphanPool {
+    orphans: VecDeque<(TxHash, RawTransaction)>, // Oldest first, so they are the first to go when it's full.
+}
+
+impl OrphanPool {
+    pub fn add(&mut self, orphan: RawTransaction) {
+        let orphan_hash = orphan.calculate_hash();
+        if self.contains(&orphan_hash) {
+            return;
+        }
+
+        self.orphans.push_back((orphan_hash, orphan));
+        if self.orphans.len() > MAX_ORPHANS {
+            self.orphans.pop_front();
+        }
+    }
+
+    pub fn take(&mut self, orphan_hashes: &[TxHash]) -> Vec<RawTransaction> {
+        let (taken, kept): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.orphans)
+            .into_iter()
+            .partition(|(orphan_hash, _)| orphan_hashes.contains(orphan_hash));
+        self.orphans = kept;
+
+        taken.into_iter().map(|(_, orphan)| orphan).collect()
+    }
+
+    pub fn contains(&self, orphan_hash: &TxHash) -> bool {
+        self.orphans.iter().any(|(hash, _)| hash == orphan_hash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&TxHash, &RawTransaction)> + '_ {
+        self.orphans
+            .iter()
+            .map(|(orphan_hash, orphan)| (orphan_hash, orphan))
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::with_max_size(DEFAULT_MAX_MEMPOOL_SIZE)
+    }
+}
+
+pub fn get_tax_rate_percentiles(transactions: &[Transaction]) -> [f64; TAX_RATE_PERCENTILES.len()] {
+    let mut tax_rates = transactions
+        .iter()
+        .map(Transaction::get_tax_rate)
+        .collect::<Vec<_>>();
+    tax_rates.sort_by(f64::total_cmp);
+
+    let mut tax_rate_percentiles = [0.0; TAX_RATE_PERCENTILES.len()];
+    if !tax_rates.is_empty() {
+        for (rate, percentile) in tax_rate_percentiles.iter_mut().zip(TAX_RATE_PERCENTILES) {
+            // Rounded to the nearest rank.
+            *rate = tax_rates[((tax_rates.len() - 1) * percentile + 50) / 100];
+        }
+    }
+
+    tax_rate_percentiles
+}
+
+fn compare_tax_rates(transaction: &Transaction, other: &Transaction) -> Ordering {
+    // Tax per byte, compared without dividing so rounding doesn't get in the way.
+    let rate = transaction.get_balance() as i128 * other.get_size() as i128;
+    let other_rate = other.get_balance() as i128 * transaction.get_size() as i128;
+
+    rate.cmp(&other_rate)
+}
+
+fn spends_same_output(input: &TransactionInput, other: &TransactionInput) -> bool {
+    match (input, other) {
+        (
+            TransactionInput::FromOutput {
+                transaction_hash,
+                output_index,
+                ..
+            },
+            TransactionInput::FromOutput {
+                transaction_hash: other_transaction_hash,
+                output_index: other_output_index,
+                ..
+            },
+        ) => transaction_hash == other_transaction_hash && output_index == other_output_index,
+
+        _ => false,
+    }
+}
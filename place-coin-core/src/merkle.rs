@@ -0,0 +1,67 @@
+// Binary Merkle trees over already hashed leaves.
+
+use crate::blockchain::Hash;
+use sha3::{Digest, Sha3_256};
+
+pub(crate) const EMPTY_ROOT: Hash = [0; 32];
+
+pub(crate) fn root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return EMPTY_ROOT;
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    level[0]
+}
+
+pub(crate) fn proof(leaves: &[Hash], mut index: usize) -> Vec<(bool, Hash)> {
+    debug_assert!(index < leaves.len());
+
+    // Each step is a sibling, and whether it sits on the left.
+    let mut proof = vec![];
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            proof.push((sibling < index, level[sibling]));
+        }
+
+        level = next_level(&level);
+        index /= 2;
+    }
+
+    proof
+}
+
+pub(crate) fn root_from_proof(leaf: &Hash, proof: &[(bool, Hash)]) -> Hash {
+    proof
+        .iter()
+        .fold(*leaf, |hash, (is_left, sibling)| match is_left {
+            true => hash_node(sibling, &hash),
+            false => hash_node(&hash, sibling),
+        })
+}
+
+pub(crate) fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha3_256::default();
+    hasher.update(left);
+    hasher.update(right);
+
+    hasher.finalize().into()
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    // An odd node out moves up as is, instead of being paired with a copy of itself.
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
@@ -1,24 +1,96 @@
 use crate::{
-    address::{PrivateKey, PublicKey},
+    address::{Address, PrivateKey, PublicKey},
     blockchain::Hash,
+    transaction::{
+        RawTransaction, TransactionInput, Version, CURRENT_TRANSACTION_VERSION,
+        SCHNORR_TRANSACTION_VERSION,
+    },
 };
-use ecdsa::signature::Signer;
-use serde::Serialize;
+use anyhow::{bail, Context, Result};
+use ecdsa::signature::{Signer, Verifier};
+use rayon::prelude::*;
+use serde::{de::Visitor, Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
+use subtle::{Choice, ConstantTimeEq};
 
-#[derive(Debug, PartialEq, Eq)]
+// Prepended to every signed message so they can never be mistaken for a transaction signature.
+const MESSAGE_PREFIX: &[u8] = b"Place Coin Signed Message:\n";
+
+#[derive(Debug, Clone)]
 pub struct Signature([u8; 64]);
 
 impl Signature {
     pub fn new(private_key: &PrivateKey, hash: &Hash) -> Self {
-        let signature_key = k256::ecdsa::SigningKey::from_bytes(private_key).unwrap();
+        let signature_key = k256::ecdsa::SigningKey::from_slice(private_key.as_bytes()).unwrap();
         let signature: k256::ecdsa::Signature = signature_key.sign(hash);
-        let signature = signature.to_vec();
 
-        Self(signature.as_slice().try_into().unwrap())
+        // Always produce the low-S form, the only one validation accepts.
+        let signature = signature.normalize_s().unwrap_or(signature);
+
+        Self(signature.to_bytes().into())
+    }
+
+    // Stands in for a signature that hasn't been made yet. It never verifies.
+    pub fn unsigned() -> Self {
+        Self([0; 64])
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes
+            .try_into()
+            .ok()
+            .context("Signatures must be exactly 64 bytes.")?;
+
+        Ok(Self(bytes))
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        *self == Self::unsigned()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn verify(&self, public_key: &PublicKey, hash: &Hash) -> Result<()> {
+        let signature = self.to_canonical_ecdsa()?;
+
+        let verifying_key = k256::ecdsa::VerifyingKey::from(public_key.to_k256());
+        if verifying_key.verify(hash, &signature).is_err() {
+            bail!("Invalid signature.")
+        }
+
+        Ok(())
+    }
+
+    fn to_canonical_ecdsa(&self) -> Result<k256::ecdsa::Signature> {
+        let signature = k256::ecdsa::Signature::try_from(self.0.as_slice())
+            .ok()
+            .context("Malformed signature.")?;
+
+        // (r, s) and (r, n - s) are both valid, so only the low one is accepted to avoid malleability.
+        if signature.normalize_s().is_some() {
+            bail!("Signature isn't in low-S form.")
+        }
+
+        Ok(signature)
+    }
+}
+
+impl ConstantTimeEq for Signature {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
     }
 }
 
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for Signature {}
+
 impl Serialize for Signature {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -28,18 +100,300 @@ impl Serialize for Signature {
     }
 }
 
+struct SignatureVisitor;
+
+impl<'de> Visitor<'de> for SignatureVisitor {
+    type Value = Signature;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a 64 bytes signature.")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Signature::from_bytes(v).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(SignatureVisitor)
+    }
+}
+
+pub trait SignatureScheme: Sync {
+    fn sign(&self, private_key: &PrivateKey, hash: &Hash) -> Signature;
+    fn verify(&self, public_key: &PublicKey, hash: &Hash, signature: &Signature) -> Result<()>;
+
+    // Checks the encoding rules without verifying the signature.
+    fn check_canonical(&self, signature: &Signature) -> Result<()>;
+}
+
+pub struct Ecdsa;
+
+impl SignatureScheme for Ecdsa {
+    fn sign(&self, private_key: &PrivateKey, hash: &Hash) -> Signature {
+        Signature::new(private_key, hash)
+    }
+
+    fn verify(&self, public_key: &PublicKey, hash: &Hash, signature: &Signature) -> Result<()> {
+        signature.verify(public_key, hash)
+    }
+
+    fn check_canonical(&self, signature: &Signature) -> Result<()> {
+        signature.to_canonical_ecdsa()?;
+
+        Ok(())
+    }
+}
+
+pub struct Schnorr;
+
+impl SignatureScheme for Schnorr {
+    fn sign(&self, private_key: &PrivateKey, hash: &Hash) -> Signature {
+        let signature_key = k256::schnorr::SigningKey::from_bytes(private_key.as_bytes()).unwrap();
+        let signature: k256::schnorr::Signature = signature_key.sign(hash);
+
+        Signature(signature.to_bytes())
+    }
+
+    fn verify(&self, public_key: &PublicKey, hash: &Hash, signature: &Signature) -> Result<()> {
+        self.check_canonical(signature)?;
+
+        let schnorr_signature = k256::schnorr::Signature::try_from(signature.0.as_slice())
+            .ok()
+            .context("Malformed signature.")?;
+
+        // Schnorr keys are only the x coordinate, so drop the parity byte of the compressed key.
+        let verifying_key = k256::schnorr::VerifyingKey::from_bytes(&public_key.as_bytes()[1..])
+            .ok()
+            .context("Malformed public key.")?;
+
+        if verifying_key.verify(hash, &schnorr_signature).is_err() {
+            bail!("Invalid signature.")
+        }
+
+        Ok(())
+    }
+
+    fn check_canonical(&self, signature: &Signature) -> Result<()> {
+        // Parsing rejects r >= p and s >= n, which leaves a single encoding for every signature.
+        let schnorr_signature = k256::schnorr::Signature::try_from(signature.0.as_slice())
+            .ok()
+            .context("Malformed signature.")?;
+
+        if schnorr_signature.to_bytes() != signature.0 {
+            bail!("Signature isn't canonically encoded.")
+        }
+
+        Ok(())
+    }
+}
+
+pub fn get_signature_scheme(version: Version) -> Result<&'static dyn SignatureScheme> {
+    match version {
+        CURRENT_TRANSACTION_VERSION => Ok(&Ecdsa),
+        SCHNORR_TRANSACTION_VERSION => Ok(&Schnorr),
+        _ => bail!("Unknown transaction version {version}."),
+    }
+}
+
 pub fn sign_transaction(
-    transaction_hash: &Hash,
-    output_index: u32,
-    public_key: &PublicKey,
+    transaction: &RawTransaction,
+    input_index: usize,
     private_key: &PrivateKey,
 ) -> Signature {
+    sign_transaction_with(&Ecdsa, transaction, input_index, private_key)
+}
+
+// The outputs, and every input, must already be in the transaction, since the signature commits
+// to all of it.
+pub fn sign_transaction_with(
+    scheme: &dyn SignatureScheme,
+    transaction: &RawTransaction,
+    input_index: usize,
+    private_key: &PrivateKey,
+) -> Signature {
+    let hash = calculate_transaction_input_hash(
+        &calculate_unsigned_transaction_hash(transaction),
+        input_index,
+    );
+
+    scheme.sign(private_key, &hash)
+}
+
+// Signs every input spending an output with the one key.
+pub fn sign_transaction_inputs(transaction: &mut RawTransaction, private_key: &PrivateKey) {
+    for input_index in 0..transaction.inputs.len() {
+        let new_signature = sign_transaction(transaction, input_index, private_key);
+        if let TransactionInput::FromOutput { signature, .. } = &mut transaction.inputs[input_index]
+        {
+            *signature = new_signature;
+        }
+    }
+}
+
+pub type SignatureCheck<'a> = (
+    &'static dyn SignatureScheme,
+    &'a PublicKey,
+    Hash,
+    &'a Signature,
+);
+
+pub fn verify_transaction_input(transaction: &RawTransaction, input_index: usize) -> Result<()> {
+    let scheme = get_signature_scheme(transaction.version)?;
+
+    let input = transaction
+        .inputs
+        .get(input_index)
+        .context("Transaction has no such input.")?;
+    let TransactionInput::FromOutput {
+        public_key,
+        signature,
+        ..
+    } = input
+    else {
+        bail!("Reward inputs aren't signed.")
+    };
+
+    let hash = calculate_transaction_input_hash(
+        &calculate_unsigned_transaction_hash(transaction),
+        input_index,
+    );
+
+    scheme.verify(public_key, &hash, signature)
+}
+
+pub fn verify_transaction_signatures(transaction: &RawTransaction) -> Result<()> {
+    verify_signatures_batch(&get_signature_checks(transaction)?)
+}
+
+// One check for each input spending an output, reward inputs aren't signed.
+pub fn get_signature_checks(transaction: &RawTransaction) -> Result<Vec<SignatureCheck<'_>>> {
+    let scheme = get_signature_scheme(transaction.version)?;
+    let unsigned_hash = calculate_unsigned_transaction_hash(transaction);
+
+    Ok(transaction
+        .inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(input_index, input)| match input {
+            TransactionInput::FromOutput {
+                public_key,
+                signature,
+                ..
+            } => {
+                let hash = calculate_transaction_input_hash(&unsigned_hash, input_index);
+
+                Some((scheme, public_key, hash, signature))
+            }
+
+            TransactionInput::FromReward { .. } => None,
+        })
+        .collect())
+}
+
+pub fn verify_signatures_batch(checks: &[SignatureCheck]) -> Result<()> {
+    // Stops at the first invalid signature.
+    checks
+        .par_iter()
+        .try_for_each(|(scheme, public_key, hash, signature)| {
+            scheme.verify(public_key, hash, signature)
+        })
+}
+
+fn calculate_unsigned_transaction_hash(transaction: &RawTransaction) -> Hash {
+    // A signature can't commit to itself, so all of them are left out, and everything else is in.
+    let mut unsigned_transaction = transaction.clone();
+    for input in &mut unsigned_transaction.inputs {
+        if let TransactionInput::FromOutput { signature, .. } = input {
+            *signature = Signature::unsigned();
+        }
+    }
+
+    let mut hasher = Sha3_256::default();
+    hasher.update(bincode::serialize(&unsigned_transaction).unwrap());
+
+    hasher.finalize().as_slice().try_into().unwrap()
+}
+
+fn calculate_transaction_input_hash(unsigned_transaction_hash: &Hash, input_index: usize) -> Hash {
+    // The index keeps a signature from being moved to another input with the same key.
     let mut hasher = Sha3_256::default();
-    hasher.update(transaction_hash);
-    hasher.update(output_index.to_le_bytes());
-    hasher.update(public_key);
+    hasher.update(unsigned_transaction_hash);
+    hasher.update((input_index as u32).to_le_bytes());
+
+    hasher.finalize().as_slice().try_into().unwrap()
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct MessageSignature {
+    public_key: PublicKey,
+    signature: Signature,
+}
+
+impl MessageSignature {
+    pub fn from_base58(base58: &str) -> Result<Self> {
+        let bytes = bs58::decode(base58)
+            .into_vec()
+            .ok()
+            .context("Message signature isn't valid base58.")?;
+
+        if bytes.len() <= 64 {
+            bail!("Message signature is too short.")
+        }
+
+        let (public_key, signature) = bytes.split_at(bytes.len() - 64);
 
-    let hash: Hash = hasher.finalize().as_slice().try_into().unwrap();
+        Ok(Self {
+            public_key: PublicKey::from_bytes(public_key)?,
+            signature: Signature(signature.try_into().unwrap()),
+        })
+    }
+
+    pub fn to_base58(&self) -> String {
+        let mut bytes = self.public_key.as_bytes().to_vec();
+        bytes.extend_from_slice(&self.signature.0);
+
+        bs58::encode(&bytes).into_string()
+    }
+}
+
+pub fn sign_message(private_key: &PrivateKey, message: &[u8]) -> MessageSignature {
+    let hash = hash_message(message);
+
+    MessageSignature {
+        public_key: PublicKey::from_private_key(private_key),
+        signature: Signature::new(private_key, &hash),
+    }
+}
+
+pub fn verify_message(
+    address: &Address,
+    message: &[u8],
+    message_signature: &MessageSignature,
+) -> Result<()> {
+    // The embedded public key must be the one the address was derived from.
+    let public_key = &message_signature.public_key;
+    if address.public_key_hash()? != public_key.calculate_hash() {
+        bail!("Message wasn't signed by the given address.")
+    }
+
+    message_signature
+        .signature
+        .verify(public_key, &hash_message(message))
+}
+
+fn hash_message(message: &[u8]) -> Hash {
+    let mut hasher = Sha3_256::default();
+    hasher.update(MESSAGE_PREFIX);
+    hasher.update((message.len() as u64).to_le_bytes());
+    hasher.update(message);
 
-    Signature::new(private_key, &hash)
+    hasher.finalize().as_slice().try_into().unwrap()
 }
@@ -1,174 +1,342 @@
-use crate::{
-    address::{Address, PublicKey},
-    blockchain::{Blockchain, Hash},
-    color::Color,
-    signature::Signature,
-};
-use anyhow::{bail, Context, Result};
-use serde::{ser::SerializeSeq, Serialize};
-use sha3::{Digest, Sha3_256};
-
-pub type Version = u32;
-pub type Point = (i32, i32);
-pub type Credits = i64;
-
-const CURRENT_TRANSACTION_VERSION: u32 = 0;
-
-#[derive(Debug, Serialize)]
-pub enum TransactionInput {
-    FromOutput {
-        transaction_hash: Hash,
-        output_index: u32,
-        public_key: PublicKey,
-        signature: Signature,
-    },
-
-    FromReward {
-        height: u64,
-        value: Credits,
-    },
-}
-
-#[derive(Debug, Serialize)]
-pub enum TransactionOutput {
-    ToInput {
-        value: Credits,
-        public_key_address: Address,
-    },
-
-    ToPixel {
-        value: Credits,
-        position: Point,
-        color: Color,
-    },
-}
-
-#[derive(Debug, Serialize)]
-struct TransactionData {
-    version: Version,
-    inputs: Vec<TransactionInput>,
-    outputs: Vec<TransactionOutput>,
-    lock_time: u32,
-}
-
-#[derive(Debug)]
-pub struct Transaction {
-    data: TransactionData,
-    balance: Credits,
-    hash: Hash,
-}
-
-impl Transaction {
-    pub fn try_new(
-        blockchain: &Blockchain,
-        inputs: Vec<TransactionInput>,
-        outputs: Vec<TransactionOutput>,
-        lock_time: u32,
-    ) -> Result<Self> {
-        // Calculate balance.
-        let input_value: Credits = inputs
-            .iter()
-            .map(|input| match input {
-                TransactionInput::FromOutput {
-                    transaction_hash: hash,
-                    output_index: index,
-                    ..
-                } => {
-                    let input_transaction = blockchain
-                        .find_transaction(hash)
-                        .context("Fail to find input transaction.")?;
-
-                    let output = input_transaction
-                        .get_outputs()
-                        .get(*index as usize)
-                        .context("Fail to find output in input transaction.")?;
-
-                    match output {
-                        TransactionOutput::ToInput { value, .. } => Ok(*value),
-                        TransactionOutput::ToPixel { .. } => {
-                            bail!("Mismatch output type in the input transaction.")
-                        }
-                    }
-                }
-
-                TransactionInput::FromReward { value, .. } => Ok(*value),
-            })
-            .collect::<Result<Vec<_>>>()?
-            .iter()
-            .sum();
-
-        let output_value: Credits = outputs
-            .iter()
-            .map(|output| match output {
-                TransactionOutput::ToInput { value, .. } => *value,
-                TransactionOutput::ToPixel { value, .. } => *value,
-            })
-            .sum();
-
-        let balance = input_value - output_value;
-        if balance < 0 {
-            bail!("A transaction can't have a negative balance.")
-        }
-
-        // Create inner data.
-        let data = TransactionData {
-            version: CURRENT_TRANSACTION_VERSION,
-            inputs,
-            outputs,
-            lock_time,
-        };
-
-        // Calculate hash.
-        let encoded = bincode::serialize(&data).unwrap();
-
-        let mut hasher = Sha3_256::default();
-        hasher.update(&encoded);
-
-        let digest = hasher.finalize();
-        let hash: Hash = digest.as_slice().try_into().unwrap();
-
-        // Return final type.
-        Ok(Self {
-            data,
-            balance,
-            hash,
-        })
-    }
-
-    pub fn get_version(&self) -> Version {
-        self.data.version
-    }
-
-    pub fn get_inputs(&self) -> &[TransactionInput] {
-        &self.data.inputs
-    }
-
-    pub fn get_outputs(&self) -> &[TransactionOutput] {
-        &self.data.outputs
-    }
-
-    pub fn get_lock_time(&self) -> u32 {
-        self.data.lock_time
-    }
-
-    pub fn get_balance(&self) -> Credits {
-        self.balance
-    }
-
-    pub fn get_hash(&self) -> &Hash {
-        &self.hash
-    }
-}
-
-impl Serialize for Transaction {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut seq = serializer.serialize_seq(Some(1))?;
-
-        seq.serialize_element(&self.data)?;
-
-        seq.end()
-    }
-}
+use crate::{
+    address::{Address, PublicKey},
+    blockchain::{decode_hex, encode_hex, Blockchain, Hash, TxHash},
+    color::Color,
+    signature::{get_signature_scheme, Signature},
+};
+use anyhow::{bail, Context, Result};
+use serde::{ser::SerializeSeq, Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+
+pub type Version = u32;
+pub type Point = (i32, i32);
+pub type Credits = i64;
+pub type Layer = u8;
+pub type FactionTag = Vec<u8>;
+
+pub const CURRENT_TRANSACTION_VERSION: Version = 0; // Inputs are signed with ECDSA.
+pub const SCHNORR_TRANSACTION_VERSION: Version = 1; // Inputs are signed with Schnorr.
+
+pub const BASE_LAYER: Layer = 0; // Higher layers are drawn on top of it.
+pub const MAX_FACTION_TAG_SIZE: usize = 16;
+pub const MAX_MEMO_SIZE: usize = 64; // In bytes, not characters.
+pub const MAX_COINBASE_DATA_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransactionInput {
+    FromOutput {
+        transaction_hash: TxHash,
+        output_index: u32,
+        public_key: PublicKey,
+        signature: Signature,
+    },
+
+    FromReward {
+        height: u64,
+        value: Credits,
+        data: Vec<u8>, // Whatever the miner wants to put in, like a pool tag.
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransactionOutput {
+    ToInput {
+        value: Credits,
+        public_key_address: Address,
+    },
+
+    ToPixel {
+        value: Credits,
+        position: Point,
+        color: Color,
+        layer: Layer,
+        faction: Option<FactionTag>, // Team the pixel is painted for, if any.
+        rental: Option<u64>, // Blocks after which the pixel goes back to what it was, if it's only rented.
+        memo: Option<String>,
+    },
+
+    Moderate {
+        value: Credits,
+        position: Point, // Top left corner of the rectangle.
+        width: i32,
+        height: i32,
+        action: ModerationAction,
+    },
+
+    LockPixel {
+        value: Credits,
+        position: Point,
+        duration: u64, // Blocks during which the pixel can't be painted over.
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModerationAction {
+    Blank,                    // Clears every layer of the rectangle.
+    Freeze { duration: u64 }, // Keeps the rectangle from being painted or locked.
+}
+
+impl TransactionOutput {
+    pub fn get_value(&self) -> Credits {
+        match self {
+            TransactionOutput::ToInput { value, .. } => *value,
+            TransactionOutput::ToPixel { value, .. } => *value,
+            TransactionOutput::LockPixel { value, .. } => *value,
+            TransactionOutput::Moderate { value, .. } => *value,
+        }
+    }
+}
+
+pub(crate) fn sum_output_values(outputs: &[TransactionOutput]) -> Result<Credits> {
+    // An output below zero would let the others pay out more than the inputs hold.
+    outputs.iter().try_fold(0, |total: Credits, output| {
+        if output.get_value() < 0 {
+            bail!("Transaction outputs can't have a negative value.")
+        }
+
+        total
+            .checked_add(output.get_value())
+            .context("Transaction output values overflow.")
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawTransaction {
+    pub version: Version,
+    pub inputs: Vec<TransactionInput>,
+    pub outputs: Vec<TransactionOutput>,
+    pub lock_time: u32,
+}
+
+impl RawTransaction {
+    pub fn calculate_hash(&self) -> TxHash {
+        let encoded = bincode::serialize(self).unwrap();
+
+        let mut hasher = Sha3_256::default();
+        hasher.update(&encoded);
+
+        let digest = hasher.finalize();
+        let hash: Hash = digest.as_slice().try_into().unwrap();
+
+        hash.into()
+    }
+
+    // The bytes that are hashed, so a transaction keeps its hash wherever the hex is taken.
+    pub fn to_hex(&self) -> String {
+        encode_hex(&bincode::serialize(self).unwrap())
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let bytes = decode_hex(hex)?;
+        let raw_transaction: Self =
+            bincode::deserialize(&bytes).context("Fail to decode raw transaction.")?;
+
+        // Anything after the transaction would give the same one under another hex.
+        if bincode::serialized_size(&raw_transaction)? != bytes.len() as u64 {
+            bail!("Raw transaction has trailing bytes.")
+        }
+
+        Ok(raw_transaction)
+    }
+
+    pub fn get_size(&self) -> usize {
+        // What the transaction will take, as it's serialized in a sequence of one.
+        bincode::serialized_size(std::slice::from_ref(self)).unwrap() as usize
+    }
+
+    pub fn get_parent_hashes(&self) -> Vec<TxHash> {
+        let mut parent_hashes = vec![];
+        for input in &self.inputs {
+            if let TransactionInput::FromOutput {
+                transaction_hash, ..
+            } = input
+            {
+                if !parent_hashes.contains(transaction_hash) {
+                    parent_hashes.push(*transaction_hash);
+                }
+            }
+        }
+
+        parent_hashes
+    }
+}
+
+#[derive(Debug)]
+pub struct Transaction {
+    data: RawTransaction,
+    balance: Credits,
+    hash: TxHash,
+}
+
+impl Transaction {
+    pub fn try_new(
+        blockchain: &Blockchain,
+        inputs: Vec<TransactionInput>,
+        outputs: Vec<TransactionOutput>,
+        lock_time: u32,
+    ) -> Result<Self> {
+        Self::try_new_with_version(
+            blockchain,
+            CURRENT_TRANSACTION_VERSION,
+            inputs,
+            outputs,
+            lock_time,
+        )
+    }
+
+    pub fn try_new_with_version(
+        blockchain: &Blockchain,
+        version: Version,
+        inputs: Vec<TransactionInput>,
+        outputs: Vec<TransactionOutput>,
+        lock_time: u32,
+    ) -> Result<Self> {
+        Self::from_raw(
+            blockchain,
+            RawTransaction {
+                version,
+                inputs,
+                outputs,
+                lock_time,
+            },
+        )
+    }
+
+    pub fn from_raw(blockchain: &Blockchain, data: RawTransaction) -> Result<Self> {
+        Self::from_raw_after(blockchain, data, &[])
+    }
+
+    pub(crate) fn from_raw_after(
+        blockchain: &Blockchain,
+        data: RawTransaction,
+        earlier_transactions: &[Transaction], // Those before it in the same block.
+    ) -> Result<Self> {
+        // The version selects the signature scheme, so it must be a known one.
+        get_signature_scheme(data.version)?;
+
+        // Calculate balance.
+        let input_value: Credits = data
+            .inputs
+            .iter()
+            .map(|input| match input {
+                TransactionInput::FromOutput {
+                    transaction_hash: hash,
+                    output_index: index,
+                    ..
+                } => {
+                    // Pending transactions can be spent too, so children can pay for their parents.
+                    let input_transaction = blockchain
+                        .find_transaction(hash)
+                        .or_else(|| {
+                            earlier_transactions
+                                .iter()
+                                .find(|transaction| transaction.get_hash() == hash)
+                        })
+                        .or_else(|| blockchain.find_pending_transaction(hash))
+                        .context("Fail to find input transaction.")?;
+
+                    let output = input_transaction
+                        .get_outputs()
+                        .get(*index as usize)
+                        .context("Fail to find output in input transaction.")?;
+
+                    match output {
+                        TransactionOutput::ToInput { value, .. } => Ok(*value),
+                        TransactionOutput::ToPixel { .. }
+                        | TransactionOutput::LockPixel { .. }
+                        | TransactionOutput::Moderate { .. } => {
+                            bail!("Mismatch output type in the input transaction.")
+                        }
+                    }
+                }
+
+                TransactionInput::FromReward { value, .. } => Ok(*value),
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .try_fold(0, Credits::checked_add)
+            .context("Transaction input values overflow.")?;
+
+        let output_value = sum_output_values(&data.outputs)?;
+
+        let balance = input_value - output_value;
+        if balance < 0 {
+            bail!("A transaction can't have a negative balance.")
+        }
+
+        // Calculate hash.
+        let hash = data.calculate_hash();
+
+        // Return final type.
+        Ok(Self {
+            data,
+            balance,
+            hash,
+        })
+    }
+
+    pub fn to_raw(&self) -> RawTransaction {
+        self.data.clone()
+    }
+
+    pub fn get_raw(&self) -> &RawTransaction {
+        &self.data
+    }
+
+    pub fn get_version(&self) -> Version {
+        self.data.version
+    }
+
+    pub fn get_inputs(&self) -> &[TransactionInput] {
+        &self.data.inputs
+    }
+
+    pub fn get_outputs(&self) -> &[TransactionOutput] {
+        &self.data.outputs
+    }
+
+    pub fn get_lock_time(&self) -> u32 {
+        self.data.lock_time
+    }
+
+    pub fn get_balance(&self) -> Credits {
+        self.balance
+    }
+
+    pub fn get_size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize
+    }
+
+    pub fn get_tax_rate(&self) -> f64 {
+        self.balance as f64 / self.get_size() as f64
+    }
+
+    pub fn get_hash(&self) -> &TxHash {
+        &self.hash
+    }
+
+    pub fn get_parent_hashes(&self) -> Vec<TxHash> {
+        self.data.get_parent_hashes()
+    }
+
+    pub fn get_signer(&self) -> Option<&PublicKey> {
+        // Whoever signed the first input, which is who paints the pixels.
+        self.data.inputs.iter().find_map(|input| match input {
+            TransactionInput::FromOutput { public_key, .. } => Some(public_key),
+            TransactionInput::FromReward { .. } => None,
+        })
+    }
+}
+
+impl Serialize for Transaction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(1))?;
+
+        seq.serialize_element(&self.data)?;
+
+        seq.end()
+    }
+}
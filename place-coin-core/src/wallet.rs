@@ -0,0 +1,617 @@
+use crate::{
+    address::{Address, PrivateKey, PublicKey},
+    block::Block,
+    blockchain::{Blockchain, TxHash},
+    chain_params::ChainParams,
+    color::Color,
+    signature::{get_signature_scheme, sign_transaction_with, verify_transaction_input, Signature},
+    transaction::{
+        Credits, Point, RawTransaction, Transaction, TransactionInput, TransactionOutput, Version,
+        BASE_LAYER,
+    },
+};
+use anyhow::{bail, Context, Result};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::HashMap,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+const DETERMINISTIC_SEED_PREFIX: &[u8] = b"Place Coin Deterministic Wallet:";
+
+pub type WalletEventCallback = Box<dyn Fn(&WalletEvent) + Send>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletEvent {
+    PaymentReceived {
+        transaction_hash: TxHash,
+        public_key_address: Address,
+        value: Credits,
+    },
+
+    TransactionConfirmed {
+        transaction_hash: TxHash,
+        height: u64,
+    },
+
+    PixelOverwritten {
+        position: Point,
+        color: Color,
+        transaction_hash: TxHash,
+    },
+}
+
+#[derive(Default)]
+pub struct Wallet {
+    chain_params: ChainParams,
+    transaction_version: Version,
+    private_keys: Vec<PrivateKey>,
+    painted_pixels: HashMap<Point, TxHash>,
+    callbacks: Vec<WalletEventCallback>,
+    senders: Vec<Sender<WalletEvent>>,
+}
+
+impl Wallet {
+    pub fn new(private_keys: Vec<PrivateKey>) -> Self {
+        Self::for_chain(ChainParams::mainnet(), private_keys)
+    }
+
+    pub fn for_chain(chain_params: ChainParams, private_keys: Vec<PrivateKey>) -> Self {
+        Self {
+            chain_params,
+            private_keys,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_chain_params(&mut self, chain_params: ChainParams) {
+        self.chain_params = chain_params;
+    }
+
+    pub fn set_transaction_version(&mut self, transaction_version: Version) -> Result<()> {
+        // The version selects how inputs are signed, so refuse the ones we don't know.
+        get_signature_scheme(transaction_version)?;
+        self.transaction_version = transaction_version;
+
+        Ok(())
+    }
+
+    pub fn deterministic(seed: &str) -> Self {
+        // Hash the seed until it lands on a valid private key.
+        let private_key = (0u32..)
+            .map(|counter| {
+                let mut hasher = Sha3_256::default();
+                hasher.update(DETERMINISTIC_SEED_PREFIX);
+                hasher.update(seed.as_bytes());
+                hasher.update(counter.to_le_bytes());
+
+                PrivateKey::from_bytes(hasher.finalize().into())
+            })
+            .find(|private_key| k256::SecretKey::from_slice(private_key.as_bytes()).is_ok())
+            .unwrap();
+
+        Self::new(vec![private_key])
+    }
+
+    pub fn on_event(&mut self, callback: WalletEventCallback) {
+        self.callbacks.push(callback);
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<WalletEvent> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+
+        receiver
+    }
+
+    pub fn connect_block(&mut self, blockchain: &Blockchain, block: &Block) -> Result<()> {
+        let addresses = self.get_addresses();
+        let height = block.get_block_height()?;
+
+        let mut events = vec![];
+        for transaction in block.get_transactions() {
+            let transaction_hash = *transaction.get_hash();
+
+            // A transaction is ours if it spends any output paid to one of our addresses.
+            let is_ours = transaction.get_inputs().iter().any(|input| match input {
+                TransactionInput::FromOutput {
+                    transaction_hash,
+                    output_index,
+                    ..
+                } => blockchain
+                    .find_transaction(transaction_hash)
+                    .and_then(|transaction| transaction.get_outputs().get(*output_index as usize))
+                    .is_some_and(|output| match output {
+                        TransactionOutput::ToInput {
+                            public_key_address, ..
+                        } => addresses.contains(public_key_address),
+                        TransactionOutput::ToPixel { .. }
+                        | TransactionOutput::LockPixel { .. }
+                        | TransactionOutput::Moderate { .. } => false,
+                    }),
+
+                TransactionInput::FromReward { .. } => false,
+            });
+
+            if is_ours {
+                events.push(WalletEvent::TransactionConfirmed {
+                    transaction_hash,
+                    height,
+                });
+            }
+
+            for output in transaction.get_outputs() {
+                match output {
+                    TransactionOutput::ToInput {
+                        value,
+                        public_key_address,
+                    } => {
+                        // Change that we send back to ourselves isn't a payment.
+                        if !is_ours && addresses.contains(public_key_address) {
+                            events.push(WalletEvent::PaymentReceived {
+                                transaction_hash,
+                                public_key_address: public_key_address.clone(),
+                                value: *value,
+                            });
+                        }
+                    }
+
+                    // Overlays and rentals don't cover the base layer for good, so they don't count.
+                    TransactionOutput::ToPixel {
+                        position,
+                        color,
+                        layer: BASE_LAYER,
+                        rental: None,
+                        ..
+                    } => {
+                        if is_ours {
+                            self.painted_pixels.insert(*position, transaction_hash);
+                        } else if self.painted_pixels.remove(position).is_some() {
+                            events.push(WalletEvent::PixelOverwritten {
+                                position: *position,
+                                color: *color,
+                                transaction_hash,
+                            });
+                        }
+                    }
+
+                    TransactionOutput::ToPixel { .. }
+                    | TransactionOutput::LockPixel { .. }
+                    | TransactionOutput::Moderate { .. } => {}
+                }
+            }
+        }
+
+        for event in events {
+            self.emit(event);
+        }
+
+        Ok(())
+    }
+
+    pub fn add_private_key(&mut self, private_key: PrivateKey) {
+        self.private_keys.push(private_key);
+    }
+
+    pub fn get_private_keys(&self) -> &[PrivateKey] {
+        &self.private_keys
+    }
+
+    pub fn get_addresses(&self) -> Vec<Address> {
+        self.private_keys
+            .iter()
+            .map(|private_key| self.get_address(private_key))
+            .collect()
+    }
+
+    pub fn sign_raw_transaction(&self, raw_transaction: &mut RawTransaction) -> Result<Vec<usize>> {
+        // Inputs naming one of our keys are signed, the rest are left for whoever has theirs. The
+        // signatures commit to the whole transaction, so it can't change after this.
+        let scheme = get_signature_scheme(raw_transaction.version)?;
+        for input_index in 0..raw_transaction.inputs.len() {
+            let TransactionInput::FromOutput { public_key, .. } =
+                &raw_transaction.inputs[input_index]
+            else {
+                continue;
+            };
+
+            let private_key = self
+                .private_keys
+                .iter()
+                .find(|private_key| PublicKey::from_private_key(private_key) == *public_key);
+            if let Some(private_key) = private_key {
+                let new_signature =
+                    sign_transaction_with(scheme, raw_transaction, input_index, private_key);
+                if let TransactionInput::FromOutput { signature, .. } =
+                    &mut raw_transaction.inputs[input_index]
+                {
+                    *signature = new_signature;
+                }
+            }
+        }
+
+        // Whatever doesn't verify still needs signing, reward inputs never do.
+        Ok(raw_transaction
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| matches!(input, TransactionInput::FromOutput { .. }))
+            .filter(|(index, _)| verify_transaction_input(raw_transaction, *index).is_err())
+            .map(|(index, _)| index)
+            .collect())
+    }
+
+    pub fn fund_raw_transaction(
+        &self,
+        blockchain: &Blockchain,
+        raw_transaction: &mut RawTransaction,
+        tax: Credits,
+    ) -> Result<Option<usize>> {
+        debug_assert!(tax >= 0);
+
+        if !raw_transaction.inputs.is_empty() {
+            bail!("Only transactions without inputs can be funded.")
+        }
+
+        if raw_transaction.outputs.is_empty() {
+            bail!("At least one output is needed.")
+        }
+
+        let value: Credits = raw_transaction
+            .outputs
+            .iter()
+            .map(TransactionOutput::get_value)
+            .sum();
+        let (outputs, total) = self.collect_outputs(blockchain, Some(value + tax));
+
+        if total < value + tax {
+            bail!("Not enough credits to fund the transaction.")
+        }
+
+        // Left unsigned, so they can be looked over before signing.
+        raw_transaction.inputs = outputs
+            .into_iter()
+            .map(|(transaction_hash, output_index, private_key)| {
+                get_unsigned_input(&transaction_hash, output_index, private_key)
+            })
+            .collect();
+
+        // The change goes back to the first wallet address, and where it went is handed back.
+        let change_value = total - value - tax;
+        if change_value <= 0 {
+            return Ok(None);
+        }
+        raw_transaction.outputs.push(TransactionOutput::ToInput {
+            value: change_value,
+            public_key_address: self.get_address(&self.private_keys[0]),
+        });
+
+        Ok(Some(raw_transaction.outputs.len() - 1))
+    }
+
+    pub fn sweep(
+        &self,
+        blockchain: &mut Blockchain,
+        to_address: &Address,
+        tax: Credits,
+    ) -> Result<()> {
+        debug_assert!(tax >= 0);
+
+        // Spend every output owned by any of the wallet keys.
+        let (inputs, total) = self.collect_inputs(blockchain, None);
+
+        if inputs.is_empty() {
+            bail!("No spendable outputs to sweep.")
+        }
+
+        if total <= tax {
+            bail!("Not enough credits to pay the sweep tax.")
+        }
+
+        // Send everything, minus the tax, to a single output.
+        let outputs = vec![TransactionOutput::ToInput {
+            value: total - tax,
+            public_key_address: to_address.clone(),
+        }];
+
+        let transaction = self.create_signed_transaction(
+            blockchain,
+            self.transaction_version,
+            inputs,
+            outputs,
+            0,
+        )?;
+        blockchain.new_transaction(transaction)?;
+
+        Ok(())
+    }
+
+    pub fn send_many(
+        &self,
+        blockchain: &mut Blockchain,
+        recipients: Vec<(Address, Credits)>,
+        tax: Credits,
+    ) -> Result<TxHash> {
+        let transaction = self.create_send_many(blockchain, recipients, tax)?;
+        let transaction_hash = *transaction.get_hash();
+        blockchain.new_transaction(transaction)?;
+
+        Ok(transaction_hash)
+    }
+
+    pub fn send_many_within(
+        &self,
+        blockchain: &mut Blockchain,
+        recipients: Vec<(Address, Credits)>,
+        target_blocks: u64,
+    ) -> Result<TxHash> {
+        let tax = self.estimate_tax(blockchain, &recipients, target_blocks)?;
+
+        self.send_many(blockchain, recipients, tax)
+    }
+
+    pub fn estimate_tax(
+        &self,
+        blockchain: &Blockchain,
+        recipients: &[(Address, Credits)],
+        target_blocks: u64,
+    ) -> Result<Credits> {
+        let estimate = blockchain
+            .estimate_fee(target_blocks)
+            .context("Not enough confirmed transactions to estimate the tax.")?;
+
+        // A draft without tax is about as big as the real thing, give or take the change output.
+        let draft = self.create_send_many(blockchain, recipients.to_vec(), 0)?;
+
+        Ok((estimate.tax_rate * draft.get_size() as f64).ceil() as Credits)
+    }
+
+    fn create_send_many(
+        &self,
+        blockchain: &Blockchain,
+        recipients: Vec<(Address, Credits)>,
+        tax: Credits,
+    ) -> Result<Transaction> {
+        debug_assert!(tax >= 0);
+
+        if recipients.is_empty() {
+            bail!("At least one recipient is needed.")
+        }
+
+        if recipients.iter().any(|(_, value)| *value <= 0) {
+            bail!("Every recipient must receive a positive amount of credits.")
+        }
+
+        let value: Credits = recipients.iter().map(|(_, value)| value).sum();
+        let (inputs, total) = self.collect_inputs(blockchain, Some(value + tax));
+
+        if total < value + tax {
+            bail!("Not enough credits to make the transaction.")
+        }
+
+        // One output per recipient, plus the change back to the first wallet address.
+        let mut outputs = recipients
+            .into_iter()
+            .map(|(public_key_address, value)| TransactionOutput::ToInput {
+                value,
+                public_key_address,
+            })
+            .collect::<Vec<_>>();
+
+        let change_value = total - value - tax;
+        if change_value > 0 {
+            outputs.push(TransactionOutput::ToInput {
+                value: change_value,
+                public_key_address: self.get_address(&self.private_keys[0]),
+            });
+        }
+
+        self.create_signed_transaction(blockchain, self.transaction_version, inputs, outputs, 0)
+    }
+
+    pub fn bump_fee(
+        &self,
+        blockchain: &mut Blockchain,
+        transaction_hash: &TxHash,
+        new_tax: Credits,
+    ) -> Result<()> {
+        let pending = blockchain
+            .find_pending_transaction(transaction_hash)
+            .context("Transaction isn't pending.")?;
+
+        let extra_tax = new_tax - pending.get_balance();
+        if extra_tax <= 0 {
+            bail!("New tax must be higher than the current one.")
+        }
+
+        // Pay the extra tax out of our change output. Inputs stay the same, but are signed again
+        // since their signatures commit to the outputs.
+        let addresses = self.get_addresses();
+        let inputs = pending.get_inputs().to_vec();
+        let mut outputs = pending.get_outputs().to_vec();
+
+        let change_value = outputs
+            .iter_mut()
+            .find_map(|output| match output {
+                TransactionOutput::ToInput {
+                    value,
+                    public_key_address,
+                } if addresses.contains(public_key_address) => Some(value),
+                _ => None,
+            })
+            .context("Transaction has no change output to take the extra tax from.")?;
+
+        if *change_value < extra_tax {
+            bail!("Change output can't cover the extra tax.")
+        }
+
+        *change_value -= extra_tax;
+
+        let transaction = self.create_signed_transaction(
+            blockchain,
+            pending.get_version(),
+            inputs,
+            outputs,
+            pending.get_lock_time(),
+        )?;
+        blockchain.replace_transaction(transaction)?;
+
+        Ok(())
+    }
+
+    pub fn bump_fee_with_child(
+        &self,
+        blockchain: &mut Blockchain,
+        parent_hash: &TxHash,
+        tax: Credits,
+    ) -> Result<()> {
+        debug_assert!(tax >= 0);
+
+        let parent = blockchain
+            .find_pending_transaction(parent_hash)
+            .context("Parent transaction isn't pending.")?;
+
+        // Spend every output of the parent that belongs to us.
+        let mut inputs = vec![];
+        let mut total = 0;
+        for (output_index, output) in parent.get_outputs().iter().enumerate() {
+            if let TransactionOutput::ToInput {
+                value,
+                public_key_address,
+            } = output
+            {
+                if let Some(private_key) = self.find_private_key(public_key_address) {
+                    inputs.push(get_unsigned_input(
+                        parent_hash,
+                        output_index as u32,
+                        private_key,
+                    ));
+
+                    total += value;
+                }
+            }
+        }
+
+        if inputs.is_empty() {
+            bail!("Parent transaction has no outputs that we can spend.")
+        }
+
+        if total <= tax {
+            bail!("Not enough credits in the parent outputs to pay the tax.")
+        }
+
+        let outputs = vec![TransactionOutput::ToInput {
+            value: total - tax,
+            public_key_address: self.get_address(&self.private_keys[0]),
+        }];
+
+        let transaction = self.create_signed_transaction(
+            blockchain,
+            self.transaction_version,
+            inputs,
+            outputs,
+            0,
+        )?;
+        blockchain.new_transaction(transaction)?;
+
+        Ok(())
+    }
+
+    fn get_address(&self, private_key: &PrivateKey) -> Address {
+        Address::from_private_key_for(&self.chain_params, private_key)
+    }
+
+    fn find_private_key(&self, address: &Address) -> Option<&PrivateKey> {
+        self.private_keys
+            .iter()
+            .find(|private_key| self.get_address(private_key) == *address)
+    }
+
+    fn collect_inputs(
+        &self,
+        blockchain: &Blockchain,
+        target: Option<Credits>,
+    ) -> (Vec<TransactionInput>, Credits) {
+        let (outputs, total) = self.collect_outputs(blockchain, target);
+        let inputs = outputs
+            .into_iter()
+            .map(|(transaction_hash, output_index, private_key)| {
+                get_unsigned_input(&transaction_hash, output_index, private_key)
+            })
+            .collect();
+
+        (inputs, total)
+    }
+
+    fn collect_outputs(
+        &self,
+        blockchain: &Blockchain,
+        target: Option<Credits>,
+    ) -> (Vec<(TxHash, u32, &PrivateKey)>, Credits) {
+        let mut outputs = vec![];
+        let mut total = 0;
+        for private_key in &self.private_keys {
+            let address = self.get_address(private_key);
+
+            for (transaction_hash, output_index, value) in
+                blockchain.get_spendable_outputs(&address)
+            {
+                // Stop as soon as the target is reached, if there is one.
+                if target.is_some_and(|target| total >= target) {
+                    return (outputs, total);
+                }
+
+                outputs.push((*transaction_hash, output_index, private_key));
+
+                total += value;
+            }
+        }
+
+        (outputs, total)
+    }
+
+    fn create_signed_transaction(
+        &self,
+        blockchain: &Blockchain,
+        version: Version,
+        inputs: Vec<TransactionInput>,
+        outputs: Vec<TransactionOutput>,
+        lock_time: u32,
+    ) -> Result<Transaction> {
+        let mut raw_transaction = RawTransaction {
+            version,
+            inputs,
+            outputs,
+            lock_time,
+        };
+        if !self.sign_raw_transaction(&mut raw_transaction)?.is_empty() {
+            bail!("Transaction has inputs the wallet has no key for.")
+        }
+
+        Transaction::from_raw(blockchain, raw_transaction)
+    }
+
+    fn emit(&mut self, event: WalletEvent) {
+        for callback in &self.callbacks {
+            callback(&event);
+        }
+
+        // Drop the channels whose receivers are gone.
+        self.senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+// Signed once the whole transaction is known.
+fn get_unsigned_input(
+    transaction_hash: &TxHash,
+    output_index: u32,
+    private_key: &PrivateKey,
+) -> TransactionInput {
+    TransactionInput::FromOutput {
+        transaction_hash: *transaction_hash,
+        output_index,
+        public_key: PublicKey::from_private_key(private_key),
+        signature: Signature::unsigned(),
+    }
+}
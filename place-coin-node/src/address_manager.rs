@@ -0,0 +1,283 @@
+// Addresses of peers this node heard about, from its seeds and from other peers. They can be kept
+// in a file with one address per line, followed by when it was last seen, when a connection to it
+// first and last worked, and how many times in a row it failed. Times are in seconds since the
+// Unix epoch, zero for never.
+//
+// Addresses are grouped by the network they're in, and a group only gets so many of them. This
+// way someone holding a lot of addresses in one place can't push out every other peer, nor take
+// all of this node's connections and feed it whatever they want (an eclipse attack).
+
+use anyhow::{Context, Result};
+use std::{
+    cmp::Reverse,
+    fs,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const MAX_ADDRESSES: usize = 1000;
+pub const MAX_ADDRESSES_PER_GROUP: usize = 64;
+pub const MAX_FAILURES: u32 = 10; // In a row, for an address that never worked.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressInfo {
+    pub address: SocketAddr,
+    pub last_seen: u64,
+    pub first_success: u64,
+    pub last_success: u64,
+    pub failures: u32, // Since the last success.
+}
+
+impl AddressInfo {
+    fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            last_seen: 0,
+            first_success: 0,
+            last_success: 0,
+            failures: 0,
+        }
+    }
+
+    fn get_quality(&self) -> impl Ord {
+        // Peers that worked before come first, the longest lived ones ahead of the others.
+        (
+            self.last_success > 0,
+            self.last_success - self.first_success,
+            Reverse(self.failures),
+            self.last_seen,
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AddressManager {
+    addresses: Vec<AddressInfo>, // Most recently seen first.
+    path: Option<PathBuf>,       // Where the addresses are saved to, if anywhere.
+}
+
+impl AddressManager {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        // Nothing saved yet is the same as nothing known.
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error).context("Fail to read peer addresses."),
+        };
+
+        let mut address_manager = Self {
+            addresses: vec![],
+            path: Some(path),
+        };
+        for line in contents.lines() {
+            // Files from before the connection times were kept only have when it was last seen.
+            let mut fields = line.split(' ');
+            let mut info =
+                AddressInfo::new(fields.next().context("Peer address is missing.")?.parse()?);
+            info.last_seen = fields
+                .next()
+                .context("Peer address has no last seen time.")?
+                .parse()?;
+            info.first_success = fields.next().map_or(Ok(0), str::parse)?;
+            info.last_success = fields.next().map_or(Ok(0), str::parse)?;
+            info.failures = fields.next().map_or(Ok(0), str::parse)?;
+
+            address_manager.insert(info);
+        }
+
+        Ok(address_manager)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let contents: String = self
+                .addresses
+                .iter()
+                .map(|info| {
+                    format!(
+                        "{} {} {} {} {}\n",
+                        info.address,
+                        info.last_seen,
+                        info.first_success,
+                        info.last_success,
+                        info.failures
+                    )
+                })
+                .collect();
+
+            fs::write(path, contents).context("Fail to save peer addresses.")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_seeds(&mut self, seeds: &[&str]) -> Result<()> {
+        // Seeds can be host names, which may stand for several nodes.
+        let mut addresses = vec![];
+        for seed in seeds {
+            addresses.extend(seed.to_socket_addrs().context("Fail to resolve seed.")?);
+        }
+        self.add(&addresses);
+
+        Ok(())
+    }
+
+    pub fn add(&mut self, addresses: &[SocketAddr]) {
+        let now = get_now();
+
+        for address in addresses {
+            let mut info = self
+                .take(address)
+                .unwrap_or_else(|| AddressInfo::new(*address));
+            info.last_seen = now;
+            self.insert(info);
+        }
+    }
+
+    pub fn mark_success(&mut self, address: &SocketAddr) {
+        let now = get_now();
+
+        let mut info = self
+            .take(address)
+            .unwrap_or_else(|| AddressInfo::new(*address));
+        info.last_seen = now;
+        if info.first_success == 0 {
+            info.first_success = now;
+        }
+        info.last_success = now;
+        info.failures = 0;
+        self.insert(info);
+    }
+
+    pub fn mark_failure(&mut self, address: &SocketAddr) {
+        if let Some(info) = self
+            .addresses
+            .iter_mut()
+            .find(|info| info.address == *address)
+        {
+            info.failures += 1;
+        }
+
+        // Addresses that never worked are given up on after a while.
+        self.addresses
+            .retain(|info| info.last_success > 0 || info.failures < MAX_FAILURES);
+    }
+
+    pub fn select_outbound(&self, count: usize, connected: &[SocketAddr]) -> Vec<SocketAddr> {
+        let mut candidates = self
+            .addresses
+            .iter()
+            .filter(|info| !connected.contains(&info.address))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|info| Reverse(info.get_quality()));
+
+        // The best one of every group first, then the rest if there aren't enough groups.
+        let mut groups = connected.iter().map(get_group).collect::<Vec<_>>();
+        let mut selected = vec![];
+        for info in &candidates {
+            let group = get_group(&info.address);
+            if selected.len() < count && !groups.contains(&group) {
+                groups.push(group);
+                selected.push(info.address);
+            }
+        }
+        for info in &candidates {
+            if selected.len() < count && !selected.contains(&info.address) {
+                selected.push(info.address);
+            }
+        }
+
+        selected
+    }
+
+    pub fn get_addresses(&self) -> Vec<SocketAddr> {
+        self.addresses.iter().map(|info| info.address).collect()
+    }
+
+    pub fn get_info(&self, address: &SocketAddr) -> Option<&AddressInfo> {
+        self.addresses.iter().find(|info| info.address == *address)
+    }
+
+    pub fn contains(&self, address: &SocketAddr) -> bool {
+        self.get_info(address).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    fn take(&mut self, address: &SocketAddr) -> Option<AddressInfo> {
+        let index = self
+            .addresses
+            .iter()
+            .position(|info| info.address == *address)?;
+
+        Some(self.addresses.remove(index))
+    }
+
+    fn insert(&mut self, info: AddressInfo) {
+        // A full group, or a full table, makes room by dropping its worst address if it's worse.
+        let group = get_group(&info.address);
+        if self.count_group(&group) >= MAX_ADDRESSES_PER_GROUP {
+            self.evict(&info, |other| get_group(&other.address) == group);
+        }
+        if self.addresses.len() >= MAX_ADDRESSES {
+            self.evict(&info, |_| true);
+        }
+        if self.count_group(&group) >= MAX_ADDRESSES_PER_GROUP
+            || self.addresses.len() >= MAX_ADDRESSES
+        {
+            return;
+        }
+
+        let index = self
+            .addresses
+            .partition_point(|other| other.last_seen >= info.last_seen);
+        self.addresses.insert(index, info);
+    }
+
+    fn evict(&mut self, info: &AddressInfo, is_candidate: impl Fn(&AddressInfo) -> bool) {
+        let worst_index = self
+            .addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, other)| is_candidate(other))
+            .min_by_key(|(_, other)| other.get_quality())
+            .map(|(index, _)| index);
+
+        if let Some(index) = worst_index {
+            if self.addresses[index].get_quality() < info.get_quality() {
+                self.addresses.remove(index);
+            }
+        }
+    }
+
+    fn count_group(&self, group: &[u8]) -> usize {
+        self.addresses
+            .iter()
+            .filter(|other| get_group(&other.address) == group)
+            .count()
+    }
+}
+
+fn get_group(address: &SocketAddr) -> Vec<u8> {
+    // The /16 of IPv4 addresses and the /32 of IPv6 ones, as those are usually handed out together.
+    match address.ip() {
+        IpAddr::V4(ip) => ip.octets()[..2].to_vec(),
+        IpAddr::V6(ip) => ip.octets()[..4].to_vec(),
+    }
+}
+
+fn get_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
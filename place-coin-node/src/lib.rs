@@ -0,0 +1,942 @@
+pub mod address_manager;
+pub mod limits;
+pub mod nat;
+pub mod net;
+pub mod p2p;
+pub mod pool;
+pub mod proxy;
+pub mod shutdown;
+pub mod stratum;
+pub mod sync;
+pub mod time;
+pub mod transport;
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        address_manager::{AddressManager, MAX_ADDRESSES_PER_GROUP, MAX_FAILURES},
+        limits::{TokenBucket, PEER_MESSAGES},
+        nat::PortMapping,
+        net::{negotiate_version, Inventory, Message, PROTOCOL_VERSION},
+        p2p::Node,
+        pool::Pool,
+        proxy::{Socks5Transport, ONION_PREFIX},
+        shutdown::{load_state, save_state, ShutdownController},
+        stratum::{StratumServer, SHARE_TARGET},
+        sync::{ChainSync, BLOCK_DOWNLOAD_TIMEOUT, MAX_HEADERS, WINDOW_SIZE},
+        time::{get_timestamp, NetworkTime, MAX_TIME_OFFSET},
+        transport::MemoryNetwork,
+    };
+    use anyhow::Result;
+    use place_coin::{
+        address::{Address, PublicKey},
+        block::BlockHeader,
+        blockchain::{BlockHash, Blockchain, Proof},
+        bloom::BloomFilter,
+        chain_params::ChainParams,
+        mempool::Rejection,
+        signature::{sign_transaction_inputs, Signature},
+        transaction::{
+            RawTransaction, TransactionInput, TransactionOutput, CURRENT_TRANSACTION_VERSION,
+        },
+        wallet::Wallet,
+    };
+    use std::{
+        io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+        net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn node_address(seed: &str) -> Address {
+        Address::from_private_key(&Wallet::deterministic(seed).get_private_keys()[0])
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        // Peers talk in the background, so give them some time.
+        let start = Instant::now();
+        while !condition() {
+            if start.elapsed() > Duration::from_secs(10) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        true
+    }
+
+    #[test]
+    fn test_stratum_mining() -> Result<()> {
+        let pool_address = node_address("pool");
+        let worker_address = node_address("worker");
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(pool_address.clone())));
+
+        let server = StratumServer::new(blockchain.clone(), pool_address);
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let server_address = listener.local_addr()?;
+        server.listen(listener);
+        server.push_job()?;
+
+        let mut stream = TcpStream::connect(server_address)?;
+        let mut lines = BufReader::new(stream.try_clone()?).lines();
+        writeln!(stream, "subscribe {worker_address}")?;
+
+        let job = lines.next().unwrap()?;
+        let words = job.split_whitespace().collect::<Vec<_>>();
+        assert_eq!(words[..3], ["job", "0", "1"]);
+        let last_proof: Proof = words[3].parse()?;
+
+        // A share is easier to find than a block, so grind one that is only a share first.
+        let share = (0..Proof::MAX)
+            .find(|proof| {
+                let hash = Blockchain::calculate_proof_hash(&last_proof, proof);
+                hash[0] != 0 && hash[0] < SHARE_TARGET
+            })
+            .unwrap();
+        writeln!(stream, "submit 0 {share}")?;
+        assert_eq!(lines.next().unwrap()?, "share");
+
+        let solution = (0..Proof::MAX)
+            .find(|proof| Blockchain::validate_proof(&last_proof, proof))
+            .unwrap();
+        writeln!(stream, "submit 0 {solution}")?;
+
+        // The next job comes in as soon as the block is found.
+        assert!(lines.next().unwrap()?.starts_with("job 1 2 "));
+        assert_eq!(lines.next().unwrap()?, "block");
+        assert_eq!(server.get_shares(&worker_address), 2);
+        assert_eq!(blockchain.lock().unwrap().get_next_block_height()?, 2);
+        assert_eq!(
+            blockchain.lock().unwrap().get_peer_credits(&worker_address),
+            1000
+        );
+
+        writeln!(stream, "submit 0 {solution}")?;
+        assert!(lines.next().unwrap()?.starts_with("rejected"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pplns_payouts() -> Result<()> {
+        let (first_address, second_address) = (node_address("first"), node_address("second"));
+        let mut blockchain = Blockchain::new(node_address("pool"));
+
+        // Only the last shares count, so the oldest one of the first worker is out of the window.
+        let mut pool = Pool::new(4);
+        for address in [
+            &first_address,
+            &first_address,
+            &second_address,
+            &first_address,
+        ] {
+            pool.record_share(address);
+        }
+        pool.record_share(&first_address);
+        assert_eq!(pool.get_share_count(&first_address), 3);
+        assert_eq!(
+            pool.get_payouts(),
+            vec![(first_address.clone(), 3), (second_address.clone(), 1)]
+        );
+
+        let template = blockchain.build_block_template_with_payouts(&pool.get_payouts())?;
+        let proof = (0..Proof::MAX)
+            .find(|proof| Blockchain::validate_proof(template.get_last_proof(), proof))
+            .unwrap();
+        blockchain.submit_block(template, proof)?;
+        assert!(blockchain.get_last_block().is_valid(&blockchain));
+        assert_eq!(blockchain.get_peer_credits(&first_address), 750);
+        assert_eq!(blockchain.get_peer_credits(&second_address), 250);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_p2p_sync() -> Result<()> {
+        let miner_address = node_address("miner");
+        let first_chain = Arc::new(Mutex::new(Blockchain::new(miner_address.clone())));
+        let second_chain = Arc::new(Mutex::new(Blockchain::new(node_address("other"))));
+        first_chain.lock().unwrap().mine()?;
+        first_chain.lock().unwrap().mine()?;
+
+        let (first_node, second_node) = (
+            Node::new(first_chain.clone()),
+            Node::new(second_chain.clone()),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let second_address = listener.local_addr()?;
+        second_node.listen(listener);
+        first_node.connect(second_address)?;
+
+        // The second node is behind, so it downloads the blocks it's missing.
+        let get_last_hash =
+            |blockchain: &Arc<Mutex<Blockchain>>| *blockchain.lock().unwrap().get_last_block_hash();
+        assert!(wait_until(
+            || get_last_hash(&second_chain) == get_last_hash(&first_chain)
+        ));
+        assert!(wait_until(|| first_node.get_peer_addresses().len() == 1
+            && second_node.get_peer_addresses().len() == 1));
+
+        // New transactions and blocks are relayed as they come.
+        let wallet = Wallet::deterministic("miner");
+        let transaction_hash = {
+            let mut blockchain = first_chain.lock().unwrap();
+            wallet.send_many(&mut blockchain, vec![(node_address("other"), 100)], 5)?;
+            *blockchain.get_pending_transactions()[0].get_hash()
+        };
+        first_node.announce_transaction(&transaction_hash)?;
+        assert!(wait_until(|| second_chain
+            .lock()
+            .unwrap()
+            .find_pending_transaction(&transaction_hash)
+            .is_some()));
+
+        first_chain.lock().unwrap().mine()?;
+        first_node.announce_block(&get_last_hash(&first_chain))?;
+        assert!(wait_until(
+            || get_last_hash(&second_chain) == get_last_hash(&first_chain)
+        ));
+        let second_chain = second_chain.lock().unwrap();
+        assert!(second_chain.get_pending_transactions().is_empty());
+        assert_eq!(second_chain.get_peer_credits(&node_address("other")), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wire_messages() -> Result<()> {
+        let blockchain = Blockchain::new(node_address("miner"));
+        let block_hash = *blockchain.get_last_block_hash();
+        let magic = blockchain.get_chain_params().magic;
+
+        let mut buffer = vec![];
+        Message::Inv(vec![Inventory::Block(block_hash)]).write_to(&magic, &mut buffer)?;
+        Message::Block(blockchain.get_last_block().to_raw()).write_to(&magic, &mut buffer)?;
+        let message = Message::read_from(&magic, &mut buffer.as_slice())?;
+        assert!(
+            matches!(message, Message::Inv(inventory) if inventory == [Inventory::Block(block_hash)])
+        );
+
+        // Messages this node doesn't know yet are skipped.
+        let mut buffer = vec![];
+        Message::Ping(7).write_to(&magic, &mut buffer)?;
+        buffer[4..10].copy_from_slice(b"future");
+        Message::Pong(7).write_to(&magic, &mut buffer)?;
+        assert!(matches!(
+            Message::read_from(&magic, &mut buffer.as_slice())?,
+            Message::Pong(7)
+        ));
+
+        // Anything damaged on the way isn't.
+        let mut buffer = vec![];
+        Message::Ping(7).write_to(&magic, &mut buffer)?;
+        *buffer.last_mut().unwrap() ^= 1;
+        assert!(Message::read_from(&magic, &mut buffer.as_slice()).is_err());
+
+        // Neither is anything from another network.
+        let mut buffer = vec![];
+        Message::Ping(7).write_to(&ChainParams::testnet().magic, &mut buffer)?;
+        assert!(Message::read_from(&magic, &mut buffer.as_slice()).is_err());
+
+        assert_eq!(negotiate_version(PROTOCOL_VERSION + 1)?, PROTOCOL_VERSION);
+        assert!(negotiate_version(0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_discovery() -> Result<()> {
+        let start_node = |seed| -> Result<(Node, std::net::SocketAddr)> {
+            let node = Node::new(Arc::new(Mutex::new(Blockchain::new(node_address(seed)))));
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let address = listener.local_addr()?;
+            node.listen(listener);
+
+            Ok((node, address))
+        };
+        let (seed_node, seed_address) = start_node("seed")?;
+        let (_, other_address) = start_node("other")?;
+        seed_node.connect(other_address)?;
+
+        // Only the seed is given, the other node is learned from it and kept for the next start.
+        let path =
+            std::env::temp_dir().join(format!("place-coin-peers-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let node = Node::with_address_manager(
+            Arc::new(Mutex::new(Blockchain::new(node_address("new")))),
+            AddressManager::load(&path)?,
+        );
+        assert_eq!(node.bootstrap(&[&seed_address.to_string()])?, 1);
+        assert!(wait_until(|| node
+            .get_known_addresses()
+            .contains(&other_address)));
+
+        let address_manager = AddressManager::load(&path)?;
+        assert!(address_manager.contains(&seed_address));
+        assert!(address_manager.contains(&other_address));
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_address_quality() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("place-coin-quality-{}.txt", std::process::id()));
+        std::fs::write(&path, "10.0.0.1:8333 5\n")?;
+        let mut address_manager = AddressManager::load(&path)?;
+
+        // A single network can only take so much of the table.
+        let crowded_addresses = (0..=u8::MAX)
+            .map(|byte| SocketAddr::from(([10, 0, 1, byte], 8333)))
+            .collect::<Vec<_>>();
+        address_manager.add(&crowded_addresses);
+        assert_eq!(address_manager.len(), MAX_ADDRESSES_PER_GROUP);
+        let other_address = SocketAddr::from(([20, 1, 0, 1], 8333));
+        address_manager.add(&[other_address]);
+
+        // Peers that worked come first, and then those from other networks.
+        let good_address = crowded_addresses[MAX_ADDRESSES_PER_GROUP * 2];
+        address_manager.mark_success(&good_address);
+        assert_eq!(
+            address_manager.select_outbound(2, &[]),
+            [good_address, other_address]
+        );
+        assert_eq!(
+            address_manager.select_outbound(1, &[good_address]),
+            [other_address]
+        );
+
+        // What is known about them is kept for the next start.
+        address_manager.save()?;
+        let address_manager = AddressManager::load(&path)?;
+        let info = address_manager.get_info(&good_address).unwrap();
+        assert!(info.first_success > 0 && info.last_success >= info.first_success);
+
+        // Addresses that never answer are forgotten.
+        let mut address_manager = address_manager;
+        for _ in 0..MAX_FAILURES {
+            address_manager.mark_failure(&other_address);
+        }
+        assert!(!address_manager.contains(&other_address));
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers_first_sync() -> Result<()> {
+        let mut full_chain = Blockchain::new(node_address("miner"));
+        for _ in 0..4 {
+            full_chain.mine()?;
+        }
+        let mut blockchain = Blockchain::new(node_address("other"));
+
+        // Headers whose proof of work doesn't hold up are turned away before any download.
+        let mut sync = ChainSync::default();
+        let locator = sync.get_locator(&blockchain);
+        let headers = full_chain.get_headers_after(&locator, MAX_HEADERS);
+        assert_eq!(headers.len(), 4);
+
+        let mut bad_header = headers[0].clone();
+        bad_header.proof = (0..Proof::MAX)
+            .find(|proof| !Blockchain::validate_proof(&bad_header.proof, proof))
+            .unwrap();
+        assert!(sync.add_header(&blockchain, bad_header).is_err());
+
+        for header in headers {
+            assert!(sync.add_header(&blockchain, header)?);
+        }
+        assert_eq!(sync.get_best_height(&blockchain)?, 4);
+
+        // Bodies are asked for in windows, and connected in order whatever order they come in.
+        let missing_hashes = sync.get_missing_blocks(&blockchain);
+        let requests = sync.schedule(&missing_hashes, &[1, 2]);
+        assert_eq!(requests, [(1, missing_hashes.clone())]);
+        assert!(sync.get_missing_blocks(&blockchain).is_empty());
+
+        for block_hash in missing_hashes.iter().rev() {
+            let block = full_chain.get_block(block_hash).unwrap().to_raw();
+            assert!(sync.receive_block(*block_hash, block));
+        }
+        while let Some(block) = sync.take_next_block(&blockchain) {
+            blockchain.submit_raw_block(block)?;
+        }
+        assert_eq!(
+            blockchain.get_last_block_hash(),
+            full_chain.get_last_block_hash()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_block_download() {
+        let mut sync = ChainSync::default();
+        let missing_hashes = (0..WINDOW_SIZE as u8 * 3)
+            .map(|byte| BlockHash::from_bytes([byte; 32]))
+            .collect::<Vec<_>>();
+        let windows = missing_hashes.chunks(WINDOW_SIZE).collect::<Vec<_>>();
+
+        // Windows of consecutive blocks go to the peers in turn.
+        let requests = sync.schedule(&missing_hashes, &[1, 2]);
+        let scheduled = Instant::now();
+        assert_eq!(
+            requests,
+            [
+                (1, windows[0].to_vec()),
+                (2, windows[1].to_vec()),
+                (1, windows[2].to_vec())
+            ]
+        );
+        assert!(sync.take_stalled_peers(scheduled).is_empty());
+
+        // Only the peer that sent nothing for too long is stalled.
+        thread::sleep(Duration::from_millis(50));
+        let block = Blockchain::new(node_address("miner"))
+            .get_last_block()
+            .to_raw();
+        assert!(sync.receive_block(windows[1][0], block));
+        let later = scheduled + BLOCK_DOWNLOAD_TIMEOUT + Duration::from_millis(25);
+        assert_eq!(sync.take_stalled_peers(later), [1]);
+
+        // What it had is handed out again.
+        let requests = sync.schedule(&[windows[0], windows[2]].concat(), &[3]);
+        assert_eq!(
+            requests,
+            [(3, windows[0].to_vec()), (3, windows[2].to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_inventory_relay() -> Result<()> {
+        // Three nodes in a line, so whatever the first one has must go through the middle one.
+        let chains = ["first", "middle", "last"]
+            .map(|seed| Arc::new(Mutex::new(Blockchain::new(node_address(seed)))));
+        let nodes = chains.clone().map(Node::new);
+        for index in 1..nodes.len() {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let address = listener.local_addr()?;
+            nodes[index].listen(listener);
+            nodes[index - 1].connect(address)?;
+        }
+        assert!(wait_until(|| nodes[1].get_peer_addresses().len() == 2));
+
+        let block_hash = nodes[0].mine()?;
+        assert!(wait_until(|| chains[2]
+            .lock()
+            .unwrap()
+            .get_block(&block_hash)
+            .is_some()));
+
+        let transaction = {
+            let blockchain = chains[0].lock().unwrap();
+            let private_key = Wallet::deterministic("first").get_private_keys()[0].clone();
+            let public_key = PublicKey::from_private_key(&private_key);
+            let (output_hash, output_index, value) =
+                blockchain.get_spendable_outputs(&node_address("first"))[0];
+
+            let mut transaction = RawTransaction {
+                version: CURRENT_TRANSACTION_VERSION,
+                inputs: vec![TransactionInput::FromOutput {
+                    transaction_hash: *output_hash,
+                    output_index,
+                    public_key,
+                    signature: Signature::unsigned(),
+                }],
+                outputs: vec![TransactionOutput::ToInput {
+                    value: value - 1,
+                    public_key_address: node_address("last"),
+                }],
+                lock_time: 0,
+            };
+            sign_transaction_inputs(&mut transaction, &private_key);
+
+            transaction
+        };
+        let transaction_hash = nodes[0].submit_transaction(transaction)?;
+        assert!(wait_until(|| chains[2]
+            .lock()
+            .unwrap()
+            .find_pending_transaction(&transaction_hash)
+            .is_some()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_transport() -> Result<()> {
+        // The same line of three nodes, without a single socket.
+        let network = MemoryNetwork::default();
+        let chains = ["first", "middle", "last"]
+            .map(|seed| Arc::new(Mutex::new(Blockchain::new(node_address(seed)))));
+        let nodes = chains.clone().map(|blockchain| {
+            Node::with_transport(
+                blockchain,
+                AddressManager::default(),
+                Arc::new(network.clone()),
+            )
+        });
+        for index in 1..nodes.len() {
+            let address = SocketAddr::from(([10, 0, 0, index as u8], 8333));
+            nodes[index].listen(network.listen(address)?);
+            nodes[index - 1].connect(address)?;
+        }
+        assert!(wait_until(|| nodes[1].get_peer_addresses().len() == 2));
+        assert!(network
+            .listen(SocketAddr::from(([10, 0, 0, 1], 8333)))
+            .is_err());
+        assert!(nodes[0]
+            .connect(SocketAddr::from(([10, 0, 0, 9], 8333)))
+            .is_err());
+
+        let block_hash = nodes[0].mine()?;
+        assert!(wait_until(|| chains[2]
+            .lock()
+            .unwrap()
+            .get_block(&block_hash)
+            .is_some()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_socks5_proxy() -> Result<()> {
+        let miner_chain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        miner_chain.lock().unwrap().mine()?;
+        let miner_node = Node::new(miner_chain.clone());
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let miner_address = listener.local_addr()?;
+        miner_node.listen(listener);
+
+        // A proxy that only knows a single onion service, the miner.
+        let proxy = TcpListener::bind("127.0.0.1:0")?;
+        let proxy_address = proxy.local_addr()?;
+        let proxy_thread = thread::spawn(move || -> Result<String> {
+            let (mut client, _) = proxy.accept()?;
+            let mut greeting = [0; 3];
+            client.read_exact(&mut greeting)?;
+            assert_eq!(greeting, [5, 1, 0]);
+            client.write_all(&[5, 0])?;
+
+            let mut request = [0; 5];
+            client.read_exact(&mut request)?;
+            assert_eq!(request[..4], [5, 1, 0, 3]);
+            let mut host = vec![0; request[4] as usize + 2];
+            client.read_exact(&mut host)?;
+            host.truncate(host.len() - 2);
+
+            let peer = TcpStream::connect(miner_address)?;
+            client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+            let (mut client_reader, mut peer_writer) = (client.try_clone()?, peer.try_clone()?);
+            thread::spawn(move || io::copy(&mut client_reader, &mut peer_writer));
+            let (mut peer_reader, mut client_writer) = (peer, client);
+            thread::spawn(move || io::copy(&mut peer_reader, &mut client_writer));
+
+            Ok(String::from_utf8(host)?)
+        });
+
+        let transport = Arc::new(Socks5Transport::new(proxy_address));
+        let onion_address = transport.add_onion_peer("examplepeeraddress.onion:8333")?;
+        assert!(matches!(onion_address.ip(), IpAddr::V6(ip) if ip.octets()[..6] == ONION_PREFIX));
+        assert_eq!(
+            transport.add_onion_peer("examplepeeraddress.onion:8333")?,
+            onion_address
+        );
+        assert!(transport.add_onion_peer("example.com:8333").is_err());
+
+        let chain = Arc::new(Mutex::new(Blockchain::new(node_address("other"))));
+        let node = Node::with_transport(chain.clone(), AddressManager::default(), transport);
+        node.connect(onion_address)?;
+        assert_eq!(proxy_thread.join().unwrap()?, "examplepeeraddress.onion");
+
+        // The proxy resolved the name, and the peer is known by its stand-in.
+        assert!(wait_until(|| chain.lock().unwrap().get_last_block_hash()
+            == miner_chain.lock().unwrap().get_last_block_hash()));
+        assert_eq!(node.get_peer_addresses(), [onion_address]);
+        assert!(node.get_known_addresses().contains(&onion_address));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_graceful_shutdown() -> Result<()> {
+        let network = MemoryNetwork::default();
+        let chains = ["miner", "other"]
+            .map(|seed| Arc::new(Mutex::new(Blockchain::new(node_address(seed)))));
+        let nodes = chains.clone().map(|blockchain| {
+            Node::with_transport(
+                blockchain,
+                AddressManager::default(),
+                Arc::new(network.clone()),
+            )
+        });
+        let address = SocketAddr::from(([10, 0, 0, 2], 8333));
+        nodes[1].listen(network.listen(address)?);
+        nodes[0].connect(address)?;
+
+        let path =
+            std::env::temp_dir().join(format!("place-coin-state-{}.bin", std::process::id()));
+        let mut controller = ShutdownController::new(nodes[0].clone(), &path);
+        controller.start_miner(Duration::from_millis(10));
+        controller.watch_downloads();
+        assert!(wait_until(|| {
+            chains[1].lock().unwrap().get_next_block_height().unwrap() > 3
+        }));
+        controller.shutdown()?;
+
+        // Nothing is mined anymore, and the peer was told to go.
+        let last_block_hash = *chains[0].lock().unwrap().get_last_block_hash();
+        assert!(wait_until(|| nodes[1].get_peer_addresses().is_empty()));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            *chains[0].lock().unwrap().get_last_block_hash(),
+            last_block_hash
+        );
+
+        let mut blockchain = Blockchain::new(node_address("restarted"));
+        load_state(&mut blockchain, &path)?;
+        assert_eq!(*blockchain.get_last_block_hash(), last_block_hash);
+
+        // Pending transactions are kept as well.
+        let transaction_hash = {
+            let mut blockchain = chains[0].lock().unwrap();
+            Wallet::deterministic("miner").send_many(
+                &mut blockchain,
+                vec![(node_address("other"), 100)],
+                5,
+            )?;
+            save_state(&blockchain, &path)?;
+
+            *blockchain.get_pending_transactions()[0].get_hash()
+        };
+        let mut blockchain = Blockchain::new(node_address("restarted"));
+        load_state(&mut blockchain, &path)?;
+        assert!(blockchain
+            .find_pending_transaction(&transaction_hash)
+            .is_some());
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mempool_sync() -> Result<()> {
+        // Transactions that were never relayed to the second node, as it wasn't connected yet.
+        let first_chain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let second_chain = Arc::new(Mutex::new(Blockchain::new(node_address("other"))));
+        let transaction_hashes = {
+            let mut blockchain = first_chain.lock().unwrap();
+            blockchain.mine()?;
+            blockchain.mine()?;
+            let wallet = Wallet::deterministic("miner");
+            wallet.send_many(&mut blockchain, vec![(node_address("other"), 100)], 5)?;
+            wallet.send_many(&mut blockchain, vec![(node_address("other"), 200)], 5)?;
+
+            blockchain
+                .get_pending_transactions()
+                .iter()
+                .map(|transaction| *transaction.get_hash())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(transaction_hashes.len(), 2);
+
+        let network = MemoryNetwork::default();
+        let (first_node, second_node) = (
+            Node::with_transport(
+                first_chain,
+                AddressManager::default(),
+                Arc::new(network.clone()),
+            ),
+            Node::with_transport(
+                second_chain.clone(),
+                AddressManager::default(),
+                Arc::new(network.clone()),
+            ),
+        );
+        let second_address = SocketAddr::from(([10, 0, 0, 2], 8333));
+        second_node.listen(network.listen(second_address)?);
+        first_node.connect(second_address)?;
+
+        assert!(wait_until(|| {
+            let blockchain = second_chain.lock().unwrap();
+            transaction_hashes.iter().all(|transaction_hash| {
+                blockchain
+                    .find_pending_transaction(transaction_hash)
+                    .is_some()
+            })
+        }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filtered_blocks() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        node.listen(listener);
+        let block_hash = node.mine()?;
+
+        // A light client that only watches the miner.
+        let mut stream = TcpStream::connect(address)?;
+        let genesis_hash = blockchain.lock().unwrap().get_main_chain()[0].calculate_hash();
+        let magic = ChainParams::mainnet().magic;
+        let mut filter = BloomFilter::new(1, 0.000_001, 0);
+        filter.insert_address(&node_address("miner"));
+        for message in [
+            Message::Version {
+                version: PROTOCOL_VERSION,
+                genesis_hash,
+                height: 0,
+                timestamp: get_timestamp(),
+            },
+            Message::VerAck,
+            Message::FilterLoad(filter),
+            Message::GetData(vec![Inventory::FilteredBlock(block_hash)]),
+        ] {
+            message.write_to(&magic, &mut stream)?;
+        }
+
+        let merkle_block = loop {
+            if let Message::MerkleBlock(merkle_block) = Message::read_from(&magic, &mut stream)? {
+                break merkle_block;
+            }
+        };
+        assert!(merkle_block.verify());
+        assert_eq!(merkle_block.header.calculate_hash(), block_hash);
+        assert_eq!(merkle_block.transactions.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limits() -> Result<()> {
+        // Budgets fill up again over time, but never past what they hold.
+        let mut bucket = TokenBucket::new((10.0, 5.0));
+        let now = Instant::now();
+        assert!(bucket.try_take(10.0, now));
+        assert!(!bucket.try_take(1.0, now));
+        assert!(bucket.try_take(5.0, now + Duration::from_secs(1)));
+        assert!(!bucket.try_take(11.0, now + Duration::from_secs(60)));
+
+        // A peer flooding the node is dropped.
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        node.listen(listener);
+
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let magic = ChainParams::mainnet().magic;
+        Message::Version {
+            version: PROTOCOL_VERSION,
+            genesis_hash: blockchain.lock().unwrap().get_main_chain()[0].calculate_hash(),
+            height: 0,
+            timestamp: get_timestamp(),
+        }
+        .write_to(&magic, &mut stream)?;
+        for nonce in 0..PEER_MESSAGES.0 as u64 * 2 {
+            // The node can hang up before every ping is out.
+            if Message::Ping(nonce).write_to(&magic, &mut stream).is_err() {
+                break;
+            }
+        }
+
+        let error = loop {
+            if let Err(error) = Message::read_from(&magic, &mut stream) {
+                break error;
+            }
+        };
+        // Closed on the peer's side, rather than the read timing out.
+        let error = error.downcast::<std::io::Error>()?;
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_port_mapping() -> Result<()> {
+        // A router that maps whatever port it's asked for to the next one.
+        let router = UdpSocket::bind("127.0.0.1:0")?;
+        let router_address = router.local_addr()?;
+        let router_thread = thread::spawn(move || {
+            let mut lifetimes = vec![];
+            for _ in 0..2 {
+                let mut request = [0; 12];
+                let (_, address) = router.recv_from(&mut request).unwrap();
+                let internal_port = u16::from_be_bytes([request[4], request[5]]);
+                let lifetime = u32::from_be_bytes(request[8..12].try_into().unwrap());
+                lifetimes.push(lifetime);
+
+                let mut response = vec![0, request[1] + 128, 0, 0, 0, 0, 0, 1];
+                response.extend_from_slice(&internal_port.to_be_bytes());
+                response.extend_from_slice(&(internal_port + 1).to_be_bytes());
+                response.extend_from_slice(&lifetime.to_be_bytes());
+                router.send_to(&response, address).unwrap();
+            }
+
+            lifetimes
+        });
+
+        let mapping = PortMapping::with_gateway(router_address, 8333, 60)?;
+        assert_eq!(mapping.get_external_port(), 8334);
+        assert_eq!(mapping.get_lifetime(), 60);
+
+        // Dropping the mapping asks the router to remove it.
+        drop(mapping);
+        assert_eq!(router_thread.join().unwrap(), [60, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_time() {
+        let mut network_time = NetworkTime::default();
+        let now = get_timestamp();
+
+        // A few peers aren't enough to move the clock.
+        for peer_id in 0..4 {
+            network_time.add_sample(peer_id, now + 600);
+        }
+        assert_eq!(network_time.get_offset(), 0);
+
+        // The median resists a peer whose clock is way off.
+        network_time.add_sample(4, now + 100_000);
+        network_time.add_sample(5, now - 100_000);
+        assert!((595..=605).contains(&network_time.get_offset()));
+
+        // An offset too large to believe is ignored.
+        for peer_id in 0..4 {
+            network_time.add_sample(peer_id, now + MAX_TIME_OFFSET * 2);
+        }
+        assert_eq!(network_time.get_offset(), 0);
+
+        // Samples leave with their peers.
+        for peer_id in 0..4 {
+            network_time.remove_peer(peer_id);
+        }
+        assert_eq!(network_time.get_offset(), 0);
+    }
+
+    #[test]
+    fn test_stolen_transaction_relay() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        node.listen(listener);
+
+        // The miner's reward, spent with a thief's own key.
+        let transaction = {
+            let blockchain = blockchain.lock().unwrap();
+            let private_key = Wallet::deterministic("thief").get_private_keys()[0].clone();
+            let public_key = PublicKey::from_private_key(&private_key);
+            let (output_hash, output_index, value) =
+                blockchain.get_spendable_outputs(&node_address("miner"))[0];
+
+            let mut transaction = RawTransaction {
+                version: CURRENT_TRANSACTION_VERSION,
+                inputs: vec![TransactionInput::FromOutput {
+                    transaction_hash: *output_hash,
+                    output_index,
+                    public_key,
+                    signature: Signature::unsigned(),
+                }],
+                outputs: vec![TransactionOutput::ToInput {
+                    value: value - 1,
+                    public_key_address: node_address("thief"),
+                }],
+                lock_time: 0,
+            };
+            sign_transaction_inputs(&mut transaction, &private_key);
+
+            transaction
+        };
+        let error = node.submit_transaction(transaction.clone()).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&Rejection::BadSignature));
+
+        // A peer relaying it is dropped.
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let magic = ChainParams::mainnet().magic;
+        Message::Version {
+            version: PROTOCOL_VERSION,
+            genesis_hash: blockchain.lock().unwrap().get_main_chain()[0].calculate_hash(),
+            height: 1,
+            timestamp: get_timestamp(),
+        }
+        .write_to(&magic, &mut stream)?;
+        Message::Tx(transaction).write_to(&magic, &mut stream)?;
+
+        let error = loop {
+            if let Err(error) = Message::read_from(&magic, &mut stream) {
+                break error;
+            }
+        };
+        let error = error.downcast::<std::io::Error>()?;
+        assert!(matches!(
+            error.kind(),
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset
+        ));
+        assert!(blockchain
+            .lock()
+            .unwrap()
+            .get_pending_transactions()
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_limits() -> Result<()> {
+        let mut full_chain = Blockchain::new(node_address("miner"));
+        for _ in 0..3 {
+            full_chain.mine()?;
+        }
+        let main_chain = full_chain.get_main_chain();
+        let mut blockchain = Blockchain::new(node_address("other"));
+        blockchain.submit_raw_block(main_chain[1].to_raw())?;
+
+        let mine_header = |previous_header: &BlockHeader, seconds| {
+            let mut header = previous_header.clone();
+            header.timestamp = previous_header.timestamp + Duration::from_secs(seconds);
+            header.previous_hash = Some(previous_header.calculate_hash());
+            header.proof = (0..Proof::MAX)
+                .find(|proof| Blockchain::validate_proof(&previous_header.proof, proof))
+                .unwrap();
+            header
+        };
+        let headers = main_chain
+            .iter()
+            .map(|block| block.get_header())
+            .collect::<Vec<_>>();
+
+        // Branches off an older block can't be switched to, and blocks from the future aren't valid.
+        let mut sync = ChainSync::with_max_headers(2);
+        assert!(!sync.add_header(&blockchain, headers[1].clone())?);
+        assert!(!sync.add_header(&blockchain, mine_header(&headers[0], 1))?);
+        let future_header = mine_header(&headers[1], 3 * 60 * 60);
+        assert!(sync.add_header(&blockchain, future_header).is_err());
+
+        assert!(sync.add_header(&blockchain, headers[2].clone())?);
+        assert!(sync.add_header(&blockchain, headers[3].clone())?);
+
+        // Once full, headers left behind by a new last block make room, and then new ones wait.
+        blockchain.submit_raw_block(main_chain[2].to_raw())?;
+        let next_header = mine_header(&headers[3], 1);
+        assert!(sync.add_header(&blockchain, next_header.clone())?);
+        assert_eq!(sync.get_best_height(&blockchain)?, 4);
+        assert!(!sync.add_header(&blockchain, mine_header(&next_header, 1))?);
+        assert_eq!(sync.get_best_height(&blockchain)?, 4);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+// Limits on how much peers can make this node do. Every peer gets a budget of messages, bytes and
+// orphan transactions that fills up again over time, and is dropped once it goes over. All peers
+// together share a bigger budget as well, which slows them down instead, so that many of them at
+// once can't keep the node busy either.
+
+use std::{io::Read, time::Instant};
+
+pub const MAX_INBOUND_PEERS: usize = 117;
+
+// How much fits in a budget, and how much of it comes back every second.
+pub const PEER_MESSAGES: (f64, f64) = (500.0, 100.0);
+pub const PEER_BYTES: (f64, f64) = (8_000_000.0, 1_000_000.0); // Room for the biggest messages.
+pub const PEER_ORPHANS: (f64, f64) = (100.0, 1.0); // Transactions whose parents must be asked for.
+pub const GLOBAL_MESSAGES: (f64, f64) = (5000.0, 2000.0);
+pub const GLOBAL_BYTES: (f64, f64) = (40_000_000.0, 20_000_000.0);
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_rate: f64, // Tokens per second.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new((capacity, refill_rate): (f64, f64)) -> Self {
+        Self {
+            capacity,
+            refill_rate,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn try_take(&mut self, amount: f64, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity);
+        self.last_refill = self.last_refill.max(now);
+
+        if amount > self.tokens {
+            return false;
+        }
+        self.tokens -= amount;
+
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerLimits {
+    messages: TokenBucket,
+    bytes: TokenBucket,
+    orphans: TokenBucket,
+}
+
+impl Default for PeerLimits {
+    fn default() -> Self {
+        Self {
+            messages: TokenBucket::new(PEER_MESSAGES),
+            bytes: TokenBucket::new(PEER_BYTES),
+            orphans: TokenBucket::new(PEER_ORPHANS),
+        }
+    }
+}
+
+impl PeerLimits {
+    pub fn try_receive(&mut self, size: usize, now: Instant) -> bool {
+        // Both are taken from, so a peer over one budget doesn't get the other one back.
+        let has_messages = self.messages.try_take(1.0, now);
+        let has_bytes = self.bytes.try_take(size as f64, now);
+
+        has_messages && has_bytes
+    }
+
+    pub fn try_receive_orphan(&mut self, now: Instant) -> bool {
+        self.orphans.try_take(1.0, now)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GlobalLimits {
+    messages: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl Default for GlobalLimits {
+    fn default() -> Self {
+        Self {
+            messages: TokenBucket::new(GLOBAL_MESSAGES),
+            bytes: TokenBucket::new(GLOBAL_BYTES),
+        }
+    }
+}
+
+impl GlobalLimits {
+    pub fn try_receive(&mut self, size: usize, now: Instant) -> bool {
+        // A message that doesn't fit is tried again whole, so nothing is taken for it yet.
+        let mut messages = self.messages.clone();
+        let mut bytes = self.bytes.clone();
+        if !messages.try_take(1.0, now) || !bytes.try_take(size as f64, now) {
+            return false;
+        }
+        self.messages = messages;
+        self.bytes = bytes;
+
+        true
+    }
+}
+
+// Counts the bytes read from a peer, to take them from its budget.
+#[derive(Debug)]
+pub struct CountingReader<R> {
+    reader: R,
+    count: usize,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, count: 0 }
+    }
+
+    pub fn take_count(&mut self) -> usize {
+        std::mem::take(&mut self.count)
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.reader.read(buffer)?;
+        self.count += size;
+
+        Ok(size)
+    }
+}
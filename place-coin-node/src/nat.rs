@@ -0,0 +1,153 @@
+// Port mapping with NAT-PMP (RFC 6886), so nodes behind a home router can take connections too.
+// The router is asked to forward a port to this node, and the mapping is removed again once it's
+// dropped. Every message is a small UDP datagram to the router:
+//
+//     request:  version (0) | opcode (2, for TCP) | reserved (2 bytes) | internal port |
+//               suggested external port | lifetime in seconds (4 bytes)
+//     response: version (0) | opcode + 128 | result code (2 bytes) | seconds since the router
+//               started (4 bytes) | internal port | external port | lifetime (4 bytes)
+
+use anyhow::{bail, Context, Result};
+use std::{
+    fs,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    time::Duration,
+};
+
+pub const NAT_PMP_PORT: u16 = 5351;
+pub const DEFAULT_MAPPING_LIFETIME: u32 = 7200;
+
+const VERSION: u8 = 0;
+const MAP_TCP_OPCODE: u8 = 2;
+const MAX_ATTEMPTS: u32 = 4; // Waiting twice as long before each new attempt, from 250ms.
+
+#[derive(Debug)]
+pub struct PortMapping {
+    gateway: SocketAddr,
+    internal_port: u16,
+    external_port: u16,
+    lifetime: u32, // Seconds the router keeps the mapping, unless it's renewed.
+}
+
+impl PortMapping {
+    pub fn new(port: u16) -> Result<Self> {
+        let gateway = find_default_gateway()?;
+
+        Self::with_gateway(
+            SocketAddr::V4(SocketAddrV4::new(gateway, NAT_PMP_PORT)),
+            port,
+            DEFAULT_MAPPING_LIFETIME,
+        )
+    }
+
+    pub fn with_gateway(gateway: SocketAddr, port: u16, lifetime: u32) -> Result<Self> {
+        let (external_port, lifetime) = request_mapping(&gateway, port, port, lifetime)?;
+
+        Ok(Self {
+            gateway,
+            internal_port: port,
+            external_port,
+            lifetime,
+        })
+    }
+
+    pub fn renew(&mut self) -> Result<()> {
+        // The router may hand out another port, so peers should be told about the new one.
+        let (external_port, lifetime) = request_mapping(
+            &self.gateway,
+            self.internal_port,
+            self.external_port,
+            self.lifetime,
+        )?;
+        self.external_port = external_port;
+        self.lifetime = lifetime;
+
+        Ok(())
+    }
+
+    pub fn get_external_port(&self) -> u16 {
+        self.external_port
+    }
+
+    pub fn get_lifetime(&self) -> u32 {
+        self.lifetime
+    }
+}
+
+impl Drop for PortMapping {
+    fn drop(&mut self) {
+        // A zero lifetime removes the mapping. If the router doesn't answer it expires anyway.
+        let _ = request_mapping(&self.gateway, self.internal_port, 0, 0);
+    }
+}
+
+fn request_mapping(
+    gateway: &SocketAddr,
+    internal_port: u16,
+    external_port: u16,
+    lifetime: u32,
+) -> Result<(u16, u32)> {
+    let mut request = vec![VERSION, MAP_TCP_OPCODE, 0, 0];
+    request.extend_from_slice(&internal_port.to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    request.extend_from_slice(&lifetime.to_be_bytes());
+
+    let socket = UdpSocket::bind(match gateway {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    })?;
+    socket.connect(gateway)?;
+
+    // UDP can get lost, so keep asking for a little while.
+    let mut response = [0; 16];
+    let mut timeout = Duration::from_millis(250);
+    let mut size = None;
+    for _ in 0..MAX_ATTEMPTS {
+        socket.send(&request)?;
+        socket.set_read_timeout(Some(timeout))?;
+        if let Ok(received) = socket.recv(&mut response) {
+            size = Some(received);
+            break;
+        }
+        timeout *= 2;
+    }
+
+    let size = size.context("Router didn't answer the port mapping request.")?;
+    if size < response.len()
+        || response[0] != VERSION
+        || response[1] != MAP_TCP_OPCODE + 128
+        || u16::from_be_bytes([response[8], response[9]]) != internal_port
+    {
+        bail!("Router sent a malformed port mapping response.")
+    }
+
+    let result_code = u16::from_be_bytes([response[2], response[3]]);
+    if result_code != 0 {
+        bail!("Router refused the port mapping with result code {result_code}.")
+    }
+
+    Ok((
+        u16::from_be_bytes([response[10], response[11]]),
+        u32::from_be_bytes(response[12..16].try_into().unwrap()),
+    ))
+}
+
+fn find_default_gateway() -> Result<Ipv4Addr> {
+    // The route to 0.0.0.0 goes through the router. Only Linux tells about it this easily.
+    let routes =
+        fs::read_to_string("/proc/net/route").context("Fail to read the routing table.")?;
+
+    routes
+        .lines()
+        .skip(1)
+        .find_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                [_, "00000000", gateway, ..] => u32::from_str_radix(gateway, 16).ok(),
+                _ => None,
+            }
+        })
+        .filter(|gateway| *gateway != 0)
+        .map(|gateway| Ipv4Addr::from(gateway.to_le_bytes()))
+        .context("No default gateway found.")
+}
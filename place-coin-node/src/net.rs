@@ -0,0 +1,215 @@
+// Messages between peers, each one framed as:
+//
+//     magic (4 bytes) | command (12 bytes) | payload size (4 bytes) | checksum (4 bytes) | payload
+//
+// The command is the name of the message padded with zeros, and the payload its fields encoded
+// with bincode. The checksum is the start of the payload's SHA3 hash. Messages with a command
+// this node doesn't know are skipped, so new ones can be added without breaking older peers.
+//
+// The magic is different on every network, see `ChainParams`, and messages for another network
+// are turned away before anything else is read.
+//
+// Mempool summaries only hold short ids, the first 8 bytes of each transaction hash, as they're
+// sent whole to every new peer.
+
+use anyhow::{bail, Context, Result};
+use place_coin::{
+    block::{BlockHeader, MerkleBlock, RawBlock},
+    blockchain::{BlockHash, TxHash},
+    bloom::BloomFilter,
+    transaction::RawTransaction,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::{
+    io::{Read, Write},
+    net::SocketAddr,
+};
+
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 2; // Peers speaking anything older are turned away.
+
+const COMMAND_SIZE: usize = 12;
+const MAX_PAYLOAD_SIZE: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Inventory {
+    Block(BlockHash),
+    Transaction(TxHash),
+    FilteredBlock(BlockHash), // Asks for the block as a Merkle block, matched against the filter.
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Version {
+        version: u32,
+        genesis_hash: BlockHash,
+        height: u64,
+        timestamp: i64, // Sender's clock, in seconds since the Unix epoch.
+    },
+    VerAck,
+    Inv(Vec<Inventory>),        // Announces what the sender has.
+    GetData(Vec<Inventory>),    // Asks for what was announced.
+    GetHeaders(Vec<BlockHash>), // Asks for the headers after the first of these on the main chain.
+    Headers(Vec<BlockHeader>),
+    Block(RawBlock),
+    Tx(RawTransaction),
+    Ping(u64),
+    Pong(u64),
+    GetAddr,
+    Addr(Vec<SocketAddr>),   // Peers the sender knows about.
+    FilterLoad(BloomFilter), // Only what matches is relayed to the sender from then on.
+    FilterClear,
+    MerkleBlock(MerkleBlock),
+    MemPool(Vec<u64>),  // Short ids of the sender's pending transactions.
+    Disconnect(String), // Why the sender is going away.
+}
+
+impl Message {
+    pub fn get_command(&self) -> &'static str {
+        match self {
+            Message::Version { .. } => "version",
+            Message::VerAck => "verack",
+            Message::Inv(_) => "inv",
+            Message::GetData(_) => "getdata",
+            Message::GetHeaders(_) => "getheaders",
+            Message::Headers(_) => "headers",
+            Message::Block(_) => "block",
+            Message::Tx(_) => "tx",
+            Message::Ping(_) => "ping",
+            Message::Pong(_) => "pong",
+            Message::GetAddr => "getaddr",
+            Message::Addr(_) => "addr",
+            Message::FilterLoad(_) => "filterload",
+            Message::FilterClear => "filterclear",
+            Message::MerkleBlock(_) => "merkleblock",
+            Message::MemPool(_) => "mempool",
+            Message::Disconnect(_) => "disconnect",
+        }
+    }
+
+    pub fn write_to(&self, magic: &[u8; 4], writer: &mut impl Write) -> Result<()> {
+        let payload = match self {
+            Message::Version {
+                version,
+                genesis_hash,
+                height,
+                timestamp,
+            } => bincode::serialize(&(version, genesis_hash, height, timestamp))?,
+            Message::VerAck | Message::GetAddr | Message::FilterClear => vec![],
+            Message::Inv(inventory) | Message::GetData(inventory) => bincode::serialize(inventory)?,
+            Message::GetHeaders(locator) => bincode::serialize(locator)?,
+            Message::Headers(headers) => bincode::serialize(headers)?,
+            Message::Block(block) => bincode::serialize(block)?,
+            Message::Tx(transaction) => bincode::serialize(transaction)?,
+            Message::Ping(nonce) | Message::Pong(nonce) => bincode::serialize(nonce)?,
+            Message::Addr(addresses) => bincode::serialize(addresses)?,
+            Message::FilterLoad(filter) => bincode::serialize(filter)?,
+            Message::MerkleBlock(block) => bincode::serialize(block)?,
+            Message::MemPool(short_ids) => bincode::serialize(short_ids)?,
+            Message::Disconnect(reason) => bincode::serialize(reason)?,
+        };
+
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            bail!("Message is too big to send.")
+        }
+
+        let mut command = [0; COMMAND_SIZE];
+        command[..self.get_command().len()].copy_from_slice(self.get_command().as_bytes());
+
+        // Written in one go, so messages sent from different threads don't get mixed up.
+        let mut frame = Vec::with_capacity(24 + payload.len());
+        frame.extend_from_slice(magic);
+        frame.extend_from_slice(&command);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&calculate_checksum(&payload));
+        frame.extend_from_slice(&payload);
+        writer.write_all(&frame)?;
+
+        Ok(())
+    }
+
+    pub fn read_from(magic: &[u8; 4], reader: &mut impl Read) -> Result<Self> {
+        loop {
+            let mut header = [0; 24];
+            reader.read_exact(&mut header)?;
+
+            if header[..4] != *magic {
+                bail!("Message is for another network.")
+            }
+
+            let size = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+            if size > MAX_PAYLOAD_SIZE {
+                bail!("Message is too big.")
+            }
+
+            let mut payload = vec![0; size];
+            reader.read_exact(&mut payload)?;
+            if calculate_checksum(&payload) != header[20..24] {
+                bail!("Message checksum doesn't match.")
+            }
+
+            let command = &header[4..4 + COMMAND_SIZE];
+            let command_size = command
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(COMMAND_SIZE);
+
+            let message = match &command[..command_size] {
+                b"version" => {
+                    let (version, genesis_hash, height, timestamp) = decode(&payload)?;
+                    Message::Version {
+                        version,
+                        genesis_hash,
+                        height,
+                        timestamp,
+                    }
+                }
+                b"verack" => Message::VerAck,
+                b"inv" => Message::Inv(decode(&payload)?),
+                b"getdata" => Message::GetData(decode(&payload)?),
+                b"getheaders" => Message::GetHeaders(decode(&payload)?),
+                b"headers" => Message::Headers(decode(&payload)?),
+                b"block" => Message::Block(decode(&payload)?),
+                b"tx" => Message::Tx(decode(&payload)?),
+                b"ping" => Message::Ping(decode(&payload)?),
+                b"pong" => Message::Pong(decode(&payload)?),
+                b"getaddr" => Message::GetAddr,
+                b"addr" => Message::Addr(decode(&payload)?),
+                b"filterload" => Message::FilterLoad(decode(&payload)?),
+                b"filterclear" => Message::FilterClear,
+                b"merkleblock" => Message::MerkleBlock(decode(&payload)?),
+                b"mempool" => Message::MemPool(decode(&payload)?),
+                b"disconnect" => Message::Disconnect(decode(&payload)?),
+
+                // Something a newer peer knows about.
+                _ => continue,
+            };
+
+            return Ok(message);
+        }
+    }
+}
+
+pub fn negotiate_version(peer_version: u32) -> Result<u32> {
+    // Both sides speak the oldest of their two versions.
+    if peer_version < MIN_PROTOCOL_VERSION {
+        bail!("Peer protocol version {peer_version} is too old.")
+    }
+
+    Ok(peer_version.min(PROTOCOL_VERSION))
+}
+
+pub fn get_short_id(transaction_hash: &TxHash) -> u64 {
+    u64::from_le_bytes(transaction_hash.as_ref()[..8].try_into().unwrap())
+}
+
+fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T> {
+    bincode::deserialize(payload).context("Malformed message payload.")
+}
+
+fn calculate_checksum(payload: &[u8]) -> [u8; 4] {
+    let digest = Sha3_256::digest(payload);
+
+    digest[..4].try_into().unwrap()
+}
@@ -0,0 +1,879 @@
+// Peer to peer protocol between nodes, on top of the messages in `net`.
+//
+// Both sides start with their version and only relay to a peer once it sent its verack. Whoever
+// is behind syncs headers first, see `sync`. New blocks and transactions are announced to the
+// other peers by their hash, and only sent to those that ask for them. Peers also tell each other
+// about the peers they know, so a node only needs a few seeds to find the rest of the network.
+//
+// Once connected, peers send each other the short ids of their pending transactions, and announce
+// back the ones the other side is missing. This way a node that was just started learns about the
+// pending paints right away, instead of waiting for new ones to be relayed.
+//
+// Peers that go away on purpose say why first, see `shutdown`.
+//
+// Light peers load a bloom filter, see `place_coin::bloom`. They only hear about the transactions
+// that match it, and ask for blocks as Merkle blocks holding just those transactions.
+
+use crate::{
+    address_manager::{AddressManager, MAX_ADDRESSES},
+    limits::{CountingReader, GlobalLimits, PeerLimits, MAX_INBOUND_PEERS},
+    net::{get_short_id, negotiate_version, Inventory, Message, PROTOCOL_VERSION},
+    shutdown::ShutdownSignal,
+    sync::{ChainSync, MAX_HEADERS},
+    time::{get_timestamp, NetworkTime},
+    transport::{Connection, Listener, TcpTransport, Transport},
+};
+use anyhow::{bail, Context, Result};
+use place_coin::{
+    block::{BlockHeader, RawBlock},
+    blockchain::{BlockHash, Blockchain, TxHash},
+    bloom::BloomFilter,
+    mempool::Rejection,
+    transaction::RawTransaction,
+};
+use std::{
+    collections::HashSet,
+    io::BufReader,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+pub const MAX_OUTBOUND_PEERS: usize = 8;
+pub const MAX_INVENTORY: usize = 50_000; // Items in a single inv or getdata message.
+
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const GLOBAL_LIMIT_WAIT: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+struct Peer {
+    id: u64,
+    address: SocketAddr,
+    stream: Box<dyn Connection>, // To close the connection, even while a write to it is stuck.
+    writer: Arc<Mutex<Box<dyn Connection>>>, // Written to without holding the lock of the peers.
+    version: Option<u32>,        // Protocol version both sides agreed on.
+    is_ready: bool,              // Whether it sent its verack.
+    filter: Option<BloomFilter>,
+    is_inbound: bool,
+}
+
+#[derive(Debug, Default)]
+struct Peers {
+    peers: Vec<Peer>,
+    next_id: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    blockchain: Arc<Mutex<Blockchain>>,
+    peers: Arc<Mutex<Peers>>,
+    address_manager: Arc<Mutex<AddressManager>>,
+    sync: Arc<Mutex<ChainSync>>,
+    network_time: Arc<Mutex<NetworkTime>>,
+    magic: [u8; 4], // Of the network the blockchain is on.
+    global_limits: Arc<Mutex<GlobalLimits>>,
+    transport: Arc<dyn Transport>, // How peers are connected to.
+    shutdown_signal: ShutdownSignal,
+}
+
+impl Node {
+    pub fn new(blockchain: Arc<Mutex<Blockchain>>) -> Self {
+        Self::with_address_manager(blockchain, AddressManager::default())
+    }
+
+    pub fn with_address_manager(
+        blockchain: Arc<Mutex<Blockchain>>,
+        address_manager: AddressManager,
+    ) -> Self {
+        Self::with_transport(blockchain, address_manager, Arc::new(TcpTransport))
+    }
+
+    pub fn with_transport(
+        blockchain: Arc<Mutex<Blockchain>>,
+        address_manager: AddressManager,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
+        let magic = blockchain.lock().unwrap().get_chain_params().magic;
+
+        Self {
+            blockchain,
+            peers: Default::default(),
+            address_manager: Arc::new(Mutex::new(address_manager)),
+            sync: Default::default(),
+            network_time: Default::default(),
+            magic,
+            global_limits: Default::default(),
+            transport,
+            shutdown_signal: Default::default(),
+        }
+    }
+
+    pub fn listen(&self, listener: impl Listener) -> JoinHandle<()> {
+        let node = self.clone();
+
+        thread::spawn(move || {
+            while let Ok(stream) = listener.accept() {
+                // Waiting for a connection can't be interrupted, so only the next one notices.
+                if node.shutdown_signal.is_requested() {
+                    break;
+                }
+
+                // Once there are enough, new peers are turned away and the others kept.
+                if node.count_inbound_peers() >= MAX_INBOUND_PEERS {
+                    continue;
+                }
+
+                // A peer that can't even be set up is simply left out.
+                let _ = node.add_peer(stream, true);
+            }
+        })
+    }
+
+    pub fn watch_downloads(&self) -> JoinHandle<()> {
+        let node = self.clone();
+
+        thread::spawn(move || {
+            while !node.shutdown_signal.wait(STALL_CHECK_INTERVAL) {
+                node.drop_stalled_peers();
+            }
+        })
+    }
+
+    pub fn connect(&self, address: impl ToSocketAddrs) -> Result<()> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .context("Peer address doesn't resolve.")?;
+        let stream = self.transport.connect(&address)?;
+
+        // Only peers connected to are known to take connections on that address.
+        let mut address_manager = self.address_manager.lock().unwrap();
+        address_manager.mark_success(&address);
+        address_manager.save()?;
+        drop(address_manager);
+
+        self.add_peer(stream, false)
+    }
+
+    pub fn bootstrap(&self, seeds: &[&str]) -> Result<usize> {
+        // The best addresses first, spread over as many networks as possible.
+        let connected_addresses = self.get_peer_addresses();
+        let addresses = {
+            let mut address_manager = self.address_manager.lock().unwrap();
+            address_manager.add_seeds(seeds)?;
+
+            address_manager.select_outbound(address_manager.len(), &connected_addresses)
+        };
+
+        // Addresses that don't answer anymore are passed over, and forgotten after a while.
+        let mut connection_count = connected_addresses.len();
+        for address in addresses {
+            if connection_count >= MAX_OUTBOUND_PEERS {
+                break;
+            }
+
+            if self.connect(address).is_ok() {
+                connection_count += 1;
+            } else {
+                let mut address_manager = self.address_manager.lock().unwrap();
+                address_manager.mark_failure(&address);
+                address_manager.save()?;
+            }
+        }
+
+        Ok(connection_count)
+    }
+
+    pub fn disconnect_all(&self, reason: &str) {
+        // Closing the connections ends the peers' threads, which clean up after them.
+        let message = Message::Disconnect(reason.to_string());
+        let connections = self
+            .peers
+            .lock()
+            .unwrap()
+            .peers
+            .iter_mut()
+            .filter_map(|peer| {
+                peer.is_ready = false;
+                Some((peer.writer.clone(), peer.stream.try_clone().ok()?))
+            })
+            .collect::<Vec<_>>();
+
+        // Written once the lock is let go, like everything sent to peers.
+        for (writer, stream) in connections {
+            let _ = message.write_to(&self.magic, &mut *writer.lock().unwrap());
+            stream.close();
+        }
+    }
+
+    pub fn get_blockchain(&self) -> &Arc<Mutex<Blockchain>> {
+        &self.blockchain
+    }
+
+    pub fn get_shutdown_signal(&self) -> &ShutdownSignal {
+        &self.shutdown_signal
+    }
+
+    // Of the best chain peers sent headers for, which may not be downloaded yet.
+    pub fn get_header_height(&self) -> Result<u64> {
+        let blockchain = self.blockchain.lock().unwrap();
+
+        self.sync.lock().unwrap().get_best_height(&blockchain)
+    }
+
+    pub fn get_known_addresses(&self) -> Vec<SocketAddr> {
+        self.address_manager.lock().unwrap().get_addresses()
+    }
+
+    pub fn get_peer_addresses(&self) -> Vec<SocketAddr> {
+        self.peers
+            .lock()
+            .unwrap()
+            .peers
+            .iter()
+            .filter(|peer| peer.is_ready)
+            .map(|peer| peer.address)
+            .collect()
+    }
+
+    pub fn get_peer_version(&self, address: &SocketAddr) -> Option<u32> {
+        self.peers
+            .lock()
+            .unwrap()
+            .peers
+            .iter()
+            .find(|peer| peer.address == *address)
+            .and_then(|peer| peer.version)
+    }
+
+    pub fn mine(&self) -> Result<BlockHash> {
+        let block_hash = {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            blockchain.mine()?;
+
+            *blockchain.get_last_block_hash()
+        };
+        self.announce(Inventory::Block(block_hash), None);
+
+        Ok(block_hash)
+    }
+
+    pub fn submit_transaction(&self, transaction: RawTransaction) -> Result<TxHash> {
+        let transaction_hash = transaction.calculate_hash();
+        accept_raw_transaction(&mut self.blockchain.lock().unwrap(), transaction)?;
+        self.announce_transaction(&transaction_hash)?;
+
+        Ok(transaction_hash)
+    }
+
+    pub fn announce_block(&self, block_hash: &BlockHash) -> Result<()> {
+        if self
+            .blockchain
+            .lock()
+            .unwrap()
+            .get_block(block_hash)
+            .is_none()
+        {
+            bail!("Block isn't known.")
+        }
+        self.announce(Inventory::Block(*block_hash), None);
+
+        Ok(())
+    }
+
+    pub fn announce_transaction(&self, transaction_hash: &TxHash) -> Result<()> {
+        // Orphans can't be checked by peers yet, so they aren't announced.
+        if self
+            .blockchain
+            .lock()
+            .unwrap()
+            .find_pending_transaction(transaction_hash)
+            .is_none()
+        {
+            bail!("Transaction isn't pending.")
+        }
+        self.announce(Inventory::Transaction(*transaction_hash), None);
+
+        Ok(())
+    }
+
+    fn count_inbound_peers(&self) -> usize {
+        self.peers
+            .lock()
+            .unwrap()
+            .peers
+            .iter()
+            .filter(|peer| peer.is_inbound)
+            .count()
+    }
+
+    fn add_peer(&self, stream: Box<dyn Connection>, is_inbound: bool) -> Result<()> {
+        let address = stream.get_peer_address()?;
+        let id = {
+            let mut peers = self.peers.lock().unwrap();
+            let id = peers.next_id;
+            peers.next_id += 1;
+            peers.peers.push(Peer {
+                id,
+                address,
+                stream: stream.try_clone()?,
+                writer: Arc::new(Mutex::new(stream.try_clone()?)),
+                version: None,
+                is_ready: false,
+                filter: None,
+                is_inbound,
+            });
+
+            id
+        };
+
+        let version = {
+            let blockchain = self.blockchain.lock().unwrap();
+            Message::Version {
+                version: PROTOCOL_VERSION,
+                genesis_hash: get_genesis_hash(&blockchain),
+                height: blockchain.get_next_block_height()? - 1,
+                timestamp: get_timestamp(),
+            }
+        };
+        self.send(id, &version)?;
+
+        let node = self.clone();
+        thread::spawn(move || {
+            // Misbehaving peers are dropped, and nobody else needs to know about it.
+            let _ = node.handle_peer(id, stream);
+            node.peers
+                .lock()
+                .unwrap()
+                .peers
+                .retain(|peer| peer.id != id);
+
+            node.sync.lock().unwrap().remove_peer(id);
+            node.remove_time_sample(id);
+            node.request_blocks();
+        });
+
+        Ok(())
+    }
+
+    fn handle_peer(&self, id: u64, stream: Box<dyn Connection>) -> Result<()> {
+        let mut reader = BufReader::new(CountingReader::new(stream));
+        let mut has_version = false;
+        let mut limits = PeerLimits::default();
+
+        loop {
+            let message = Message::read_from(&self.magic, &mut reader)?;
+            let size = reader.get_mut().take_count();
+            if !limits.try_receive(size, Instant::now()) {
+                bail!("Peer sends too much.")
+            }
+            self.wait_for_global_limits(size);
+
+            match message {
+                Message::Version {
+                    version,
+                    genesis_hash,
+                    height,
+                    timestamp,
+                } => {
+                    let version = negotiate_version(version)?;
+                    let (is_same_chain, locator, best_height) = {
+                        let blockchain = self.blockchain.lock().unwrap();
+                        let sync = self.sync.lock().unwrap();
+                        (
+                            get_genesis_hash(&blockchain) == genesis_hash,
+                            sync.get_locator(&blockchain),
+                            sync.get_best_height(&blockchain)?,
+                        )
+                    };
+
+                    if !is_same_chain {
+                        bail!("Peer is on another chain.")
+                    }
+
+                    has_version = true;
+                    self.update_peer(id, |peer| peer.version = Some(version));
+                    self.add_time_sample(id, timestamp);
+                    self.send(id, &Message::VerAck)?;
+                    self.send(id, &Message::GetAddr)?;
+
+                    // Catch up with peers that are ahead.
+                    if height > best_height {
+                        self.send(id, &Message::GetHeaders(locator))?;
+                    }
+                }
+
+                _ if !has_version => bail!("Peer must send its version first."),
+
+                Message::VerAck => {
+                    self.update_peer(id, |peer| peer.is_ready = true);
+
+                    let short_ids = self
+                        .blockchain
+                        .lock()
+                        .unwrap()
+                        .get_pending_transactions()
+                        .iter()
+                        .take(MAX_INVENTORY)
+                        .map(|transaction| get_short_id(transaction.get_hash()))
+                        .collect();
+                    self.send(id, &Message::MemPool(short_ids))?;
+                }
+
+                Message::GetHeaders(locator) => {
+                    let headers = self
+                        .blockchain
+                        .lock()
+                        .unwrap()
+                        .get_headers_after(&locator, MAX_HEADERS);
+                    self.send(id, &Message::Headers(headers))?;
+                }
+
+                Message::Headers(headers) => self.receive_headers(id, headers)?,
+
+                Message::GetData(inventory) => {
+                    if inventory.len() > MAX_INVENTORY {
+                        bail!("Peer asked for too much inventory.")
+                    }
+
+                    for item in inventory {
+                        let message = {
+                            let blockchain = self.blockchain.lock().unwrap();
+                            match item {
+                                Inventory::Block(block_hash) => blockchain
+                                    .get_block(&block_hash)
+                                    .map(|block| Message::Block(block.to_raw())),
+                                Inventory::Transaction(transaction_hash) => blockchain
+                                    .find_pending_transaction(&transaction_hash)
+                                    .map(|transaction| Message::Tx(transaction.to_raw())),
+                                Inventory::FilteredBlock(block_hash) => {
+                                    blockchain.get_block(&block_hash).map(|block| {
+                                        // Peers without a filter get all of it.
+                                        let mut peers = self.peers.lock().unwrap();
+                                        match peers
+                                            .peers
+                                            .iter_mut()
+                                            .find(|peer| peer.id == id)
+                                            .and_then(|peer| peer.filter.as_mut())
+                                        {
+                                            Some(filter) => {
+                                                Message::MerkleBlock(block.get_merkle_block(filter))
+                                            }
+                                            None => Message::Block(block.to_raw()),
+                                        }
+                                    })
+                                }
+                            }
+                        };
+
+                        // Whatever isn't known anymore is left out.
+                        if let Some(message) = message {
+                            self.send(id, &message)?;
+                        }
+                    }
+                }
+
+                Message::Block(block) => self.receive_block(id, block)?,
+                Message::Tx(transaction) => {
+                    self.receive_transaction(id, transaction, &mut limits)?
+                }
+                Message::Ping(nonce) => self.send(id, &Message::Pong(nonce))?,
+                Message::Pong(_) => {}
+
+                Message::GetAddr => {
+                    let addresses = self.address_manager.lock().unwrap().get_addresses();
+                    self.send(id, &Message::Addr(addresses))?;
+                }
+
+                Message::Addr(addresses) => {
+                    if addresses.len() > MAX_ADDRESSES {
+                        bail!("Peer sent too many addresses.")
+                    }
+
+                    let mut address_manager = self.address_manager.lock().unwrap();
+                    address_manager.add(&addresses);
+                    address_manager.save()?;
+                }
+
+                Message::Inv(inventory) => self.receive_inventory(id, inventory)?,
+
+                Message::FilterLoad(filter) => {
+                    if !filter.is_valid() {
+                        bail!("Peer sent a bloom filter that is too big.")
+                    }
+                    self.update_peer(id, |peer| peer.filter = Some(filter));
+                }
+
+                Message::FilterClear => self.update_peer(id, |peer| peer.filter = None),
+
+                Message::MemPool(short_ids) => self.receive_mempool(id, short_ids)?,
+                Message::Disconnect(_) => return Ok(()),
+
+                // Only light clients have any use for these.
+                Message::MerkleBlock(_) => {}
+            }
+        }
+    }
+
+    fn receive_inventory(&self, id: u64, inventory: Vec<Inventory>) -> Result<()> {
+        if inventory.len() > MAX_INVENTORY {
+            bail!("Peer sent too much inventory.")
+        }
+
+        // Only ask for what isn't known yet.
+        let missing = {
+            let blockchain = self.blockchain.lock().unwrap();
+            inventory
+                .into_iter()
+                .filter(|item| match item {
+                    Inventory::Block(block_hash) => blockchain.get_block(block_hash).is_none(),
+                    Inventory::Transaction(transaction_hash) => {
+                        blockchain
+                            .find_pending_transaction(transaction_hash)
+                            .is_none()
+                            && !blockchain.get_orphans().contains(transaction_hash)
+                            && blockchain.find_transaction(transaction_hash).is_none()
+                    }
+                    Inventory::FilteredBlock(_) => false,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if !missing.is_empty() {
+            self.send(id, &Message::GetData(missing))?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_mempool(&self, id: u64, short_ids: Vec<u64>) -> Result<()> {
+        if short_ids.len() > MAX_INVENTORY {
+            bail!("Peer sent too big a mempool summary.")
+        }
+
+        // The peer asks for whatever it's missing itself, as with any other announcement.
+        let short_ids = short_ids.into_iter().collect::<HashSet<_>>();
+        let missing = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let mut peers = self.peers.lock().unwrap();
+            let mut filter = peers
+                .peers
+                .iter_mut()
+                .find(|peer| peer.id == id)
+                .and_then(|peer| peer.filter.as_mut());
+
+            blockchain
+                .get_pending_transactions()
+                .iter()
+                .filter(|transaction| !short_ids.contains(&get_short_id(transaction.get_hash())))
+                .filter(|transaction| {
+                    filter
+                        .as_mut()
+                        .is_none_or(|filter| filter.matches_transaction(transaction))
+                })
+                .map(|transaction| Inventory::Transaction(*transaction.get_hash()))
+                .collect::<Vec<_>>()
+        };
+
+        for inventory in missing.chunks(MAX_INVENTORY) {
+            self.send(id, &Message::Inv(inventory.to_vec()))?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_headers(&self, id: u64, headers: Vec<BlockHeader>) -> Result<()> {
+        if headers.len() > MAX_HEADERS {
+            bail!("Peer sent too many headers.")
+        }
+
+        let is_full = headers.len() == MAX_HEADERS;
+        let locator = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let mut sync = self.sync.lock().unwrap();
+            for header in headers {
+                sync.add_header(&blockchain, header)?;
+            }
+
+            sync.get_locator(&blockchain)
+        };
+
+        // A full message means there are more to come.
+        if is_full {
+            self.send(id, &Message::GetHeaders(locator))?;
+        }
+        self.request_blocks();
+
+        Ok(())
+    }
+
+    fn request_blocks(&self) {
+        let peer_ids = self
+            .peers
+            .lock()
+            .unwrap()
+            .peers
+            .iter()
+            .filter(|peer| peer.is_ready)
+            .map(|peer| peer.id)
+            .collect::<Vec<_>>();
+
+        let requests = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let mut sync = self.sync.lock().unwrap();
+            let missing_hashes = sync.get_missing_blocks(&blockchain);
+
+            sync.schedule(&missing_hashes, &peer_ids)
+        };
+
+        for (peer_id, block_hashes) in requests {
+            let inventory = block_hashes.into_iter().map(Inventory::Block).collect();
+
+            // Peers that went away give their blocks back once their thread notices.
+            let _ = self.send(peer_id, &Message::GetData(inventory));
+        }
+    }
+
+    fn drop_stalled_peers(&self) {
+        let peer_ids = self.sync.lock().unwrap().take_stalled_peers(Instant::now());
+        if peer_ids.is_empty() {
+            return;
+        }
+
+        // Closing the connection ends the peer's thread, which cleans up after it.
+        for id in peer_ids {
+            self.update_peer(id, |peer| {
+                peer.is_ready = false;
+                peer.stream.close();
+            });
+        }
+        self.request_blocks();
+    }
+
+    fn connect_downloaded_blocks(&self) -> Result<()> {
+        let mut blockchain = self.blockchain.lock().unwrap();
+        loop {
+            let block = match self.sync.lock().unwrap().take_next_block(&blockchain) {
+                Some(block) => block,
+                None => return Ok(()),
+            };
+
+            blockchain.submit_raw_block(block)?;
+        }
+    }
+
+    fn receive_block(&self, id: u64, block: RawBlock) -> Result<()> {
+        if self
+            .sync
+            .lock()
+            .unwrap()
+            .receive_block(block.calculate_hash(), block.clone())
+        {
+            self.connect_downloaded_blocks()?;
+            self.request_blocks();
+
+            return Ok(());
+        }
+
+        // Otherwise it was just found, and is passed on if it's new.
+        let block_hash = block.calculate_hash();
+
+        let is_connected = {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            let last_block_hash = *blockchain.get_last_block_hash();
+
+            match block.previous_hash {
+                Some(previous_hash) if previous_hash == last_block_hash => {
+                    blockchain.submit_raw_block(block)?;
+                    true
+                }
+
+                // Either one already known, or one of a branch that can't be switched to.
+                Some(previous_hash) if blockchain.get_block(&previous_hash).is_some() => false,
+
+                // Blocks in between are missing.
+                _ => {
+                    let locator = self.sync.lock().unwrap().get_locator(&blockchain);
+                    drop(blockchain);
+
+                    return self.send(id, &Message::GetHeaders(locator));
+                }
+            }
+        };
+
+        if is_connected {
+            self.announce(Inventory::Block(block_hash), Some(id));
+        }
+
+        Ok(())
+    }
+
+    fn wait_for_global_limits(&self, size: usize) {
+        // Everyone slows down together, instead of some peers being dropped for the others.
+        while !self
+            .global_limits
+            .lock()
+            .unwrap()
+            .try_receive(size, Instant::now())
+        {
+            thread::sleep(GLOBAL_LIMIT_WAIT);
+        }
+    }
+
+    fn receive_transaction(
+        &self,
+        id: u64,
+        transaction: RawTransaction,
+        limits: &mut PeerLimits,
+    ) -> Result<()> {
+        let transaction_hash = transaction.calculate_hash();
+
+        let (is_accepted, is_orphan) = {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            let is_known = blockchain
+                .find_pending_transaction(&transaction_hash)
+                .is_some()
+                || blockchain.get_orphans().contains(&transaction_hash)
+                || blockchain.find_transaction(&transaction_hash).is_some();
+
+            // Peers may not have seen the same transactions yet, so one that doesn't fit isn't
+            // held against them, but one that spends what its signer doesn't own is.
+            let is_submitted = !is_known
+                && match accept_raw_transaction(&mut blockchain, transaction) {
+                    Ok(()) => true,
+                    Err(error) => {
+                        if error.downcast_ref() == Some(&Rejection::BadSignature) {
+                            bail!("Peer sent a transaction that isn't signed by its owner.")
+                        }
+                        false
+                    }
+                };
+
+            (
+                is_submitted
+                    && blockchain
+                        .find_pending_transaction(&transaction_hash)
+                        .is_some(),
+                is_submitted && blockchain.get_orphans().contains(&transaction_hash),
+            )
+        };
+
+        // Orphans sit in memory until their parents show up, so only so many are taken.
+        if is_orphan && !limits.try_receive_orphan(Instant::now()) {
+            bail!("Peer sent too many orphan transactions.")
+        }
+
+        if is_accepted {
+            self.announce(Inventory::Transaction(transaction_hash), Some(id));
+        }
+
+        Ok(())
+    }
+
+    fn add_time_sample(&self, id: u64, timestamp: i64) {
+        let mut network_time = self.network_time.lock().unwrap();
+        network_time.add_sample(id, timestamp);
+        self.blockchain
+            .lock()
+            .unwrap()
+            .set_time_offset(network_time.get_offset());
+    }
+
+    fn remove_time_sample(&self, id: u64) {
+        let mut network_time = self.network_time.lock().unwrap();
+        network_time.remove_peer(id);
+        self.blockchain
+            .lock()
+            .unwrap()
+            .set_time_offset(network_time.get_offset());
+    }
+
+    fn update_peer(&self, id: u64, update: impl FnOnce(&mut Peer)) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(peer) = peers.peers.iter_mut().find(|peer| peer.id == id) {
+            update(peer);
+        }
+    }
+
+    fn send(&self, id: u64, message: &Message) -> Result<()> {
+        // Only the writer of the peer is held while writing, so a peer that stops reading doesn't
+        // hold up what's sent to the others, or the blockchain.
+        let writer = self
+            .peers
+            .lock()
+            .unwrap()
+            .peers
+            .iter()
+            .find(|peer| peer.id == id)
+            .map(|peer| peer.writer.clone())
+            .context("Peer is gone.")?;
+
+        let mut stream = writer.lock().unwrap();
+        message.write_to(&self.magic, &mut *stream)
+    }
+
+    fn announce(&self, item: Inventory, except_id: Option<u64>) {
+        let message = Message::Inv(vec![item]);
+        let writers = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let transaction = match item {
+                Inventory::Transaction(transaction_hash) => {
+                    blockchain.find_pending_transaction(&transaction_hash)
+                }
+                _ => None,
+            };
+
+            self.peers
+                .lock()
+                .unwrap()
+                .peers
+                .iter_mut()
+                .filter_map(|peer| {
+                    // Light peers only hear about the transactions that match their filter.
+                    let is_wanted = match (&mut peer.filter, transaction) {
+                        (Some(filter), Some(transaction)) => {
+                            filter.matches_transaction(transaction)
+                        }
+                        _ => true,
+                    };
+
+                    (peer.is_ready && Some(peer.id) != except_id && is_wanted)
+                        .then(|| (peer.id, peer.writer.clone()))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Written once the locks are let go, so a peer that stops reading only holds up this.
+        let failed_ids = writers
+            .into_iter()
+            .filter(|(_, writer)| {
+                message
+                    .write_to(&self.magic, &mut *writer.lock().unwrap())
+                    .is_err()
+            })
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        if !failed_ids.is_empty() {
+            self.peers
+                .lock()
+                .unwrap()
+                .peers
+                .retain(|peer| !failed_ids.contains(&peer.id));
+        }
+    }
+}
+
+fn get_genesis_hash(blockchain: &Blockchain) -> BlockHash {
+    blockchain.get_main_chain()[0].calculate_hash()
+}
+
+// What sendrawtransaction checks, signatures and owners included, except that transactions missing
+// their parents are held as orphans until those show up.
+fn accept_raw_transaction(blockchain: &mut Blockchain, transaction: RawTransaction) -> Result<()> {
+    match blockchain.test_accept_raw_transaction(transaction.clone()) {
+        Ok(transaction) => blockchain.new_transaction(transaction),
+        Err(Rejection::MissingInputs) => blockchain.submit_raw_transaction(transaction),
+        Err(rejection) => Err(rejection.into()),
+    }
+}
@@ -0,0 +1,59 @@
+// Pay Per Last N Shares: the reward of a block is split by how many of the last shares each worker
+// found, so hopping in just before a block is found doesn't pay off.
+
+use place_coin::address::Address;
+use std::collections::{HashMap, VecDeque};
+
+pub const DEFAULT_PPLNS_WINDOW: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct Pool {
+    window: usize,
+    shares: VecDeque<Address>, // Who found each of the last shares, oldest first.
+}
+
+impl Pool {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            shares: Default::default(),
+        }
+    }
+
+    pub fn record_share(&mut self, address: &Address) {
+        self.shares.push_back(address.clone());
+        while self.shares.len() > self.window {
+            self.shares.pop_front();
+        }
+    }
+
+    pub fn get_share_count(&self, address: &Address) -> u64 {
+        self.shares.iter().filter(|share| *share == address).count() as u64
+    }
+
+    pub fn get_payouts(&self) -> Vec<(Address, u64)> {
+        let mut counts = HashMap::<&Address, u64>::new();
+        for address in &self.shares {
+            *counts.entry(address).or_insert(0) += 1;
+        }
+
+        // Biggest first, so rounding leftovers go to whoever did the most work.
+        let mut payouts = counts
+            .into_iter()
+            .map(|(address, count)| (address.clone(), count))
+            .collect::<Vec<_>>();
+        payouts.sort_by(|(address, count), (other_address, other_count)| {
+            other_count
+                .cmp(count)
+                .then_with(|| address.to_string().cmp(&other_address.to_string()))
+        });
+
+        payouts
+    }
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new(DEFAULT_PPLNS_WINDOW)
+    }
+}
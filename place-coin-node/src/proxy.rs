@@ -0,0 +1,173 @@
+// Outbound connections through a SOCKS5 proxy (RFC 1928), such as the one Tor runs, so peers
+// never see this node's own address. Only connecting without authentication is supported:
+//
+//     greeting: version (5) | method count (1) | method (0, no authentication)
+//     choice:   version (5) | chosen method (0)
+//     request:  version (5) | command (1, connect) | reserved (0) | address type | address | port
+//     reply:    version (5) | reply code (0 on success) | reserved (0) | address type | bound
+//               address | bound port
+//
+// Addresses are IPv4 (type 1, 4 bytes), IPv6 (type 4, 16 bytes) or host names (type 3, a length
+// byte and then the name). Onion services only have names, which the proxy resolves itself. They
+// are given a stand-in IPv6 address in fd87:d87e:eb43::/48, like OnionCat does, so they can be
+// connected to and kept like any other peer.
+
+use crate::transport::{Connection, Transport};
+use anyhow::{bail, Context, Result};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv6Addr, Shutdown, SocketAddr, TcpStream},
+    sync::Mutex,
+};
+
+pub const ONION_PREFIX: [u8; 6] = [0xfd, 0x87, 0xd8, 0x7e, 0xeb, 0x43];
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const HOST_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+#[derive(Debug)]
+pub struct Socks5Transport {
+    proxy: SocketAddr,
+    onion_hosts: Mutex<HashMap<SocketAddr, String>>, // Host names behind their stand-in addresses.
+}
+
+impl Socks5Transport {
+    pub fn new(proxy: SocketAddr) -> Self {
+        Self {
+            proxy,
+            onion_hosts: Default::default(),
+        }
+    }
+
+    pub fn add_onion_peer(&self, address: &str) -> Result<SocketAddr> {
+        let (host, port) = address
+            .rsplit_once(':')
+            .context("Onion address has no port.")?;
+        if !host.ends_with(".onion") || host.len() > u8::MAX as usize {
+            bail!("Address isn't an onion address.")
+        }
+
+        // The same host always gets the same stand-in, so saved addresses keep working.
+        let digest = Sha3_256::digest(host.as_bytes());
+        let mut octets = [0; 16];
+        octets[..6].copy_from_slice(&ONION_PREFIX);
+        octets[6..].copy_from_slice(&digest[..10]);
+        let stand_in = SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port.parse()?);
+
+        self.onion_hosts
+            .lock()
+            .unwrap()
+            .insert(stand_in, host.to_string());
+
+        Ok(stand_in)
+    }
+}
+
+impl Transport for Socks5Transport {
+    fn connect(&self, address: &SocketAddr) -> Result<Box<dyn Connection>> {
+        let mut stream = TcpStream::connect(self.proxy).context("Fail to connect to proxy.")?;
+
+        stream.write_all(&[VERSION, 1, NO_AUTHENTICATION])?;
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice)?;
+        if choice != [VERSION, NO_AUTHENTICATION] {
+            bail!("Proxy wants authentication.")
+        }
+
+        let mut request = vec![VERSION, CONNECT, 0];
+        let onion_host = self.onion_hosts.lock().unwrap().get(address).cloned();
+        match (onion_host, address.ip()) {
+            (Some(host), _) => {
+                request.push(HOST_NAME);
+                request.push(host.len() as u8);
+                request.extend_from_slice(host.as_bytes());
+            }
+            (None, IpAddr::V4(ip)) => {
+                request.push(IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            (None, IpAddr::V6(ip)) => {
+                if ip.octets()[..6] == ONION_PREFIX {
+                    bail!("Onion peer is unknown.")
+                }
+                request.push(IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+        }
+        request.extend_from_slice(&address.port().to_be_bytes());
+        stream.write_all(&request)?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            bail!("Proxy doesn't speak SOCKS5.")
+        }
+        if reply[1] != 0 {
+            bail!("Proxy couldn't connect to peer, reply code {}.", reply[1])
+        }
+
+        // The address the proxy connected from is of no use, but must be read past.
+        let bound_size = match reply[3] {
+            IPV4 => 4,
+            IPV6 => 16,
+            HOST_NAME => {
+                let mut size = [0];
+                stream.read_exact(&mut size)?;
+                size[0] as usize
+            }
+            _ => bail!("Proxy reply is malformed."),
+        };
+        stream.read_exact(&mut vec![0; bound_size + 2])?;
+
+        Ok(Box::new(ProxyConnection {
+            stream,
+            peer_address: *address,
+        }))
+    }
+}
+
+// The stream itself only knows the proxy's address.
+#[derive(Debug)]
+pub struct ProxyConnection {
+    stream: TcpStream,
+    peer_address: SocketAddr,
+}
+
+impl Read for ProxyConnection {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buffer)
+    }
+}
+
+impl Write for ProxyConnection {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.stream.write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Connection for ProxyConnection {
+    fn get_peer_address(&self) -> Result<SocketAddr> {
+        Ok(self.peer_address)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(Self {
+            stream: self.stream.try_clone()?,
+            peer_address: self.peer_address,
+        }))
+    }
+
+    fn close(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
@@ -0,0 +1,138 @@
+// Stopping a node without losing anything. The miner is stopped first, so no block is found that
+// nobody hears about. Then every peer is told the node is going away and disconnected, and only
+// once nothing changes anymore are the chain and the pending transactions saved.
+//
+// The state file holds the main chain after the genesis block and the pending transactions,
+// encoded with bincode. Loading it replays both, so everything is checked again.
+
+use crate::p2p::Node;
+use anyhow::{Context, Result};
+use place_coin::{block::RawBlock, blockchain::Blockchain, transaction::RawTransaction};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+pub const DISCONNECT_REASON: &str = "Node is shutting down.";
+
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal(Arc<(Mutex<bool>, Condvar)>);
+
+impl ShutdownSignal {
+    pub fn request(&self) {
+        let (is_requested, condvar) = &*self.0;
+        *is_requested.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        let (is_requested, _) = &*self.0;
+
+        *is_requested.lock().unwrap()
+    }
+
+    pub fn wait(&self, timeout: Duration) -> bool {
+        // Wakes up right away once a shutdown is requested, instead of at the end of the timeout.
+        let (is_requested, condvar) = &*self.0;
+        let (is_requested, _) = condvar
+            .wait_timeout_while(is_requested.lock().unwrap(), timeout, |is_requested| {
+                !*is_requested
+            })
+            .unwrap();
+
+        *is_requested
+    }
+}
+
+#[derive(Debug)]
+pub struct ShutdownController {
+    node: Node,
+    state_path: PathBuf,
+    threads: Vec<JoinHandle<()>>, // Joined before anything is saved.
+}
+
+impl ShutdownController {
+    pub fn new(node: Node, state_path: impl AsRef<Path>) -> Self {
+        Self {
+            node,
+            state_path: state_path.as_ref().to_path_buf(),
+            threads: vec![],
+        }
+    }
+
+    pub fn start_miner(&mut self, block_interval: Duration) {
+        let node = self.node.clone();
+        let signal = node.get_shutdown_signal().clone();
+
+        self.threads.push(thread::spawn(move || {
+            while !signal.wait(block_interval) {
+                // A block that can't be mined now may be later, once the mempool changed.
+                let _ = node.mine();
+            }
+        }));
+    }
+
+    pub fn watch_downloads(&mut self) {
+        self.threads.push(self.node.watch_downloads());
+    }
+
+    pub fn shutdown(self) -> Result<()> {
+        self.node.get_shutdown_signal().request();
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+
+        self.node.disconnect_all(DISCONNECT_REASON);
+
+        save_state(
+            &self.node.get_blockchain().lock().unwrap(),
+            &self.state_path,
+        )
+    }
+}
+
+pub fn save_state(blockchain: &Blockchain, path: impl AsRef<Path>) -> Result<()> {
+    let blocks = blockchain
+        .get_main_chain()
+        .iter()
+        .skip(1)
+        .map(|block| block.to_raw())
+        .collect::<Vec<_>>();
+    let transactions = blockchain
+        .get_pending_transactions()
+        .iter()
+        .map(|transaction| transaction.to_raw())
+        .collect::<Vec<_>>();
+
+    // Written next to it first, so a crash halfway doesn't leave half a state behind.
+    let path = path.as_ref();
+    let temporary_path = path.with_extension("tmp");
+    fs::write(
+        &temporary_path,
+        bincode::serialize(&(blocks, transactions))?,
+    )
+    .context("Fail to save node state.")?;
+    fs::rename(&temporary_path, path).context("Fail to save node state.")?;
+
+    Ok(())
+}
+
+pub fn load_state(blockchain: &mut Blockchain, path: impl AsRef<Path>) -> Result<()> {
+    let contents = fs::read(path).context("Fail to read node state.")?;
+    let (blocks, transactions): (Vec<RawBlock>, Vec<RawTransaction>) =
+        bincode::deserialize(&contents).context("Node state is malformed.")?;
+
+    for block in blocks {
+        blockchain.submit_raw_block(block)?;
+    }
+
+    // Transactions may not fit anymore, say after the mempool got smaller, and are just left out.
+    for transaction in transactions {
+        let _ = blockchain.submit_raw_transaction(transaction);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+// A stratum like protocol for remote miners, with one message per line:
+//
+//     miner: subscribe <address>     server: job <id> <height> <last proof>
+//     miner: submit <id> <proof>     server: share | block | rejected <reason>
+//
+// Jobs are pushed to every subscribed miner, and only the latest one can be submitted. Blocks pay
+// whoever found the last shares, falling back to the coinbase address before there are any.
+
+use crate::pool::Pool;
+use anyhow::{bail, Context, Result};
+use place_coin::{
+    address::Address,
+    block::BlockTemplate,
+    blockchain::{Blockchain, Proof},
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+pub const SHARE_TARGET: u8 = 0x10; // The proof hash of a share must start below it, a block's with zero.
+
+#[derive(Debug, Default)]
+struct State {
+    job: Option<(u64, BlockTemplate)>,
+    next_job_id: u64,
+    miners: Vec<TcpStream>, // Where new jobs are pushed to.
+    pool: Pool,
+}
+
+#[derive(Debug, Clone)]
+pub struct StratumServer {
+    blockchain: Arc<Mutex<Blockchain>>,
+    coinbase_address: Address,
+    state: Arc<Mutex<State>>,
+}
+
+impl StratumServer {
+    pub fn new(blockchain: Arc<Mutex<Blockchain>>, coinbase_address: Address) -> Self {
+        Self::with_pool(blockchain, coinbase_address, Pool::default())
+    }
+
+    pub fn with_pool(
+        blockchain: Arc<Mutex<Blockchain>>,
+        coinbase_address: Address,
+        pool: Pool,
+    ) -> Self {
+        Self {
+            blockchain,
+            coinbase_address,
+            state: Arc::new(Mutex::new(State {
+                pool,
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn listen(&self, listener: TcpListener) -> JoinHandle<()> {
+        let server = self.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+
+                // A miner going away is nothing the others need to know about.
+                thread::spawn(move || {
+                    let _ = server.handle_miner(stream);
+                });
+            }
+        })
+    }
+
+    pub fn push_job(&self) -> Result<u64> {
+        let template = self
+            .blockchain
+            .lock()
+            .unwrap()
+            .build_block_template(&self.coinbase_address)?;
+
+        let mut state = self.state.lock().unwrap();
+        let job_id = state.next_job_id;
+        state.next_job_id += 1;
+
+        let message = format_job(job_id, &template);
+        state
+            .miners
+            .retain_mut(|miner| writeln!(miner, "{message}").is_ok());
+        state.job = Some((job_id, template));
+
+        Ok(job_id)
+    }
+
+    pub fn get_shares(&self, address: &Address) -> u64 {
+        self.state.lock().unwrap().pool.get_share_count(address)
+    }
+
+    pub fn submit(&self, address: &Address, job_id: u64, proof: Proof) -> Result<bool> {
+        let template = match &self.state.lock().unwrap().job {
+            Some((current_job_id, template)) if *current_job_id == job_id => template.clone(),
+            _ => bail!("Job is stale."),
+        };
+
+        let hash = Blockchain::calculate_proof_hash(template.get_last_proof(), &proof);
+        if hash[0] >= SHARE_TARGET {
+            bail!("Proof doesn't meet the share target.")
+        }
+
+        let mut payouts = {
+            let mut state = self.state.lock().unwrap();
+            state.pool.record_share(address);
+
+            state.pool.get_payouts()
+        };
+
+        if !Blockchain::validate_proof(template.get_last_proof(), &proof) {
+            return Ok(false);
+        }
+
+        if payouts.is_empty() {
+            payouts.push((self.coinbase_address.clone(), 1));
+        }
+
+        // The proof only depends on the last one, so the reward can still be split by the latest
+        // shares. Then everyone moves on to the next block.
+        {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            let template = blockchain.build_block_template_with_payouts(&payouts)?;
+            blockchain.submit_block(template, proof)?;
+        }
+        self.push_job()?;
+
+        Ok(true)
+    }
+
+    fn handle_miner(&self, stream: TcpStream) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut address = None;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            let reply = match words.as_slice() {
+                ["subscribe", miner_address] => match miner_address.parse::<Address>() {
+                    Ok(miner_address) => {
+                        address = Some(miner_address);
+
+                        let mut state = self.state.lock().unwrap();
+                        state.miners.push(writer.try_clone()?);
+                        match &state.job {
+                            Some((job_id, template)) => format_job(*job_id, template),
+                            None => continue,
+                        }
+                    }
+                    Err(error) => format!("rejected {error}"),
+                },
+
+                ["submit", job_id, proof] => {
+                    let result = address
+                        .as_ref()
+                        .context("Subscribe before submitting.")
+                        .and_then(|address| {
+                            let job_id = job_id.parse().context("Job id isn't a number.")?;
+                            let proof = proof.parse().context("Proof isn't a number.")?;
+
+                            self.submit(address, job_id, proof)
+                        });
+
+                    match result {
+                        Ok(true) => "block".to_string(),
+                        Ok(false) => "share".to_string(),
+                        Err(error) => format!("rejected {error}"),
+                    }
+                }
+
+                _ => "rejected Unknown message.".to_string(),
+            };
+
+            writeln!(writer, "{reply}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn format_job(job_id: u64, template: &BlockTemplate) -> String {
+    format!(
+        "job {job_id} {} {}",
+        template.get_height(),
+        template.get_last_proof()
+    )
+}
@@ -0,0 +1,304 @@
+// Headers first chain synchronization. Headers are small and their proof of work can be checked
+// on their own, so they are downloaded first to find the best chain. Bodies are then fetched from
+// several peers at once, and connected in order as soon as the next one is there.
+//
+// The missing blocks are split into windows of consecutive blocks, each one asked from a single
+// peer. A peer that sends nothing from its window for too long is taken off it, and the window
+// goes to someone else.
+//
+// Headers are only kept while they build on the last block, since branches can't be switched to,
+// and only so many of them. Once there are too many, those left behind by a new last block go
+// first, and if that isn't enough new ones are turned away until blocks get connected.
+
+use anyhow::{bail, Context, Result};
+use place_coin::{
+    block::{check_block_time, BlockHeader, RawBlock},
+    blockchain::{BlockHash, Blockchain},
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub const MAX_HEADERS: usize = 2000; // Per message, asking again for the rest.
+pub const DEFAULT_MAX_UNCONNECTED_HEADERS: usize = 20 * MAX_HEADERS;
+pub const WINDOW_SIZE: usize = 16; // Blocks asked from a peer at once.
+pub const MAX_WINDOWS_PER_PEER: usize = 2; // So the next one is already asked for.
+pub const BLOCK_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct Window {
+    peer_id: u64,
+    block_hashes: Vec<BlockHash>, // Those still to come.
+    last_progress: Instant,       // When it was asked for, or the last of its blocks came in.
+}
+
+#[derive(Debug)]
+pub struct ChainSync {
+    headers: HashMap<BlockHash, (BlockHeader, u64)>, // With their heights. Only blocks not yet connected.
+    max_headers: usize,
+    best_header: Option<(BlockHash, u64)>,
+    windows: Vec<Window>,
+    downloaded: HashMap<BlockHash, RawBlock>,
+}
+
+impl ChainSync {
+    pub fn with_max_headers(max_headers: usize) -> Self {
+        Self {
+            headers: HashMap::new(),
+            max_headers,
+            best_header: None,
+            windows: vec![],
+            downloaded: HashMap::new(),
+        }
+    }
+
+    pub fn add_header(&mut self, blockchain: &Blockchain, header: BlockHeader) -> Result<bool> {
+        let header_hash = header.calculate_hash();
+        if self.headers.contains_key(&header_hash) || blockchain.get_block(&header_hash).is_some() {
+            return Ok(false);
+        }
+
+        let previous_hash = header
+            .previous_hash
+            .context("Only the genesis block has no previous block.")?;
+        let (previous_proof, previous_height) = match self.headers.get(&previous_hash) {
+            Some((previous_header, height)) => (previous_header.proof, *height),
+            None => {
+                let previous_block = blockchain
+                    .get_block(&previous_hash)
+                    .context("Header doesn't connect to a known block.")?;
+
+                // Honest peers can be on another branch, it just isn't of any use.
+                if previous_hash != *blockchain.get_last_block_hash() {
+                    return Ok(false);
+                }
+
+                (
+                    *previous_block.get_proof(),
+                    previous_block.get_block_height()?,
+                )
+            }
+        };
+
+        // What a block needs to be valid, as far as its header can tell.
+        if !Blockchain::validate_proof(&previous_proof, &header.proof) {
+            bail!("Header proof of work isn't valid.")
+        }
+        check_block_time(blockchain, &header.timestamp)?;
+
+        if self.headers.len() >= self.max_headers {
+            self.prune(blockchain);
+            if self.headers.len() >= self.max_headers
+                || !self.headers.contains_key(&previous_hash)
+                    && previous_hash != *blockchain.get_last_block_hash()
+            {
+                return Ok(false);
+            }
+        }
+
+        // Every block takes the same work, so the best chain is the longest one.
+        let height = previous_height + 1;
+        if self
+            .best_header
+            .is_none_or(|(_, best_height)| height > best_height)
+        {
+            self.best_header = Some((header_hash, height));
+        }
+        self.headers.insert(header_hash, (header, height));
+
+        Ok(true)
+    }
+
+    fn prune(&mut self, blockchain: &Blockchain) {
+        // Only the headers going back to the last block through the others are still of any use.
+        let last_block_hash = *blockchain.get_last_block_hash();
+        let mut is_kept = HashMap::new();
+        let header_hashes = self.headers.keys().copied().collect::<Vec<_>>();
+        for header_hash in header_hashes {
+            let mut branch = vec![];
+            let mut hash = header_hash;
+            let is_connected = loop {
+                if let Some(is_connected) = is_kept.get(&hash) {
+                    break *is_connected;
+                }
+                if blockchain.get_block(&hash).is_some() {
+                    break hash == last_block_hash;
+                }
+                let Some((header, _)) = self.headers.get(&hash) else {
+                    break false;
+                };
+
+                branch.push(hash);
+                match header.previous_hash {
+                    Some(previous_hash) => hash = previous_hash,
+                    None => break false,
+                }
+            };
+
+            for hash in branch {
+                is_kept.insert(hash, is_connected);
+            }
+        }
+        self.headers
+            .retain(|header_hash, _| is_kept.get(header_hash) == Some(&true));
+
+        if self
+            .best_header
+            .is_some_and(|(best_hash, _)| !self.headers.contains_key(&best_hash))
+        {
+            self.best_header = self
+                .headers
+                .iter()
+                .max_by_key(|(_, (_, height))| *height)
+                .map(|(header_hash, (_, height))| (*header_hash, *height));
+        }
+    }
+
+    pub fn get_best_height(&self, blockchain: &Blockchain) -> Result<u64> {
+        let last_height = blockchain.get_next_block_height()? - 1;
+
+        Ok(self
+            .best_header
+            .map_or(last_height, |(_, height)| height.max(last_height)))
+    }
+
+    pub fn get_locator(&self, blockchain: &Blockchain) -> Vec<BlockHash> {
+        // The peer answers with what follows the first of these on its main chain.
+        self.best_header
+            .map(|(best_hash, _)| best_hash)
+            .into_iter()
+            .chain(blockchain.block_locator())
+            .collect()
+    }
+
+    pub fn get_missing_blocks(&self, blockchain: &Blockchain) -> Vec<BlockHash> {
+        // Walk back from the best header to a block that is already connected.
+        let mut block_hashes = vec![];
+        let mut block_hash = self.best_header.map(|(best_hash, _)| best_hash);
+        while let Some(hash) = block_hash.filter(|hash| blockchain.get_block(hash).is_none()) {
+            block_hashes.push(hash);
+            block_hash = self
+                .headers
+                .get(&hash)
+                .and_then(|(header, _)| header.previous_hash);
+        }
+
+        // There is no switching branches, so only blocks on top of the last one are of any use.
+        if block_hash != Some(*blockchain.get_last_block_hash()) {
+            return vec![];
+        }
+        block_hashes.reverse();
+
+        block_hashes
+            .into_iter()
+            .filter(|hash| {
+                !self
+                    .windows
+                    .iter()
+                    .any(|window| window.block_hashes.contains(hash))
+                    && !self.downloaded.contains_key(hash)
+            })
+            .collect()
+    }
+
+    pub fn schedule(
+        &mut self,
+        missing_hashes: &[BlockHash],
+        peer_ids: &[u64],
+    ) -> Vec<(u64, Vec<BlockHash>)> {
+        // Hand out the windows in turn, so the peers all download at the same time.
+        let mut requests = vec![];
+        let mut windows = missing_hashes.chunks(WINDOW_SIZE);
+
+        'schedule: loop {
+            let mut is_scheduled = false;
+            for peer_id in peer_ids {
+                let window_count = self
+                    .windows
+                    .iter()
+                    .filter(|window| window.peer_id == *peer_id)
+                    .count();
+                if window_count >= MAX_WINDOWS_PER_PEER {
+                    continue;
+                }
+
+                let block_hashes = match windows.next() {
+                    Some(block_hashes) => block_hashes.to_vec(),
+                    None => break 'schedule,
+                };
+                self.windows.push(Window {
+                    peer_id: *peer_id,
+                    block_hashes: block_hashes.clone(),
+                    last_progress: Instant::now(),
+                });
+                requests.push((*peer_id, block_hashes));
+                is_scheduled = true;
+            }
+
+            if !is_scheduled {
+                break;
+            }
+        }
+
+        requests
+    }
+
+    pub fn receive_block(&mut self, block_hash: BlockHash, block: RawBlock) -> bool {
+        // Only blocks that were asked for are kept around until they can be connected.
+        let window = match self
+            .windows
+            .iter_mut()
+            .find(|window| window.block_hashes.contains(&block_hash))
+        {
+            Some(window) => window,
+            None => return false,
+        };
+        window.block_hashes.retain(|hash| *hash != block_hash);
+        window.last_progress = Instant::now();
+
+        self.windows
+            .retain(|window| !window.block_hashes.is_empty());
+        self.downloaded.insert(block_hash, block);
+
+        true
+    }
+
+    pub fn take_stalled_peers(&mut self, now: Instant) -> Vec<u64> {
+        // Their windows are given up, to be asked from other peers.
+        let mut peer_ids = vec![];
+        self.windows.retain(|window| {
+            let is_stalled = now.duration_since(window.last_progress) > BLOCK_DOWNLOAD_TIMEOUT;
+            if is_stalled && !peer_ids.contains(&window.peer_id) {
+                peer_ids.push(window.peer_id);
+            }
+
+            !is_stalled
+        });
+
+        peer_ids
+    }
+
+    pub fn take_next_block(&mut self, blockchain: &Blockchain) -> Option<RawBlock> {
+        let last_block_hash = *blockchain.get_last_block_hash();
+        let block_hash = *self
+            .downloaded
+            .iter()
+            .find(|(_, block)| block.previous_hash == Some(last_block_hash))?
+            .0;
+
+        self.headers.remove(&block_hash);
+        self.downloaded.remove(&block_hash)
+    }
+
+    pub fn remove_peer(&mut self, peer_id: u64) {
+        // Whatever the peer was still asked for goes to someone else.
+        self.windows.retain(|window| window.peer_id != peer_id);
+    }
+}
+
+impl Default for ChainSync {
+    fn default() -> Self {
+        Self::with_max_headers(DEFAULT_MAX_UNCONNECTED_HEADERS)
+    }
+}
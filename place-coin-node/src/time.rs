@@ -0,0 +1,49 @@
+// Network adjusted time. Every peer tells its clock in its version message, and the median of how
+// far those clocks are from the local one is used to check block timestamps. A single peer, or a
+// badly set local clock, can then no longer push blocks in or out of the allowed time window.
+
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const MIN_TIME_SAMPLES: usize = 5; // Fewer peers than this are too easy to fool.
+pub const MAX_TIME_OFFSET: i64 = 70 * 60; // In seconds. Beyond it the local clock is trusted instead.
+
+#[derive(Debug, Default)]
+pub struct NetworkTime {
+    offsets: HashMap<u64, i64>, // How many seconds each peer's clock is ahead of the local one.
+}
+
+impl NetworkTime {
+    pub fn add_sample(&mut self, peer_id: u64, peer_timestamp: i64) {
+        self.offsets
+            .insert(peer_id, peer_timestamp - get_timestamp());
+    }
+
+    pub fn remove_peer(&mut self, peer_id: u64) {
+        self.offsets.remove(&peer_id);
+    }
+
+    pub fn get_offset(&self) -> i64 {
+        if self.offsets.len() < MIN_TIME_SAMPLES {
+            return 0;
+        }
+
+        let mut offsets = self.offsets.values().copied().collect::<Vec<_>>();
+        offsets.sort_unstable();
+        let median = offsets[offsets.len() / 2];
+
+        if median.abs() > MAX_TIME_OFFSET {
+            0
+        } else {
+            median
+        }
+    }
+}
+
+pub fn get_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
@@ -0,0 +1,235 @@
+// How nodes reach each other. Over the internet that's TCP, but nodes can also be put on a network
+// that only exists in memory, so tests with several of them don't need any sockets. Connections
+// there are a pair of channels carrying the same bytes a TCP stream would.
+
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+pub trait Connection: Read + Write + Send + Debug {
+    fn get_peer_address(&self) -> Result<SocketAddr>;
+    fn try_clone(&self) -> Result<Box<dyn Connection>>;
+    fn close(&self); // Ends reads on both sides.
+}
+
+pub trait Listener: Send + 'static {
+    fn accept(&self) -> Result<Box<dyn Connection>>;
+}
+
+pub trait Transport: Send + Sync + Debug {
+    fn connect(&self, address: &SocketAddr) -> Result<Box<dyn Connection>>;
+}
+
+impl Connection for TcpStream {
+    fn get_peer_address(&self) -> Result<SocketAddr> {
+        Ok(self.peer_addr()?)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> Result<Box<dyn Connection>> {
+        let (stream, _) = TcpListener::accept(self)?;
+
+        Ok(Box::new(stream))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn connect(&self, address: &SocketAddr) -> Result<Box<dyn Connection>> {
+        let stream = TcpStream::connect(address).context("Fail to connect to peer.")?;
+
+        Ok(Box::new(stream))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<HashMap<SocketAddr, Sender<MemoryConnection>>>>,
+    next_port: Arc<Mutex<u16>>, // For the addresses of connecting sides.
+}
+
+impl MemoryNetwork {
+    pub fn listen(&self, address: SocketAddr) -> Result<MemoryListener> {
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.contains_key(&address) {
+            bail!("Address is already in use.")
+        }
+
+        let (sender, receiver) = channel();
+        listeners.insert(address, sender);
+
+        Ok(MemoryListener {
+            receiver: Mutex::new(receiver),
+        })
+    }
+}
+
+impl Transport for MemoryNetwork {
+    fn connect(&self, address: &SocketAddr) -> Result<Box<dyn Connection>> {
+        let local_address = {
+            let mut next_port = self.next_port.lock().unwrap();
+            *next_port += 1;
+
+            SocketAddr::from(([10, 255, 255, 255], *next_port))
+        };
+
+        let (to_remote, remote_receiver) = channel();
+        let (to_local, local_receiver) = channel();
+        let local = MemoryConnection::new(
+            *address,
+            to_local.clone(),
+            to_remote.clone(),
+            local_receiver,
+        );
+        let remote = MemoryConnection::new(local_address, to_remote, to_local, remote_receiver);
+
+        let listeners = self.listeners.lock().unwrap();
+        let listener = listeners.get(address).context("Fail to connect to peer.")?;
+        if listener.send(remote).is_err() {
+            bail!("Fail to connect to peer.")
+        }
+
+        Ok(Box::new(local))
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryListener {
+    receiver: Mutex<Receiver<MemoryConnection>>,
+}
+
+impl Listener for MemoryListener {
+    fn accept(&self) -> Result<Box<dyn Connection>> {
+        let connection = self
+            .receiver
+            .lock()
+            .unwrap()
+            .recv()
+            .context("Network is gone.")?;
+
+        Ok(Box::new(connection))
+    }
+}
+
+// An empty chunk means the connection was closed.
+#[derive(Debug)]
+pub struct MemoryConnection {
+    peer_address: SocketAddr,
+    sender: Sender<Vec<u8>>,     // Towards the other side.
+    own_sender: Sender<Vec<u8>>, // Towards this side, to close it.
+    incoming: Arc<Mutex<Incoming>>,
+    closer: Arc<Closer>,
+}
+
+#[derive(Debug)]
+struct Incoming {
+    chunks: Receiver<Vec<u8>>,
+    rest: Vec<u8>, // What's left of the last chunk.
+}
+
+// Closes the other side once every clone of this one is gone, like dropping a TCP stream does.
+#[derive(Debug)]
+struct Closer(Sender<Vec<u8>>);
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        let _ = self.0.send(vec![]);
+    }
+}
+
+impl MemoryConnection {
+    fn new(
+        peer_address: SocketAddr,
+        own_sender: Sender<Vec<u8>>,
+        sender: Sender<Vec<u8>>,
+        receiver: Receiver<Vec<u8>>,
+    ) -> Self {
+        Self {
+            peer_address,
+            closer: Arc::new(Closer(sender.clone())),
+            sender,
+            own_sender,
+            incoming: Arc::new(Mutex::new(Incoming {
+                chunks: receiver,
+                rest: vec![],
+            })),
+        }
+    }
+}
+
+impl Read for MemoryConnection {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut incoming = self.incoming.lock().unwrap();
+        if incoming.rest.is_empty() {
+            // A closed channel is the same as a closed connection.
+            incoming.rest = incoming.chunks.recv().unwrap_or_default();
+            if incoming.rest.is_empty() {
+                return Ok(0);
+            }
+        }
+
+        let size = buffer.len().min(incoming.rest.len());
+        buffer[..size].copy_from_slice(&incoming.rest[..size]);
+        incoming.rest.drain(..size);
+
+        Ok(size)
+    }
+}
+
+impl Write for MemoryConnection {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        self.sender
+            .send(buffer.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for MemoryConnection {
+    fn get_peer_address(&self) -> Result<SocketAddr> {
+        Ok(self.peer_address)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(Self {
+            peer_address: self.peer_address,
+            sender: self.sender.clone(),
+            own_sender: self.own_sender.clone(),
+            incoming: self.incoming.clone(),
+            closer: self.closer.clone(),
+        }))
+    }
+
+    fn close(&self) {
+        let _ = self.sender.send(vec![]);
+        let _ = self.own_sender.send(vec![]);
+    }
+}
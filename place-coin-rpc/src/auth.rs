@@ -0,0 +1,117 @@
+// Who may use the RPC server, and for what. Clients send a token with every request:
+//
+//     Authorization: Bearer <token>
+//
+// Each token has a permission, and every permission allows what the ones before it do:
+//
+//     read-only   chain, mempool and canvas data, over JSON-RPC, REST, tiles and the WebSocket
+//     wallet      spending, from the node's wallet or with raw transactions, and the wallet's
+//                 balance
+//     admin       everything, as the cookie's token has, which adds invalidateblock,
+//                 reconsiderblock and any method not listed for the others
+//
+// A cookie file holds a fresh admin token each time one is created, so tools on the same machine
+// can read it from there and nobody has to pick a password. Requests without a known token are
+// refused with a 401, and methods above the token's permission with a JSON-RPC error.
+
+use crate::http::Request;
+use anyhow::{Context, Result};
+use std::{fs, io::Write, path::Path};
+
+pub const TOKEN_SIZE: usize = 32; // Random bytes in a cookie token.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    ReadOnly,
+    Wallet,
+    Admin,
+}
+
+impl Permission {
+    pub fn of_method(method: &str) -> Self {
+        match method {
+            "getblockcount"
+            | "getbestblockhash"
+            | "getblock"
+            | "getblockheader"
+            | "getblockstats"
+            | "getchaintips"
+            | "getaddresshistory"
+            | "getpixelhistory"
+            | "listblockstats"
+            | "getrichlist"
+            | "getcanvasregion"
+            | "getmempoolinfo"
+            | "getconnectioncount"
+            | "getnodestatus"
+            | "createrawtransaction"
+            | "decoderawtransaction" => Permission::ReadOnly,
+            "getbalance" | "sendtoaddress" | "paintpixel" | "fundrawtransaction"
+            | "signrawtransaction" | "sendrawtransaction" => Permission::Wallet,
+
+            // Anything not listed above, invalidateblock and reconsiderblock included, and methods
+            // added later until they are.
+            _ => Permission::Admin,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    tokens: Vec<(String, Permission)>,
+}
+
+impl Auth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_token(&mut self, token: &str, permission: Permission) {
+        self.tokens.push((token.to_string(), permission));
+    }
+
+    pub fn create_cookie(&mut self, path: impl AsRef<Path>) -> Result<String> {
+        let mut bytes = [0; TOKEN_SIZE];
+        getrandom::getrandom(&mut bytes).context("Fail to generate cookie token.")?;
+        let token = bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        // Only the node's own user gets to read the cookie.
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(token.as_bytes()))
+            .context("Fail to write cookie file.")?;
+
+        self.add_token(&token, Permission::Admin);
+
+        Ok(token)
+    }
+
+    pub fn authenticate(&self, request: &Request) -> Option<Permission> {
+        let token = request
+            .get_header("authorization")?
+            .strip_prefix("Bearer ")?
+            .trim();
+
+        // Every token is compared in full, so the time taken says nothing about how close it was.
+        self.tokens
+            .iter()
+            .filter(|(known_token, _)| is_same_token(known_token, token))
+            .map(|(_, permission)| *permission)
+            .max()
+    }
+}
+
+fn is_same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
@@ -0,0 +1,152 @@
+// Just enough HTTP/1.1 (RFC 9112) to carry requests with a body of a known length. Connections are
+// kept open for more requests until the client closes them or asks to.
+
+use anyhow::{bail, Context, Result};
+use std::io::{BufRead, Read, Write};
+
+pub const MAX_HEADER_SIZE: usize = 8 * 1024; // Each line.
+pub const MAX_HEADER_COUNT: usize = 64;
+pub const MAX_BODY_SIZE: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub target: String, // The path, with the query if there is one.
+    pub headers: Vec<(String, String)>, // Names in lowercase.
+    pub body: Vec<u8>,
+    pub is_last: bool, // Whether the client closes the connection after the response.
+}
+
+impl Request {
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn read_request(reader: &mut impl BufRead) -> Result<Option<Request>> {
+    // Nothing at all means the client is done.
+    let request_line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) => (method, target, version),
+        _ => bail!("HTTP request line is malformed."),
+    };
+    if !version.starts_with("HTTP/1.") {
+        bail!("HTTP version isn't supported.")
+    }
+
+    // HTTP/1.0 clients close the connection unless they say otherwise.
+    let mut is_last = version == "HTTP/1.0";
+    let mut content_length = 0;
+    let mut headers = vec![];
+    for header_index in 0.. {
+        let line = read_line(reader)?.context("HTTP request ends early.")?;
+        if line.is_empty() {
+            break;
+        }
+        if header_index >= MAX_HEADER_COUNT {
+            bail!("HTTP request has too many headers.")
+        }
+
+        let (name, value) = line.split_once(':').context("HTTP header is malformed.")?;
+        let (name, value) = (name.to_ascii_lowercase(), value.trim());
+        match name.as_str() {
+            "content-length" => content_length = value.parse()?,
+            "transfer-encoding" => bail!("HTTP transfer encodings aren't supported."),
+            "connection" if value.eq_ignore_ascii_case("close") => is_last = true,
+            "connection" if value.eq_ignore_ascii_case("keep-alive") => is_last = false,
+            _ => {}
+        }
+        headers.push((name, value.to_string()));
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        bail!("HTTP request body is too big.")
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        headers,
+        body,
+        is_last,
+    }))
+}
+
+pub fn write_response(
+    writer: &mut impl Write,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    write_response_with_headers(writer, status, &[], content_type, body.as_bytes())
+}
+
+pub fn write_response_with_headers(
+    writer: &mut impl Write,
+    status: u16,
+    headers: &[(&str, &str)],
+    content_type: &str,
+    body: &[u8],
+) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+
+    // Clients that were refused are told how to get in.
+    let challenge = if status == 401 {
+        "WWW-Authenticate: Bearer\r\n"
+    } else {
+        ""
+    };
+    let headers = headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\r\n"))
+        .collect::<String>();
+
+    // Written in one go, so small responses go out in a single packet.
+    let mut response = format!(
+        "HTTP/1.1 {status} {reason}\r\n{challenge}{headers}Content-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    writer.write_all(&response)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = vec![];
+    reader
+        .by_ref()
+        .take(MAX_HEADER_SIZE as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.len() > MAX_HEADER_SIZE || !line.ends_with(b"\n") {
+        bail!("HTTP header is too long.")
+    }
+
+    let line = String::from_utf8(line).context("HTTP header isn't text.")?;
+
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
@@ -0,0 +1,375 @@
+// Just enough JSON (RFC 8259) for requests and responses. Integers are kept apart from other
+// numbers, as credits don't fit in a float without losing some of them. Objects keep their keys in
+// the order they were written.
+
+use anyhow::{bail, Context, Result};
+use std::fmt;
+
+const MAX_DEPTH: usize = 64; // So deeply nested requests can't overflow the stack.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            position: 0,
+        };
+        let value = parser.parse_value(0)?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            bail!("JSON has trailing characters.")
+        }
+
+        Ok(value)
+    }
+
+    pub fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Integer(integer) => Some(*integer),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Self {
+        Json::Integer(value)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Self {
+        // Nothing on the chain gets anywhere near this big.
+        Json::Integer(value as i64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Integer(value as i64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        Json::Float(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(values: Vec<T>) -> Self {
+        Json::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{value}"),
+            Json::Integer(value) => write!(f, "{value}"),
+
+            // There is no infinity in JSON.
+            Json::Float(value) if value.is_finite() => write!(f, "{value:?}"), // Keeps the fraction.
+            Json::Float(_) => write!(f, "null"),
+
+            Json::String(string) => write_string(f, string),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for character in string.chars() {
+        match character {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            character if (character as u32) < 0x20 => write!(f, "\\u{:04x}", character as u32)?,
+            character => write!(f, "{character}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn parse_value(&mut self, depth: usize) -> Result<Json> {
+        if depth > MAX_DEPTH {
+            bail!("JSON is nested too deeply.")
+        }
+
+        self.skip_whitespace();
+        match self.peek().context("JSON ends early.")? {
+            b'n' => self.parse_literal("null", Json::Null),
+            b't' => self.parse_literal("true", Json::Bool(true)),
+            b'f' => self.parse_literal("false", Json::Bool(false)),
+            b'"' => Ok(Json::String(self.parse_string()?)),
+            b'[' => {
+                self.position += 1;
+                let mut values = vec![];
+                if !self.try_consume(b']') {
+                    loop {
+                        values.push(self.parse_value(depth + 1)?);
+                        if self.try_consume(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+
+                Ok(Json::Array(values))
+            }
+            b'{' => {
+                self.position += 1;
+                let mut fields = vec![];
+                if !self.try_consume(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.parse_string()?;
+                        self.expect(b':')?;
+                        fields.push((key, self.parse_value(depth + 1)?));
+                        if self.try_consume(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+
+                Ok(Json::Object(fields))
+            }
+            b'-' | b'0'..=b'9' => self.parse_number(),
+            _ => bail!("JSON has an unexpected character."),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Json) -> Result<Json> {
+        if !self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            bail!("JSON has an unexpected character.")
+        }
+        self.position += literal.len();
+
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<Json> {
+        let start = self.position;
+        while self.peek().is_some_and(|byte| {
+            byte.is_ascii_digit() || matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E')
+        }) {
+            self.position += 1;
+        }
+
+        let number = std::str::from_utf8(&self.bytes[start..self.position])?;
+        if number.contains(['.', 'e', 'E']) {
+            Ok(Json::Float(
+                number.parse().context("JSON number is malformed.")?,
+            ))
+        } else {
+            Ok(Json::Integer(
+                number.parse().context("JSON number is malformed.")?,
+            ))
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+
+        let mut string = String::new();
+        loop {
+            let start = self.position;
+            while self
+                .peek()
+                .is_some_and(|byte| byte != b'"' && byte != b'\\')
+            {
+                self.position += 1;
+            }
+            string.push_str(std::str::from_utf8(&self.bytes[start..self.position])?);
+
+            match self.take_byte().context("JSON string isn't closed.")? {
+                b'"' => return Ok(string),
+                _ => {
+                    let character = match self.take_byte().context("JSON string isn't closed.")? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.parse_unicode_escape()?,
+                        _ => bail!("JSON string has an unknown escape."),
+                    };
+                    string.push(character);
+                }
+            }
+        }
+    }
+
+    fn parse_unicode_escape(&mut self) -> Result<char> {
+        let first = self.parse_hex_digits()?;
+
+        // Characters outside the basic plane come as two halves.
+        let code_point = if (0xD800..0xDC00).contains(&first) {
+            if self.take_byte() != Some(b'\\') || self.take_byte() != Some(b'u') {
+                bail!("JSON string has half a character.")
+            }
+            let second = self.parse_hex_digits()?;
+            if !(0xDC00..0xE000).contains(&second) {
+                bail!("JSON string has half a character.")
+            }
+
+            0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00)
+        } else {
+            first
+        };
+
+        char::from_u32(code_point).context("JSON string has half a character.")
+    }
+
+    fn parse_hex_digits(&mut self) -> Result<u32> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .context("JSON string isn't closed.")?;
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            bail!("JSON escape is malformed.")
+        }
+        self.position += 4;
+
+        u32::from_str_radix(std::str::from_utf8(digits)?, 16).context("JSON escape is malformed.")
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|byte| matches!(byte, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.position += 1;
+        }
+    }
+
+    fn try_consume(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if !self.try_consume(byte) {
+            bail!("JSON is missing a '{}'.", byte as char)
+        }
+
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn take_byte(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.position += 1;
+
+        Some(byte)
+    }
+}
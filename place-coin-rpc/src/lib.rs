@@ -0,0 +1,1084 @@
+pub mod auth;
+pub mod http;
+pub mod json;
+pub mod rest;
+pub mod server;
+pub mod tiles;
+pub mod websocket;
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        auth::{Auth, Permission},
+        http::Request,
+        json::Json,
+        server::{
+            RpcError, RpcServer, FORBIDDEN, INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR,
+            REJECTED, SERVER_ERROR,
+        },
+        tiles::TileServer,
+    };
+    use anyhow::Result;
+    use place_coin::{
+        address::{Address, PublicKey},
+        blockchain::{encode_hex, Blockchain},
+        color::Color,
+        explorer::Explorer,
+        signature::{sign_transaction_inputs, Signature},
+        transaction::{
+            RawTransaction, TransactionInput, TransactionOutput, CURRENT_TRANSACTION_VERSION,
+        },
+        wallet::Wallet,
+    };
+    use place_coin_node::p2p::Node;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+    };
+
+    fn node_address(seed: &str) -> Address {
+        Address::from_private_key(&Wallet::deterministic(seed).get_private_keys()[0])
+    }
+
+    fn send(address: SocketAddr, method: &str, target: &str, body: &str) -> Result<(String, Json)> {
+        send_with_token(address, None, method, target, body)
+    }
+
+    fn send_with_token(
+        address: SocketAddr,
+        token: Option<&str>,
+        method: &str,
+        target: &str,
+        body: &str,
+    ) -> Result<(String, Json)> {
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {token}\r\n"))
+            .unwrap_or_default();
+        let mut stream = TcpStream::connect(address)?;
+        write!(
+            stream,
+            "{method} {target} HTTP/1.1\r\n{authorization}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+
+        Ok((
+            status,
+            if body.is_empty() {
+                Json::Null
+            } else {
+                Json::parse(body)?
+            },
+        ))
+    }
+
+    #[test]
+    fn test_json() -> Result<()> {
+        let text = r#"{"a": [1, -2.5e1, true, null], "b": "\"é😀\n", "c": {}}"#;
+        let json = Json::parse(text)?;
+        assert_eq!(
+            json.get("a").unwrap().as_array().unwrap()[0],
+            Json::Integer(1)
+        );
+        assert_eq!(
+            json.get("a").unwrap().as_array().unwrap()[1],
+            Json::Float(-25.0)
+        );
+        assert_eq!(json.get("b").unwrap().as_str(), Some("\"é😀\n"));
+
+        // Printing and parsing again gives the same thing back.
+        assert_eq!(Json::parse(&json.to_string())?, json);
+        assert_eq!(
+            Json::parse("9007199254740993")?,
+            Json::Integer(9_007_199_254_740_993)
+        );
+
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse(&"[".repeat(1000)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_rpc() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let wallet = Arc::new(Mutex::new(Wallet::deterministic("miner")));
+        let server = RpcServer::new(node.clone(), wallet);
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        server.listen(listener);
+
+        let call = |method: &str, params: &str| -> Result<Json> {
+            let (status, response) = send(
+                address,
+                "POST",
+                "/",
+                &format!(
+                    r#"{{"jsonrpc": "2.0", "method": "{method}", "params": {params}, "id": 7}}"#
+                ),
+            )?;
+            assert_eq!(status, "HTTP/1.1 200 OK");
+            assert_eq!(response.get("id"), Some(&Json::Integer(7)));
+
+            Ok(response)
+        };
+        let get_result = |response: Json| response.get("result").unwrap().clone();
+        let get_error_code = |response: Json| {
+            response
+                .get("error")
+                .and_then(|error| error.get("code"))
+                .and_then(Json::as_i64)
+        };
+
+        assert_eq!(get_result(call("getblockcount", "[]")?), Json::Integer(1));
+        let block_hash = blockchain.lock().unwrap().get_last_block_hash().to_string();
+        assert_eq!(
+            get_result(call("getbestblockhash", "[]")?).as_str(),
+            Some(block_hash.as_str())
+        );
+        let block = get_result(call("getblock", &format!(r#"{{"hash": "{block_hash}"}}"#))?);
+        assert_eq!(block.get("height"), Some(&Json::Integer(1)));
+        assert_eq!(get_result(call("getbalance", "[]")?), Json::Integer(1000));
+
+        // Credits and pixels are paid for by the wallet, and wait in the mempool.
+        let other_address = node_address("other");
+        let transaction_hash = get_result(call(
+            "sendtoaddress",
+            &format!(r#"["{other_address}", 100, 5]"#),
+        )?);
+        assert!(blockchain
+            .lock()
+            .unwrap()
+            .find_pending_transaction(&transaction_hash.as_str().unwrap().parse()?)
+            .is_some());
+        get_result(call("paintpixel", "[3, 4, 9, 5]")?);
+        let mempool_info = get_result(call("getmempoolinfo", "[]")?);
+        assert_eq!(
+            mempool_info.get("transaction_count"),
+            Some(&Json::Integer(2))
+        );
+        assert_eq!(
+            mempool_info.get("age_counts"),
+            Some(&Json::parse("[2, 0, 0, 0]")?)
+        );
+
+        node.mine()?;
+        assert_eq!(
+            get_result(call("getbalance", &format!(r#"["{other_address}"]"#))?),
+            Json::Integer(100)
+        );
+        let region = get_result(call("getcanvasregion", "[3, 3, 2, 2]")?);
+        let rows = region.as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].as_array().unwrap(), [Json::Null, Json::Null]);
+        assert_eq!(
+            rows[1].as_array().unwrap()[0].get("color"),
+            Some(&Json::Integer(9))
+        );
+
+        assert_eq!(
+            get_error_code(call("nosuchmethod", "[]")?),
+            Some(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            get_error_code(call("paintpixel", "[3, 4, 99, 5]")?),
+            Some(INVALID_PARAMS)
+        );
+        assert_eq!(
+            get_error_code(call("getcanvasregion", "[0, 0, 1000, 1000]")?),
+            Some(INVALID_PARAMS)
+        );
+        let (_, response) = send(address, "POST", "/", "{")?;
+        assert_eq!(get_error_code(response), Some(PARSE_ERROR));
+
+        // Notifications are left out of batches, and get nothing back on their own.
+        let (_, response) = send(
+            address,
+            "POST",
+            "/",
+            r#"[{"jsonrpc": "2.0", "method": "getblockcount", "id": 1},
+                {"jsonrpc": "2.0", "method": "getblockcount"}]"#,
+        )?;
+        assert_eq!(response.as_array().unwrap().len(), 1);
+        let (status, _) = send(
+            address,
+            "POST",
+            "/",
+            r#"{"jsonrpc": "2.0", "method": "getblockcount"}"#,
+        )?;
+        assert_eq!(status, "HTTP/1.1 204 No Content");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rest_api() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let (pending_hash, confirmed_hash, block_hash) = {
+            let mut blockchain = blockchain.lock().unwrap();
+            let private_key = Wallet::deterministic("miner").get_private_keys()[0].clone();
+            blockchain.create_pixel_transaction(&private_key, &[((1, 2), Color::Red)], 5)?;
+            let confirmed_hash = *blockchain.get_pending_transactions()[0].get_hash();
+            blockchain.mine()?;
+            Wallet::deterministic("miner").send_many(
+                &mut blockchain,
+                vec![(node_address("other"), 100)],
+                5,
+            )?;
+
+            (
+                *blockchain.get_pending_transactions()[0].get_hash(),
+                confirmed_hash,
+                *blockchain.get_last_block_hash(),
+            )
+        };
+
+        let server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        server.listen(listener);
+        let get = |target: &str| send(address, "GET", target, "");
+
+        let (status, block) = get(&format!("/blocks/{block_hash}"))?;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(block.get("height"), Some(&Json::Integer(2)));
+        assert!(block
+            .get("transactions")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .contains(&confirmed_hash.to_string().into()));
+
+        let (_, transaction) = get(&format!("/tx/{confirmed_hash}"))?;
+        assert_eq!(
+            transaction.get("status").unwrap().as_str(),
+            Some("confirmed")
+        );
+        let output = &transaction.get("outputs").unwrap().as_array().unwrap()[0];
+        assert_eq!(output.get("type").unwrap().as_str(), Some("pixel"));
+        let (_, transaction) = get(&format!("/tx/{pending_hash}"))?;
+        assert_eq!(transaction.get("status").unwrap().as_str(), Some("pending"));
+
+        // Outputs the pending transaction spends are still unspent until it's mined.
+        let (_, outputs) = get(&format!("/address/{}/utxos", node_address("miner")))?;
+        let total = outputs
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|output| output.get("value").unwrap().as_i64().unwrap())
+            .sum::<i64>();
+        assert_eq!(
+            total,
+            blockchain
+                .lock()
+                .unwrap()
+                .get_peer_credits(&node_address("miner"))
+        );
+
+        let (_, region) = get("/canvas/region?x=1&y=2&width=1&height=1")?;
+        let pixel = &region.as_array().unwrap()[0].as_array().unwrap()[0];
+        assert_eq!(
+            pixel.get("color"),
+            Some(&Json::Integer(Color::Red as u8 as i64))
+        );
+
+        let (status, error) = get("/blocks/nothex")?;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(error.get("error").is_some());
+        assert_eq!(
+            get(&format!("/tx/{}", "0".repeat(64)))?.0,
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(get("/canvas/region?x=1")?.0, "HTTP/1.1 400 Bad Request");
+        assert_eq!(get("/nothing")?.0, "HTTP/1.1 404 Not Found");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_websocket_notifications() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let server = RpcServer::new(
+            node.clone(),
+            Arc::new(Mutex::new(Wallet::deterministic("miner"))),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        server.listen(listener);
+
+        // The key and answer from the example in RFC 6455.
+        let mut stream = TcpStream::connect(address)?;
+        write!(
+            stream,
+            "GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head)?;
+        }
+        assert!(head.starts_with("HTTP/1.1 101 "));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let mut write_frame = |opcode: u8, payload: &[u8]| -> Result<()> {
+            let mask = [1, 2, 3, 4];
+            let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .enumerate()
+                    .map(|(index, byte)| byte ^ mask[index % 4]),
+            );
+            stream.write_all(&frame)?;
+
+            Ok(())
+        };
+        let mut read_frame = || -> Result<(u8, Vec<u8>)> {
+            let mut header = [0; 2];
+            reader.read_exact(&mut header)?;
+            let size = match header[1] {
+                126 => {
+                    let mut size = [0; 2];
+                    reader.read_exact(&mut size)?;
+                    u16::from_be_bytes(size) as usize
+                }
+                size => size as usize,
+            };
+            let mut payload = vec![0; size];
+            reader.read_exact(&mut payload)?;
+
+            Ok((header[0] & 0x0F, payload))
+        };
+
+        // Messages are handled in order, so the pong means the subscription is in.
+        write_frame(0x1, br#"{"subscribe": ["new_block", "pixel_painted"]}"#)?;
+        write_frame(0x9, b"ping")?;
+        assert_eq!(read_frame()?, (0xA, b"ping".to_vec()));
+
+        {
+            let mut blockchain = blockchain.lock().unwrap();
+            let private_key = Wallet::deterministic("miner").get_private_keys()[0].clone();
+            blockchain.create_pixel_transaction(&private_key, &[((5, 6), Color::Blue)], 5)?;
+        }
+        let block_hash = node.mine()?;
+
+        // The transaction isn't sent, as nobody asked for new ones.
+        let mut notifications = vec![];
+        for _ in 0..2 {
+            let (opcode, payload) = read_frame()?;
+            assert_eq!(opcode, 0x1);
+            notifications.push(Json::parse(std::str::from_utf8(&payload)?)?);
+        }
+        let get_data = |topic: &str| {
+            notifications
+                .iter()
+                .find(|notification| notification.get("topic").unwrap().as_str() == Some(topic))
+                .and_then(|notification| notification.get("data"))
+                .cloned()
+                .unwrap()
+        };
+        assert_eq!(
+            get_data("new_block").get("hash").unwrap().as_str(),
+            Some(block_hash.to_string().as_str())
+        );
+        let pixel = get_data("pixel_painted");
+        assert_eq!(pixel.get("x"), Some(&Json::Integer(5)));
+        assert_eq!(
+            pixel.get("color"),
+            Some(&Json::Integer(Color::Blue as u8 as i64))
+        );
+
+        write_frame(0x8, b"")?;
+        assert_eq!(read_frame()?.0, 0x8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rpc_auth() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+
+        let path = std::env::temp_dir().join(format!("place-coin-cookie-{}", std::process::id()));
+        let mut auth = Auth::new();
+        auth.add_token("viewer", Permission::ReadOnly);
+        let cookie = auth.create_cookie(&path)?;
+        assert_eq!(std::fs::read_to_string(&path)?, cookie);
+        std::fs::remove_file(&path)?;
+
+        let server = RpcServer::with_auth(
+            node,
+            Arc::new(Mutex::new(Wallet::deterministic("miner"))),
+            auth,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        server.listen(listener);
+        let call = |token: Option<&str>, method: &str| {
+            send_with_token(
+                address,
+                token,
+                "POST",
+                "/",
+                &format!(r#"{{"jsonrpc": "2.0", "method": "{method}", "id": 1}}"#),
+            )
+        };
+
+        // Without a known token nothing gets through, not even chain data.
+        assert_eq!(call(None, "getblockcount")?.0, "HTTP/1.1 401 Unauthorized");
+        assert_eq!(
+            call(Some("guess"), "getblockcount")?.0,
+            "HTTP/1.1 401 Unauthorized"
+        );
+        assert_eq!(
+            send(
+                address,
+                "GET",
+                "/canvas/region?x=0&y=0&width=1&height=1",
+                ""
+            )?
+            .0,
+            "HTTP/1.1 401 Unauthorized"
+        );
+
+        // Read-only tokens can look, but not spend.
+        let (_, response) = call(Some("viewer"), "getblockcount")?;
+        assert_eq!(response.get("result"), Some(&Json::Integer(1)));
+        let (_, response) = call(Some("viewer"), "getbalance")?;
+        assert_eq!(
+            response.get("error").unwrap().get("code"),
+            Some(&Json::Integer(FORBIDDEN))
+        );
+        assert_eq!(
+            send_with_token(
+                address,
+                Some("viewer"),
+                "GET",
+                "/canvas/region?x=0&y=0&width=1&height=1",
+                ""
+            )?
+            .0,
+            "HTTP/1.1 200 OK"
+        );
+
+        // The cookie's token can do everything.
+        let (_, response) = call(Some(&cookie), "getbalance")?;
+        assert!(response.get("result").unwrap().as_i64().unwrap() > 0);
+
+        // Methods that aren't listed need the cookie's token, so new ones start out closed.
+        assert_eq!(Permission::of_method("getnodestatus"), Permission::ReadOnly);
+        assert_eq!(Permission::of_method("stopnode"), Permission::Admin);
+        let (_, response) = call(Some("viewer"), "stopnode")?;
+        assert_eq!(
+            response.get("error").unwrap().get("code"),
+            Some(&Json::Integer(FORBIDDEN))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_transactions() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+
+        let reward_hash = *blockchain
+            .lock()
+            .unwrap()
+            .get_last_block()
+            .get_transactions()[0]
+            .get_hash();
+        let public_key =
+            PublicKey::from_private_key(&Wallet::deterministic("miner").get_private_keys()[0]);
+        let inputs = Json::Array(vec![Json::object(vec![
+            ("transaction_hash", reward_hash.to_string().into()),
+            ("output_index", 0i64.into()),
+            ("public_key", encode_hex(public_key.as_bytes()).into()),
+        ])]);
+        let outputs = Json::parse(&format!(
+            r#"[{{"type": "credits", "value": 10, "address": "{}"}},
+                {{"type": "pixel", "value": 5, "x": 3, "y": -4, "color": 2, "memo": "hi"}}]"#,
+            node_address("other")
+        ))?;
+
+        let hex = server.call(
+            "createrawtransaction",
+            &Json::Array(vec![inputs, outputs.clone(), 7i64.into()]),
+        )?;
+        let raw_transaction = RawTransaction::from_hex(hex.as_str().unwrap())?;
+        assert_eq!(raw_transaction.lock_time, 7);
+
+        let decoded = server.call("decoderawtransaction", &Json::Array(vec![hex.clone()]))?;
+        assert_eq!(
+            decoded.get("hash").unwrap().as_str(),
+            Some(raw_transaction.calculate_hash().to_string().as_str())
+        );
+        let input = &decoded.get("inputs").unwrap().as_array().unwrap()[0];
+        assert_eq!(input.get("signed"), Some(&Json::Bool(false)));
+
+        // What's decoded can be made again into the same transaction.
+        let params = Json::object(vec![
+            ("inputs", decoded.get("inputs").unwrap().clone()),
+            ("outputs", decoded.get("outputs").unwrap().clone()),
+            ("lock_time", 7i64.into()),
+        ]);
+        assert_eq!(server.call("createrawtransaction", &params)?, hex);
+
+        let error = server
+            .call("decoderawtransaction", &Json::Array(vec!["00ff".into()]))
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+        let error = server
+            .call(
+                "createrawtransaction",
+                &Json::parse(r#"[[], [{"type": "paint", "value": 1}]]"#)?,
+            )
+            .unwrap_err();
+        assert_eq!(error.code, INVALID_PARAMS);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_raw_transaction() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+
+        let private_key = Wallet::deterministic("miner").get_private_keys()[0].clone();
+        let public_key = PublicKey::from_private_key(&private_key);
+        let reward_hash = *blockchain
+            .lock()
+            .unwrap()
+            .get_last_block()
+            .get_transactions()[0]
+            .get_hash();
+        let mut raw_transaction = RawTransaction {
+            version: CURRENT_TRANSACTION_VERSION,
+            inputs: vec![TransactionInput::FromOutput {
+                transaction_hash: reward_hash,
+                output_index: 0,
+                public_key,
+                signature: Signature::unsigned(),
+            }],
+            outputs: vec![TransactionOutput::ToInput {
+                value: 10,
+                public_key_address: node_address("other"),
+            }],
+            lock_time: 0,
+        };
+        let send_raw = |raw_transaction: &RawTransaction| {
+            server.call(
+                "sendrawtransaction",
+                &Json::Array(vec![raw_transaction.to_hex().into()]),
+            )
+        };
+        let get_reason = |error: RpcError| {
+            assert_eq!(error.code, REJECTED);
+            error.data.unwrap().get("reason").unwrap().clone()
+        };
+
+        assert_eq!(
+            get_reason(send_raw(&raw_transaction).unwrap_err()),
+            "bad_signature".into()
+        );
+
+        sign_transaction_inputs(&mut raw_transaction, &private_key);
+        let transaction_hash = send_raw(&raw_transaction)?;
+        assert_eq!(
+            transaction_hash.as_str(),
+            Some(raw_transaction.calculate_hash().to_string().as_str())
+        );
+        assert_eq!(
+            blockchain.lock().unwrap().get_pending_transactions().len(),
+            1
+        );
+
+        assert_eq!(
+            get_reason(send_raw(&raw_transaction).unwrap_err()),
+            "already_known".into()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_raw_transaction() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let inputs = {
+            let mut blockchain = blockchain.lock().unwrap();
+            Wallet::deterministic("miner").send_many(
+                &mut blockchain,
+                vec![(node_address("other"), 100)],
+                0,
+            )?;
+            blockchain.mine()?;
+
+            // One output of the node's wallet, and one of someone else's.
+            ["miner", "other"]
+                .iter()
+                .map(|seed| {
+                    let (transaction_hash, output_index, _) =
+                        blockchain.get_spendable_outputs(&node_address(seed))[0];
+                    let public_key = PublicKey::from_private_key(
+                        &Wallet::deterministic(seed).get_private_keys()[0],
+                    );
+
+                    Json::object(vec![
+                        ("transaction_hash", transaction_hash.to_string().into()),
+                        ("output_index", (output_index as u64).into()),
+                        ("public_key", encode_hex(public_key.as_bytes()).into()),
+                    ])
+                })
+                .collect::<Vec<_>>()
+        };
+        let server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+
+        let outputs = Json::parse(&format!(
+            r#"[{{"type": "credits", "value": 50, "address": "{}"}}]"#,
+            node_address("miner")
+        ))?;
+        let hex = server.call(
+            "createrawtransaction",
+            &Json::Array(vec![Json::Array(inputs), outputs]),
+        )?;
+
+        // The wallet only has the key for the first input.
+        let signed = server.call("signrawtransaction", &Json::Array(vec![hex]))?;
+        assert_eq!(signed.get("complete"), Some(&Json::Bool(false)));
+        assert_eq!(
+            signed.get("unsigned_inputs"),
+            Some(&Json::Array(vec![1i64.into()]))
+        );
+
+        // The other owner signs offline, and then it can be sent.
+        let mut raw_transaction =
+            RawTransaction::from_hex(signed.get("hex").unwrap().as_str().unwrap())?;
+        assert!(Wallet::deterministic("other")
+            .sign_raw_transaction(&mut raw_transaction)?
+            .is_empty());
+        server.call(
+            "sendrawtransaction",
+            &Json::Array(vec![raw_transaction.to_hex().into()]),
+        )?;
+        assert!(blockchain
+            .lock()
+            .unwrap()
+            .find_pending_transaction(&raw_transaction.calculate_hash())
+            .is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fund_raw_transaction() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        blockchain.lock().unwrap().set_min_tax_rate(1);
+        let server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+
+        // What a paint bot would make: a pixel, and nothing to pay for it with yet.
+        let hex = server.call(
+            "createrawtransaction",
+            &Json::parse(r#"[[], [{"type": "pixel", "value": 10, "x": 1, "y": 1, "color": 3}]]"#)?,
+        )?;
+        let funded = server.call("fundrawtransaction", &Json::Array(vec![hex.clone()]))?;
+        let raw_transaction =
+            RawTransaction::from_hex(funded.get("hex").unwrap().as_str().unwrap())?;
+        let tax = funded.get("tax").unwrap().as_i64().unwrap();
+        assert_eq!(tax, raw_transaction.get_size() as i64);
+        assert_eq!(funded.get("change_index"), Some(&Json::Integer(1)));
+        assert!(!raw_transaction.inputs.is_empty());
+
+        // Funded isn't signed yet, but once it is, it pays enough to get in.
+        let signed = server.call(
+            "signrawtransaction",
+            &Json::Array(vec![funded.get("hex").unwrap().clone()]),
+        )?;
+        assert_eq!(signed.get("complete"), Some(&Json::Bool(true)));
+        let transaction_hash = server.call(
+            "sendrawtransaction",
+            &Json::Array(vec![signed.get("hex").unwrap().clone()]),
+        )?;
+        {
+            let blockchain = blockchain.lock().unwrap();
+            let transaction = blockchain
+                .find_pending_transaction(&transaction_hash.as_str().unwrap().parse()?)
+                .unwrap();
+            assert_eq!(transaction.get_balance(), tax);
+            assert_eq!(transaction.get_size(), raw_transaction.get_size());
+        }
+
+        let funded = server.call(
+            "fundrawtransaction",
+            &Json::object(vec![("hex", hex), ("tax", 7i64.into())]),
+        )?;
+        assert_eq!(funded.get("tax"), Some(&Json::Integer(7)));
+        let error = server
+            .call(
+                "fundrawtransaction",
+                &server.call(
+                    "createrawtransaction",
+                    &Json::parse(&format!(
+                        r#"[[], [{{"type": "credits", "value": 1000000000000, "address": "{}"}}]]"#,
+                        node_address("other")
+                    ))?,
+                )?
+                .as_str()
+                .map(|hex| Json::Array(vec![hex.into()]))
+                .unwrap(),
+            )
+            .unwrap_err();
+        assert_eq!(error.code, SERVER_ERROR);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_verbosity() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let (block_hash, raw_block) = {
+            let mut blockchain = blockchain.lock().unwrap();
+            let private_key = Wallet::deterministic("miner").get_private_keys()[0].clone();
+            blockchain.create_pixel_transaction(&private_key, &[((1, 2), Color::Red)], 5)?;
+            blockchain.mine()?;
+            let block_hash = *blockchain.get_last_block_hash();
+
+            (
+                block_hash,
+                blockchain.get_block(&block_hash).unwrap().to_raw(),
+            )
+        };
+        let server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+
+        // Heights and hashes find the same block.
+        let by_height = server.call("getblock", &Json::parse("[2]")?)?;
+        let by_hash = server.call(
+            "getblock",
+            &Json::Array(vec![block_hash.to_string().into()]),
+        )?;
+        assert_eq!(by_height, by_hash);
+        assert_eq!(by_hash.get("transaction_count"), Some(&Json::Integer(2)));
+        assert!(by_hash.get("transactions").unwrap().as_array().unwrap()[0]
+            .as_str()
+            .is_some());
+
+        let hex = server.call("getblock", &Json::parse("[2, 0]")?)?;
+        assert_eq!(hex.as_str(), Some(raw_block.to_hex().as_str()));
+
+        let decoded = server.call(
+            "getblock",
+            &Json::object(vec![("height", 2i64.into()), ("verbosity", 2i64.into())]),
+        )?;
+        let pixel = decoded
+            .get("transactions")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|transaction| transaction.get("outputs").unwrap().as_array().unwrap())
+            .find(|output| output.get("type").unwrap().as_str() == Some("pixel"))
+            .unwrap();
+        assert_eq!(pixel.get("x"), Some(&Json::Integer(1)));
+        assert_eq!(
+            pixel.get("color"),
+            Some(&Json::Integer(Color::Red as u8 as i64))
+        );
+
+        let header = server.call("getblockheader", &Json::parse("[2]")?)?;
+        assert_eq!(header.get("hash"), by_hash.get("hash"));
+        assert!(header.get("transactions").is_none());
+        let hex = server.call("getblockheader", &Json::parse("[2, false]")?)?;
+        assert_eq!(hex.as_str(), Some(raw_block.get_header().to_hex().as_str()));
+
+        assert_eq!(
+            server
+                .call("getblock", &Json::parse("[2, 3]")?)
+                .unwrap_err()
+                .code,
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            server
+                .call("getblock", &Json::parse("[\"nothex\"]")?)
+                .unwrap_err()
+                .code,
+            INVALID_PARAMS
+        );
+        assert!(server.call("getblock", &Json::parse("[9]")?).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_stats() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let size = {
+            let mut blockchain = blockchain.lock().unwrap();
+            let private_key = Wallet::deterministic("miner").get_private_keys()[0].clone();
+            blockchain.create_pixel_transaction(
+                &private_key,
+                &[((1, 2), Color::Red), ((2, 2), Color::Blue)],
+                5,
+            )?;
+            blockchain.mine()?;
+
+            blockchain
+                .get_block(blockchain.get_last_block_hash())
+                .unwrap()
+                .get_transactions()
+                .iter()
+                .map(|transaction| transaction.get_size() as i64)
+                .sum::<i64>()
+        };
+        let server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+
+        let stats = server.call("getblockstats", &Json::parse("[2]")?)?;
+        assert_eq!(stats.get("transaction_count"), Some(&Json::Integer(2)));
+        assert_eq!(stats.get("size"), Some(&Json::Integer(size)));
+        assert_eq!(stats.get("total_tax"), Some(&Json::Integer(5)));
+        assert_eq!(stats.get("pixel_count"), Some(&Json::Integer(2)));
+        assert_eq!(stats.get("subsidy"), Some(&Json::Integer(1000)));
+
+        // Only the painting pays tax, so every percentile is its rate.
+        let percentiles = stats
+            .get("tax_rate_percentiles")
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert!(percentiles.iter().all(|rate| rate == &percentiles[0]));
+        assert!(matches!(percentiles[0], Json::Float(rate) if rate > 0.0));
+
+        // Nothing was taxed in the first block.
+        let stats = server.call("getblockstats", &Json::parse("[1]")?)?;
+        assert_eq!(stats.get("total_tax"), Some(&Json::Integer(0)));
+        assert_eq!(stats.get("pixel_count"), Some(&Json::Integer(0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_tips() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        node.mine()?;
+        let server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+
+        // Every block builds on the last one, so there's only the main chain.
+        let tips = server.call("getchaintips", &Json::Array(vec![]))?;
+        let tips = tips.as_array().unwrap();
+        assert_eq!(tips.len(), 1);
+        assert_eq!(
+            tips[0].get("hash").unwrap().as_str(),
+            Some(
+                blockchain
+                    .lock()
+                    .unwrap()
+                    .get_last_block_hash()
+                    .to_string()
+                    .as_str()
+            )
+        );
+        assert_eq!(tips[0].get("height"), Some(&Json::Integer(2)));
+        assert_eq!(tips[0].get("branch_length"), Some(&Json::Integer(0)));
+        assert_eq!(tips[0].get("status").unwrap().as_str(), Some("active"));
+
+        // Going off the last block leaves it as the tip of an invalid branch, until it's reconsidered.
+        let hash = tips[0].get("hash").unwrap().clone();
+        assert_eq!(Permission::of_method("invalidateblock"), Permission::Admin);
+        server.call("invalidateblock", &Json::Array(vec![hash.clone()]))?;
+        let tips = server.call("getchaintips", &Json::Array(vec![]))?;
+        let tips = tips.as_array().unwrap();
+        assert_eq!(tips[0].get("height"), Some(&Json::Integer(1)));
+        assert_eq!(tips[1].get("hash"), Some(&hash));
+        assert_eq!(tips[1].get("branch_length"), Some(&Json::Integer(1)));
+        assert_eq!(tips[1].get("status").unwrap().as_str(), Some("invalid"));
+        assert!(server
+            .call("getblock", &Json::Array(vec![hash.clone()]))
+            .is_ok());
+
+        server.call("reconsiderblock", &Json::Array(vec![hash.clone()]))?;
+        assert_eq!(server.call("getbestblockhash", &Json::Array(vec![]))?, hash);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explorer_rpc() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let wallet = Arc::new(Mutex::new(Wallet::deterministic("miner")));
+        let mut server = RpcServer::new(node.clone(), wallet.clone());
+        let params = Json::Array(vec![node_address("miner").as_str().into()]);
+        assert_eq!(
+            server.call("getaddresshistory", &params).unwrap_err().code,
+            SERVER_ERROR
+        );
+
+        // Blocks mined before the explorer was given are indexed on the first call.
+        server.set_explorer(Explorer::new(
+            blockchain.lock().unwrap().get_chain_params().clone(),
+        ));
+        server.call("paintpixel", &Json::parse("[3, 4, 9, 5]")?)?;
+        node.mine()?;
+
+        let history = server.call("getaddresshistory", &params)?;
+        assert_eq!(history.get("total"), Some(&Json::Integer(3)));
+        let history = server.call("getpixelhistory", &Json::parse(r#"{"x": 3, "y": 4}"#)?)?;
+        let items = history.get("items").unwrap().as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].get("color"), Some(&Json::Integer(9)));
+        assert_eq!(items[0].get("height"), Some(&Json::Integer(2)));
+
+        let stats = server.call("listblockstats", &Json::parse("[1, 1]")?)?;
+        assert_eq!(stats.get("total"), Some(&Json::Integer(3)));
+        let items = stats.get("items").unwrap().as_array().unwrap();
+        assert_eq!(items[0].get("height"), Some(&Json::Integer(1)));
+
+        let rich_list = server.call("getrichlist", &Json::parse("[1]")?)?;
+        let addresses = rich_list.get("addresses").unwrap().as_array().unwrap();
+        assert_eq!(addresses.len(), 1);
+        assert_eq!(
+            addresses[0].get("balance").unwrap().as_i64(),
+            Some(
+                blockchain
+                    .lock()
+                    .unwrap()
+                    .get_peer_credits(&node_address("miner"))
+            )
+        );
+        assert!(!rich_list
+            .get("distribution")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_node_status() -> Result<()> {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new(node_address("miner"))));
+        let node = Node::new(blockchain.clone());
+        node.mine()?;
+        let mut server = RpcServer::new(node, Arc::new(Mutex::new(Wallet::deterministic("miner"))));
+        server.call("paintpixel", &Json::parse("[3, 4, 9, 5]")?)?;
+
+        let status = server.call("getnodestatus", &Json::Array(vec![]))?;
+        assert_eq!(status.get("blocks"), Some(&Json::Integer(1)));
+        assert_eq!(status.get("headers"), Some(&Json::Integer(1)));
+        assert_eq!(status.get("is_synced"), Some(&Json::Bool(true)));
+        assert_eq!(
+            status.get("best_block_hash").unwrap().as_str(),
+            Some(
+                blockchain
+                    .lock()
+                    .unwrap()
+                    .get_last_block_hash()
+                    .to_string()
+                    .as_str()
+            )
+        );
+        assert_eq!(status.get("peer_count"), Some(&Json::Integer(0)));
+        assert_eq!(
+            status.get("mempool_transaction_count"),
+            Some(&Json::Integer(1))
+        );
+        assert_eq!(status.get("disk_usage"), Some(&Json::Null));
+        assert_eq!(status.get("canvas_width"), Some(&Json::Integer(1000)));
+
+        // Files further down count too, but not what links point to.
+        let data_dir = std::env::temp_dir().join(format!("place-coin-data-{}", std::process::id()));
+        std::fs::create_dir_all(data_dir.join("blocks"))?;
+        std::fs::write(data_dir.join("peers.txt"), [0; 10])?;
+        std::fs::write(data_dir.join("blocks").join("state.bin"), [0; 32])?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&data_dir, data_dir.join("blocks").join("loop"))?;
+        server.set_data_dir(&data_dir);
+        let status = server.call("getnodestatus", &Json::Array(vec![]))?;
+        std::fs::remove_dir_all(&data_dir)?;
+        assert_eq!(status.get("disk_usage"), Some(&Json::Integer(42)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_canvas_tiles() -> Result<()> {
+        let mut blockchain = Blockchain::new(node_address("miner"));
+        let private_key = Wallet::deterministic("miner").get_private_keys()[0].clone();
+        blockchain.mine()?;
+        blockchain.create_pixel_transaction(&private_key, &[((1, 2), Color::Red)], 5)?;
+        blockchain.mine()?;
+
+        let mut tiles = TileServer::new();
+        let mut get = |blockchain: &Blockchain, target: &str, etag: Option<&str>| {
+            tiles.handle_request(
+                blockchain,
+                &Request {
+                    method: "GET".to_string(),
+                    target: target.to_string(),
+                    headers: etag
+                        .map(|etag| vec![("if-none-match".to_string(), etag.to_string())])
+                        .unwrap_or_default(),
+                    body: vec![],
+                    is_last: true,
+                },
+            )
+        };
+
+        let tile = get(&blockchain, "/tiles/4/0/0.png", None)?;
+        assert_eq!(tile.status, 200);
+        assert!(tile.png.starts_with(b"\x89PNG"));
+        let etag = tile.etag.unwrap();
+        let far_etag = get(&blockchain, "/tiles/4/5/5.png", None)?.etag.unwrap();
+        assert_eq!(get(&blockchain, "/tiles/0/0/0.png", None)?.status, 200);
+        assert_eq!(get(&blockchain, "/tiles/4/16/0.png", None)?.status, 404);
+        assert_eq!(get(&blockchain, "/tiles/5/0/0.png", None)?.status, 404);
+        assert_eq!(get(&blockchain, "/tiles/4/0/x.png", None)?.status, 404);
+
+        let tile = get(&blockchain, "/tiles/4/0/0.png", Some(&etag))?;
+        assert_eq!(tile.status, 304);
+        assert!(tile.png.is_empty());
+
+        // Painting again only makes the tiles showing the pixel dirty.
+        blockchain.create_pixel_transaction(&private_key, &[((1, 2), Color::Blue)], 5)?;
+        blockchain.mine()?;
+        let tile = get(&blockchain, "/tiles/4/0/0.png", Some(&etag))?;
+        assert_eq!(tile.status, 200);
+        assert_ne!(tile.etag.unwrap(), etag);
+        assert_eq!(
+            get(&blockchain, "/tiles/4/5/5.png", Some(&far_etag))?.status,
+            304
+        );
+
+        Ok(())
+    }
+}
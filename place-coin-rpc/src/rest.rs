@@ -0,0 +1,234 @@
+// Read-only chain data over plain HTTP GET, for web frontends and quick looks with curl:
+//
+//     /blocks/<hash>               a block, as getblock gives it
+//     /tx/<hash>                   a confirmed or pending transaction
+//     /address/<address>/utxos     unspent confirmed outputs paying the address
+//     /canvas/region?x=<x>&y=<y>&width=<width>&height=<height>
+//                                  pixels row by row, as getcanvasregion gives them
+//
+// Answers are JSON. Anything that doesn't exist is a 404 and anything malformed a 400, both with
+// an `error` message.
+
+use crate::{
+    json::Json,
+    server::{get_block_json, get_region_json, MAX_REGION_AREA},
+};
+use place_coin::{
+    address::Address,
+    blockchain::{encode_hex, BlockHash, Blockchain, TxHash},
+    transaction::{ModerationAction, Transaction, TransactionInput, TransactionOutput},
+};
+use rayon::iter::ParallelIterator;
+
+pub fn handle_rest(blockchain: &Blockchain, target: &str) -> (u16, Json) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+    let result = match segments[..] {
+        ["blocks", block_hash] => get_block(blockchain, block_hash),
+        ["tx", transaction_hash] => get_transaction(blockchain, transaction_hash),
+        ["address", address, "utxos"] => get_unspent_outputs(blockchain, address),
+        ["canvas", "region"] => get_region(blockchain, query),
+        _ => Err((404, "Not found.".to_string())),
+    };
+
+    match result {
+        Ok(json) => (200, json),
+        Err((status, message)) => (status, Json::object(vec![("error", message.into())])),
+    }
+}
+
+type RestResult = Result<Json, (u16, String)>;
+
+fn get_block(blockchain: &Blockchain, block_hash: &str) -> RestResult {
+    let block_hash: BlockHash = block_hash
+        .parse()
+        .map_err(|_| (400, "Block hash is malformed.".to_string()))?;
+    let block = blockchain
+        .get_block(&block_hash)
+        .ok_or((404, "Block isn't known.".to_string()))?;
+
+    get_block_json(block).map_err(|error| (500, error.to_string()))
+}
+
+fn get_transaction(blockchain: &Blockchain, transaction_hash: &str) -> RestResult {
+    let transaction_hash: TxHash = transaction_hash
+        .parse()
+        .map_err(|_| (400, "Transaction hash is malformed.".to_string()))?;
+
+    let (transaction, status) = match blockchain.find_pending_transaction(&transaction_hash) {
+        Some(transaction) => (transaction, "pending"),
+        None => (
+            blockchain
+                .find_transaction(&transaction_hash)
+                .ok_or((404, "Transaction isn't known.".to_string()))?,
+            "confirmed",
+        ),
+    };
+
+    Ok(get_transaction_json(transaction, status))
+}
+
+fn get_unspent_outputs(blockchain: &Blockchain, address: &str) -> RestResult {
+    let address =
+        Address::parse(address).map_err(|_| (400, "Address is malformed.".to_string()))?;
+
+    let mut outputs = blockchain
+        .get_all_unspent_outputs()
+        .filter_map(|(transaction, output, output_index)| match output {
+            TransactionOutput::ToInput {
+                value,
+                public_key_address,
+            } if *public_key_address == address => {
+                Some((*transaction.get_hash(), output_index, *value))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // Outputs are found in no particular order.
+    outputs.sort();
+
+    Ok(Json::Array(
+        outputs
+            .into_iter()
+            .map(|(transaction_hash, output_index, value)| {
+                Json::object(vec![
+                    ("transaction_hash", transaction_hash.to_string().into()),
+                    ("output_index", output_index.into()),
+                    ("value", value.into()),
+                ])
+            })
+            .collect(),
+    ))
+}
+
+fn get_region(blockchain: &Blockchain, query: &str) -> RestResult {
+    let get_parameter = |name: &str| -> Result<i32, (u16, String)> {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+            .ok_or((400, format!("Parameter {name} is missing.")))?
+            .parse()
+            .map_err(|_| (400, format!("Parameter {name} is malformed.")))
+    };
+    let (x, y) = (get_parameter("x")?, get_parameter("y")?);
+    let (width, height) = (get_parameter("width")?, get_parameter("height")?);
+    if width < 0 || height < 0 || width as i64 * height as i64 > MAX_REGION_AREA {
+        return Err((400, "Region is too big.".to_string()));
+    }
+
+    get_region_json(blockchain, (x, y), (width, height)).map_err(|error| (500, error.to_string()))
+}
+
+fn get_transaction_json(transaction: &Transaction, status: &str) -> Json {
+    Json::object(vec![
+        ("hash", transaction.get_hash().to_string().into()),
+        ("status", status.into()),
+        ("inputs", get_inputs_json(transaction.get_inputs())),
+        ("outputs", get_outputs_json(transaction.get_outputs())),
+        ("lock_time", (transaction.get_lock_time() as u64).into()),
+        ("size", transaction.get_size().into()),
+    ])
+}
+
+pub(crate) fn get_inputs_json(inputs: &[TransactionInput]) -> Json {
+    Json::Array(
+        inputs
+            .iter()
+            .map(|input| match input {
+                TransactionInput::FromOutput {
+                    transaction_hash,
+                    output_index,
+                    public_key,
+                    signature,
+                } => Json::object(vec![
+                    ("type", "output".into()),
+                    ("transaction_hash", transaction_hash.to_string().into()),
+                    ("output_index", (*output_index as u64).into()),
+                    ("public_key", encode_hex(public_key.as_bytes()).into()),
+                    ("signed", (!signature.is_unsigned()).into()),
+                ]),
+                TransactionInput::FromReward { height, value, .. } => Json::object(vec![
+                    ("type", "reward".into()),
+                    ("height", (*height).into()),
+                    ("value", (*value).into()),
+                ]),
+            })
+            .collect(),
+    )
+}
+
+pub(crate) fn get_outputs_json(outputs: &[TransactionOutput]) -> Json {
+    Json::Array(
+        outputs
+            .iter()
+            .map(|output| match output {
+                TransactionOutput::ToInput {
+                    value,
+                    public_key_address,
+                } => Json::object(vec![
+                    ("type", "credits".into()),
+                    ("value", (*value).into()),
+                    ("address", public_key_address.as_str().into()),
+                ]),
+                TransactionOutput::ToPixel {
+                    value,
+                    position: (x, y),
+                    color,
+                    layer,
+                    faction,
+                    rental,
+                    memo,
+                } => Json::object(vec![
+                    ("type", "pixel".into()),
+                    ("value", (*value).into()),
+                    ("x", (*x as i64).into()),
+                    ("y", (*y as i64).into()),
+                    ("color", (*color as u8 as i64).into()),
+                    ("layer", (*layer as i64).into()),
+                    (
+                        "faction",
+                        faction.as_ref().map(|faction| encode_hex(faction)).into(),
+                    ),
+                    ("rental", (*rental).into()),
+                    ("memo", memo.clone().into()),
+                ]),
+                TransactionOutput::Moderate {
+                    value,
+                    position: (x, y),
+                    width,
+                    height,
+                    action,
+                } => {
+                    let (action, duration) = match action {
+                        ModerationAction::Blank => ("blank", None),
+                        ModerationAction::Freeze { duration } => ("freeze", Some(*duration)),
+                    };
+
+                    Json::object(vec![
+                        ("type", "moderation".into()),
+                        ("value", (*value).into()),
+                        ("x", (*x as i64).into()),
+                        ("y", (*y as i64).into()),
+                        ("width", (*width as i64).into()),
+                        ("height", (*height as i64).into()),
+                        ("action", action.into()),
+                        ("duration", duration.into()),
+                    ])
+                }
+                TransactionOutput::LockPixel {
+                    value,
+                    position: (x, y),
+                    duration,
+                } => Json::object(vec![
+                    ("type", "lock".into()),
+                    ("value", (*value).into()),
+                    ("x", (*x as i64).into()),
+                    ("y", (*y as i64).into()),
+                    ("duration", (*duration).into()),
+                ]),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,1216 @@
+// JSON-RPC 2.0 over HTTP, so wallets, explorers and bots can use a node without linking Rust.
+// Every request is POSTed to `/` as:
+//
+//     {"jsonrpc": "2.0", "method": "getblockcount", "params": [], "id": 1}
+//
+// and answered with either a `result` or an `error` with a code and a message, under the same id.
+// Params can be given in order or by name. Requests without an id are notifications, which get
+// no answer, and several requests can be sent at once in an array. Read-only data can also be had
+// with a GET, see `rest`, the canvas as PNG tiles, see `tiles`, and notifications pushed over a
+// WebSocket, see `websocket`. A server made with `with_auth` wants a token for all of them, see
+// `auth`; one made with `new` lets anyone who can reach it do anything, so it should only listen
+// where nobody else can.
+//
+// Methods:
+//
+//     getblockcount                           height of the last block
+//     getbestblockhash                        hash of the last block
+//     getblock <hash|height> [verbosity]      a block: with verbosity 0 its hex, 1 its header
+//                                             and the hashes of its transactions, the default, or
+//                                             2 its header and its transactions decoded
+//     getblockheader <hash|height> [verbose]  the header of a block, or its hex if not verbose
+//     getblockstats <hash|height>             what a block holds: its transactions, size, taxes and
+//                                             their rates, pixels painted and subsidy
+//     getchaintips                            the last block of every known branch, with its
+//                                             height, status (active, valid-fork or invalid) and
+//                                             how many blocks it has since it left the main chain
+//     invalidateblock <hash>                  marks the block invalid, moving the main chain off
+//                                             it, to the branch with the most blocks left
+//     reconsiderblock <hash>                  undoes invalidateblock for the block and its branch
+//     getaddresshistory <address> [offset] [limit]
+//                                             transactions paying or spending from the address,
+//                                             with how much, newest first
+//     getpixelhistory <x> <y> [offset] [limit]
+//                                             every paint of the pixel, newest first
+//     listblockstats [offset] [limit]         getblockstats of every block, newest first
+//     getrichlist [count]                     the addresses with the most confirmed credits, and
+//                                             how many addresses hold from 1, 10, 100... credits
+//     getbalance [address]                    credits of the address, or of the whole wallet
+//     sendtoaddress <address> <amount> <tax>  hash of the new transaction
+//     paintpixel <x> <y> <color> <tax>        hash of the new transaction, colors being palette
+//                                             indices
+//     getcanvasregion <x> <y> <width> <height>
+//                                             pixels row by row, null where nothing was painted
+//     getmempoolinfo                          size and tax rates of the pending transactions, and
+//                                             how many have waited up to a minute, an hour, a day
+//                                             and longer
+//     getconnectioncount                      number of peers
+//     getnodestatus                           everything a dashboard or a health check wants at
+//                                             once: blocks and headers, the last block, peers,
+//                                             the mempool, disk usage and the canvas size
+//     createrawtransaction <inputs> <outputs> [lock_time] [version]
+//                                             hex of an unsigned transaction, with inputs and
+//                                             outputs written as decoderawtransaction shows them
+//     decoderawtransaction <hex>              the transaction, and which of its inputs are signed
+//     fundrawtransaction <hex> [tax] [target_blocks]
+//                                             the transaction with inputs from the wallet and
+//                                             change back to it, still unsigned; without a tax,
+//                                             one likely to confirm within the target is paid
+//     signrawtransaction <hex>                the transaction with the inputs the wallet has keys
+//                                             for signed, and the indices of those still unsigned;
+//                                             signatures cover all the inputs and outputs, so any
+//                                             change afterwards needs signing again
+//     sendrawtransaction <hex>                hash of the transaction, once it's in the mempool;
+//                                             otherwise the error's data has a `reason`, like
+//                                             missing_inputs, bad_signature or below_min_tax_rate
+//
+// History and balances are only kept by a server given an `Explorer` with `set_explorer`, and comes in pages of
+// `{"total": ..., "items": [...]}`, of up to `MAX_PAGE_SIZE` items.
+//
+// Raw transactions are hex of the bytes their hash is taken over, so they can be made, kept and
+// passed around away from any wallet.
+
+use crate::{
+    auth::{Auth, Permission},
+    http::{read_request, write_response, Request},
+    json::Json,
+    rest::{get_inputs_json, get_outputs_json, handle_rest},
+    tiles::TileServer,
+    websocket::{handle_websocket, is_upgrade},
+};
+use anyhow::{Context, Result};
+use place_coin::{
+    address::{Address, PublicKey},
+    block::{Block, BlockStats},
+    blockchain::{decode_hex, encode_hex, BlockHash, Blockchain, TipStatus},
+    canvas::Pixel,
+    color::Color,
+    explorer::{Explorer, Page, MAX_PAGE_SIZE},
+    mempool::Rejection,
+    signature::{get_signature_scheme, Signature},
+    transaction::{
+        Credits, ModerationAction, RawTransaction, TransactionInput, TransactionOutput, BASE_LAYER,
+        CURRENT_TRANSACTION_VERSION,
+    },
+    wallet::Wallet,
+};
+use place_coin_node::p2p::Node;
+use std::{
+    fmt, fs,
+    io::{BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+pub const MAX_REGION_AREA: i64 = 256 * 256; // Pixels in a single getcanvasregion answer.
+pub const MAX_BATCH_SIZE: usize = 100;
+pub const DEFAULT_TARGET_BLOCKS: u64 = 6; // For fundrawtransaction, when no tax is given.
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const SERVER_ERROR: i64 = -32000; // The request was fine, but couldn't be done.
+pub const FORBIDDEN: i64 = -32001; // The token doesn't allow the method.
+pub const REJECTED: i64 = -32002; // The transaction isn't let in, with why in `data`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Json>, // More on what went wrong, for programs rather than people.
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+impl From<Rejection> for RpcError {
+    fn from(rejection: Rejection) -> Self {
+        let reason = match &rejection {
+            Rejection::AlreadyKnown => "already_known",
+            Rejection::MissingInputs => "missing_inputs",
+            Rejection::SpentInputs => "spent_inputs",
+            Rejection::BadSignature => "bad_signature",
+            Rejection::BelowMinTaxRate { .. } => "below_min_tax_rate",
+            Rejection::RecentlyEvicted => "recently_evicted",
+            Rejection::MempoolFull => "mempool_full",
+            Rejection::Invalid(_) => "invalid",
+        };
+        let mut data = vec![("reason", reason.into())];
+        if let Rejection::BelowMinTaxRate { min_tax_rate } = rejection {
+            data.push(("min_tax_rate", min_tax_rate.into()));
+        }
+
+        Self {
+            code: REJECTED,
+            message: rejection.to_string(),
+            data: Some(Json::object(data)),
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(SERVER_ERROR, error)
+    }
+}
+
+#[derive(Clone)]
+pub struct RpcServer {
+    node: Node,
+    wallet: Arc<Mutex<Wallet>>, // Pays for sent credits and painted pixels.
+    auth: Option<Arc<Auth>>,    // Nothing means everyone can do everything.
+    explorer: Option<Arc<Mutex<Explorer>>>,
+    data_dir: Option<PathBuf>, // Where the node keeps its files, to tell how much space they take.
+    tiles: Arc<Mutex<TileServer>>,
+}
+
+impl RpcServer {
+    pub fn new(node: Node, wallet: Arc<Mutex<Wallet>>) -> Self {
+        Self {
+            node,
+            wallet,
+            auth: None,
+            explorer: None,
+            data_dir: None,
+            tiles: Arc::new(Mutex::new(TileServer::new())),
+        }
+    }
+
+    pub fn with_auth(node: Node, wallet: Arc<Mutex<Wallet>>, auth: Auth) -> Self {
+        Self {
+            node,
+            wallet,
+            auth: Some(Arc::new(auth)),
+            explorer: None,
+            data_dir: None,
+            tiles: Arc::new(Mutex::new(TileServer::new())),
+        }
+    }
+
+    pub fn set_explorer(&mut self, explorer: Explorer) {
+        self.explorer = Some(Arc::new(Mutex::new(explorer)));
+    }
+
+    pub fn set_data_dir(&mut self, data_dir: impl AsRef<Path>) {
+        self.data_dir = Some(data_dir.as_ref().to_path_buf());
+    }
+
+    pub fn listen(&self, listener: TcpListener) -> JoinHandle<()> {
+        let server = self.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+
+                // A client going away is nothing the others need to know about.
+                thread::spawn(move || {
+                    let _ = server.handle_client(stream);
+                });
+            }
+        })
+    }
+
+    pub fn handle_body(&self, body: &str, permission: Permission) -> Option<Json> {
+        let request = match Json::parse(body) {
+            Ok(request) => request,
+            Err(error) => {
+                return Some(get_error_response(
+                    Json::Null,
+                    RpcError::new(PARSE_ERROR, error),
+                ))
+            }
+        };
+
+        // Batches are answered in one array, leaving out the notifications.
+        match request {
+            Json::Array(requests) if requests.is_empty() || requests.len() > MAX_BATCH_SIZE => {
+                Some(get_error_response(
+                    Json::Null,
+                    RpcError::new(
+                        INVALID_REQUEST,
+                        "Batch must have between 1 and 100 requests.",
+                    ),
+                ))
+            }
+            Json::Array(requests) => {
+                let responses = requests
+                    .iter()
+                    .filter_map(|request| self.handle_request(request, permission))
+                    .collect::<Vec<_>>();
+
+                (!responses.is_empty()).then_some(Json::Array(responses))
+            }
+            request => self.handle_request(&request, permission),
+        }
+    }
+
+    // Permissions are up to the caller, as they only come with HTTP requests.
+    pub fn call(&self, method: &str, params: &Json) -> Result<Json, RpcError> {
+        let params = Params(params);
+
+        match method {
+            "getblockcount" => {
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+
+                Ok((blockchain.get_next_block_height()? - 1).into())
+            }
+
+            "getbestblockhash" => {
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+
+                Ok(blockchain.get_last_block_hash().to_string().into())
+            }
+
+            "getblock" => {
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+                let block = find_block(&blockchain, &params)?;
+
+                match params.get(1, "verbosity").map(Json::as_i64) {
+                    Some(Some(0)) => Ok(block.to_raw().to_hex().into()),
+                    None | Some(Some(1)) => Ok(get_block_json(block)?),
+                    Some(Some(2)) => Ok(get_decoded_block_json(block)?),
+                    _ => Err(RpcError::new(
+                        INVALID_PARAMS,
+                        "Param verbosity must be 0, 1 or 2.",
+                    )),
+                }
+            }
+
+            "getblockheader" => {
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+                let block = find_block(&blockchain, &params)?;
+
+                match params.get(1, "verbose") {
+                    None | Some(Json::Bool(true)) => Ok(Json::object(get_header_fields(block)?)),
+                    Some(Json::Bool(false)) => Ok(block.get_header().to_hex().into()),
+                    _ => Err(RpcError::new(INVALID_PARAMS, "Param verbose isn't a bool.")),
+                }
+            }
+
+            "getblockstats" => {
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+                let block = find_block(&blockchain, &params)?;
+
+                Ok(get_block_stats_json(
+                    &block.calculate_hash(),
+                    &block.get_stats()?,
+                ))
+            }
+
+            "getaddresshistory" => {
+                let address = params.get_parsed_with(0, "address", Address::parse)?;
+                let (offset, limit) = params.get_page(1)?;
+                let page = self.query_explorer(|explorer| {
+                    explorer.get_address_history(&address, offset, limit)
+                })?;
+
+                Ok(get_page_json(page, |entry| {
+                    Json::object(vec![
+                        (
+                            "transaction_hash",
+                            entry.transaction_hash.to_string().into(),
+                        ),
+                        ("height", entry.height.into()),
+                        ("received", entry.received.into()),
+                        ("sent", entry.sent.into()),
+                    ])
+                }))
+            }
+
+            "getpixelhistory" => {
+                let point = (params.get_i32(0, "x")?, params.get_i32(1, "y")?);
+                let (offset, limit) = params.get_page(2)?;
+                let page = self
+                    .query_explorer(|explorer| explorer.get_pixel_history(&point, offset, limit))?;
+
+                Ok(get_page_json(page, |entry| {
+                    Json::object(vec![
+                        (
+                            "transaction_hash",
+                            entry.transaction_hash.to_string().into(),
+                        ),
+                        ("height", entry.height.into()),
+                        ("color", (entry.color as u8 as i64).into()),
+                        ("layer", (entry.layer as i64).into()),
+                        (
+                            "painter",
+                            entry
+                                .painter
+                                .as_ref()
+                                .map(|painter| painter.as_str())
+                                .into(),
+                        ),
+                        ("value", entry.value.into()),
+                    ])
+                }))
+            }
+
+            "getrichlist" => {
+                let count = match params.get(0, "count") {
+                    Some(_) => params
+                        .get_converted::<usize>(0, "count")?
+                        .min(MAX_PAGE_SIZE),
+                    None => MAX_PAGE_SIZE,
+                };
+                let (rich_list, distribution) = self.query_explorer(|explorer| {
+                    (
+                        explorer.get_rich_list(count),
+                        explorer.get_balance_distribution(),
+                    )
+                })?;
+
+                Ok(Json::object(vec![
+                    (
+                        "addresses",
+                        Json::Array(
+                            rich_list
+                                .into_iter()
+                                .map(|(address, balance)| {
+                                    Json::object(vec![
+                                        ("address", address.as_str().into()),
+                                        ("balance", balance.into()),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                    (
+                        "distribution",
+                        Json::Array(
+                            distribution
+                                .into_iter()
+                                .map(|bucket| {
+                                    Json::object(vec![
+                                        ("min_balance", bucket.min_balance.into()),
+                                        ("address_count", bucket.address_count.into()),
+                                        ("total_balance", bucket.total_balance.into()),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                ]))
+            }
+
+            "listblockstats" => {
+                let (offset, limit) = params.get_page(0)?;
+                let page =
+                    self.query_explorer(|explorer| explorer.get_block_stats(offset, limit))?;
+
+                Ok(get_page_json(page, |(block_hash, stats)| {
+                    get_block_stats_json(&block_hash, &stats)
+                }))
+            }
+
+            "getchaintips" => {
+                let tips = self
+                    .node
+                    .get_blockchain()
+                    .lock()
+                    .unwrap()
+                    .get_chain_tips()?;
+
+                Ok(Json::Array(
+                    tips.into_iter()
+                        .map(|tip| {
+                            let status = match tip.status {
+                                TipStatus::Active => "active",
+                                TipStatus::ValidFork => "valid-fork",
+                                TipStatus::Invalid => "invalid",
+                            };
+
+                            Json::object(vec![
+                                ("hash", tip.block_hash.to_string().into()),
+                                ("height", tip.height.into()),
+                                ("branch_length", tip.branch_length.into()),
+                                ("status", status.into()),
+                            ])
+                        })
+                        .collect(),
+                ))
+            }
+
+            "getbalance" => {
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+                let addresses = match params.get(0, "address") {
+                    Some(_) => vec![params.get_parsed_with(0, "address", Address::parse)?],
+                    None => self.wallet.lock().unwrap().get_addresses(),
+                };
+
+                Ok(addresses
+                    .iter()
+                    .map(|address| blockchain.get_peer_credits(address))
+                    .sum::<i64>()
+                    .into())
+            }
+
+            "sendtoaddress" => {
+                let address = params.get_parsed_with(0, "address", Address::parse)?;
+                let amount = params.get_credits(1, "amount")?;
+                let tax = params.get_credits(2, "tax")?;
+
+                let transaction_hash = {
+                    let mut blockchain = self.node.get_blockchain().lock().unwrap();
+                    self.wallet.lock().unwrap().send_many(
+                        &mut blockchain,
+                        vec![(address, amount)],
+                        tax,
+                    )?
+                };
+                self.node.announce_transaction(&transaction_hash)?;
+
+                Ok(transaction_hash.to_string().into())
+            }
+
+            "paintpixel" => {
+                let x = params.get_i32(0, "x")?;
+                let y = params.get_i32(1, "y")?;
+                let color = params.get_color(2, "color")?;
+                let tax = params.get_credits(3, "tax")?;
+
+                let transaction_hash = {
+                    let mut blockchain = self.node.get_blockchain().lock().unwrap();
+                    let wallet = self.wallet.lock().unwrap();
+                    let private_key = wallet
+                        .get_private_keys()
+                        .first()
+                        .context("Wallet has no keys.")?;
+
+                    blockchain.create_pixel_transaction(private_key, &[((x, y), color)], tax)?
+                };
+                self.node.announce_transaction(&transaction_hash)?;
+
+                Ok(transaction_hash.to_string().into())
+            }
+
+            "getcanvasregion" => {
+                let x = params.get_i32(0, "x")?;
+                let y = params.get_i32(1, "y")?;
+                let width = params.get_i32(2, "width")?;
+                let height = params.get_i32(3, "height")?;
+                if width < 0 || height < 0 || width as i64 * height as i64 > MAX_REGION_AREA {
+                    return Err(RpcError::new(INVALID_PARAMS, "Region is too big."));
+                }
+
+                Ok(get_region_json(
+                    &self.node.get_blockchain().lock().unwrap(),
+                    (x, y),
+                    (width, height),
+                )?)
+            }
+
+            "getmempoolinfo" => {
+                let info = self
+                    .node
+                    .get_blockchain()
+                    .lock()
+                    .unwrap()
+                    .get_mempool()
+                    .info();
+
+                Ok(Json::object(vec![
+                    ("transaction_count", info.transaction_count.into()),
+                    ("size", info.size.into()),
+                    ("total_tax", info.total_tax.into()),
+                    (
+                        "tax_rate_percentiles",
+                        info.tax_rate_percentiles.to_vec().into(),
+                    ),
+                    ("age_counts", info.age_counts.to_vec().into()),
+                    (
+                        "oldest_added_at",
+                        info.oldest_added_at.map(|time| time.timestamp()).into(),
+                    ),
+                ]))
+            }
+
+            "invalidateblock" => {
+                let block_hash: BlockHash = params.get_parsed(0, "hash")?;
+                let mut blockchain = self.node.get_blockchain().lock().unwrap();
+                blockchain.invalidate_block(&block_hash)?;
+
+                Ok(Json::Null)
+            }
+
+            "reconsiderblock" => {
+                let block_hash: BlockHash = params.get_parsed(0, "hash")?;
+                let mut blockchain = self.node.get_blockchain().lock().unwrap();
+                blockchain.reconsider_block(&block_hash)?;
+
+                Ok(Json::Null)
+            }
+
+            "getconnectioncount" => Ok(self.node.get_peer_addresses().len().into()),
+
+            "getnodestatus" => {
+                let header_height = self.node.get_header_height()?;
+                let peer_count = self.node.get_peer_addresses().len();
+                let disk_usage = match &self.data_dir {
+                    Some(data_dir) => Some(get_disk_usage(data_dir)?),
+                    None => None,
+                };
+
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+                let last_block = blockchain.get_last_block();
+                let height = last_block.get_block_height()?;
+                let (canvas_width, canvas_height) =
+                    blockchain.get_chain_params().get_canvas_size(height);
+                let mempool = blockchain.get_mempool();
+
+                // Blocks can be known before their headers are, as when they're mined here.
+                let header_height = header_height.max(height);
+
+                Ok(Json::object(vec![
+                    ("blocks", height.into()),
+                    ("headers", header_height.into()),
+                    (
+                        "sync_progress",
+                        (height as f64 / header_height.max(1) as f64).into(),
+                    ),
+                    ("is_synced", (height == header_height).into()),
+                    (
+                        "best_block_hash",
+                        last_block.calculate_hash().to_string().into(),
+                    ),
+                    (
+                        "best_block_time",
+                        last_block.get_timestamp().timestamp().into(),
+                    ),
+                    ("peer_count", peer_count.into()),
+                    ("mempool_transaction_count", mempool.len().into()),
+                    ("mempool_size", mempool.get_size().into()),
+                    ("disk_usage", disk_usage.into()),
+                    ("canvas_width", (canvas_width as i64).into()),
+                    ("canvas_height", (canvas_height as i64).into()),
+                ]))
+            }
+
+            "createrawtransaction" => {
+                let inputs = params
+                    .get_array(0, "inputs")?
+                    .iter()
+                    .map(get_raw_input)
+                    .collect::<Result<_, _>>()?;
+                let outputs = params
+                    .get_array(1, "outputs")?
+                    .iter()
+                    .map(get_raw_output)
+                    .collect::<Result<_, _>>()?;
+                let lock_time = match params.get(2, "lock_time") {
+                    Some(_) => params.get_converted(2, "lock_time")?,
+                    None => 0,
+                };
+                let version = match params.get(3, "version") {
+                    Some(_) => params.get_converted(3, "version")?,
+                    None => CURRENT_TRANSACTION_VERSION,
+                };
+                get_signature_scheme(version)
+                    .map_err(|error| RpcError::new(INVALID_PARAMS, error))?;
+
+                let raw_transaction = RawTransaction {
+                    version,
+                    inputs,
+                    outputs,
+                    lock_time,
+                };
+
+                Ok(raw_transaction.to_hex().into())
+            }
+
+            "decoderawtransaction" => {
+                let raw_transaction = params.get_parsed_with(0, "hex", RawTransaction::from_hex)?;
+
+                Ok(get_raw_transaction_json(&raw_transaction))
+            }
+
+            "fundrawtransaction" => {
+                let mut raw_transaction =
+                    params.get_parsed_with(0, "hex", RawTransaction::from_hex)?;
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+                let wallet = self.wallet.lock().unwrap();
+
+                let tax = match params.get(1, "tax") {
+                    Some(_) => params.get_credits(1, "tax")?,
+                    None => {
+                        let target_blocks = match params.get(2, "target_blocks") {
+                            Some(_) => params.get_converted(2, "target_blocks")?,
+                            None => DEFAULT_TARGET_BLOCKS,
+                        };
+                        let min_tax_rate = blockchain.get_mempool().get_min_tax_rate() as f64;
+                        let tax_rate = blockchain
+                            .estimate_fee(target_blocks)
+                            .map_or(min_tax_rate, |estimate| estimate.tax_rate.max(min_tax_rate));
+
+                        // A draft without tax is about as big as the real thing, give or take the
+                        // change output.
+                        let mut draft = raw_transaction.clone();
+                        wallet.fund_raw_transaction(&blockchain, &mut draft, 0)?;
+
+                        (tax_rate * draft.get_size() as f64).ceil() as Credits
+                    }
+                };
+                let change_index =
+                    wallet.fund_raw_transaction(&blockchain, &mut raw_transaction, tax)?;
+
+                Ok(Json::object(vec![
+                    ("hex", raw_transaction.to_hex().into()),
+                    ("tax", tax.into()),
+                    ("change_index", change_index.into()),
+                ]))
+            }
+
+            "signrawtransaction" => {
+                let mut raw_transaction =
+                    params.get_parsed_with(0, "hex", RawTransaction::from_hex)?;
+                let unsigned_inputs = self
+                    .wallet
+                    .lock()
+                    .unwrap()
+                    .sign_raw_transaction(&mut raw_transaction)?;
+
+                Ok(Json::object(vec![
+                    ("hex", raw_transaction.to_hex().into()),
+                    ("complete", unsigned_inputs.is_empty().into()),
+                    ("unsigned_inputs", unsigned_inputs.into()),
+                ]))
+            }
+
+            "sendrawtransaction" => {
+                let raw_transaction = params.get_parsed_with(0, "hex", RawTransaction::from_hex)?;
+
+                let transaction_hash = {
+                    let mut blockchain = self.node.get_blockchain().lock().unwrap();
+                    let transaction = blockchain.test_accept_raw_transaction(raw_transaction)?;
+                    let transaction_hash = *transaction.get_hash();
+                    blockchain.new_transaction(transaction)?;
+
+                    transaction_hash
+                };
+                self.node.announce_transaction(&transaction_hash)?;
+
+                Ok(transaction_hash.to_string().into())
+            }
+
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "Method isn't known.")),
+        }
+    }
+
+    fn handle_client(&self, stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        while let Some(request) = read_request(&mut reader)? {
+            let permission = match &self.auth {
+                Some(auth) => auth.authenticate(&request),
+                None => Some(Permission::Admin),
+            };
+            let Some(permission) = permission else {
+                write_response(
+                    &mut writer,
+                    401,
+                    "application/json",
+                    &Json::object(vec![("error", "Token is missing or unknown.".into())])
+                        .to_string(),
+                )?;
+                if request.is_last {
+                    break;
+                }
+                continue;
+            };
+
+            // The connection is the WebSocket's from then on.
+            if is_upgrade(&request) {
+                return handle_websocket(self.node.get_blockchain(), &request, writer);
+            }
+
+            self.handle_http_request(&request, permission, &mut writer)?;
+            if request.is_last {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_http_request(
+        &self,
+        request: &Request,
+        permission: Permission,
+        writer: &mut impl Write,
+    ) -> Result<()> {
+        if request.method == "GET" && request.target.starts_with("/tiles/") {
+            let response = {
+                let blockchain = self.node.get_blockchain().lock().unwrap();
+                self.tiles
+                    .lock()
+                    .unwrap()
+                    .handle_request(&blockchain, request)
+            };
+
+            return match response {
+                Ok(response) => response.write(writer),
+                Err(error) => write_response(writer, 500, "text/plain", &error.to_string()),
+            };
+        }
+        if request.method == "GET" {
+            let (status, response) =
+                handle_rest(&self.node.get_blockchain().lock().unwrap(), &request.target);
+
+            return write_response(writer, status, "application/json", &response.to_string());
+        }
+        if request.target != "/" {
+            return write_response(writer, 404, "text/plain", "Not found.");
+        }
+        if request.method != "POST" {
+            return write_response(writer, 405, "text/plain", "Requests must be POSTed.");
+        }
+
+        let body = String::from_utf8_lossy(&request.body);
+        match self.handle_body(&body, permission) {
+            Some(response) => {
+                write_response(writer, 200, "application/json", &response.to_string())
+            }
+            None => write_response(writer, 204, "application/json", ""),
+        }
+    }
+
+    fn query_explorer<T>(&self, query: impl FnOnce(&Explorer) -> T) -> Result<T, RpcError> {
+        let explorer = self
+            .explorer
+            .as_ref()
+            .context("Explorer isn't enabled on this server.")?;
+
+        // Caught up on every query, so it never answers for a chain that's moved on.
+        let blockchain = self.node.get_blockchain().lock().unwrap();
+        let mut explorer = explorer.lock().unwrap();
+        explorer.sync(&blockchain)?;
+
+        Ok(query(&explorer))
+    }
+
+    fn handle_request(&self, request: &Json, permission: Permission) -> Option<Json> {
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(Json::as_str);
+        let params = request
+            .get("params")
+            .cloned()
+            .unwrap_or(Json::Array(vec![]));
+
+        let result = match (request.get("jsonrpc").and_then(Json::as_str), method) {
+            (Some("2.0"), Some(method)) if matches!(params, Json::Array(_) | Json::Object(_)) => {
+                if Permission::of_method(method) > permission {
+                    Err(RpcError::new(FORBIDDEN, "Token doesn't allow the method."))
+                } else {
+                    self.call(method, &params)
+                }
+            }
+            _ => Err(RpcError::new(
+                INVALID_REQUEST,
+                "Request isn't JSON-RPC 2.0.",
+            )),
+        };
+
+        // Notifications get no answer, even when they fail.
+        let id = match (id, &result) {
+            (Some(id), _) => id,
+            (None, Err(error)) if error.code == INVALID_REQUEST => Json::Null,
+            (None, _) => return None,
+        };
+
+        Some(match result {
+            Ok(result) => Json::object(vec![
+                ("jsonrpc", "2.0".into()),
+                ("result", result),
+                ("id", id),
+            ]),
+            Err(error) => get_error_response(id, error),
+        })
+    }
+}
+
+// Params in order, or by name.
+struct Params<'a>(&'a Json);
+
+impl Params<'_> {
+    fn get(&self, index: usize, name: &str) -> Option<&Json> {
+        match self.0 {
+            Json::Array(values) => values.get(index),
+            params => params.get(name),
+        }
+        .filter(|value| **value != Json::Null)
+    }
+
+    fn get_required(&self, index: usize, name: &str) -> Result<&Json, RpcError> {
+        self.get(index, name)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Param {name} is missing.")))
+    }
+
+    fn get_parsed<T: std::str::FromStr>(&self, index: usize, name: &str) -> Result<T, RpcError> {
+        self.get_parsed_with(index, name, |text| {
+            text.parse().map_err(|_| anyhow::anyhow!("Can't parse."))
+        })
+    }
+
+    fn get_parsed_with<T>(
+        &self,
+        index: usize,
+        name: &str,
+        parse: impl Fn(&str) -> Result<T>,
+    ) -> Result<T, RpcError> {
+        self.get_required(index, name)?
+            .as_str()
+            .and_then(|text| parse(text).ok())
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Param {name} is malformed.")))
+    }
+
+    fn get_integer(&self, index: usize, name: &str) -> Result<i64, RpcError> {
+        self.get_required(index, name)?
+            .as_i64()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Param {name} isn't an integer.")))
+    }
+
+    fn get_credits(&self, index: usize, name: &str) -> Result<i64, RpcError> {
+        let credits = self.get_integer(index, name)?;
+        if credits < 0 {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("Param {name} can't be negative."),
+            ));
+        }
+
+        Ok(credits)
+    }
+
+    fn get_i32(&self, index: usize, name: &str) -> Result<i32, RpcError> {
+        self.get_converted(index, name)
+    }
+
+    fn get_converted<T: TryFrom<i64>>(&self, index: usize, name: &str) -> Result<T, RpcError> {
+        self.get_integer(index, name)?
+            .try_into()
+            .map_err(|_| RpcError::new(INVALID_PARAMS, format!("Param {name} is out of range.")))
+    }
+
+    // An offset and a limit, from the index on.
+    fn get_page(&self, index: usize) -> Result<(usize, usize), RpcError> {
+        let offset = match self.get(index, "offset") {
+            Some(_) => self.get_converted(index, "offset")?,
+            None => 0,
+        };
+        let limit = match self.get(index + 1, "limit") {
+            Some(_) => self.get_converted(index + 1, "limit")?,
+            None => MAX_PAGE_SIZE,
+        };
+
+        Ok((offset, limit))
+    }
+
+    fn get_array(&self, index: usize, name: &str) -> Result<&[Json], RpcError> {
+        self.get_required(index, name)?
+            .as_array()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Param {name} isn't an array.")))
+    }
+
+    fn get_color(&self, index: usize, name: &str) -> Result<Color, RpcError> {
+        u8::try_from(self.get_integer(index, name)?)
+            .ok()
+            .and_then(|index| Color::try_from(index).ok())
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Param {name} isn't a color.")))
+    }
+}
+
+// Inputs and outputs are objects, whose fields are read like params by name.
+fn get_raw_input(input: &Json) -> Result<TransactionInput, RpcError> {
+    let input = Params(input);
+    if input
+        .get(0, "type")
+        .is_some_and(|kind| kind.as_str() != Some("output"))
+    {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            "Only inputs spending outputs can be created.",
+        ));
+    }
+
+    Ok(TransactionInput::FromOutput {
+        transaction_hash: input.get_parsed(0, "transaction_hash")?,
+        output_index: input.get_converted(0, "output_index")?,
+        public_key: input.get_parsed_with(0, "public_key", |hex| {
+            PublicKey::from_bytes(&decode_hex(hex)?)
+        })?,
+        signature: Signature::unsigned(),
+    })
+}
+
+fn get_raw_output(output: &Json) -> Result<TransactionOutput, RpcError> {
+    let output = Params(output);
+    let value = output.get_credits(0, "value")?;
+    let get_position = || Ok::<_, RpcError>((output.get_i32(0, "x")?, output.get_i32(0, "y")?));
+
+    match output.get_required(0, "type")?.as_str() {
+        Some("credits") => Ok(TransactionOutput::ToInput {
+            value,
+            public_key_address: output.get_parsed_with(0, "address", Address::parse)?,
+        }),
+        Some("pixel") => Ok(TransactionOutput::ToPixel {
+            value,
+            position: get_position()?,
+            color: output.get_color(0, "color")?,
+            layer: match output.get(0, "layer") {
+                Some(_) => output.get_converted(0, "layer")?,
+                None => BASE_LAYER,
+            },
+            faction: match output.get(0, "faction") {
+                Some(_) => Some(output.get_parsed_with(0, "faction", decode_hex)?),
+                None => None,
+            },
+            rental: match output.get(0, "rental") {
+                Some(_) => Some(output.get_converted(0, "rental")?),
+                None => None,
+            },
+            memo: match output.get(0, "memo") {
+                Some(_) => Some(output.get_parsed_with(0, "memo", |memo| Ok(memo.to_string()))?),
+                None => None,
+            },
+        }),
+        Some("moderation") => Ok(TransactionOutput::Moderate {
+            value,
+            position: get_position()?,
+            width: output.get_i32(0, "width")?,
+            height: output.get_i32(0, "height")?,
+            action: match output.get_required(0, "action")?.as_str() {
+                Some("blank") => ModerationAction::Blank,
+                Some("freeze") => ModerationAction::Freeze {
+                    duration: output.get_converted(0, "duration")?,
+                },
+                _ => return Err(RpcError::new(INVALID_PARAMS, "Param action isn't known.")),
+            },
+        }),
+        Some("lock") => Ok(TransactionOutput::LockPixel {
+            value,
+            position: get_position()?,
+            duration: output.get_converted(0, "duration")?,
+        }),
+        _ => Err(RpcError::new(INVALID_PARAMS, "Param type isn't known.")),
+    }
+}
+
+fn get_raw_transaction_json(raw_transaction: &RawTransaction) -> Json {
+    Json::object(vec![
+        ("hash", raw_transaction.calculate_hash().to_string().into()),
+        ("version", (raw_transaction.version as u64).into()),
+        ("inputs", get_inputs_json(&raw_transaction.inputs)),
+        ("outputs", get_outputs_json(&raw_transaction.outputs)),
+        ("lock_time", (raw_transaction.lock_time as u64).into()),
+    ])
+}
+
+fn get_error_response(id: Json, error: RpcError) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        (
+            "error",
+            Json::object(
+                [
+                    ("code", error.code.into()),
+                    ("message", error.message.into()),
+                ]
+                .into_iter()
+                .chain(error.data.map(|data| ("data", data)))
+                .collect(),
+            ),
+        ),
+        ("id", id),
+    ])
+}
+
+fn find_block<'a>(blockchain: &'a Blockchain, params: &Params) -> Result<&'a Block, RpcError> {
+    // Heights are looked up on the main chain.
+    let block = match params.get(0, "hash").or_else(|| params.get(0, "height")) {
+        Some(Json::Integer(height)) => usize::try_from(*height)
+            .ok()
+            .and_then(|height| blockchain.get_main_chain().get(height).copied()),
+        Some(Json::String(block_hash)) => {
+            let block_hash: BlockHash = block_hash
+                .parse()
+                .map_err(|_| RpcError::new(INVALID_PARAMS, "Param hash is malformed."))?;
+
+            // Blocks off the main chain can only be found by hash.
+            blockchain
+                .get_block(&block_hash)
+                .or_else(|| blockchain.get_stale_block(&block_hash))
+        }
+        _ => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "Param hash or height is missing.",
+            ))
+        }
+    };
+
+    Ok(block.context("Block isn't known.")?)
+}
+
+fn get_header_fields(block: &Block) -> Result<Vec<(&'static str, Json)>> {
+    let header = block.get_header();
+
+    Ok(vec![
+        ("hash", header.calculate_hash().to_string().into()),
+        ("height", block.get_block_height()?.into()),
+        (
+            "previous_hash",
+            header.previous_hash.map(|hash| hash.to_string()).into(),
+        ),
+        ("timestamp", header.timestamp.timestamp().into()),
+        ("proof", header.proof.to_string().into()),
+        ("canvas_root", header.canvas_root.to_string().into()),
+        (
+            "transactions_root",
+            encode_hex(&header.transactions_root).into(),
+        ),
+        ("transaction_count", block.get_transactions().len().into()),
+    ])
+}
+
+pub(crate) fn get_block_json(block: &Block) -> Result<Json> {
+    let mut fields = get_header_fields(block)?;
+    fields.push((
+        "transactions",
+        block
+            .get_transactions()
+            .iter()
+            .map(|transaction| transaction.get_hash().to_string())
+            .collect::<Vec<_>>()
+            .into(),
+    ));
+
+    Ok(Json::object(fields))
+}
+
+fn get_decoded_block_json(block: &Block) -> Result<Json> {
+    let mut fields = get_header_fields(block)?;
+    fields.push((
+        "transactions",
+        Json::Array(
+            block
+                .get_transactions()
+                .iter()
+                .map(|transaction| {
+                    Json::object(vec![
+                        ("hash", transaction.get_hash().to_string().into()),
+                        ("version", (transaction.get_version() as u64).into()),
+                        ("inputs", get_inputs_json(transaction.get_inputs())),
+                        ("outputs", get_outputs_json(transaction.get_outputs())),
+                        ("lock_time", (transaction.get_lock_time() as u64).into()),
+                        ("size", transaction.get_size().into()),
+                        ("tax", transaction.get_balance().into()),
+                    ])
+                })
+                .collect(),
+        ),
+    ));
+
+    Ok(Json::object(fields))
+}
+
+fn get_pixel_json(pixel: &Pixel) -> Json {
+    Json::object(vec![
+        ("color", (pixel.color as u8 as i64).into()),
+        (
+            "owner",
+            pixel.owner.as_ref().map(|owner| owner.as_str()).into(),
+        ),
+        ("height", pixel.height.into()),
+        (
+            "transaction_hash",
+            pixel.transaction_hash.to_string().into(),
+        ),
+        ("price", pixel.price.into()),
+    ])
+}
+
+fn get_block_stats_json(block_hash: &BlockHash, stats: &BlockStats) -> Json {
+    Json::object(vec![
+        ("hash", block_hash.to_string().into()),
+        ("height", stats.height.into()),
+        ("transaction_count", stats.transaction_count.into()),
+        ("size", stats.size.into()),
+        ("total_tax", stats.total_tax.into()),
+        (
+            "tax_rate_percentiles",
+            stats.tax_rate_percentiles.to_vec().into(),
+        ),
+        ("pixel_count", stats.pixel_count.into()),
+        ("subsidy", stats.subsidy.into()),
+    ])
+}
+
+// Bytes taken by the files in the directory, and in the ones below it. Links aren't followed, so a
+// loop can't send it around forever, and what they point to isn't counted.
+fn get_disk_usage(path: &Path) -> Result<u64> {
+    let mut disk_usage = 0;
+    for entry in fs::read_dir(path).context("Fail to read data directory.")? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            continue;
+        }
+
+        disk_usage += match file_type.is_dir() {
+            true => get_disk_usage(&entry.path())?,
+            false => entry.metadata()?.len(),
+        };
+    }
+
+    Ok(disk_usage)
+}
+
+fn get_page_json<T>(page: Page<T>, get_item_json: impl Fn(T) -> Json) -> Json {
+    Json::object(vec![
+        ("total", page.total.into()),
+        (
+            "items",
+            Json::Array(page.items.into_iter().map(get_item_json).collect()),
+        ),
+    ])
+}
+
+pub(crate) fn get_region_json(
+    blockchain: &Blockchain,
+    (x, y): (i32, i32),
+    (width, height): (i32, i32),
+) -> Result<Json> {
+    let canvas = blockchain.canvas()?;
+    let pixels = canvas.region(x, y, width, height);
+
+    Ok(Json::Array(
+        pixels
+            .chunks(width.max(1) as usize)
+            .map(|row| {
+                Json::Array(
+                    row.iter()
+                        .map(|pixel| pixel.map_or(Json::Null, get_pixel_json))
+                        .collect(),
+                )
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,186 @@
+// The canvas as PNG tiles over plain HTTP GET, laid out the way web map viewers (Leaflet and the
+// like) ask for them:
+//
+//     /tiles/<z>/<x>/<y>.png   the tile at column x and row y of zoom level z, from the top left
+//
+// Tiles are `TILE_SIZE` pixels square. At `MAX_ZOOM` each of their pixels is a pixel of the
+// canvas, and every level below shows twice as much, keeping one pixel in four, so a single tile
+// at zoom 0 covers the first 1024 by 1024 pixels. Viewers should be set up with the same tile size
+// and `MAX_ZOOM` as their highest native zoom, and zoom in further themselves.
+//
+// Tiles are encoded once and kept until a block paints on the part of the canvas they show. Each
+// comes with an ETag and `Cache-Control: no-cache`, so viewers ask again on every refresh, and get
+// a 304 without the image while the tile hasn't changed. Tiles off the canvas are a 404.
+
+use crate::http::{write_response, write_response_with_headers, Request};
+use anyhow::Result;
+use place_coin::{
+    blockchain::{BlockHash, Blockchain},
+    canvas::{Canvas, TILE_SIZE},
+    transaction::Point,
+};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+};
+
+pub const MAX_ZOOM: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileResponse {
+    pub status: u16,
+    pub etag: Option<String>,
+    pub png: Vec<u8>, // Empty unless the status is 200.
+}
+
+impl TileResponse {
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        let Some(etag) = &self.etag else {
+            return write_response(writer, self.status, "text/plain", "Not found.");
+        };
+
+        write_response_with_headers(
+            writer,
+            self.status,
+            &[("Cache-Control", "no-cache"), ("ETag", etag)],
+            "image/png",
+            &self.png,
+        )
+    }
+}
+
+#[derive(Debug)]
+struct EncodedTile {
+    version: u64, // Of the canvas tiles it shows, when it was encoded.
+    etag: String,
+    png: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct TileServer {
+    canvas: Canvas,
+    last_block_hash: Option<BlockHash>, // The main chain the canvas was replayed up to.
+    block_count: usize,
+    tiles: HashMap<(u32, Point), EncodedTile>,
+}
+
+impl TileServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sync(&mut self, blockchain: &Blockchain) -> Result<()> {
+        let main_chain = blockchain.get_main_chain();
+
+        // Blocks can't be taken back from a canvas, so one that left the main chain means starting
+        // over. Heights are indices on the main chain, and a block's hash commits to all before it.
+        let is_extended = self.block_count.checked_sub(1).is_some_and(|height| {
+            main_chain
+                .get(height)
+                .is_some_and(|block| Some(block.calculate_hash()) == self.last_block_hash)
+        });
+        if !is_extended {
+            self.canvas = Canvas::new(blockchain.get_chain_params().clone());
+            self.block_count = 0;
+            self.tiles.clear();
+        }
+
+        for block in &main_chain[self.block_count..] {
+            self.canvas.connect_block(block)?;
+        }
+        self.block_count = main_chain.len();
+        self.last_block_hash = main_chain.last().map(|block| block.calculate_hash());
+
+        Ok(())
+    }
+
+    pub fn handle_request(
+        &mut self,
+        blockchain: &Blockchain,
+        request: &Request,
+    ) -> Result<TileResponse> {
+        let not_found = TileResponse {
+            status: 404,
+            etag: None,
+            png: vec![],
+        };
+        let Some((zoom, tile_position)) = parse_target(&request.target) else {
+            return Ok(not_found);
+        };
+
+        self.sync(blockchain)?;
+        let Some(tile) = self.get_tile(zoom, tile_position)? else {
+            return Ok(not_found);
+        };
+
+        // Clients may send several ETags, weak ones, or `*` for whatever the server has.
+        let is_unchanged = request.get_header("if-none-match").is_some_and(|etags| {
+            etags.split(',').map(str::trim).any(|known_etag| {
+                known_etag == "*" || known_etag.trim_start_matches("W/") == tile.etag
+            })
+        });
+
+        Ok(TileResponse {
+            status: if is_unchanged { 304 } else { 200 },
+            etag: Some(tile.etag.clone()),
+            png: if is_unchanged {
+                vec![]
+            } else {
+                tile.png.clone()
+            },
+        })
+    }
+
+    fn get_tile(&mut self, zoom: u32, tile_position: Point) -> Result<Option<&EncodedTile>> {
+        let scale = 1 << (MAX_ZOOM - zoom);
+        let span = TILE_SIZE as i64 * scale as i64; // Canvas pixels across the tile.
+        let (tile_x, tile_y) = tile_position;
+        if tile_x < 0
+            || tile_y < 0
+            || tile_x as i64 * span >= self.canvas.get_width() as i64
+            || tile_y as i64 * span >= self.canvas.get_height() as i64
+        {
+            return Ok(None);
+        }
+
+        // Tiles painted on since they were encoded are dirty, and encoded again.
+        let version = self.canvas.get_scaled_tile_version(&tile_position, scale);
+        let key = (zoom, tile_position);
+        if self
+            .tiles
+            .get(&key)
+            .is_none_or(|tile| tile.version != version)
+        {
+            let mut png = vec![];
+            self.canvas
+                .write_scaled_tile_png(&tile_position, scale, &mut png)?;
+
+            // Canvases replayed after a reorganization count versions again from the start, so the
+            // ETag comes from the image itself.
+            let mut hasher = DefaultHasher::new();
+            png.hash(&mut hasher);
+            let etag = format!("\"{:016x}\"", hasher.finish());
+
+            self.tiles.insert(key, EncodedTile { version, etag, png });
+        }
+
+        Ok(self.tiles.get(&key))
+    }
+}
+
+fn parse_target(target: &str) -> Option<(u32, Point)> {
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    let segments = path.strip_prefix("/tiles/")?.split('/').collect::<Vec<_>>();
+    let [zoom, tile_x, tile_y] = segments[..] else {
+        return None;
+    };
+
+    let zoom = zoom.parse().ok().filter(|zoom| *zoom <= MAX_ZOOM)?;
+    let tile_position = (
+        tile_x.parse().ok()?,
+        tile_y.strip_suffix(".png")?.parse().ok()?,
+    );
+
+    Some((zoom, tile_position))
+}
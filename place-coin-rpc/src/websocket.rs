@@ -0,0 +1,358 @@
+// Live notifications over a WebSocket (RFC 6455), so canvas viewers don't have to poll. Clients
+// connect to `/ws` and send text messages to pick the topics they want:
+//
+//     {"subscribe": ["new_block", "pixel_painted"]}
+//     {"unsubscribe": ["new_block"]}
+//
+// Every notification is then pushed as:
+//
+//     {"topic": "new_block", "data": ...}
+//
+// Topics:
+//
+//     new_block       a block joined the chain, as getblock gives it
+//     new_tx          a transaction joined the mempool, with its hash
+//     pixel_painted   a pixel changed, with its position, color, painter and height; pixels going
+//                     back to what they were have no painter, and no color if they're blank again
+//     reorg           a block left the main chain, with its hash and height, as it was marked
+//                     invalid or another branch took over; its pixels aren't sent back
+//
+// Only unfragmented frames are taken from clients, of up to `MAX_FRAME_SIZE` bytes.
+
+use crate::{http::Request, json::Json, server::get_block_json};
+use anyhow::{bail, Context, Result};
+use place_coin::{
+    blockchain::{Blockchain, ChainEvent},
+    canvas::CanvasEvent,
+};
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    thread,
+};
+
+pub const TOPICS: [&str; 4] = ["new_block", "new_tx", "pixel_painted", "reorg"];
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+pub fn is_upgrade(request: &Request) -> bool {
+    request.method == "GET"
+        && request
+            .get_header("upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+}
+
+pub fn handle_websocket(
+    blockchain: &Arc<Mutex<Blockchain>>,
+    request: &Request,
+    mut stream: TcpStream,
+) -> Result<()> {
+    if request.target != "/ws" {
+        bail!("WebSocket endpoint is /ws.")
+    }
+    let key = request
+        .get_header("sec-websocket-key")
+        .context("WebSocket key is missing.")?;
+
+    let accept = encode_base64(&calculate_sha1(format!("{key}{ACCEPT_GUID}").as_bytes()));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+    )?;
+
+    // Events are forwarded from their own threads, which end with the first one that can't be sent.
+    let topics = Arc::new(Mutex::new(HashSet::<String>::new()));
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let (chain_events, canvas_events) = {
+        let mut blockchain = blockchain.lock().unwrap();
+        (blockchain.subscribe_chain(), blockchain.subscribe_canvas())
+    };
+    forward_events(
+        chain_events,
+        blockchain.clone(),
+        &topics,
+        &writer,
+        get_chain_notification,
+    );
+    forward_events(
+        canvas_events,
+        blockchain.clone(),
+        &topics,
+        &writer,
+        |_, event| Some(get_canvas_notification(event)),
+    );
+
+    let result = read_messages(&mut stream, &topics, &writer);
+
+    // Whatever forwarded events stops at the next one.
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+
+    result
+}
+
+fn read_messages(
+    stream: &mut TcpStream,
+    topics: &Mutex<HashSet<String>>,
+    writer: &Mutex<TcpStream>,
+) -> Result<()> {
+    loop {
+        let (opcode, payload) = read_frame(stream)?;
+        match opcode {
+            TEXT => {
+                let message = Json::parse(std::str::from_utf8(&payload)?)?;
+                let mut topics = topics.lock().unwrap();
+                for (key, is_subscribed) in [("subscribe", true), ("unsubscribe", false)] {
+                    for topic in message
+                        .get(key)
+                        .and_then(Json::as_array)
+                        .unwrap_or_default()
+                    {
+                        let topic = topic.as_str().context("Topics must be strings.")?;
+                        if !TOPICS.contains(&topic) {
+                            bail!("Topic {topic} isn't known.")
+                        }
+
+                        if is_subscribed {
+                            topics.insert(topic.to_string());
+                        } else {
+                            topics.remove(topic);
+                        }
+                    }
+                }
+            }
+            PING => write_frame(&mut *writer.lock().unwrap(), PONG, &payload)?,
+            PONG => {}
+            CLOSE => {
+                write_frame(&mut *writer.lock().unwrap(), CLOSE, &payload)?;
+                return Ok(());
+            }
+            _ => bail!("WebSocket frame type isn't supported."),
+        }
+    }
+}
+
+fn forward_events<E: Send + 'static>(
+    events: Receiver<E>,
+    blockchain: Arc<Mutex<Blockchain>>,
+    topics: &Arc<Mutex<HashSet<String>>>,
+    writer: &Arc<Mutex<TcpStream>>,
+    get_notification: impl Fn(&Blockchain, E) -> Option<(&'static str, Json)> + Send + 'static,
+) {
+    let (topics, writer) = (topics.clone(), writer.clone());
+
+    thread::spawn(move || {
+        for event in events {
+            // Events are sent while the chain is busy, so it's only looked at once they're here.
+            let notification = get_notification(&blockchain.lock().unwrap(), event);
+            let Some((topic, data)) = notification else {
+                continue;
+            };
+            if !topics.lock().unwrap().contains(topic) {
+                continue;
+            }
+
+            let message = Json::object(vec![("topic", topic.into()), ("data", data)]);
+            if write_frame(
+                &mut *writer.lock().unwrap(),
+                TEXT,
+                message.to_string().as_bytes(),
+            )
+            .is_err()
+            {
+                return;
+            }
+        }
+    });
+}
+
+fn get_chain_notification(
+    blockchain: &Blockchain,
+    event: ChainEvent,
+) -> Option<(&'static str, Json)> {
+    match event {
+        ChainEvent::BlockConnected { block_hash, .. } => {
+            let block = blockchain.get_block(&block_hash)?;
+
+            Some(("new_block", get_block_json(block).ok()?))
+        }
+        ChainEvent::BlockDisconnected { block_hash, height } => Some((
+            "reorg",
+            Json::object(vec![
+                ("hash", block_hash.to_string().into()),
+                ("height", height.into()),
+            ]),
+        )),
+        ChainEvent::TransactionAdded(transaction_hash) => Some((
+            "new_tx",
+            Json::object(vec![("hash", transaction_hash.to_string().into())]),
+        )),
+    }
+}
+
+fn get_canvas_notification(event: CanvasEvent) -> (&'static str, Json) {
+    let (point, color, painter, height) = match event {
+        CanvasEvent::PixelPainted {
+            point,
+            color,
+            painter,
+            height,
+        } => (point, Some(color), painter, height),
+        CanvasEvent::PixelReverted {
+            point,
+            color,
+            height,
+        } => (point, color, None, height),
+    };
+
+    (
+        "pixel_painted",
+        Json::object(vec![
+            ("x", (point.0 as i64).into()),
+            ("y", (point.1 as i64).into()),
+            ("color", color.map(|color| color as u8 as i64).into()),
+            (
+                "painter",
+                painter.as_ref().map(|painter| painter.as_str()).into(),
+            ),
+            ("height", height.into()),
+        ]),
+    )
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    if header[0] & 0x80 == 0 {
+        bail!("WebSocket fragments aren't supported.")
+    }
+    if header[1] & 0x80 == 0 {
+        bail!("WebSocket frames from clients must be masked.")
+    }
+
+    let size = match header[1] & 0x7F {
+        126 => {
+            let mut size = [0; 2];
+            reader.read_exact(&mut size)?;
+            u16::from_be_bytes(size) as usize
+        }
+        127 => {
+            let mut size = [0; 8];
+            reader.read_exact(&mut size)?;
+            u64::from_be_bytes(size).try_into().unwrap_or(usize::MAX)
+        }
+        size => size as usize,
+    };
+    if size > MAX_FRAME_SIZE {
+        bail!("WebSocket frame is too big.")
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; size];
+    reader.read_exact(&mut payload)?;
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+
+    Ok((header[0] & 0x0F, payload))
+}
+
+fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> Result<()> {
+    // Frames from the server are never masked.
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        size if size < 126 => frame.push(size as u8),
+        size if size <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(size as u16).to_be_bytes());
+        }
+        size => {
+            frame.push(127);
+            frame.extend_from_slice(&(size as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)?;
+
+    Ok(())
+}
+
+// SHA-1 (RFC 3174), which the handshake needs. It's broken for anything else.
+fn calculate_sha1(message: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+    for chunk in padded.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (index, word) in chunk.chunks_exact(4).enumerate() {
+            words[index] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temporary = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temporary);
+        }
+
+        for (value, new_value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(new_value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+
+    digest
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| {
+            bits | (*byte as u32) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}